@@ -0,0 +1,108 @@
+//! Throughput of concurrent `/getHeader` calls against the SQLite data source.
+//!
+//! Run with `cargo bench --bench get_header`. The server state is created in a
+//! scratch directory under the system temp dir, so the repository's `data_`
+//! directory is left untouched.
+
+use std::time::{Duration, Instant};
+
+use axum::{
+    body::{to_bytes, Body},
+    http::{header::CONTENT_TYPE, Request, StatusCode},
+    Router,
+};
+use excel_app::config::{Config, DatabaseConfig};
+use tower::ServiceExt;
+
+const REQUESTS_PER_RUN: usize = 2000;
+const CONCURRENCY_LEVELS: [usize; 4] = [1, 8, 32, 128];
+const BOUNDARY: &str = "excel-app-bench-boundary";
+
+#[tokio::main]
+async fn main() {
+    let work_dir = std::env::temp_dir().join(format!("excel_app_bench_{}", std::process::id()));
+    std::fs::create_dir_all(&work_dir).unwrap();
+    std::env::set_current_dir(&work_dir).unwrap();
+
+    for pool_size in [1, 8] {
+        let config = Config {
+            database: DatabaseConfig::Sqlite {
+                path: work_dir.join(format!("bench_{pool_size}.sqlite")),
+                pool_size,
+            },
+            ..Config::default()
+        };
+        let app = excel_app::get_app_router(&config).await.unwrap();
+        let file_id = upload_sample(&app).await;
+
+        println!("pool size {pool_size}");
+        for concurrency in CONCURRENCY_LEVELS {
+            let elapsed = run(&app, &file_id, concurrency).await;
+            println!(
+                "  concurrency {concurrency:>4}: {REQUESTS_PER_RUN} requests in {:>8.2?} ({:.0} req/s)",
+                elapsed,
+                REQUESTS_PER_RUN as f64 / elapsed.as_secs_f64()
+            );
+        }
+    }
+
+    let _ = std::fs::remove_dir_all(&work_dir);
+}
+
+async fn run(app: &Router, file_id: &str, concurrency: usize) -> Duration {
+    let start = Instant::now();
+    let mut tasks = Vec::with_capacity(concurrency);
+    for worker in 0..concurrency {
+        let app = app.clone();
+        let uri = format!("/getHeader/{file_id}");
+        let requests = (worker..REQUESTS_PER_RUN).step_by(concurrency).count();
+        tasks.push(tokio::spawn(async move {
+            for _ in 0..requests {
+                let request = Request::get(&uri).body(Body::empty()).unwrap();
+                let response = app.clone().oneshot(request).await.unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+            }
+        }));
+    }
+    for task in tasks {
+        task.await.unwrap();
+    }
+    start.elapsed()
+}
+
+async fn upload_sample(app: &Router) -> String {
+    let mut book = umya_spreadsheet::new_file();
+    let sheet = book.get_sheet_mut(&0).unwrap();
+    for col in 1..=10u32 {
+        sheet
+            .get_cell_mut((col, 1))
+            .set_value(format!("Column {col}"));
+        for row in 2..=50u32 {
+            sheet
+                .get_cell_mut((col, row))
+                .set_value(format!("value {col}-{row}"));
+        }
+    }
+    let mut xlsx = std::io::Cursor::new(Vec::new());
+    umya_spreadsheet::writer::xlsx::write_writer(&book, &mut xlsx).unwrap();
+
+    let mut body = format!(
+        "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"bench.xlsx\"\r\nContent-Type: application/octet-stream\r\n\r\n"
+    )
+    .into_bytes();
+    body.extend_from_slice(&xlsx.into_inner());
+    body.extend_from_slice(format!("\r\n--{BOUNDARY}--\r\n").as_bytes());
+
+    let request = Request::post("/upload")
+        .header(
+            CONTENT_TYPE,
+            format!("multipart/form-data; boundary={BOUNDARY}"),
+        )
+        .body(Body::from(body))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let entry: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    entry["id"].as_str().unwrap().to_string()
+}
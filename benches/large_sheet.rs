@@ -0,0 +1,229 @@
+//! Peak memory and time of a `/runJob` on a generated sheet with 500k rows.
+//!
+//! Run with `cargo bench --bench large_sheet`. The row count can be changed
+//! with `EXCEL_APP_BENCH_ROWS`. The workbook is written as raw SpreadsheetML,
+//! building it with umya-spreadsheet would itself need gigabytes. Peak memory
+//! is the process' resident set high water mark, reset before every phase, so
+//! this only reports it on Linux.
+
+use std::{
+    io::{Cursor, Write},
+    time::Instant,
+};
+
+use axum::{
+    body::{to_bytes, Body},
+    http::{header::CONTENT_TYPE, Request, StatusCode},
+    Router,
+};
+use excel_app::config::{Config, DatabaseConfig};
+use tower::ServiceExt;
+use zip::{write::FileOptions, ZipWriter};
+
+const DEFAULT_ROWS: usize = 500_000;
+const BOUNDARY: &str = "excel-app-bench-boundary";
+const STATUSES: [&str; 3] = ["open", "closed", "pending"];
+const NAMES: [&str; 8] = [
+    "alice", "bob", "carol", "dave", "erin", "frank", "grace", "heidi",
+];
+
+#[tokio::main]
+async fn main() {
+    let rows = std::env::var("EXCEL_APP_BENCH_ROWS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_ROWS);
+
+    let work_dir = std::env::temp_dir().join(format!("excel_app_bench_{}", std::process::id()));
+    std::fs::create_dir_all(&work_dir).unwrap();
+    std::env::set_current_dir(&work_dir).unwrap();
+
+    let start = Instant::now();
+    let xlsx = generate_workbook(rows);
+    println!(
+        "generated {rows} rows, {:.1} MB in {:.2?}",
+        xlsx.len() as f64 / 1_000_000.0,
+        start.elapsed()
+    );
+
+    let config = Config {
+        database: DatabaseConfig::Sqlite {
+            path: work_dir.join("bench.sqlite"),
+            pool_size: 1,
+        },
+        max_upload_bytes: usize::MAX,
+        ..Config::default()
+    };
+    let app = excel_app::get_app_router(&config).await.unwrap();
+
+    reset_peak_rss();
+    let start = Instant::now();
+    let file_id = upload(&app, xlsx).await;
+    report("upload", start);
+
+    reset_peak_rss();
+    let start = Instant::now();
+    let output_len = run_job(&app, &file_id).await;
+    report("runJob", start);
+    println!("  output {:.1} MB", output_len as f64 / 1_000_000.0);
+
+    let _ = std::fs::remove_dir_all(&work_dir);
+}
+
+fn report(phase: &str, start: Instant) {
+    let elapsed = start.elapsed();
+    match peak_rss_kb() {
+        Some(kb) => println!(
+            "{phase:>8}: {elapsed:>9.2?}, peak RSS {:.0} MB",
+            kb as f64 / 1024.0
+        ),
+        None => println!("{phase:>8}: {elapsed:>9.2?}"),
+    }
+}
+
+fn reset_peak_rss() {
+    // Writing 5 resets VmHWM to the current RSS.
+    let _ = std::fs::write("/proc/self/clear_refs", "5");
+}
+
+fn peak_rss_kb() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmHWM:"))
+        .and_then(|v| v.trim().trim_end_matches("kB").trim().parse().ok())
+}
+
+/// A single sheet workbook with a Status, Name, Date, Amount and Note column.
+/// Every seventh note contains the search term.
+fn generate_workbook(rows: usize) -> Vec<u8> {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = FileOptions::default();
+    let mut add = |name: &str, content: &str| {
+        zip.start_file(name, options).unwrap();
+        zip.write_all(content.as_bytes()).unwrap();
+    };
+
+    add(
+        "[Content_Types].xml",
+        r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types"><Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/><Default Extension="xml" ContentType="application/xml"/><Override PartName="/xl/workbook.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml"/><Override PartName="/xl/worksheets/sheet1.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml"/><Override PartName="/xl/styles.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.styles+xml"/><Override PartName="/xl/sharedStrings.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sharedStrings+xml"/></Types>"#,
+    );
+    add(
+        "_rels/.rels",
+        r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="xl/workbook.xml"/></Relationships>"#,
+    );
+    add(
+        "xl/workbook.xml",
+        r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<workbook xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships"><sheets><sheet name="Sheet1" sheetId="1" r:id="rId1"/></sheets></workbook>"#,
+    );
+    add(
+        "xl/_rels/workbook.xml.rels",
+        r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="worksheets/sheet1.xml"/><Relationship Id="rId2" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/styles" Target="styles.xml"/><Relationship Id="rId3" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/sharedStrings" Target="sharedStrings.xml"/></Relationships>"#,
+    );
+    add(
+        "xl/styles.xml",
+        r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<styleSheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"><fonts count="1"><font><sz val="11"/><name val="Calibri"/></font></fonts><fills count="2"><fill><patternFill patternType="none"/></fill><fill><patternFill patternType="gray125"/></fill></fills><borders count="1"><border/></borders><cellStyleXfs count="1"><xf numFmtId="0" fontId="0" fillId="0" borderId="0"/></cellStyleXfs><cellXfs count="1"><xf numFmtId="0" fontId="0" fillId="0" borderId="0" xfId="0"/></cellXfs></styleSheet>"#,
+    );
+
+    let shared_strings: Vec<&str> = ["Status", "Name", "Date", "Amount", "Note"]
+        .into_iter()
+        .chain(STATUSES)
+        .chain(NAMES)
+        .collect();
+    let mut sst = String::from(
+        r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?><sst xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main">"#,
+    );
+    for s in &shared_strings {
+        sst.push_str(&format!("<si><t>{s}</t></si>"));
+    }
+    sst.push_str("</sst>");
+    add("xl/sharedStrings.xml", &sst);
+
+    zip.start_file("xl/worksheets/sheet1.xml", options).unwrap();
+    zip.write_all(
+        br#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?><worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"><sheetData><row r="1"><c r="A1" t="s"><v>0</v></c><c r="B1" t="s"><v>1</v></c><c r="C1" t="s"><v>2</v></c><c r="D1" t="s"><v>3</v></c><c r="E1" t="s"><v>4</v></c></row>"#,
+    )
+    .unwrap();
+    for idx in 0..rows {
+        let r = idx + 2;
+        let status = 5 + idx % STATUSES.len();
+        let name = 5 + STATUSES.len() + (idx * 7) % NAMES.len();
+        let date = format!("{:02}{:02}{:02}", idx % 12 + 1, idx % 28 + 1, idx % 100);
+        let note = if idx % 7 == 0 {
+            "urgent follow up"
+        } else {
+            "routine"
+        };
+        write!(
+            zip,
+            r#"<row r="{r}"><c r="A{r}" t="s"><v>{status}</v></c><c r="B{r}" t="s"><v>{name}</v></c><c r="C{r}" t="inlineStr"><is><t>{date}</t></is></c><c r="D{r}"><v>{amount}</v></c><c r="E{r}" t="inlineStr"><is><t>{note} {idx}</t></is></c></row>"#,
+            amount = (idx * 37) % 10_000
+        )
+        .unwrap();
+    }
+    zip.write_all(b"</sheetData></worksheet>").unwrap();
+
+    zip.finish().unwrap().into_inner()
+}
+
+async fn upload(app: &Router, xlsx: Vec<u8>) -> String {
+    let mut body = format!(
+        "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"large.xlsx\"\r\nContent-Type: application/octet-stream\r\n\r\n"
+    )
+    .into_bytes();
+    body.extend_from_slice(&xlsx);
+    body.extend_from_slice(format!("\r\n--{BOUNDARY}--\r\n").as_bytes());
+
+    let response = app
+        .clone()
+        .oneshot(multipart_request("/upload", body))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let entry: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    entry["id"].as_str().unwrap().to_string()
+}
+
+/// Sorts by status and name, highlights a search term and checks the date
+/// column. Returns the size of the produced workbook.
+async fn run_job(app: &Router, file_id: &str) -> usize {
+    let mut body = String::new();
+    for (name, value) in [
+        ("fileId", file_id),
+        ("sortCol", "asc,1"),
+        ("sortCol", "desc,2"),
+        ("searchTerm", "urgent"),
+        ("checkDate", "3"),
+    ] {
+        body.push_str(&format!(
+            "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"{name}\"\r\n\r\n{value}\r\n"
+        ));
+    }
+    body.push_str(&format!("--{BOUNDARY}--\r\n"));
+
+    let response = app
+        .clone()
+        .oneshot(multipart_request("/runJob", body.into_bytes()))
+        .await
+        .unwrap();
+    let status = response.status();
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(status, StatusCode::OK, "{}", String::from_utf8_lossy(&body));
+    body.len()
+}
+
+fn multipart_request(uri: &str, body: Vec<u8>) -> Request<Body> {
+    Request::post(uri)
+        .header(
+            CONTENT_TYPE,
+            format!("multipart/form-data; boundary={BOUNDARY}"),
+        )
+        .body(Body::from(body))
+        .unwrap()
+}
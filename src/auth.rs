@@ -0,0 +1,386 @@
+//! Users, login sessions, API tokens and the principal requests are made
+//! for. Sessions live in a cookie holding a random token, API tokens are
+//! sent as `Authorization: Bearer <token>`. Only the sha256 of either is
+//! stored.
+
+use std::time::Duration;
+
+use axum::{
+    extract::{Request, State},
+    http::{
+        header::{AUTHORIZATION, COOKIE},
+        HeaderMap,
+    },
+    middleware::Next,
+    response::Response,
+};
+use chrono::{DateTime, Utc};
+use hmac::Hmac;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+use crate::{
+    data::{
+        model::{Scope, UploadFileEntry},
+        DataSource,
+    },
+    error::Error,
+    web::AppState,
+    Result,
+};
+
+pub const SESSION_COOKIE: &str = "excel_app_session";
+const PASSWORD_SCHEME: &str = "pbkdf2-sha256";
+const PBKDF2_ROUNDS: u32 = 100_000;
+const SALT_BYTES: usize = 16;
+const HASH_BYTES: usize = 32;
+const TOKEN_BYTES: usize = 32;
+const BEARER_SCHEME: &str = "Bearer";
+pub const MIN_PASSWORD_LENGTH: usize = 8;
+/// Days API tokens last unless asked otherwise, and at most.
+pub const DEFAULT_TOKEN_DAYS: u32 = 90;
+pub const MAX_TOKEN_DAYS: u32 = 365;
+/// A hash no password verifies against, checked for unknown users so logins
+/// take as long whether or not the user exists. Hashed with
+/// [`PBKDF2_ROUNDS`] like stored passwords.
+pub const DUMMY_PASSWORD_HASH: &str = "pbkdf2-sha256$100000$2c9dbc4636dd735b3cc81774d468d665$d3684ce0d5dcf91b8f6ccc7ae5d7674c4dfaacb996dfcd2f0c816a5a086c8b68";
+
+/// Who a request is made for, put in the request extensions by
+/// [`authenticate`].
+#[derive(Debug, Clone)]
+pub enum Principal {
+    /// Authentication is disabled, every upload is open to everyone.
+    Anonymous,
+    User {
+        id: String,
+        username: String,
+        /// Scopes of the API token used, `None` for login sessions, which
+        /// may do everything.
+        scopes: Option<Vec<Scope>>,
+    },
+}
+
+impl Principal {
+    /// Id recorded as the owner of the files uploaded.
+    pub fn owner(&self) -> Option<&str> {
+        match self {
+            Principal::Anonymous => None,
+            Principal::User { id, .. } => Some(id),
+        }
+    }
+
+    /// Name the principal is logged as.
+    pub fn name(&self) -> &str {
+        match self {
+            Principal::Anonymous => "anonymous",
+            Principal::User { username, .. } => username,
+        }
+    }
+
+    /// Id of the user, failing for anonymous principals.
+    pub fn user_id(&self) -> Result<&str> {
+        match self {
+            Principal::Anonymous => Err(Error::Unauthorized("Login required".into())),
+            Principal::User { id, .. } => Ok(id),
+        }
+    }
+
+    /// Fails with 403 for API tokens without `scope` or `admin`.
+    pub fn require(&self, scope: Scope) -> Result<()> {
+        match self {
+            Principal::Anonymous | Principal::User { scopes: None, .. } => Ok(()),
+            Principal::User {
+                scopes: Some(scopes),
+                ..
+            } if scopes.contains(&scope) || scopes.contains(&Scope::Admin) => Ok(()),
+            Principal::User { .. } => Err(Error::Forbidden(format!(
+                "The API token lacks the {} scope",
+                scope.as_str()
+            ))),
+        }
+    }
+
+    /// Fails for uploads of someone else. Those are reported as unknown ids
+    /// rather than as forbidden, so the ids of other users can't be probed.
+    pub fn authorize(&self, entry: &UploadFileEntry) -> Result<()> {
+        match self {
+            Principal::Anonymous => Ok(()),
+            Principal::User { id, .. } if entry.owner.as_deref() == Some(id) => Ok(()),
+            Principal::User { .. } => Err(Error::NoEntryFound(entry.id.clone())),
+        }
+    }
+}
+
+/// Resolves the principal of a request. With authentication required,
+/// requests without a valid API token or session are answered with 401.
+pub async fn authenticate<D: DataSource>(
+    State(state): State<AppState<D>>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response> {
+    let principal = match state.auth.required {
+        false => Principal::Anonymous,
+        true => user_principal(&state.datasource, request.headers()).await?,
+    };
+    request.extensions_mut().insert(principal);
+    Ok(next.run(request).await)
+}
+
+/// As [`authenticate`], for routes that always need a user whether or not
+/// authentication is required.
+pub async fn authenticate_user<D: DataSource>(
+    State(state): State<AppState<D>>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response> {
+    let principal = user_principal(&state.datasource, request.headers()).await?;
+    request.extensions_mut().insert(principal);
+    Ok(next.run(request).await)
+}
+
+/// The user of the API token in the `Authorization` header, or of the
+/// session cookie without one.
+async fn user_principal<D: DataSource>(datasource: &D, headers: &HeaderMap) -> Result<Principal> {
+    match headers.get(AUTHORIZATION) {
+        Some(value) => token_principal(datasource, value.to_str().unwrap_or_default()).await,
+        None => session_principal(datasource, headers).await,
+    }
+}
+
+async fn token_principal<D: DataSource>(datasource: &D, authorization: &str) -> Result<Principal> {
+    let token = match authorization.trim().split_once(' ') {
+        Some((scheme, token)) if scheme.eq_ignore_ascii_case(BEARER_SCHEME) => token.trim(),
+        _ => {
+            return Err(Error::Unauthorized(
+                "Expected an Authorization: Bearer <token> header".into(),
+            ))
+        }
+    };
+    let (token, user) = match datasource.get_api_token(token_hash(token)).await {
+        Err(Error::NoEntryFound(_)) => return Err(Error::Unauthorized("Unknown API token".into())),
+        Err(e) => return Err(e),
+        Ok(token) => token,
+    };
+    if expired(&token.expires_at) {
+        return Err(Error::Unauthorized("API token expired".into()));
+    }
+    Ok(Principal::User {
+        id: user.id,
+        username: user.username,
+        scopes: Some(token.scopes),
+    })
+}
+
+async fn session_principal<D: DataSource>(
+    datasource: &D,
+    headers: &HeaderMap,
+) -> Result<Principal> {
+    let Some(token) = session_token(headers) else {
+        return Err(Error::Unauthorized("Login required".into()));
+    };
+    let session = match datasource.get_session(token_hash(token)).await {
+        Err(Error::NoEntryFound(_)) => return Err(Error::Unauthorized("Unknown session".into())),
+        Err(e) => return Err(e),
+        Ok(session) => session,
+    };
+    if expired(&session.expires_at) {
+        datasource.remove_session(session.token_hash).await?;
+        return Err(Error::Unauthorized("Session expired".into()));
+    }
+    Ok(Principal::User {
+        id: session.user.id,
+        username: session.user.username,
+        scopes: None,
+    })
+}
+
+/// Whether an RFC 3339 time has passed, unreadable times count as passed.
+fn expired(expires_at: &str) -> bool {
+    DateTime::parse_from_rfc3339(expires_at).map_or(true, |expires_at| expires_at <= Utc::now())
+}
+
+/// `pbkdf2-sha256$<rounds>$<salt>$<hash>`, salt and hash in hex.
+pub fn hash_password(password: &str) -> String {
+    let mut salt = [0u8; SALT_BYTES];
+    rand::thread_rng().fill_bytes(&mut salt);
+    let mut hash = [0u8; HASH_BYTES];
+    pbkdf2::pbkdf2::<Hmac<Sha256>>(password.as_bytes(), &salt, PBKDF2_ROUNDS, &mut hash);
+    format!(
+        "{PASSWORD_SCHEME}${PBKDF2_ROUNDS}${}${}",
+        to_hex(&salt),
+        to_hex(&hash)
+    )
+}
+
+/// Whether `password` hashes to `stored`, compared in constant time.
+pub fn verify_password(password: &str, stored: &str) -> bool {
+    let parts: Vec<&str> = stored.split('$').collect();
+    let [PASSWORD_SCHEME, rounds, salt, hash] = parts.as_slice() else {
+        return false;
+    };
+    let (Ok(rounds), Some(salt), Some(hash)) =
+        (rounds.parse::<u32>(), from_hex(salt), from_hex(hash))
+    else {
+        return false;
+    };
+    // An empty hash would equal the empty hash computed for any password.
+    if rounds == 0 || hash.len() != HASH_BYTES {
+        return false;
+    }
+    let mut computed = vec![0u8; hash.len()];
+    pbkdf2::pbkdf2::<Hmac<Sha256>>(password.as_bytes(), &salt, rounds, &mut computed);
+    computed.ct_eq(&hash).into()
+}
+
+/// A random session or API token, in hex.
+pub fn new_token() -> String {
+    let mut token = [0u8; TOKEN_BYTES];
+    rand::thread_rng().fill_bytes(&mut token);
+    to_hex(&token)
+}
+
+/// What tokens are stored as.
+pub fn token_hash(token: &str) -> String {
+    to_hex(&Sha256::digest(token.as_bytes()))
+}
+
+pub fn session_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(name, _)| *name == SESSION_COOKIE)
+        .map(|(_, value)| value)
+        .filter(|value| !value.is_empty())
+}
+
+/// `Set-Cookie` value of a session lasting `max_age`. The cookie is kept
+/// from scripts and from requests started by other sites, and from plain
+/// http requests when `secure`, as it should be whenever the server serves
+/// https.
+pub fn session_cookie(token: &str, max_age: Duration, secure: bool) -> String {
+    format!(
+        "{SESSION_COOKIE}={token}; Max-Age={}; Path=/; HttpOnly; SameSite=Strict{}",
+        max_age.as_secs(),
+        secure_attribute(secure)
+    )
+}
+
+/// `Set-Cookie` value removing the session cookie.
+pub fn cleared_session_cookie(secure: bool) -> String {
+    format!(
+        "{SESSION_COOKIE}=; Max-Age=0; Path=/; HttpOnly; SameSite=Strict{}",
+        secure_attribute(secure)
+    )
+}
+
+fn secure_attribute(secure: bool) -> &'static str {
+    if secure {
+        "; Secure"
+    } else {
+        ""
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn passwords() {
+        let stored = hash_password("correct horse");
+        assert!(stored.starts_with(&format!("{PASSWORD_SCHEME}${PBKDF2_ROUNDS}$")));
+        assert!(verify_password("correct horse", &stored));
+        assert!(!verify_password("correct horsE", &stored));
+        // Salted, the same password hashes differently every time.
+        assert_ne!(stored, hash_password("correct horse"));
+    }
+
+    #[test]
+    fn dummy_hash_costs_as_much_as_stored_ones() {
+        let stored = hash_password("correct horse");
+        let prefix = |hash: &str| hash.rsplitn(3, '$').last().unwrap().to_owned();
+        assert_eq!(prefix(DUMMY_PASSWORD_HASH), prefix(&stored));
+        assert_eq!(DUMMY_PASSWORD_HASH.len(), stored.len());
+        assert!(!verify_password("", DUMMY_PASSWORD_HASH));
+        assert!(!verify_password("correct horse", DUMMY_PASSWORD_HASH));
+    }
+
+    #[test]
+    fn malformed_hashes_verify_nothing() {
+        let stored = hash_password("secret password");
+        let (rest, _) = stored.rsplit_once('$').unwrap();
+        for stored in [
+            String::new(),
+            format!("{rest}$"),
+            format!("{rest}$abc"),
+            format!("{rest}$zz"),
+            format!("{rest}${}", "00".repeat(HASH_BYTES)),
+            stored.replacen(&format!("${PBKDF2_ROUNDS}$"), "$0$", 1),
+            stored.replacen(PASSWORD_SCHEME, "md5", 1),
+            format!("{stored}$extra"),
+        ] {
+            assert!(!verify_password("secret password", &stored), "{stored}");
+        }
+    }
+
+    #[test]
+    fn tokens() {
+        let token = new_token();
+        assert_eq!(token.len(), TOKEN_BYTES * 2);
+        assert_ne!(token, new_token());
+        assert_eq!(token_hash(&token), token_hash(&token));
+        assert_ne!(token_hash(&token), token);
+        assert_eq!(
+            token_hash("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(from_hex(&to_hex(&[0, 15, 255])), Some(vec![0, 15, 255]));
+        assert_eq!(from_hex("abc"), None);
+    }
+
+    #[test]
+    fn session_cookies() {
+        let cookie = session_cookie("abc", Duration::from_secs(60), false);
+        assert_eq!(
+            cookie,
+            format!("{SESSION_COOKIE}=abc; Max-Age=60; Path=/; HttpOnly; SameSite=Strict")
+        );
+        assert!(session_cookie("abc", Duration::from_secs(60), true).ends_with("; Secure"));
+        assert!(cleared_session_cookie(true).ends_with("; Secure"));
+        assert!(!cleared_session_cookie(false).contains("Secure"));
+
+        let mut headers = HeaderMap::new();
+        headers.append(COOKIE, HeaderValue::from_static("theme=dark"));
+        headers.append(
+            COOKIE,
+            HeaderValue::from_str(&format!("a=b; {SESSION_COOKIE}=abc")).unwrap(),
+        );
+        assert_eq!(session_token(&headers), Some("abc"));
+        let mut headers = HeaderMap::new();
+        headers.append(
+            COOKIE,
+            HeaderValue::from_str(&format!("{SESSION_COOKIE}=")).unwrap(),
+        );
+        assert_eq!(session_token(&headers), None);
+    }
+}
@@ -0,0 +1,439 @@
+use std::{
+    env,
+    path::{PathBuf, MAIN_SEPARATOR},
+    time::Duration,
+};
+
+use axum::http::{header, HeaderName, HeaderValue, Method};
+
+use crate::{error::Error, Result, DATA_DIR_NAME};
+
+const DATABASE_URL_ENV: &str = "EXCEL_APP_DATABASE_URL";
+const DATABASE_POOL_SIZE_ENV: &str = "EXCEL_APP_DATABASE_POOL_SIZE";
+const DEFAULT_POOL_SIZE: usize = 8;
+const MAX_CONCURRENT_JOBS_ENV: &str = "EXCEL_APP_MAX_CONCURRENT_JOBS";
+const MAX_UPLOAD_BYTES_ENV: &str = "EXCEL_APP_MAX_UPLOAD_BYTES";
+const DEFAULT_MAX_UPLOAD_BYTES: usize = 10_000_000;
+const AUTH_REQUIRED_ENV: &str = "EXCEL_APP_AUTH_REQUIRED";
+const SESSION_TTL_HOURS_ENV: &str = "EXCEL_APP_SESSION_TTL_HOURS";
+const DEFAULT_SESSION_TTL_HOURS: usize = 12;
+const UPLOADS_PER_MINUTE_ENV: &str = "EXCEL_APP_UPLOADS_PER_MINUTE";
+const JOBS_PER_MINUTE_ENV: &str = "EXCEL_APP_JOBS_PER_MINUTE";
+const DEFAULT_REQUESTS_PER_MINUTE: usize = 60;
+const MAX_JOBS_PER_CLIENT_ENV: &str = "EXCEL_APP_MAX_JOBS_PER_CLIENT";
+const DEFAULT_MAX_JOBS_PER_CLIENT: usize = 2;
+const DAILY_UPLOAD_QUOTA_ENV: &str = "EXCEL_APP_DAILY_UPLOAD_QUOTA_BYTES";
+const DEFAULT_DAILY_UPLOAD_QUOTA: usize = 1_000_000_000;
+const CORS_ORIGINS_ENV: &str = "EXCEL_APP_CORS_ORIGINS";
+const CORS_METHODS_ENV: &str = "EXCEL_APP_CORS_METHODS";
+const CORS_HEADERS_ENV: &str = "EXCEL_APP_CORS_HEADERS";
+const CORS_ALLOW_CREDENTIALS_ENV: &str = "EXCEL_APP_CORS_ALLOW_CREDENTIALS";
+const CORS_MAX_AGE_ENV: &str = "EXCEL_APP_CORS_MAX_AGE_SECS";
+const DEFAULT_CORS_MAX_AGE_SECS: usize = 600;
+const TLS_CERT_PATH_ENV: &str = "EXCEL_APP_TLS_CERT_PATH";
+const TLS_KEY_PATH_ENV: &str = "EXCEL_APP_TLS_KEY_PATH";
+const SHUTDOWN_TIMEOUT_ENV: &str = "EXCEL_APP_SHUTDOWN_TIMEOUT_SECS";
+const DEFAULT_SHUTDOWN_TIMEOUT_SECS: usize = 30;
+const LOG_DIR_ENV: &str = "EXCEL_APP_LOG_DIR";
+const LOG_FORMAT_ENV: &str = "EXCEL_APP_LOG_FORMAT";
+const LOG_ROTATION_ENV: &str = "EXCEL_APP_LOG_ROTATION";
+const LOG_MAX_BYTES_ENV: &str = "EXCEL_APP_LOG_MAX_BYTES";
+const DEFAULT_LOG_MAX_BYTES: usize = 100_000_000;
+const LOG_MAX_FILES_ENV: &str = "EXCEL_APP_LOG_MAX_FILES";
+const DEFAULT_LOG_MAX_FILES: usize = 48;
+const LOG_STDOUT_ENV: &str = "EXCEL_APP_LOG_STDOUT";
+const OTLP_ENDPOINT_ENV: &str = "EXCEL_APP_OTLP_ENDPOINT";
+
+/// Runtime configuration of the server, read from the environment.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub database: DatabaseConfig,
+    /// Jobs allowed to process workbooks at the same time, further
+    /// `/runJob` requests are answered with 429.
+    pub max_concurrent_jobs: usize,
+    /// Largest request body accepted, uploads included.
+    pub max_upload_bytes: usize,
+    pub auth: AuthConfig,
+    pub limits: LimitsConfig,
+    pub cors: CorsConfig,
+    /// Serves https instead of plain http when set.
+    pub tls: Option<TlsConfig>,
+    /// How long a shutdown waits for running requests and jobs before
+    /// dropping them.
+    pub shutdown_timeout: Duration,
+    pub log: LogConfig,
+}
+
+/// Where and how the server logs. What is logged is up to `RUST_LOG`.
+#[derive(Debug, Clone)]
+pub struct LogConfig {
+    /// Directory of the `server.log` files.
+    pub directory: PathBuf,
+    pub format: LogFormat,
+    pub rotation: LogRotation,
+    /// Log files kept, the oldest are removed on rotation.
+    pub max_files: usize,
+    /// Whether to log to stdout too.
+    pub stdout: bool,
+    /// OTLP/gRPC endpoint of the collector spans are exported to, needs
+    /// the `otel` feature.
+    pub otlp_endpoint: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// One json object per event, with the fields of its spans.
+    Json,
+    /// Multi line text meant for people.
+    Pretty,
+}
+
+impl LogFormat {
+    pub const JSON: &'static str = "json";
+    pub const PRETTY: &'static str = "pretty";
+}
+
+impl TryFrom<&str> for LogFormat {
+    type Error = Error;
+
+    fn try_from(value: &str) -> Result<Self> {
+        match value.trim().to_lowercase().as_str() {
+            Self::JSON => Ok(Self::Json),
+            Self::PRETTY => Ok(Self::Pretty),
+            _ => Err(Error::Generic(format!(
+                "Invalid {LOG_FORMAT_ENV} value: {value}, expected {} / {}",
+                Self::JSON,
+                Self::PRETTY
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogRotation {
+    /// A new file every hour, named after it.
+    Hourly,
+    /// A new file every day, named after it.
+    Daily,
+    /// A new file once the current one reaches this many bytes, the
+    /// previous ones numbered from `.1`, the most recent.
+    Size(u64),
+}
+
+impl LogRotation {
+    pub const HOURLY: &'static str = "hourly";
+    pub const DAILY: &'static str = "daily";
+    pub const SIZE: &'static str = "size";
+}
+
+/// PEM files of the certificate chain and of its private key.
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+}
+
+#[derive(Debug, Clone)]
+pub struct AuthConfig {
+    /// Whether the routes working on uploads need a logged in user. Users
+    /// only see the files they uploaded themselves when they do.
+    pub required: bool,
+    /// How long a login session lasts.
+    pub session_ttl: Duration,
+}
+
+/// Limits applying to every client, a user or the address of requests
+/// made without authentication.
+#[derive(Debug, Clone)]
+pub struct LimitsConfig {
+    /// `/upload` requests a client may make per minute, in bursts of as
+    /// many.
+    pub uploads_per_minute: usize,
+    /// `/runJob` requests a client may make per minute.
+    pub jobs_per_minute: usize,
+    /// Jobs a client may run at the same time, below the server wide
+    /// `max_concurrent_jobs`.
+    pub max_jobs_per_client: usize,
+    /// Bytes a client may upload per UTC day.
+    pub daily_upload_quota_bytes: usize,
+}
+
+/// What browsers may do from pages of other origins. Pages served by the
+/// server itself don't need any of it.
+#[derive(Debug, Clone)]
+pub struct CorsConfig {
+    pub allowed_origins: AllowedOrigins,
+    pub allowed_methods: Vec<Method>,
+    pub allowed_headers: Vec<HeaderName>,
+    /// Whether browsers may send the session cookie along. Not allowed
+    /// with any origin.
+    pub allow_credentials: bool,
+    /// How long browsers may cache the answer to a preflight request.
+    pub max_age: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub enum AllowedOrigins {
+    /// `scheme://host[:port]` origins, none by default.
+    List(Vec<HeaderValue>),
+    /// `*`, every origin.
+    Any,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: AllowedOrigins::List(Vec::new()),
+            allowed_methods: vec![Method::GET, Method::POST, Method::DELETE],
+            allowed_headers: vec![header::CONTENT_TYPE, header::AUTHORIZATION],
+            allow_credentials: false,
+            max_age: Duration::from_secs(DEFAULT_CORS_MAX_AGE_SECS as u64),
+        }
+    }
+}
+
+impl CorsConfig {
+    fn from_env() -> Result<Self> {
+        let mut cors = Self::default();
+        if let Some(origins) = read_env(CORS_ORIGINS_ENV) {
+            cors.allowed_origins = parse_origins(&origins)?;
+        }
+        if let Some(methods) = read_env(CORS_METHODS_ENV) {
+            cors.allowed_methods = parse_list(CORS_METHODS_ENV, &methods, |method| {
+                Method::from_bytes(method.to_uppercase().as_bytes()).ok()
+            })?;
+        }
+        if let Some(headers) = read_env(CORS_HEADERS_ENV) {
+            cors.allowed_headers = parse_list(CORS_HEADERS_ENV, &headers, |name| {
+                HeaderName::from_bytes(name.as_bytes()).ok()
+            })?;
+        }
+        cors.allow_credentials = read_bool(CORS_ALLOW_CREDENTIALS_ENV, cors.allow_credentials)?;
+        cors.max_age = Duration::from_secs(read_positive_number(
+            CORS_MAX_AGE_ENV,
+            DEFAULT_CORS_MAX_AGE_SECS,
+        )? as u64);
+
+        if cors.allow_credentials && matches!(cors.allowed_origins, AllowedOrigins::Any) {
+            return Err(Error::Generic(format!(
+                "{CORS_ALLOW_CREDENTIALS_ENV} can't be true with any origin allowed, list the origins in {CORS_ORIGINS_ENV}"
+            )));
+        }
+        Ok(cors)
+    }
+}
+
+/// `*` or a comma separated list of `scheme://host[:port]` origins.
+fn parse_origins(text: &str) -> Result<AllowedOrigins> {
+    if text == "*" {
+        return Ok(AllowedOrigins::Any);
+    }
+    let origins = parse_list(CORS_ORIGINS_ENV, text, |origin| {
+        let origin = origin.trim_end_matches('/');
+        let (scheme, host) = origin.split_once("://")?;
+        let valid = matches!(scheme, "http" | "https") && !host.is_empty() && !host.contains('/');
+        valid.then(|| HeaderValue::from_str(origin).ok()).flatten()
+    })?;
+    Ok(AllowedOrigins::List(origins))
+}
+
+fn parse_list<T>(key: &str, text: &str, parse: impl Fn(&str) -> Option<T>) -> Result<Vec<T>> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| {
+            parse(item).ok_or_else(|| Error::Generic(format!("Invalid {key} value: {item}")))
+        })
+        .collect()
+}
+
+impl LogConfig {
+    fn from_env(mut log: Self) -> Result<Self> {
+        if let Some(directory) = read_env(LOG_DIR_ENV) {
+            log.directory = PathBuf::from(directory);
+        }
+        if let Some(format) = read_env(LOG_FORMAT_ENV) {
+            log.format = LogFormat::try_from(format.as_str())?;
+        }
+        let max_bytes = read_positive_number(LOG_MAX_BYTES_ENV, DEFAULT_LOG_MAX_BYTES)? as u64;
+        if let Some(rotation) = read_env(LOG_ROTATION_ENV) {
+            log.rotation = match rotation.to_lowercase().as_str() {
+                LogRotation::HOURLY => LogRotation::Hourly,
+                LogRotation::DAILY => LogRotation::Daily,
+                LogRotation::SIZE => LogRotation::Size(max_bytes),
+                _ => {
+                    return Err(Error::Generic(format!(
+                        "Invalid {LOG_ROTATION_ENV} value: {rotation}, expected {} / {} / {}",
+                        LogRotation::HOURLY,
+                        LogRotation::DAILY,
+                        LogRotation::SIZE
+                    )))
+                }
+            };
+        }
+        log.max_files = read_positive_number(LOG_MAX_FILES_ENV, log.max_files)?;
+        log.stdout = read_bool(LOG_STDOUT_ENV, log.stdout)?;
+        log.otlp_endpoint = read_env(OTLP_ENDPOINT_ENV);
+        Ok(log)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum DatabaseConfig {
+    Sqlite { path: PathBuf, pool_size: usize },
+    Postgres { url: String, pool_size: usize },
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            database: DatabaseConfig::Sqlite {
+                path: default_sqlite_path(),
+                pool_size: DEFAULT_POOL_SIZE,
+            },
+            max_concurrent_jobs: std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1),
+            max_upload_bytes: DEFAULT_MAX_UPLOAD_BYTES,
+            auth: AuthConfig {
+                required: false,
+                session_ttl: hours(DEFAULT_SESSION_TTL_HOURS),
+            },
+            limits: LimitsConfig {
+                uploads_per_minute: DEFAULT_REQUESTS_PER_MINUTE,
+                jobs_per_minute: DEFAULT_REQUESTS_PER_MINUTE,
+                max_jobs_per_client: DEFAULT_MAX_JOBS_PER_CLIENT,
+                daily_upload_quota_bytes: DEFAULT_DAILY_UPLOAD_QUOTA,
+            },
+            cors: CorsConfig::default(),
+            tls: None,
+            shutdown_timeout: Duration::from_secs(DEFAULT_SHUTDOWN_TIMEOUT_SECS as u64),
+            log: LogConfig {
+                directory: PathBuf::from("."),
+                format: LogFormat::Json,
+                rotation: LogRotation::Daily,
+                max_files: DEFAULT_LOG_MAX_FILES,
+                stdout: false,
+                otlp_endpoint: None,
+            },
+        }
+    }
+}
+
+impl Config {
+    /// Builds the configuration from `EXCEL_APP_*` environment variables,
+    /// falling back to the defaults for anything that isn't set.
+    ///
+    /// `EXCEL_APP_DATABASE_URL` selects the backend, `postgres://...` or
+    /// `postgresql://...` for Postgres and `sqlite://<path>` for SQLite.
+    pub fn from_env() -> Result<Self> {
+        let mut config = Self::default();
+
+        let pool_size = read_positive_number(DATABASE_POOL_SIZE_ENV, DEFAULT_POOL_SIZE)?;
+        config.database = match read_env(DATABASE_URL_ENV) {
+            Some(url) => DatabaseConfig::from_url(&url, pool_size)?,
+            None => DatabaseConfig::Sqlite {
+                path: default_sqlite_path(),
+                pool_size,
+            },
+        };
+        config.max_concurrent_jobs =
+            read_positive_number(MAX_CONCURRENT_JOBS_ENV, config.max_concurrent_jobs)?;
+        config.max_upload_bytes =
+            read_positive_number(MAX_UPLOAD_BYTES_ENV, config.max_upload_bytes)?;
+        config.auth.required = read_bool(AUTH_REQUIRED_ENV, config.auth.required)?;
+        config.auth.session_ttl = hours(read_positive_number(
+            SESSION_TTL_HOURS_ENV,
+            DEFAULT_SESSION_TTL_HOURS,
+        )?);
+        let limits = &mut config.limits;
+        limits.uploads_per_minute =
+            read_positive_number(UPLOADS_PER_MINUTE_ENV, limits.uploads_per_minute)?;
+        limits.jobs_per_minute = read_positive_number(JOBS_PER_MINUTE_ENV, limits.jobs_per_minute)?;
+        limits.max_jobs_per_client =
+            read_positive_number(MAX_JOBS_PER_CLIENT_ENV, limits.max_jobs_per_client)?;
+        limits.daily_upload_quota_bytes =
+            read_positive_number(DAILY_UPLOAD_QUOTA_ENV, limits.daily_upload_quota_bytes)?;
+        config.cors = CorsConfig::from_env()?;
+        config.tls = match (read_env(TLS_CERT_PATH_ENV), read_env(TLS_KEY_PATH_ENV)) {
+            (None, None) => None,
+            (Some(cert_path), Some(key_path)) => Some(TlsConfig {
+                cert_path: PathBuf::from(cert_path),
+                key_path: PathBuf::from(key_path),
+            }),
+            _ => {
+                return Err(Error::Generic(format!(
+                    "{TLS_CERT_PATH_ENV} and {TLS_KEY_PATH_ENV} have to be set together"
+                )))
+            }
+        };
+        config.shutdown_timeout = Duration::from_secs(read_positive_number(
+            SHUTDOWN_TIMEOUT_ENV,
+            DEFAULT_SHUTDOWN_TIMEOUT_SECS,
+        )? as u64);
+        config.log = LogConfig::from_env(config.log)?;
+
+        Ok(config)
+    }
+}
+
+impl DatabaseConfig {
+    fn from_url(url: &str, pool_size: usize) -> Result<Self> {
+        if url.starts_with("postgres://") || url.starts_with("postgresql://") {
+            return Ok(Self::Postgres {
+                url: url.to_string(),
+                pool_size,
+            });
+        }
+        if let Some(path) = url.strip_prefix("sqlite://") {
+            return Ok(Self::Sqlite {
+                path: PathBuf::from(path),
+                pool_size,
+            });
+        }
+        Err(Error::Generic(format!(
+            "Unsupported {DATABASE_URL_ENV}: {url}, expected a postgres:// or sqlite:// url"
+        )))
+    }
+}
+
+fn default_sqlite_path() -> PathBuf {
+    PathBuf::from(format!(
+        ".{MAIN_SEPARATOR}{DATA_DIR_NAME}{MAIN_SEPARATOR}db.sqlite"
+    ))
+}
+
+fn read_positive_number(key: &str, default: usize) -> Result<usize> {
+    match read_env(key) {
+        None => Ok(default),
+        Some(v) => v.parse::<usize>().ok().filter(|v| *v > 0).ok_or_else(|| {
+            Error::Generic(format!(
+                "Invalid {key} value: {v}, expected a positive number"
+            ))
+        }),
+    }
+}
+
+fn read_bool(key: &str, default: bool) -> Result<bool> {
+    match read_env(key).map(|v| v.to_lowercase()) {
+        None => Ok(default),
+        Some(v) => match v.as_str() {
+            "true" | "1" => Ok(true),
+            "false" | "0" => Ok(false),
+            _ => Err(Error::Generic(format!(
+                "Invalid {key} value: {v}, expected true / false"
+            ))),
+        },
+    }
+}
+
+fn hours(hours: usize) -> Duration {
+    Duration::from_secs(hours as u64 * 3600)
+}
+
+fn read_env(key: &str) -> Option<String> {
+    env::var(key)
+        .ok()
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
@@ -0,0 +1,410 @@
+//! Legacy xls and OpenDocument ods workbooks.
+//!
+//! Both are read with calamine and converted into the rows of a new xlsx
+//! workbook, results always come back as xlsx. Only the values of a single
+//! sheet are converted: formulas become their cached results and formatting
+//! is lost, except for the number format of dates and times.
+
+use std::{
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use calamine::{Data, Ods, Reader, Xls};
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use zip::ZipArchive;
+
+use crate::{
+    dates,
+    error::Error,
+    formats,
+    xlsx::{
+        Cell, CellValue, Row, DATE_STYLE, DATE_TIME_STYLE, DURATION_STYLE, MAX_COLUMNS, MAX_ROWS,
+    },
+    Result,
+};
+
+/// Directories of an ods package holding Basic macros and scripts.
+const ODS_MACRO_DIRS: [&str; 2] = ["Basic/", "Scripts/"];
+
+/// A sheet of a converted workbook.
+pub struct Converted {
+    /// Names of all the sheets of the workbook.
+    pub sheet_names: Vec<String>,
+    pub header: Row,
+    pub rows: Vec<Row>,
+    /// The workbook has macros, which are never converted.
+    pub has_macros: bool,
+}
+
+/// Reads the sheet named or numbered `sheet`, the first one if `None`.
+pub fn read_xls(path: &Path, sheet: Option<&str>) -> Result<Converted> {
+    read_xls_from(open(path)?, sheet)
+}
+
+/// [`read_xls`] for a workbook in memory or in a stream.
+pub fn read_xls_from<RS: Read + Seek>(input: RS, sheet: Option<&str>) -> Result<Converted> {
+    let mut workbook = Xls::new(input).map_err(invalid)?;
+    let has_macros = workbook.vba_project().is_some();
+    read_sheet(&mut workbook, sheet, has_macros)
+}
+
+/// Reads the sheet named or numbered `sheet`, the first one if `None`.
+pub fn read_ods(path: &Path, sheet: Option<&str>) -> Result<Converted> {
+    read_ods_from(open(path)?, sheet)
+}
+
+/// [`read_ods`] for a workbook in memory or in a stream.
+pub fn read_ods_from<RS: Read + Seek>(mut input: RS, sheet: Option<&str>) -> Result<Converted> {
+    let has_macros = ZipArchive::new(&mut input)
+        .map_err(invalid)?
+        .file_names()
+        .any(|name| ODS_MACRO_DIRS.iter().any(|dir| name.starts_with(dir)));
+    input
+        .seek(SeekFrom::Start(0))
+        .map_err(|e| Error::IOError(e.to_string()))?;
+    let mut workbook = Ods::new(input).map_err(invalid)?;
+    read_sheet(&mut workbook, sheet, has_macros)
+}
+
+fn open(path: &Path) -> Result<BufReader<File>> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|e| Error::IOError(e.to_string()))
+}
+
+fn read_sheet<RS, R>(workbook: &mut R, sheet: Option<&str>, has_macros: bool) -> Result<Converted>
+where
+    RS: Read + Seek,
+    R: Reader<RS>,
+    R::Error: std::fmt::Display,
+{
+    let sheet_names = workbook.sheet_names();
+    let sheet_idx = formats::sheet_index(&sheet_names, sheet)?;
+    let range = match workbook.worksheet_range_at(sheet_idx) {
+        None => return Err(invalid("The workbook has no sheet")),
+        Some(range) => range.map_err(invalid)?,
+    };
+
+    let (first_row, first_col) = range.start().unwrap_or((0, 0));
+    if let Some((last_row, last_col)) = range.end() {
+        if last_row >= MAX_ROWS || last_col >= MAX_COLUMNS {
+            return Err(invalid(format!(
+                "The sheet goes past the last row or column of an xlsx sheet, {MAX_ROWS} rows and {MAX_COLUMNS} columns"
+            )));
+        }
+    }
+    let mut header = Row::default();
+    let mut rows: Vec<Row> = Vec::new();
+    for (row_pos, data) in range.rows().enumerate() {
+        let cells: Vec<Cell> = data
+            .iter()
+            .zip(first_col + 1..)
+            .filter_map(|(data, col)| to_cell(col, data))
+            .collect();
+        // Row numbers are 0 based in calamine, row 0 being the header.
+        match first_row as usize + row_pos {
+            0 => header = Row::from_cells(cells),
+            row_idx => {
+                rows.resize_with(row_idx - 1, Row::default);
+                rows.push(Row::from_cells(cells));
+            }
+        }
+    }
+    // Trailing empty rows are within the range but not part of the data.
+    while rows.last().is_some_and(|row| row.cells.is_empty()) {
+        rows.pop();
+    }
+
+    Ok(Converted {
+        sheet_names,
+        header,
+        rows,
+        has_macros,
+    })
+}
+
+/// The xlsx cell holding `data`, `None` for empty cells. Dates and times are
+/// stored as serial numbers formatted as such, the way Excel stores them.
+fn to_cell(col: u32, data: &Data) -> Option<Cell> {
+    let (style, value) = match data {
+        Data::Empty => return None,
+        Data::String(text) if text.is_empty() => return None,
+        Data::String(text) => (0, CellValue::Text(text.as_str().into())),
+        Data::Int(v) => (0, CellValue::Number(v.to_string().into())),
+        Data::Float(v) => (0, CellValue::Number(v.to_string().into())),
+        Data::Bool(v) => (0, CellValue::Bool(*v)),
+        Data::Error(e) => (0, CellValue::Error(e.to_string().into())),
+        Data::DateTime(dt) if dt.is_duration() => (
+            DURATION_STYLE,
+            CellValue::Number(dt.as_f64().to_string().into()),
+        ),
+        Data::DateTime(dt) => match dt.as_datetime() {
+            None => (0, CellValue::Number(dt.as_f64().to_string().into())),
+            Some(dt) => date_cell(dt),
+        },
+        Data::DateTimeIso(text) => match parse_iso_datetime(text) {
+            None => (0, CellValue::Text(text.as_str().into())),
+            Some(dt) => date_cell(dt),
+        },
+        Data::DurationIso(text) => match parse_iso_duration(text) {
+            None => (0, CellValue::Text(text.as_str().into())),
+            Some(days) => (DURATION_STYLE, CellValue::Number(days.to_string().into())),
+        },
+    };
+    Some(Cell::new(col, style, value))
+}
+
+fn date_cell(dt: NaiveDateTime) -> (u32, CellValue) {
+    let serial = dates::to_serial(dt);
+    let style = if dt.time() == NaiveTime::MIN {
+        DATE_STYLE
+    } else {
+        DATE_TIME_STYLE
+    };
+    (style, CellValue::Number(serial.to_string().into()))
+}
+
+/// Parses the `2024-01-31` and `2024-01-31T10:30:00` values of ods cells.
+fn parse_iso_datetime(text: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M:%S%.f")
+        .ok()
+        .or_else(|| {
+            NaiveDate::parse_from_str(text, "%Y-%m-%d")
+                .ok()
+                .map(|date| date.and_time(NaiveTime::MIN))
+        })
+}
+
+/// Parses the `PT10H30M00S` values of ods time cells into days.
+fn parse_iso_duration(text: &str) -> Option<f64> {
+    let mut rest = text.strip_prefix("PT")?;
+    let mut seconds = 0.0;
+    while !rest.is_empty() {
+        let unit_pos = rest.find(|c: char| c.is_ascii_alphabetic())?;
+        let value: f64 = rest[..unit_pos].parse().ok()?;
+        seconds += value
+            * match rest.as_bytes()[unit_pos] {
+                b'H' => 3600.0,
+                b'M' => 60.0,
+                b'S' => 1.0,
+                _ => return None,
+            };
+        rest = &rest[unit_pos + 1..];
+    }
+    Some(seconds / 86_400.0)
+}
+
+fn invalid(e: impl std::fmt::Display) -> Error {
+    Error::InValidExcelFile(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Cursor, Write},
+        path::PathBuf,
+    };
+
+    use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+    use super::*;
+
+    const MANIFEST: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<manifest:manifest xmlns:manifest="urn:oasis:names:tc:opendocument:xmlns:manifest:1.0" manifest:version="1.2"><manifest:file-entry manifest:full-path="/" manifest:media-type="application/vnd.oasis.opendocument.spreadsheet"/></manifest:manifest>"#;
+    const CONTENT_START: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<office:document-content xmlns:office="urn:oasis:names:tc:opendocument:xmlns:office:1.0" xmlns:table="urn:oasis:names:tc:opendocument:xmlns:table:1.0" xmlns:text="urn:oasis:names:tc:opendocument:xmlns:text:1.0" office:version="1.2"><office:body><office:spreadsheet>"#;
+    const CONTENT_END: &str = "</office:spreadsheet></office:body></office:document-content>";
+
+    /// A file removed when dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(extension: &str, bytes: &[u8]) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "excel_app_test_{}.{extension}",
+                uuid::Uuid::now_v7()
+            ));
+            std::fs::write(&path, bytes).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    /// An ods package of `tables`, pairs of a sheet name and the xml of its
+    /// rows, along with the `extra` parts.
+    fn ods(tables: &[(&str, &str)], extra: &[&str]) -> TempFile {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        let stored = FileOptions::default().compression_method(CompressionMethod::Stored);
+        zip.start_file("mimetype", stored).unwrap();
+        zip.write_all(b"application/vnd.oasis.opendocument.spreadsheet")
+            .unwrap();
+        zip.start_file("META-INF/manifest.xml", FileOptions::default())
+            .unwrap();
+        zip.write_all(MANIFEST.as_bytes()).unwrap();
+        zip.start_file("content.xml", FileOptions::default())
+            .unwrap();
+        zip.write_all(CONTENT_START.as_bytes()).unwrap();
+        for (name, rows) in tables {
+            write!(
+                zip,
+                r#"<table:table table:name="{name}">{rows}</table:table>"#
+            )
+            .unwrap();
+        }
+        zip.write_all(CONTENT_END.as_bytes()).unwrap();
+        for name in extra {
+            zip.start_file(*name, FileOptions::default()).unwrap();
+        }
+        TempFile::new("ods", &zip.finish().unwrap().into_inner())
+    }
+
+    fn text(value: &str) -> String {
+        format!(
+            r#"<table:table-cell office:value-type="string"><text:p>{value}</text:p></table:table-cell>"#
+        )
+    }
+
+    fn row(cells: &[String]) -> String {
+        format!("<table:table-row>{}</table:table-row>", cells.concat())
+    }
+
+    #[test]
+    fn converts_values_and_dates() {
+        let rows = [
+            row(&[text("name"), text("born"), text("at"), text("score")]),
+            row(&[
+                text("Ann"),
+                r#"<table:table-cell office:value-type="date" office:date-value="2024-01-31"/>"#
+                    .into(),
+                r#"<table:table-cell office:value-type="time" office:time-value="PT10H30M00S"/>"#
+                    .into(),
+                r#"<table:table-cell office:value-type="float" office:value="2.5"/>"#.into(),
+            ]),
+        ]
+        .concat();
+        let file = ods(&[("Data", &rows)], &[]);
+        let converted = read_ods(&file.0, None).unwrap();
+
+        assert_eq!(converted.sheet_names, ["Data"]);
+        assert!(!converted.has_macros);
+        assert_eq!(converted.header.value(2), "born");
+        assert_eq!(converted.rows.len(), 1);
+        let row = &converted.rows[0];
+        assert_eq!(row.value(1), "Ann");
+        assert_eq!(row.cell(2).unwrap().style, DATE_STYLE);
+        assert_eq!(row.value(2), "45322");
+        assert_eq!(row.cell(3).unwrap().style, DURATION_STYLE);
+        assert_eq!(row.value(3), "0.4375");
+        assert_eq!(row.value(4), "2.5");
+    }
+
+    #[test]
+    fn keeps_rows_and_columns_at_their_position() {
+        let rows = [
+            row(&[text("a"), text("b")]),
+            r#"<table:table-row table:number-rows-repeated="2"><table:table-cell/></table:table-row>"#
+                .to_string(),
+            row(&[r#"<table:table-cell table:number-columns-repeated="2"/>"#.into(), text("c")]),
+            r#"<table:table-row table:number-rows-repeated="5"><table:table-cell/></table:table-row>"#
+                .to_string(),
+        ]
+        .concat();
+        let file = ods(&[("Data", &rows)], &[]);
+        let converted = read_ods(&file.0, None).unwrap();
+
+        assert_eq!(converted.rows.len(), 3);
+        assert!(converted.rows[0].cells.is_empty());
+        assert!(converted.rows[1].cells.is_empty());
+        assert_eq!(converted.rows[2].cells.len(), 1);
+        assert_eq!(converted.rows[2].value(3), "c");
+    }
+
+    #[test]
+    fn sheets_by_name_or_number() {
+        let first = row(&[text("first")]);
+        let second = row(&[text("second")]);
+        let file = ods(&[("One", &first), ("Two", &second)], &[]);
+
+        assert_eq!(
+            read_ods(&file.0, Some("Two")).unwrap().header.value(1),
+            "second"
+        );
+        assert_eq!(
+            read_ods(&file.0, Some("0")).unwrap().header.value(1),
+            "first"
+        );
+        assert!(matches!(
+            read_ods(&file.0, Some("Three")),
+            Err(Error::InvalidPayload(_))
+        ));
+    }
+
+    #[test]
+    fn macros_are_detected() {
+        let rows = row(&[text("a")]);
+        let file = ods(&[("Data", &rows)], &["Basic/Standard/Module1.xml"]);
+        assert!(read_ods(&file.0, None).unwrap().has_macros);
+    }
+
+    #[test]
+    fn refuses_sheets_past_the_last_column() {
+        let rows = row(&[
+            format!(r#"<table:table-cell table:number-columns-repeated="{MAX_COLUMNS}"/>"#),
+            text("too far"),
+        ]);
+        let file = ods(&[("Data", &rows)], &[]);
+        assert!(matches!(
+            read_ods(&file.0, None),
+            Err(Error::InValidExcelFile(_))
+        ));
+    }
+
+    #[test]
+    fn refuses_files_in_another_format() {
+        let file = TempFile::new("xls", b"name,score\nAnn,2\n");
+        assert!(matches!(
+            read_xls(&file.0, None),
+            Err(Error::InValidExcelFile(_))
+        ));
+        assert!(matches!(
+            read_ods(&file.0, None),
+            Err(Error::InValidExcelFile(_))
+        ));
+    }
+
+    #[test]
+    fn iso_values() {
+        let date = NaiveDate::from_ymd_opt(2024, 1, 31).unwrap();
+        assert_eq!(
+            parse_iso_datetime("2024-01-31"),
+            Some(date.and_time(NaiveTime::MIN))
+        );
+        assert_eq!(
+            parse_iso_datetime("2024-01-31T10:30:00.5"),
+            Some(date.and_hms_milli_opt(10, 30, 0, 500).unwrap())
+        );
+        assert_eq!(parse_iso_datetime("31/01/2024"), None);
+
+        assert_eq!(parse_iso_duration("PT12H"), Some(0.5));
+        assert_eq!(parse_iso_duration("PT0H1M30S"), Some(90.0 / 86_400.0));
+        assert_eq!(parse_iso_duration("P1D"), None);
+        assert_eq!(parse_iso_duration("PT1X"), None);
+    }
+
+    #[test]
+    fn date_cells() {
+        let date = NaiveDate::from_ymd_opt(2024, 1, 31).unwrap();
+        assert_eq!(date_cell(date.and_time(NaiveTime::MIN)).0, DATE_STYLE);
+        let (style, value) = date_cell(date.and_hms_opt(18, 0, 0).unwrap());
+        assert_eq!(style, DATE_TIME_STYLE);
+        assert_eq!(value.text(), "45322.75");
+        assert!(to_cell(1, &Data::String(String::new())).is_none());
+    }
+}
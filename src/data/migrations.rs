@@ -0,0 +1,190 @@
+/// A numbered schema change. The sql is kept portable so the same
+/// statements run on both the SQLite and the Postgres backend.
+#[derive(Debug)]
+pub struct Migration {
+    pub version: u32,
+    pub name: &'static str,
+    pub sql: &'static str,
+    /// Columns added after `sql` ran. SQLite has no `ADD COLUMN IF NOT
+    /// EXISTS`, so each backend checks for the column its own way.
+    pub add_columns: &'static [AddColumn],
+}
+
+#[derive(Debug)]
+pub struct AddColumn {
+    pub table: &'static str,
+    pub column: &'static str,
+    pub definition: &'static str,
+}
+
+impl AddColumn {
+    pub fn sql(&self) -> String {
+        format!(
+            "ALTER TABLE {} ADD COLUMN {} {};",
+            self.table, self.column, self.definition
+        )
+    }
+}
+
+pub const SCHEMA_VERSION_TABLE_NAME: &str = "schema_version";
+
+pub const CREATE_SCHEMA_VERSION_TABLE: &str = "CREATE TABLE IF NOT EXISTS schema_version (version INTEGER PRIMARY KEY, name TEXT NOT NULL, applied_at TEXT NOT NULL);";
+
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "create_upload_entries_table",
+        sql: "CREATE TABLE IF NOT EXISTS UploadEntriesTable (ID TEXT PRIMARY KEY, FILE_NAME TEXT NOT NULL);",
+        add_columns: &[],
+    },
+    Migration {
+        version: 2,
+        name: "add_upload_entry_metadata",
+        sql: "",
+        add_columns: &[
+            AddColumn {
+                table: "UploadEntriesTable",
+                column: "ORIGINAL_NAME",
+                definition: "TEXT",
+            },
+            AddColumn {
+                table: "UploadEntriesTable",
+                column: "SIZE",
+                definition: "BIGINT",
+            },
+            AddColumn {
+                table: "UploadEntriesTable",
+                column: "OWNER",
+                definition: "TEXT",
+            },
+            AddColumn {
+                table: "UploadEntriesTable",
+                column: "CREATED_AT",
+                definition: "TEXT",
+            },
+        ],
+    },
+    Migration {
+        version: 3,
+        name: "create_users_and_sessions_tables",
+        sql: "CREATE TABLE IF NOT EXISTS UsersTable (ID TEXT PRIMARY KEY, USERNAME TEXT NOT NULL UNIQUE, PASSWORD_HASH TEXT NOT NULL, CREATED_AT TEXT NOT NULL);
+              CREATE TABLE IF NOT EXISTS SessionsTable (TOKEN_HASH TEXT PRIMARY KEY, USER_ID TEXT NOT NULL REFERENCES UsersTable (ID) ON DELETE CASCADE, EXPIRES_AT TEXT NOT NULL);",
+        add_columns: &[],
+    },
+    Migration {
+        version: 4,
+        name: "create_api_tokens_table",
+        sql: "CREATE TABLE IF NOT EXISTS ApiTokensTable (ID TEXT PRIMARY KEY, USER_ID TEXT NOT NULL REFERENCES UsersTable (ID) ON DELETE CASCADE, NAME TEXT NOT NULL, TOKEN_HASH TEXT NOT NULL UNIQUE, SCOPES TEXT NOT NULL, CREATED_AT TEXT NOT NULL, EXPIRES_AT TEXT NOT NULL);",
+        add_columns: &[],
+    },
+    Migration {
+        version: 5,
+        name: "create_upload_usage_table",
+        sql: "CREATE TABLE IF NOT EXISTS UploadUsageTable (CLIENT TEXT NOT NULL, DAY TEXT NOT NULL, BYTES BIGINT NOT NULL, PRIMARY KEY (CLIENT, DAY));",
+        add_columns: &[],
+    },
+];
+
+/// State of a single migration, as reported by `excel_app migrate status`.
+#[derive(Debug, Clone)]
+pub struct MigrationStatus {
+    pub version: u32,
+    pub name: String,
+    /// `None` while the migration is still pending.
+    pub applied_at: Option<String>,
+}
+
+/// Merges the migrations known to this build with the ones recorded in the
+/// database. Versions only the database knows about (applied by a newer
+/// build) are kept so they show up in the report.
+pub fn status(applied: Vec<MigrationStatus>) -> Vec<MigrationStatus> {
+    let mut result: Vec<MigrationStatus> = MIGRATIONS
+        .iter()
+        .map(|m| MigrationStatus {
+            version: m.version,
+            name: m.name.to_string(),
+            applied_at: applied
+                .iter()
+                .find(|a| a.version == m.version)
+                .and_then(|a| a.applied_at.clone()),
+        })
+        .collect();
+    result.extend(
+        applied
+            .into_iter()
+            .filter(|a| MIGRATIONS.iter().all(|m| m.version != a.version)),
+    );
+    result.sort_by_key(|m| m.version);
+    result
+}
+
+/// Migrations with a version above `current_version`, in the order they have
+/// to be applied.
+pub fn pending(current_version: u32) -> impl Iterator<Item = &'static Migration> {
+    MIGRATIONS
+        .iter()
+        .filter(move |m| m.version > current_version)
+}
+
+pub fn applied_at() -> String {
+    chrono::Utc::now().to_rfc3339()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn applied(version: u32, name: &str) -> MigrationStatus {
+        MigrationStatus {
+            version,
+            name: name.to_string(),
+            applied_at: Some("2024-01-01T00:00:00+00:00".to_string()),
+        }
+    }
+
+    #[test]
+    fn versions_are_increasing() {
+        assert!(MIGRATIONS.windows(2).all(|w| w[0].version < w[1].version));
+        assert_eq!(MIGRATIONS[0].version, 1);
+    }
+
+    #[test]
+    fn pending_starts_after_the_current_version() {
+        let versions: Vec<u32> = pending(2).map(|m| m.version).collect();
+        let expected: Vec<u32> = MIGRATIONS
+            .iter()
+            .map(|m| m.version)
+            .filter(|v| *v > 2)
+            .collect();
+        assert_eq!(versions, expected);
+        assert_eq!(pending(0).count(), MIGRATIONS.len());
+    }
+
+    #[test]
+    fn status_merges_applied_and_unknown_versions() {
+        let status = status(vec![
+            applied(1, "create_upload_entries_table"),
+            applied(99, "newer"),
+        ]);
+        assert_eq!(status.len(), MIGRATIONS.len() + 1);
+        assert!(status[0].applied_at.is_some());
+        assert!(status[1..MIGRATIONS.len()]
+            .iter()
+            .all(|m| m.applied_at.is_none()));
+        let last = status.last().unwrap();
+        assert_eq!((last.version, last.name.as_str()), (99, "newer"));
+    }
+
+    #[test]
+    fn added_columns() {
+        let column = AddColumn {
+            table: "UploadEntriesTable",
+            column: "SIZE",
+            definition: "BIGINT",
+        };
+        assert_eq!(
+            column.sql(),
+            "ALTER TABLE UploadEntriesTable ADD COLUMN SIZE BIGINT;"
+        );
+    }
+}
@@ -1,26 +1,96 @@
-use std::path::Path;
-
 use crate::Result;
 use async_trait::async_trait;
 use uuid::Uuid;
 
-use self::model::UploadFileEntry;
+use self::{
+    migrations::MigrationStatus,
+    model::{
+        ApiTokenEntry, NewApiToken, NewSession, NewUploadEntry, NewUser, SessionEntry,
+        UploadFileEntry, UserEntry,
+    },
+};
 
+pub mod migrations;
 pub mod model;
+#[cfg(feature = "postgres")]
+pub mod postgres_ds;
 pub mod sqlite_ds;
 
 #[async_trait]
-pub trait DataSource: Clone {
+pub trait DataSource: Clone + Send + Sync + 'static {
     async fn init_database(&self) -> Result<()>
     where
         Self: Sized + Clone;
-    async fn add_file_entry(&self, file_path: &Path) -> Result<Uuid>
+    /// Runs a trivial query, failing when the database can't be reached.
+    async fn ping(&self) -> Result<()>
+    where
+        Self: Sized + Clone;
+    /// Migrations recorded in the `schema_version` table.
+    async fn applied_migrations(&self) -> Result<Vec<MigrationStatus>>
+    where
+        Self: Sized + Clone;
+    async fn add_file_entry(&self, entry: NewUploadEntry<'_>) -> Result<Uuid>
     where
         Self: Sized + Clone;
+    #[allow(dead_code)]
     async fn remove_file_entry(&self, id: String) -> Result<()>
     where
         Self: Sized + Clone;
     async fn get_file_entry(&self, id: String) -> Result<UploadFileEntry>
     where
         Self: Sized + Clone;
+    async fn add_user(&self, user: NewUser<'_>) -> Result<Uuid>
+    where
+        Self: Sized + Clone;
+    /// `Error::NoEntryFound` for unknown user names.
+    async fn get_user(&self, username: String) -> Result<UserEntry>
+    where
+        Self: Sized + Clone;
+    async fn add_session(&self, session: NewSession<'_>) -> Result<()>
+    where
+        Self: Sized + Clone;
+    /// The session with the given token hash along with its user, expired
+    /// or not.
+    async fn get_session(&self, token_hash: String) -> Result<SessionEntry>
+    where
+        Self: Sized + Clone;
+    async fn remove_session(&self, token_hash: String) -> Result<()>
+    where
+        Self: Sized + Clone;
+    async fn add_api_token(&self, token: NewApiToken<'_>) -> Result<ApiTokenEntry>
+    where
+        Self: Sized + Clone;
+    /// The token with the given hash along with its user, expired or not.
+    async fn get_api_token(&self, token_hash: String) -> Result<(ApiTokenEntry, UserEntry)>
+    where
+        Self: Sized + Clone;
+    /// Tokens of a user, oldest first.
+    async fn list_api_tokens(&self, user_id: String) -> Result<Vec<ApiTokenEntry>>
+    where
+        Self: Sized + Clone;
+    /// `Error::NoEntryFound` unless the user has a token with the id.
+    async fn remove_api_token(&self, id: String, user_id: String) -> Result<()>
+    where
+        Self: Sized + Clone;
+    /// Adds `bytes` to what `client` uploaded on `day`, a `yyyy-mm-dd` UTC
+    /// date. Negative `bytes` give back a reservation.
+    async fn add_upload_usage(&self, client: String, day: String, bytes: i64) -> Result<()>
+    where
+        Self: Sized + Clone;
+    /// Adds `bytes` to what `client` uploaded on `day` unless that takes it
+    /// past `quota`, in a single statement so concurrent uploads can't go
+    /// over together. False if the bytes weren't added.
+    async fn reserve_upload_usage(
+        &self,
+        client: String,
+        day: String,
+        bytes: i64,
+        quota: i64,
+    ) -> Result<bool>
+    where
+        Self: Sized + Clone;
+    /// Bytes `client` uploaded on `day`, 0 if nothing.
+    async fn get_upload_usage(&self, client: String, day: String) -> Result<i64>
+    where
+        Self: Sized + Clone;
 }
@@ -1,23 +1,208 @@
-use crate::Result;
+use std::path::Path;
+
+use crate::{
+    processor::{
+        Aggregate, DedupeAction, DedupeStrategy, FilterAction, FilterLogic, JobSpec, Macros,
+        OutputFormat, RowFilter, SortInfo,
+    },
+    xlsx::Row,
+    Result,
+};
 use axum::body::Bytes;
 use axum::extract::Multipart;
 use serde::{Deserialize, Serialize};
-use utoipa::ToSchema;
+use utoipa::{IntoParams, ToSchema};
 
 use crate::error::Error;
 
+#[allow(dead_code)]
 #[derive(ToSchema)]
 pub struct RunJobResponse(Vec<u8>);
 
+/// The multipart form of `/runJob`.
 #[allow(dead_code)]
 #[derive(ToSchema)]
 #[schema(rename_all = "camelCase")]
-pub struct RunJobRequest {
+pub struct RunJobForm {
     file_id: String,
     contraction_file: Option<Vec<u8>>,
     search_term: Option<Vec<String>>,
     check_date: Option<Vec<usize>>,
     sort_col: Option<Vec<String>>,
+    /// `xlsx` (default), `csv` or `json`. Csv results come as a zip with
+    /// the csv and a json file of the match positions, json results as a
+    /// `JobResult`.
+    output_format: Option<String>,
+    /// `keep` (default) or `strip`, what to do with the macros of the
+    /// uploaded workbook. Macros of xls and ods files can't be kept.
+    macros: Option<String>,
+    /// Conditions rows have to meet, of the form `column,operator,value`:
+    /// `eq`, `contains` and `regex` on the text, `range` with two numbers
+    /// either of which can be left empty, `before` and `after` a `mmddyy`
+    /// or `yyyy-mm-dd` date, or `blank` and `notblank` without a value.
+    filter: Option<Vec<String>>,
+    /// `and` (default) if rows have to meet every filter, `or` if one is
+    /// enough.
+    filter_logic: Option<String>,
+    /// `drop` (default), `sheet` to move the rows that don't pass the
+    /// filters to a `Filtered` sheet, or `hide` to hide them. Csv and json
+    /// results only support `drop`.
+    filter_action: Option<String>,
+    /// Columns identifying a record, rows with the same values in all of
+    /// them are duplicates.
+    dedupe_col: Option<Vec<usize>>,
+    /// The row kept of duplicates, `first` (default), `last` or `complete`
+    /// for the one with the most non blank cells.
+    dedupe_strategy: Option<String>,
+    /// `remove` (default) to move the other rows to a `Duplicates` sheet,
+    /// or `highlight` to color them. Csv and json results only support
+    /// `remove` and have no `Duplicates` sheet.
+    dedupe_action: Option<String>,
+    /// Totals of a column written below every group of rows sharing the
+    /// values of the sort columns, of the form `function,column` with
+    /// `count`, `sum`, `avg`, `min` or `max`. Xlsx results only.
+    subtotal: Option<Vec<String>>,
+    /// Columns whose values make the rows of a `Summary` sheet pivoting
+    /// the processed rows. Xlsx results only.
+    pivot_row: Option<Vec<usize>>,
+    /// Columns whose values split the summary values into columns.
+    pivot_col: Option<Vec<usize>>,
+    /// Values of the summary, of the form `function,column` as for
+    /// `subtotal`. Without any the summary counts the rows.
+    pivot_value: Option<Vec<String>>,
+    /// `true` to add a `Report` sheet with the match report sent in the
+    /// `X-Match-Report` header, `false` (default) otherwise. Xlsx results
+    /// only.
+    report_sheet: Option<bool>,
+}
+
+/// The json body of `/runJob`, the typed counterpart of [`RunJobForm`].
+/// Columns are given by their 1 based index or their name in the header
+/// row.
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct RunJobRequest {
+    pub file_id: String,
+    /// Id of an upload whose values are highlighted as contractions.
+    pub contraction_file_id: Option<String>,
+    /// At most 5 texts to highlight, empty ones are left out.
+    #[serde(default)]
+    pub search_terms: Vec<String>,
+    /// Columns whose values have to be `mmddyy` dates.
+    #[serde(default)]
+    pub check_date: Vec<ColumnRef>,
+    /// Columns to sort by, each sorted within the groups of rows sharing
+    /// the values of the ones before it.
+    #[serde(default)]
+    pub sort: Vec<SortColumn>,
+    /// `xlsx` (default), `csv` or `json`, as for the form.
+    #[serde(default)]
+    pub output_format: String,
+    /// `keep` (default) or `strip`.
+    #[serde(default)]
+    pub macros: String,
+    #[serde(default)]
+    pub filters: Vec<ColumnFilter>,
+    /// `and` (default) or `or`.
+    #[serde(default)]
+    pub filter_logic: String,
+    /// `drop` (default), `sheet` or `hide`.
+    #[serde(default)]
+    pub filter_action: String,
+    #[serde(default)]
+    pub dedupe_cols: Vec<ColumnRef>,
+    /// `first` (default), `last` or `complete`.
+    #[serde(default)]
+    pub dedupe_strategy: String,
+    /// `remove` (default) or `highlight`.
+    #[serde(default)]
+    pub dedupe_action: String,
+    #[serde(default)]
+    pub subtotals: Vec<ColumnAggregate>,
+    #[serde(default)]
+    pub pivot_rows: Vec<ColumnRef>,
+    #[serde(default)]
+    pub pivot_cols: Vec<ColumnRef>,
+    #[serde(default)]
+    pub pivot_values: Vec<ColumnAggregate>,
+    #[serde(default)]
+    pub report_sheet: bool,
+}
+
+/// A column, by its 1 based index or by its name in the header row.
+#[derive(Debug, Clone, Deserialize, ToSchema)]
+#[serde(untagged)]
+pub enum ColumnRef {
+    Index(u32),
+    Name(String),
+}
+
+impl ColumnRef {
+    /// The index of the column in a sheet with `header`, indexes past the
+    /// last column of the header are refused. Names match the
+    /// header values ignoring surrounding whitespace, and ignoring case if
+    /// no value matches exactly.
+    pub fn resolve(&self, header: &Row) -> Result<u32> {
+        let name = match self {
+            ColumnRef::Index(0) => {
+                return Err(Error::InvalidPayload(
+                    "Invalid column index: 0, columns are numbered from 1".into(),
+                ))
+            }
+            ColumnRef::Index(column_index) if *column_index > header.last_column() => {
+                return Err(Error::InvalidPayload(format!(
+                    "Invalid column index: {}, the header row has {} columns",
+                    column_index,
+                    header.last_column()
+                )))
+            }
+            ColumnRef::Index(column_index) => return Ok(*column_index),
+            ColumnRef::Name(name) => name.trim(),
+        };
+        let columns = 1..=header.last_column();
+        columns
+            .clone()
+            .find(|col_idx| header.value(*col_idx).trim() == name)
+            .or_else(|| {
+                columns
+                    .clone()
+                    .find(|col_idx| header.value(*col_idx).trim().eq_ignore_ascii_case(name))
+            })
+            .ok_or_else(|| {
+                Error::InvalidPayload(format!(
+                    "Unknown column: Got {}, Expected: a column index or a name of the header row",
+                    name
+                ))
+            })
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SortColumn {
+    pub column: ColumnRef,
+    /// `asc` (default) or `desc`.
+    pub order: Option<String>,
+}
+
+/// A condition rows have to meet, with the operators of the `filter` form
+/// field.
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ColumnFilter {
+    pub column: ColumnRef,
+    pub operator: String,
+    /// Left out for `blank` and `notblank`, `min,max` for `range`.
+    #[serde(default)]
+    pub value: String,
+}
+
+/// `count`, `sum`, `avg`, `min` or `max` of a column.
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ColumnAggregate {
+    pub function: String,
+    pub column: ColumnRef,
 }
 
 #[allow(dead_code)]
@@ -27,10 +212,176 @@ pub struct ExcelFileForm {
 }
 
 #[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
 pub struct UploadFileEntry {
     pub id: String,
     #[serde(skip)]
     pub file_path: String,
+    pub original_name: Option<String>,
+    pub size: Option<i64>,
+    pub created_at: Option<String>,
+    /// Id of the user who uploaded the file, `None` for uploads made
+    /// without authentication.
+    #[serde(skip)]
+    pub owner: Option<String>,
+}
+
+/// Metadata recorded along with a newly uploaded file.
+#[derive(Debug)]
+pub struct NewUploadEntry<'a> {
+    pub file_path: &'a Path,
+    pub original_name: &'a str,
+    pub size: i64,
+    pub owner: Option<&'a str>,
+}
+
+#[derive(Debug, Clone)]
+pub struct UserEntry {
+    pub id: String,
+    pub username: String,
+    /// `pbkdf2-sha256$<rounds>$<salt>$<hash>`, see `auth::hash_password`.
+    pub password_hash: String,
+}
+
+#[derive(Debug)]
+pub struct NewUser<'a> {
+    pub username: &'a str,
+    pub password_hash: &'a str,
+}
+
+#[derive(Debug)]
+pub struct NewSession<'a> {
+    pub token_hash: &'a str,
+    pub user_id: &'a str,
+    pub expires_at: &'a str,
+}
+
+/// A login session. Only the sha256 of the token given to the client is
+/// stored.
+#[derive(Debug)]
+pub struct SessionEntry {
+    pub token_hash: String,
+    pub user: UserEntry,
+    /// RFC 3339 time in UTC.
+    pub expires_at: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct LoginRequest {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct LoginResponse {
+    pub username: String,
+    /// RFC 3339 time the session cookie stops being accepted.
+    pub expires_at: String,
+}
+
+/// What an API token may be used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// `/upload`
+    Upload,
+    /// `/runJob`
+    Run,
+    /// `/getHeader` and the `/uploads/...` routes.
+    Read,
+    /// Everything, managing API tokens included.
+    Admin,
+}
+
+impl Scope {
+    const UPLOAD: &'static str = "upload";
+    const RUN: &'static str = "run";
+    const READ: &'static str = "read";
+    const ADMIN: &'static str = "admin";
+
+    pub fn parse(text: &str) -> Result<Self> {
+        match text.trim().to_lowercase().as_str() {
+            Self::UPLOAD => Ok(Self::Upload),
+            Self::RUN => Ok(Self::Run),
+            Self::READ => Ok(Self::Read),
+            Self::ADMIN => Ok(Self::Admin),
+            other => Err(Error::InvalidPayload(format!(
+                "Invalid scope: Got {}, Expected: upload / run / read / admin",
+                other
+            ))),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Upload => Self::UPLOAD,
+            Self::Run => Self::RUN,
+            Self::Read => Self::READ,
+            Self::Admin => Self::ADMIN,
+        }
+    }
+
+    /// Scopes as stored, comma separated.
+    pub fn join(scopes: &[Scope]) -> String {
+        scopes
+            .iter()
+            .map(|scope| scope.as_str())
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    pub fn split(text: &str) -> Result<Vec<Scope>> {
+        text.split(',')
+            .filter(|scope| !scope.trim().is_empty())
+            .map(Scope::parse)
+            .collect()
+    }
+}
+
+/// An API token as listed, without the token itself.
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiTokenEntry {
+    pub id: String,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub created_at: String,
+    /// RFC 3339 time the token stops being accepted.
+    pub expires_at: String,
+    #[serde(skip)]
+    pub user_id: String,
+}
+
+#[derive(Debug)]
+pub struct NewApiToken<'a> {
+    pub user_id: &'a str,
+    pub name: &'a str,
+    /// sha256 of the token, the token itself is never stored.
+    pub token_hash: &'a str,
+    pub scopes: &'a [Scope],
+    pub expires_at: &'a str,
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateApiTokenRequest {
+    /// What the token is for, shown when listing tokens.
+    pub name: String,
+    /// `upload`, `run`, `read` and `admin`.
+    pub scopes: Vec<String>,
+    /// Days until the token expires, 90 by default.
+    pub expires_in_days: Option<u32>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CreatedApiToken {
+    /// The token to send as `Authorization: Bearer <token>`. It is only
+    /// ever shown in this response.
+    pub token: String,
+    #[serde(flatten)]
+    pub entry: ApiTokenEntry,
 }
 
 #[derive(Serialize, ToSchema)]
@@ -38,38 +389,189 @@ pub struct RowsPayload {
     pub columns: Vec<String>,
 }
 
-#[derive(Debug)]
-pub enum SortInfo {
-    Asc { column_index: u32 },
-    Desc { column_index: u32 },
+/// Query of `/uploads/{id}/rows`.
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RowsPreviewQuery {
+    /// Data rows to skip, defaults to 0.
+    pub offset: Option<usize>,
+    /// Data rows to return, defaults to 100 and is capped at 1000.
+    pub limit: Option<usize>,
+    /// Name of the sheet, or its number counting from 0. Defaults to the
+    /// first sheet.
+    pub sheet: Option<String>,
+}
+
+/// A page of the data rows of a sheet, with statistics over all of them.
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RowsPreview {
+    pub sheet: String,
+    /// Names of all the sheets of the file, delimited files have a single
+    /// sheet.
+    pub sheets: Vec<String>,
+    pub offset: usize,
+    pub limit: usize,
+    /// Data rows of the sheet, the header not included.
+    pub total_rows: usize,
+    pub header: Vec<String>,
+    pub rows: Vec<PreviewRow>,
+    /// One entry per column of the header.
+    pub columns: Vec<ColumnStats>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PreviewRow {
+    /// 1 based row number, the header being row 1.
+    pub row: u32,
+    /// The non empty cells of the row.
+    pub cells: Vec<PreviewCell>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct PreviewCell {
+    pub column: u32,
+    /// Numbers and booleans as json numbers and booleans, dates as ISO 8601
+    /// strings and anything else as a string.
+    #[schema(value_type = Object)]
+    pub value: serde_json::Value,
+    #[serde(rename = "type")]
+    pub kind: CellType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub style: Option<PreviewStyle>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum CellType {
+    String,
+    Number,
+    Boolean,
+    Date,
+    Error,
+}
+
+/// The formatting of a cell that differs from the default.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PreviewStyle {
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub bold: bool,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub italic: bool,
+    /// `#RRGGBB` font color.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+    /// `#RRGGBB` color of a solid fill.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub background: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub number_format: Option<String>,
+}
+
+/// Statistics of a column over all the data rows of the sheet.
+#[derive(Debug, Default, Serialize, ToSchema)]
+pub struct ColumnStats {
+    pub column: u32,
+    /// The header value of the column.
+    pub name: String,
+    /// Non blank cells by type.
+    pub types: TypeCounts,
+    /// Missing cells and cells with empty text.
+    pub blank: usize,
+    /// Distinct values among the non blank cells.
+    pub distinct: usize,
 }
 
-impl SortInfo {
-    const ASC: &'static str = "asc";
-    const DESC: &'static str = "desc";
+#[derive(Debug, Default, Serialize, ToSchema)]
+pub struct TypeCounts {
+    pub string: usize,
+    pub number: usize,
+    pub boolean: usize,
+    pub date: usize,
+    pub error: usize,
 }
 
+/// Query of `/uploads/{id}/profile`.
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ProfileQuery {
+    /// Name of the sheet, or its number counting from 0. Defaults to the
+    /// first sheet.
+    pub sheet: Option<String>,
+}
+
+/// The inferred type and statistics of every column of a sheet.
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SheetProfile {
+    pub sheet: String,
+    pub sheets: Vec<String>,
+    /// Data rows of the sheet, the header not included.
+    pub total_rows: usize,
+    /// One entry per column of the header.
+    pub columns: Vec<ColumnProfile>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ColumnProfile {
+    pub column: u32,
+    /// The header value of the column.
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: InferredType,
+    /// Format of the values of date columns, `mmddyy` for the text dates
+    /// `checkDate` validates or the number format of Excel dates.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub date_format: Option<String>,
+    /// The date format most values follow, when most of them do, even if
+    /// some values aren't dates.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub suggested_date_format: Option<String>,
+    /// Most values follow the `mmddyy` format `checkDate` validates, the
+    /// column is worth validating.
+    pub check_date: bool,
+    /// Share of the data rows with a blank value, from 0 to 1.
+    pub null_ratio: f64,
+    /// Smallest and largest values of integer, decimal, date, boolean and
+    /// text columns. Numbers as json numbers, dates as ISO 8601 strings.
+    #[schema(value_type = Option<Object>)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min: Option<serde_json::Value>,
+    #[schema(value_type = Option<Object>)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<serde_json::Value>,
+    /// The most frequent values, most frequent first.
+    pub top_values: Vec<ValueCount>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum InferredType {
+    Integer,
+    Decimal,
+    Date,
+    Boolean,
+    Text,
+    /// Values of more than one type.
+    Mixed,
+    /// Every value is blank.
+    Empty,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ValueCount {
+    pub value: String,
+    pub count: usize,
+}
+
+/// A `/runJob` request, the uploaded file to run a job on and what the job
+/// does.
+#[derive(Debug)]
 pub struct JobDetails {
     file_id: String,
-    contraction_file: Option<Bytes>,
-    search_terms: Vec<String>,
-    check_date_cols: Vec<u32>,
-    sort_cols_info: Vec<SortInfo>,
-}
-
-impl std::fmt::Debug for JobDetails {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("JobDetails")
-            .field("file_id", &self.file_id)
-            .field(
-                "contraction_file not None",
-                &self.contraction_file.is_some(),
-            )
-            .field("search_terms", &self.search_terms)
-            .field("check_date_cols", &self.check_date_cols)
-            .field("sort_cols_info", &self.sort_cols_info)
-            .finish()
-    }
+    spec: JobSpec,
 }
 
 impl JobDetails {
@@ -78,36 +580,35 @@ impl JobDetails {
     const SEARCH_TERMS_FIELD_N: &'static str = "searchTerm";
     const CHECK_DATE_FIELD_N: &'static str = "checkDate";
     const SORT_COL_FIELD_N: &'static str = "sortCol";
+    const OUTPUT_FORMAT_FIELD_N: &'static str = "outputFormat";
+    const MACROS_FIELD_N: &'static str = "macros";
+    const FILTER_FIELD_N: &'static str = "filter";
+    const FILTER_LOGIC_FIELD_N: &'static str = "filterLogic";
+    const FILTER_ACTION_FIELD_N: &'static str = "filterAction";
+    const DEDUPE_COL_FIELD_N: &'static str = "dedupeCol";
+    const DEDUPE_STRATEGY_FIELD_N: &'static str = "dedupeStrategy";
+    const DEDUPE_ACTION_FIELD_N: &'static str = "dedupeAction";
+    const SUBTOTAL_FIELD_N: &'static str = "subtotal";
+    const PIVOT_ROW_FIELD_N: &'static str = "pivotRow";
+    const PIVOT_COL_FIELD_N: &'static str = "pivotCol";
+    const PIVOT_VALUE_FIELD_N: &'static str = "pivotValue";
+    const REPORT_SHEET_FIELD_N: &'static str = "reportSheet";
     const SEARCH_TERM_COUNTER_LIMIT: usize = 5;
 
-    pub fn sort_infos(&self) -> &[SortInfo] {
-        &self.sort_cols_info
-    }
-
     pub fn file_id(&self) -> &str {
         &self.file_id
     }
 
-    pub fn pop_contraction_file(&mut self) -> Option<Bytes> {
-        let bytes = self.contraction_file.clone();
-        self.contraction_file = None;
-        bytes
-    }
-
-    pub fn search_terms(&self) -> &Vec<String> {
-        &self.search_terms
-    }
-
-    pub fn check_date_cols(&self) -> &Vec<u32> {
-        &self.check_date_cols
+    pub fn spec(&self) -> &JobSpec {
+        &self.spec
     }
 
+    /// The job of a multipart request. The upload isn't known until the
+    /// `fileId` field is read, so the column indexes are checked against its
+    /// header afterwards with [`JobDetails::check_columns`].
     pub async fn try_from(mut value: Multipart) -> Result<Self> {
         let mut file_id: Option<String> = None;
-        let mut contraction_file: Option<Bytes> = None;
-        let mut search_terms: Vec<String> = Vec::with_capacity(5);
-        let mut check_date_cols: Vec<u32> = Vec::new();
-        let mut sor_infos: Vec<SortInfo> = Vec::new();
+        let mut spec = JobSpec::builder();
 
         let mut search_t_counter = 0;
 
@@ -125,17 +626,17 @@ impl JobDetails {
                     if bytes.is_empty() {
                         continue;
                     }
-                    contraction_file = Some(bytes);
+                    spec = spec.contraction_file(bytes);
                 }
-                JobDetails::SEARCH_TERMS_FIELD_N => {
-                    if search_t_counter < JobDetails::SEARCH_TERM_COUNTER_LIMIT {
-                        let text = field.text().await?;
-                        if text.is_empty() {
-                            continue;
-                        }
-                        search_terms.insert(search_t_counter, text);
-                        search_t_counter += 1;
+                JobDetails::SEARCH_TERMS_FIELD_N
+                    if search_t_counter < JobDetails::SEARCH_TERM_COUNTER_LIMIT =>
+                {
+                    let text = field.text().await?;
+                    if text.is_empty() {
+                        continue;
                     }
+                    spec = spec.search_term(text);
+                    search_t_counter += 1;
                 }
                 JobDetails::CHECK_DATE_FIELD_N => {
                     let text = field.text().await?;
@@ -145,9 +646,12 @@ impl JobDetails {
                     }
                     let number = text.parse::<u32>();
                     if number.is_err() {
-                        return Err(Error::Generic(format!("Invalid column index: {}", text)));
+                        return Err(Error::InvalidPayload(format!(
+                            "Invalid column index: {}",
+                            text
+                        )));
                     }
-                    check_date_cols.push(number.unwrap());
+                    spec = spec.check_date(number.unwrap());
                 }
                 JobDetails::SORT_COL_FIELD_N => {
                     // payload has to be of format ORDER,index
@@ -159,33 +663,117 @@ impl JobDetails {
                     }
                     let text_parts: Vec<&str> = text.split(',').collect();
                     if text_parts.len() < 2 {
-                        return Err(Error::Generic(format!("sortCol data has to be of form order,index Where order can take as value either asc or desc. Got: {}", text)));
+                        return Err(Error::InvalidPayload(format!("sortCol data has to be of form order,index Where order can take as value either asc or desc. Got: {}", text)));
                     }
                     let order = text_parts[0];
-                    let order = order.to_lowercase();
                     let index = text_parts[1];
                     let index_val = index.parse::<u32>();
                     if index_val.is_err() {
-                        return Err(Error::Generic(format!(
+                        return Err(Error::InvalidPayload(format!(
                             "Invalid value passed as column index. Got {}, expected a valid number",
                             index
                         )));
                     }
-                    let sort_info = match order.as_str() {
-                        SortInfo::ASC => SortInfo::Asc {
-                            column_index: index_val.unwrap(),
-                        },
-                        SortInfo::DESC => SortInfo::Desc {
-                            column_index: index_val.unwrap(),
-                        },
+                    spec = spec.sort(SortInfo::new(order, index_val.unwrap())?);
+                }
+                JobDetails::OUTPUT_FORMAT_FIELD_N => {
+                    let text = field.text().await?;
+                    let output_format = OutputFormat::parse(&text)?;
+                    spec = spec.output_format(output_format);
+                }
+                JobDetails::MACROS_FIELD_N => {
+                    let text = field.text().await?;
+                    let macros = Macros::parse(&text)?;
+                    spec = spec.macros(macros);
+                }
+                JobDetails::FILTER_FIELD_N => {
+                    let text = field.text().await?;
+                    if text.trim().is_empty() {
+                        continue;
+                    }
+                    spec = spec.filter(RowFilter::parse(&text)?);
+                }
+                JobDetails::FILTER_LOGIC_FIELD_N => {
+                    let text = field.text().await?;
+                    let filter_logic = FilterLogic::parse(&text)?;
+                    spec = spec.filter_logic(filter_logic);
+                }
+                JobDetails::FILTER_ACTION_FIELD_N => {
+                    let text = field.text().await?;
+                    let filter_action = FilterAction::parse(&text)?;
+                    spec = spec.filter_action(filter_action);
+                }
+                JobDetails::DEDUPE_COL_FIELD_N => {
+                    let text = field.text().await?;
+                    let text = text.trim();
+                    if text.is_empty() {
+                        continue;
+                    }
+                    match text.parse::<u32>() {
+                        Ok(col) if col > 0 => spec = spec.dedupe_col(col),
                         _ => {
-                            return Err(Error::Generic(format!(
-                                "Invalid sort order value: Got {}, Expected: asc / desc",
-                                order
+                            return Err(Error::InvalidPayload(format!(
+                                "Invalid column index: {}",
+                                text
+                            )))
+                        }
+                    }
+                }
+                JobDetails::DEDUPE_STRATEGY_FIELD_N => {
+                    let text = field.text().await?;
+                    let dedupe_strategy = DedupeStrategy::parse(&text)?;
+                    spec = spec.dedupe_strategy(dedupe_strategy);
+                }
+                JobDetails::DEDUPE_ACTION_FIELD_N => {
+                    let text = field.text().await?;
+                    let dedupe_action = DedupeAction::parse(&text)?;
+                    spec = spec.dedupe_action(dedupe_action);
+                }
+                JobDetails::SUBTOTAL_FIELD_N => {
+                    let text = field.text().await?;
+                    if text.trim().is_empty() {
+                        continue;
+                    }
+                    spec = spec.subtotal(Aggregate::parse("subtotal", &text)?);
+                }
+                JobDetails::PIVOT_ROW_FIELD_N | JobDetails::PIVOT_COL_FIELD_N => {
+                    let is_row = name == JobDetails::PIVOT_ROW_FIELD_N;
+                    let text = field.text().await?;
+                    let text = text.trim();
+                    if text.is_empty() {
+                        continue;
+                    }
+                    match text.parse::<u32>() {
+                        Ok(col) if col > 0 && is_row => spec = spec.pivot_row(col),
+                        Ok(col) if col > 0 => spec = spec.pivot_col(col),
+                        _ => {
+                            return Err(Error::InvalidPayload(format!(
+                                "Invalid column index: {}",
+                                text
+                            )))
+                        }
+                    }
+                }
+                JobDetails::PIVOT_VALUE_FIELD_N => {
+                    let text = field.text().await?;
+                    if text.trim().is_empty() {
+                        continue;
+                    }
+                    spec = spec.pivot_value(Aggregate::parse("pivotValue", &text)?);
+                }
+                JobDetails::REPORT_SHEET_FIELD_N => {
+                    let text = field.text().await?;
+                    let report_sheet = match text.trim().to_lowercase().as_str() {
+                        "" | "false" => false,
+                        "true" => true,
+                        other => {
+                            return Err(Error::InvalidPayload(format!(
+                                "Invalid reportSheet option: Got {}, Expected: true / false",
+                                other
                             )));
                         }
                     };
-                    sor_infos.push(sort_info);
+                    spec = spec.report_sheet(report_sheet);
                 }
                 _ => {}
             }
@@ -197,10 +785,221 @@ impl JobDetails {
         }
         Ok(Self {
             file_id: file_id.unwrap(),
-            contraction_file,
-            check_date_cols,
-            search_terms,
-            sort_cols_info: sor_infos,
+            spec: spec.build()?,
+        })
+    }
+    /// Fails for columns of the job past the last column of `header`, as
+    /// [`ColumnRef::resolve`] does for json requests.
+    pub fn check_columns(&self, header: &Row) -> Result<()> {
+        let spec = &self.spec;
+        spec.sort_infos()
+            .iter()
+            .map(SortInfo::column_index)
+            .chain(spec.check_date_cols().iter().copied())
+            .chain(spec.filters().iter().map(|filter| filter.column_index))
+            .chain(spec.dedupe_cols().iter().copied())
+            .chain(
+                spec.subtotals()
+                    .iter()
+                    .map(|subtotal| subtotal.column_index),
+            )
+            .chain(spec.pivot_rows().iter().copied())
+            .chain(spec.pivot_cols().iter().copied())
+            .chain(spec.pivot_values().iter().map(|value| value.column_index))
+            .try_for_each(|column_index| ColumnRef::Index(column_index).resolve(header).map(drop))
+    }
+
+    /// The job of a json request on the sheet with `header`, the columns
+    /// named in it resolved against the header. `contraction_file` holds
+    /// the upload of `contraction_file_id`.
+    pub fn from_json(
+        request: RunJobRequest,
+        header: &Row,
+        contraction_file: Option<Bytes>,
+    ) -> Result<Self> {
+        let search_terms: Vec<String> = request
+            .search_terms
+            .into_iter()
+            .filter(|term| !term.is_empty())
+            .collect();
+        if search_terms.len() > JobDetails::SEARCH_TERM_COUNTER_LIMIT {
+            return Err(Error::InvalidPayload(format!(
+                "At most {} search terms are allowed, got {}",
+                JobDetails::SEARCH_TERM_COUNTER_LIMIT,
+                search_terms.len()
+            )));
+        }
+
+        let mut spec = JobSpec::builder()
+            .output_format(OutputFormat::parse(&request.output_format)?)
+            .macros(Macros::parse(&request.macros)?)
+            .filter_logic(FilterLogic::parse(&request.filter_logic)?)
+            .filter_action(FilterAction::parse(&request.filter_action)?)
+            .dedupe_strategy(DedupeStrategy::parse(&request.dedupe_strategy)?)
+            .dedupe_action(DedupeAction::parse(&request.dedupe_action)?)
+            .report_sheet(request.report_sheet);
+        if let Some(bytes) = contraction_file {
+            spec = spec.contraction_file(bytes);
+        }
+        for term in search_terms {
+            spec = spec.search_term(term);
+        }
+        for column in &request.check_date {
+            spec = spec.check_date(column.resolve(header)?);
+        }
+        for sort in &request.sort {
+            let order = sort.order.as_deref().unwrap_or(SortInfo::ASC);
+            spec = spec.sort(SortInfo::new(order, sort.column.resolve(header)?)?);
+        }
+        for filter in &request.filters {
+            let column_index = filter.column.resolve(header)?;
+            spec = spec.filter(RowFilter::new(
+                column_index,
+                &filter.operator,
+                &filter.value,
+            )?);
+        }
+        for column in &request.dedupe_cols {
+            spec = spec.dedupe_col(column.resolve(header)?);
+        }
+        for subtotal in &request.subtotals {
+            let column_index = subtotal.column.resolve(header)?;
+            spec = spec.subtotal(Aggregate::new(
+                "subtotals",
+                &subtotal.function,
+                column_index,
+            )?);
+        }
+        for column in &request.pivot_rows {
+            spec = spec.pivot_row(column.resolve(header)?);
+        }
+        for column in &request.pivot_cols {
+            spec = spec.pivot_col(column.resolve(header)?);
+        }
+        for value in &request.pivot_values {
+            let column_index = value.column.resolve(header)?;
+            spec = spec.pivot_value(Aggregate::new(
+                "pivotValues",
+                &value.function,
+                column_index,
+            )?);
+        }
+        Ok(Self {
+            file_id: request.file_id,
+            spec: spec.build()?,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::JobSpecBuilder;
+
+    fn header() -> Row {
+        Row::from_text(["name", " Amount ", "AMOUNT"])
+    }
+
+    fn job(json: &str) -> Result<JobDetails> {
+        let request: RunJobRequest = serde_json::from_str(json).unwrap();
+        JobDetails::from_json(request, &header(), None)
+    }
+
+    #[test]
+    fn column_refs() {
+        let resolve = |column: ColumnRef| column.resolve(&header());
+        assert_eq!(resolve(ColumnRef::Index(2)).unwrap(), 2);
+        assert_eq!(resolve(ColumnRef::Name("name".into())).unwrap(), 1);
+        assert_eq!(resolve(ColumnRef::Name(" Amount".into())).unwrap(), 2);
+        // Exact matches win over ones ignoring case.
+        assert_eq!(resolve(ColumnRef::Name("AMOUNT".into())).unwrap(), 3);
+        assert_eq!(resolve(ColumnRef::Name("amount".into())).unwrap(), 2);
+        assert_eq!(resolve(ColumnRef::Index(3)).unwrap(), 3);
+        for column_index in [0, 4] {
+            assert!(matches!(
+                resolve(ColumnRef::Index(column_index)),
+                Err(Error::InvalidPayload(_))
+            ));
+        }
+        assert!(matches!(
+            resolve(ColumnRef::Name("price".into())),
+            Err(Error::InvalidPayload(_))
+        ));
+    }
+
+    #[test]
+    fn json_requests() {
+        let details = job(r#"{
+            "fileId": "file",
+            "sort": [{"column": "amount", "order": "desc"}, {"column": 1}],
+            "checkDate": ["NAME"],
+            "filters": [{"column": "name", "operator": "notblank"}],
+            "pivotValues": [{"function": "sum", "column": 3}]
+        }"#)
+        .unwrap();
+        let spec = details.spec();
+        assert!(matches!(
+            spec.sort_infos(),
+            [
+                SortInfo::Desc { column_index: 2 },
+                SortInfo::Asc { column_index: 1 }
+            ]
+        ));
+        assert_eq!(spec.check_date_cols(), &[1]);
+        assert_eq!(spec.filters()[0].column_index, 1);
+        assert_eq!(spec.pivot_values()[0].column_index, 3);
+    }
+
+    #[test]
+    fn unknown_header_names_are_refused() {
+        for json in [
+            r#"{"fileId": "file", "sort": [{"column": "price"}]}"#,
+            r#"{"fileId": "file", "dedupeCols": ["price"]}"#,
+            r#"{"fileId": "file", "subtotals": [{"function": "sum", "column": "price"}]}"#,
+            r#"{"fileId": "file", "pivotRows": [4]}"#,
+        ] {
+            assert!(matches!(job(json), Err(Error::InvalidPayload(_))), "{json}");
+        }
+    }
+
+    #[test]
+    fn form_columns_past_the_header_are_refused() {
+        let details = |spec: JobSpecBuilder| JobDetails {
+            file_id: "file".into(),
+            spec: spec.build().unwrap(),
+        };
+        let header = header();
+        assert!(
+            details(JobSpec::builder().sort(SortInfo::new("asc", 3).unwrap()))
+                .check_columns(&header)
+                .is_ok()
+        );
+        for spec in [
+            JobSpec::builder().sort(SortInfo::new("desc", 4).unwrap()),
+            JobSpec::builder().check_date(4),
+            JobSpec::builder().dedupe_col(4),
+            JobSpec::builder().pivot_row(4),
+        ] {
+            assert!(matches!(
+                details(spec).check_columns(&header),
+                Err(Error::InvalidPayload(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn unknown_fields_are_refused() {
+        for json in [
+            r#"{"fileId": "file", "sorts": []}"#,
+            r#"{"fileId": "file", "sort": [{"column": 1, "direction": "asc"}]}"#,
+            r#"{"fileId": "file", "filters": [{"column": 1, "operator": "eq", "values": "x"}]}"#,
+            r#"{"fileId": "file", "pivotValues": [{"function": "sum", "column": 1, "as": "x"}]}"#,
+        ] {
+            assert!(
+                serde_json::from_str::<RunJobRequest>(json).is_err(),
+                "{json}"
+            );
+        }
+        assert!(serde_json::from_str::<RunJobRequest>(r#"{"fileId": "file"}"#).is_ok());
+    }
+}
@@ -0,0 +1,748 @@
+use super::{
+    migrations::{self, MigrationStatus},
+    model::{
+        ApiTokenEntry, NewApiToken, NewSession, NewUploadEntry, NewUser, Scope, SessionEntry,
+        UploadFileEntry, UserEntry,
+    },
+    DataSource,
+};
+use crate::{error::Error, Result};
+use async_trait::async_trait;
+use deadpool_postgres::{Config, Object, Pool, PoolConfig, Runtime};
+use tokio_postgres::NoTls;
+use uuid::Uuid;
+
+#[derive(Clone, Debug)]
+pub struct PostgresDataSource(Pool);
+
+impl PostgresDataSource {
+    const UPLOAD_TABLE_NAME: &'static str = "UploadEntriesTable";
+    const UPLOAD_T_ID_COL: &'static str = "ID";
+    const UPLOAD_T_FILE_NAME_COL: &'static str = "FILE_NAME";
+    const UPLOAD_T_ORIGINAL_NAME_COL: &'static str = "ORIGINAL_NAME";
+    const UPLOAD_T_SIZE_COL: &'static str = "SIZE";
+    const UPLOAD_T_CREATED_AT_COL: &'static str = "CREATED_AT";
+    const UPLOAD_T_OWNER_COL: &'static str = "OWNER";
+    const USER_TABLE_NAME: &'static str = "UsersTable";
+    const USER_T_ID_COL: &'static str = "ID";
+    const USER_T_USERNAME_COL: &'static str = "USERNAME";
+    const USER_T_PASSWORD_HASH_COL: &'static str = "PASSWORD_HASH";
+    const USER_T_CREATED_AT_COL: &'static str = "CREATED_AT";
+    const SESSION_TABLE_NAME: &'static str = "SessionsTable";
+    const SESSION_T_TOKEN_HASH_COL: &'static str = "TOKEN_HASH";
+    const SESSION_T_USER_ID_COL: &'static str = "USER_ID";
+    const SESSION_T_EXPIRES_AT_COL: &'static str = "EXPIRES_AT";
+    const TOKEN_TABLE_NAME: &'static str = "ApiTokensTable";
+    const TOKEN_T_ID_COL: &'static str = "ID";
+    const TOKEN_T_USER_ID_COL: &'static str = "USER_ID";
+    const TOKEN_T_NAME_COL: &'static str = "NAME";
+    const TOKEN_T_TOKEN_HASH_COL: &'static str = "TOKEN_HASH";
+    const TOKEN_T_SCOPES_COL: &'static str = "SCOPES";
+    const TOKEN_T_CREATED_AT_COL: &'static str = "CREATED_AT";
+    const TOKEN_T_EXPIRES_AT_COL: &'static str = "EXPIRES_AT";
+    const USAGE_TABLE_NAME: &'static str = "UploadUsageTable";
+    const USAGE_T_CLIENT_COL: &'static str = "CLIENT";
+    const USAGE_T_DAY_COL: &'static str = "DAY";
+    const USAGE_T_BYTES_COL: &'static str = "BYTES";
+
+    pub fn new(url: &str, pool_size: usize) -> Result<Self> {
+        let mut cfg = Config::new();
+        cfg.url = Some(url.to_string());
+        cfg.pool = Some(PoolConfig::new(pool_size));
+        match cfg.create_pool(Some(Runtime::Tokio1), NoTls) {
+            Err(e) => Err(Error::DatabaseOperationFailed(e.to_string())),
+            Ok(pool) => Ok(Self(pool)),
+        }
+    }
+
+    async fn client(&self) -> Result<Object> {
+        self.0
+            .get()
+            .await
+            .map_err(|e| Error::DatabaseOperationFailed(e.to_string()))
+    }
+
+    /// Token columns in the order `token_entry` reads them, prefixed with
+    /// `alias.`.
+    fn token_columns(alias: &str) -> String {
+        [
+            Self::TOKEN_T_ID_COL,
+            Self::TOKEN_T_USER_ID_COL,
+            Self::TOKEN_T_NAME_COL,
+            Self::TOKEN_T_SCOPES_COL,
+            Self::TOKEN_T_CREATED_AT_COL,
+            Self::TOKEN_T_EXPIRES_AT_COL,
+        ]
+        .map(|col| format!("{alias}.{col}"))
+        .join(", ")
+    }
+
+    /// Reads the columns listed by `token_columns`, starting at the first
+    /// one.
+    fn token_entry(row: &tokio_postgres::Row) -> Result<ApiTokenEntry> {
+        Ok(ApiTokenEntry {
+            id: row.get(0),
+            user_id: row.get(1),
+            name: row.get(2),
+            scopes: Scope::split(row.get(3))
+                .map_err(|e| Error::DatabaseOperationFailed(e.to_string()))?,
+            created_at: row.get(4),
+            expires_at: row.get(5),
+        })
+    }
+}
+
+#[async_trait]
+impl DataSource for PostgresDataSource {
+    async fn init_database(&self) -> Result<()> {
+        let mut client = self.client().await?;
+
+        if let Err(e) = client
+            .batch_execute(migrations::CREATE_SCHEMA_VERSION_TABLE)
+            .await
+        {
+            return Err(Error::DatabaseOperationFailed(e.to_string()));
+        }
+
+        let stmt = format!(
+            "SELECT COALESCE(MAX(version), 0) FROM {t_name};",
+            t_name = migrations::SCHEMA_VERSION_TABLE_NAME
+        );
+        let current_version: i32 = match client.query_one(&stmt, &[]).await {
+            Err(e) => return Err(Error::DatabaseOperationFailed(e.to_string())),
+            Ok(row) => row.get(0),
+        };
+
+        let stmt = format!(
+            "INSERT INTO {t_name} (version, name, applied_at) values ($1, $2, $3);",
+            t_name = migrations::SCHEMA_VERSION_TABLE_NAME
+        );
+        for migration in migrations::pending(current_version as u32) {
+            // Each migration is applied along with its version row, so a
+            // failure never leaves the schema half way through a version.
+            let tx = match client.transaction().await {
+                Err(e) => return Err(Error::DatabaseOperationFailed(e.to_string())),
+                Ok(tx) => tx,
+            };
+            let add_columns: String = migration
+                .add_columns
+                .iter()
+                .map(|column| {
+                    format!(
+                        "ALTER TABLE {} ADD COLUMN IF NOT EXISTS {} {};",
+                        column.table, column.column, column.definition
+                    )
+                })
+                .collect();
+            if let Err(e) = tx
+                .batch_execute(&format!("{}{}", migration.sql, add_columns))
+                .await
+            {
+                return Err(Error::DatabaseOperationFailed(format!(
+                    "Migration {} ({}) failed: {}",
+                    migration.version, migration.name, e
+                )));
+            }
+            if let Err(e) = tx
+                .execute(
+                    &stmt,
+                    &[
+                        &(migration.version as i32),
+                        &migration.name,
+                        &migrations::applied_at(),
+                    ],
+                )
+                .await
+            {
+                return Err(Error::DatabaseOperationFailed(e.to_string()));
+            }
+            if let Err(e) = tx.commit().await {
+                return Err(Error::DatabaseOperationFailed(e.to_string()));
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn ping(&self) -> Result<()> {
+        match self.client().await?.query_one("SELECT 1;", &[]).await {
+            Err(e) => Err(Error::DatabaseOperationFailed(e.to_string())),
+            Ok(_) => Ok(()),
+        }
+    }
+
+    async fn applied_migrations(&self) -> Result<Vec<MigrationStatus>> {
+        let client = self.client().await?;
+
+        // Databases no migration ran on yet have no version table, which is
+        // left for `init_database` to create.
+        let has_versions = client
+            .query_one(
+                "SELECT to_regclass($1::text) IS NOT NULL;",
+                &[&migrations::SCHEMA_VERSION_TABLE_NAME],
+            )
+            .await;
+        match has_versions {
+            Err(e) => return Err(Error::DatabaseOperationFailed(e.to_string())),
+            Ok(row) if !row.get::<usize, bool>(0) => return Ok(Vec::new()),
+            Ok(_) => {}
+        }
+
+        let stmt = format!(
+            "SELECT version, name, applied_at FROM {t_name} ORDER BY version;",
+            t_name = migrations::SCHEMA_VERSION_TABLE_NAME
+        );
+        match client.query(&stmt, &[]).await {
+            Err(e) => Err(Error::DatabaseOperationFailed(e.to_string())),
+            Ok(rows) => Ok(rows
+                .into_iter()
+                .map(|row| MigrationStatus {
+                    version: row.get::<usize, i32>(0) as u32,
+                    name: row.get(1),
+                    applied_at: Some(row.get(2)),
+                })
+                .collect()),
+        }
+    }
+
+    async fn add_file_entry(&self, entry: NewUploadEntry<'_>) -> Result<Uuid> {
+        let id_val = Uuid::now_v7();
+        let stmt = format!(
+            "INSERT INTO {t_name} ({id_col}, {fname_col}, {oname_col}, {size_col}, {created_col}, {owner_col}) values ($1, $2, $3, $4, $5, $6);",
+            t_name = Self::UPLOAD_TABLE_NAME,
+            id_col = Self::UPLOAD_T_ID_COL,
+            fname_col = Self::UPLOAD_T_FILE_NAME_COL,
+            oname_col = Self::UPLOAD_T_ORIGINAL_NAME_COL,
+            size_col = Self::UPLOAD_T_SIZE_COL,
+            created_col = Self::UPLOAD_T_CREATED_AT_COL,
+            owner_col = Self::UPLOAD_T_OWNER_COL,
+        );
+
+        if let Err(e) = self
+            .client()
+            .await?
+            .execute(
+                &stmt,
+                &[
+                    &id_val.to_string(),
+                    &entry.file_path.to_string_lossy().as_ref(),
+                    &entry.original_name,
+                    &entry.size,
+                    &chrono::Utc::now().to_rfc3339(),
+                    &entry.owner,
+                ],
+            )
+            .await
+        {
+            return Err(Error::DatabaseOperationFailed(e.to_string()));
+        }
+
+        Ok(id_val)
+    }
+
+    async fn remove_file_entry(&self, id: String) -> Result<()> {
+        let stmt = format!(
+            "DELETE FROM {t_name} where {id_col} = $1",
+            t_name = Self::UPLOAD_TABLE_NAME,
+            id_col = Self::UPLOAD_T_ID_COL
+        );
+
+        let _ = self.client().await?.execute(&stmt, &[&id]).await;
+
+        Ok(())
+    }
+
+    async fn get_file_entry(&self, id: String) -> Result<UploadFileEntry> {
+        let stmt = format!(
+            "SELECT {id_col}, {fname_col}, {oname_col}, {size_col}, {created_col}, {owner_col} FROM {t_name} where {id_col} = $1;",
+            t_name = Self::UPLOAD_TABLE_NAME,
+            id_col = Self::UPLOAD_T_ID_COL,
+            fname_col = Self::UPLOAD_T_FILE_NAME_COL,
+            oname_col = Self::UPLOAD_T_ORIGINAL_NAME_COL,
+            size_col = Self::UPLOAD_T_SIZE_COL,
+            created_col = Self::UPLOAD_T_CREATED_AT_COL,
+            owner_col = Self::UPLOAD_T_OWNER_COL,
+        );
+
+        match self.client().await?.query_opt(&stmt, &[&id]).await {
+            Err(e) => Err(Error::DatabaseOperationFailed(e.to_string())),
+            Ok(None) => Err(Error::NoEntryFound(id)),
+            Ok(Some(row)) => Ok(UploadFileEntry {
+                id: row.get(0),
+                file_path: row.get(1),
+                original_name: row.get(2),
+                size: row.get(3),
+                created_at: row.get(4),
+                owner: row.get(5),
+            }),
+        }
+    }
+
+    async fn add_user(&self, user: NewUser<'_>) -> Result<Uuid> {
+        let id_val = Uuid::now_v7();
+        let stmt = format!(
+            "INSERT INTO {t_name} ({id_col}, {name_col}, {hash_col}, {created_col}) values ($1, $2, $3, $4);",
+            t_name = Self::USER_TABLE_NAME,
+            id_col = Self::USER_T_ID_COL,
+            name_col = Self::USER_T_USERNAME_COL,
+            hash_col = Self::USER_T_PASSWORD_HASH_COL,
+            created_col = Self::USER_T_CREATED_AT_COL,
+        );
+
+        if let Err(e) = self
+            .client()
+            .await?
+            .execute(
+                &stmt,
+                &[
+                    &id_val.to_string(),
+                    &user.username,
+                    &user.password_hash,
+                    &chrono::Utc::now().to_rfc3339(),
+                ],
+            )
+            .await
+        {
+            return Err(Error::DatabaseOperationFailed(e.to_string()));
+        }
+
+        Ok(id_val)
+    }
+
+    async fn get_user(&self, username: String) -> Result<UserEntry> {
+        let stmt = format!(
+            "SELECT {id_col}, {name_col}, {hash_col} FROM {t_name} where {name_col} = $1;",
+            t_name = Self::USER_TABLE_NAME,
+            id_col = Self::USER_T_ID_COL,
+            name_col = Self::USER_T_USERNAME_COL,
+            hash_col = Self::USER_T_PASSWORD_HASH_COL,
+        );
+
+        match self.client().await?.query_opt(&stmt, &[&username]).await {
+            Err(e) => Err(Error::DatabaseOperationFailed(e.to_string())),
+            Ok(None) => Err(Error::NoEntryFound(username)),
+            Ok(Some(row)) => Ok(UserEntry {
+                id: row.get(0),
+                username: row.get(1),
+                password_hash: row.get(2),
+            }),
+        }
+    }
+
+    async fn add_session(&self, session: NewSession<'_>) -> Result<()> {
+        let stmt = format!(
+            "INSERT INTO {t_name} ({token_col}, {user_col}, {expires_col}) values ($1, $2, $3);",
+            t_name = Self::SESSION_TABLE_NAME,
+            token_col = Self::SESSION_T_TOKEN_HASH_COL,
+            user_col = Self::SESSION_T_USER_ID_COL,
+            expires_col = Self::SESSION_T_EXPIRES_AT_COL,
+        );
+
+        if let Err(e) = self
+            .client()
+            .await?
+            .execute(
+                &stmt,
+                &[&session.token_hash, &session.user_id, &session.expires_at],
+            )
+            .await
+        {
+            return Err(Error::DatabaseOperationFailed(e.to_string()));
+        }
+
+        Ok(())
+    }
+
+    async fn get_session(&self, token_hash: String) -> Result<SessionEntry> {
+        let stmt = format!(
+            "SELECT s.{token_col}, s.{expires_col}, u.{id_col}, u.{name_col}, u.{hash_col} FROM {s_name} s JOIN {u_name} u ON u.{id_col} = s.{user_col} where s.{token_col} = $1;",
+            s_name = Self::SESSION_TABLE_NAME,
+            u_name = Self::USER_TABLE_NAME,
+            token_col = Self::SESSION_T_TOKEN_HASH_COL,
+            user_col = Self::SESSION_T_USER_ID_COL,
+            expires_col = Self::SESSION_T_EXPIRES_AT_COL,
+            id_col = Self::USER_T_ID_COL,
+            name_col = Self::USER_T_USERNAME_COL,
+            hash_col = Self::USER_T_PASSWORD_HASH_COL,
+        );
+
+        match self.client().await?.query_opt(&stmt, &[&token_hash]).await {
+            Err(e) => Err(Error::DatabaseOperationFailed(e.to_string())),
+            Ok(None) => Err(Error::NoEntryFound("session".into())),
+            Ok(Some(row)) => Ok(SessionEntry {
+                token_hash: row.get(0),
+                expires_at: row.get(1),
+                user: UserEntry {
+                    id: row.get(2),
+                    username: row.get(3),
+                    password_hash: row.get(4),
+                },
+            }),
+        }
+    }
+
+    async fn remove_session(&self, token_hash: String) -> Result<()> {
+        let stmt = format!(
+            "DELETE FROM {t_name} where {token_col} = $1",
+            t_name = Self::SESSION_TABLE_NAME,
+            token_col = Self::SESSION_T_TOKEN_HASH_COL
+        );
+
+        let _ = self.client().await?.execute(&stmt, &[&token_hash]).await;
+
+        Ok(())
+    }
+
+    async fn add_api_token(&self, token: NewApiToken<'_>) -> Result<ApiTokenEntry> {
+        let entry = ApiTokenEntry {
+            id: Uuid::now_v7().to_string(),
+            user_id: token.user_id.to_string(),
+            name: token.name.to_string(),
+            scopes: token.scopes.to_vec(),
+            created_at: chrono::Utc::now().to_rfc3339(),
+            expires_at: token.expires_at.to_string(),
+        };
+        let stmt = format!(
+            "INSERT INTO {t_name} ({id_col}, {user_col}, {name_col}, {hash_col}, {scopes_col}, {created_col}, {expires_col}) values ($1, $2, $3, $4, $5, $6, $7);",
+            t_name = Self::TOKEN_TABLE_NAME,
+            id_col = Self::TOKEN_T_ID_COL,
+            user_col = Self::TOKEN_T_USER_ID_COL,
+            name_col = Self::TOKEN_T_NAME_COL,
+            hash_col = Self::TOKEN_T_TOKEN_HASH_COL,
+            scopes_col = Self::TOKEN_T_SCOPES_COL,
+            created_col = Self::TOKEN_T_CREATED_AT_COL,
+            expires_col = Self::TOKEN_T_EXPIRES_AT_COL,
+        );
+
+        if let Err(e) = self
+            .client()
+            .await?
+            .execute(
+                &stmt,
+                &[
+                    &entry.id,
+                    &entry.user_id,
+                    &entry.name,
+                    &token.token_hash,
+                    &Scope::join(token.scopes),
+                    &entry.created_at,
+                    &entry.expires_at,
+                ],
+            )
+            .await
+        {
+            return Err(Error::DatabaseOperationFailed(e.to_string()));
+        }
+
+        Ok(entry)
+    }
+
+    async fn get_api_token(&self, token_hash: String) -> Result<(ApiTokenEntry, UserEntry)> {
+        let stmt = format!(
+            "SELECT {token_cols}, u.{name_col}, u.{hash_col} FROM {t_name} t JOIN {u_name} u ON u.{id_col} = t.{user_col} where t.{token_hash_col} = $1;",
+            token_cols = Self::token_columns("t"),
+            t_name = Self::TOKEN_TABLE_NAME,
+            u_name = Self::USER_TABLE_NAME,
+            user_col = Self::TOKEN_T_USER_ID_COL,
+            token_hash_col = Self::TOKEN_T_TOKEN_HASH_COL,
+            id_col = Self::USER_T_ID_COL,
+            name_col = Self::USER_T_USERNAME_COL,
+            hash_col = Self::USER_T_PASSWORD_HASH_COL,
+        );
+
+        match self.client().await?.query_opt(&stmt, &[&token_hash]).await {
+            Err(e) => Err(Error::DatabaseOperationFailed(e.to_string())),
+            Ok(None) => Err(Error::NoEntryFound("api token".into())),
+            Ok(Some(row)) => {
+                let token = Self::token_entry(&row)?;
+                let user = UserEntry {
+                    id: token.user_id.clone(),
+                    username: row.get(6),
+                    password_hash: row.get(7),
+                };
+                Ok((token, user))
+            }
+        }
+    }
+
+    async fn list_api_tokens(&self, user_id: String) -> Result<Vec<ApiTokenEntry>> {
+        let stmt = format!(
+            "SELECT {token_cols} FROM {t_name} t where t.{user_col} = $1 ORDER BY t.{created_col};",
+            token_cols = Self::token_columns("t"),
+            t_name = Self::TOKEN_TABLE_NAME,
+            user_col = Self::TOKEN_T_USER_ID_COL,
+            created_col = Self::TOKEN_T_CREATED_AT_COL,
+        );
+
+        match self.client().await?.query(&stmt, &[&user_id]).await {
+            Err(e) => Err(Error::DatabaseOperationFailed(e.to_string())),
+            Ok(rows) => rows.iter().map(Self::token_entry).collect(),
+        }
+    }
+
+    async fn remove_api_token(&self, id: String, user_id: String) -> Result<()> {
+        let stmt = format!(
+            "DELETE FROM {t_name} where {id_col} = $1 AND {user_col} = $2",
+            t_name = Self::TOKEN_TABLE_NAME,
+            id_col = Self::TOKEN_T_ID_COL,
+            user_col = Self::TOKEN_T_USER_ID_COL,
+        );
+
+        match self.client().await?.execute(&stmt, &[&id, &user_id]).await {
+            Err(e) => Err(Error::DatabaseOperationFailed(e.to_string())),
+            Ok(0) => Err(Error::NoEntryFound(id)),
+            Ok(_) => Ok(()),
+        }
+    }
+
+    async fn add_upload_usage(&self, client: String, day: String, bytes: i64) -> Result<()> {
+        let stmt = format!(
+            "INSERT INTO {t_name} ({client_col}, {day_col}, {bytes_col}) values ($1, $2, $3) ON CONFLICT ({client_col}, {day_col}) DO UPDATE SET {bytes_col} = {t_name}.{bytes_col} + excluded.{bytes_col};",
+            t_name = Self::USAGE_TABLE_NAME,
+            client_col = Self::USAGE_T_CLIENT_COL,
+            day_col = Self::USAGE_T_DAY_COL,
+            bytes_col = Self::USAGE_T_BYTES_COL,
+        );
+
+        if let Err(e) = self
+            .client()
+            .await?
+            .execute(&stmt, &[&client, &day, &bytes])
+            .await
+        {
+            return Err(Error::DatabaseOperationFailed(e.to_string()));
+        }
+
+        Ok(())
+    }
+
+    async fn reserve_upload_usage(
+        &self,
+        client: String,
+        day: String,
+        bytes: i64,
+        quota: i64,
+    ) -> Result<bool> {
+        let stmt = format!(
+            "INSERT INTO {t_name} ({client_col}, {day_col}, {bytes_col}) SELECT $1::TEXT, $2::TEXT, $3::BIGINT WHERE $3::BIGINT <= $4::BIGINT ON CONFLICT ({client_col}, {day_col}) DO UPDATE SET {bytes_col} = {t_name}.{bytes_col} + excluded.{bytes_col} WHERE {t_name}.{bytes_col} + excluded.{bytes_col} <= $4::BIGINT;",
+            t_name = Self::USAGE_TABLE_NAME,
+            client_col = Self::USAGE_T_CLIENT_COL,
+            day_col = Self::USAGE_T_DAY_COL,
+            bytes_col = Self::USAGE_T_BYTES_COL,
+        );
+
+        match self
+            .client()
+            .await?
+            .execute(&stmt, &[&client, &day, &bytes, &quota])
+            .await
+        {
+            Err(e) => Err(Error::DatabaseOperationFailed(e.to_string())),
+            Ok(changed) => Ok(changed > 0),
+        }
+    }
+
+    async fn get_upload_usage(&self, client: String, day: String) -> Result<i64> {
+        let stmt = format!(
+            "SELECT {bytes_col} FROM {t_name} where {client_col} = $1 AND {day_col} = $2;",
+            t_name = Self::USAGE_TABLE_NAME,
+            client_col = Self::USAGE_T_CLIENT_COL,
+            day_col = Self::USAGE_T_DAY_COL,
+            bytes_col = Self::USAGE_T_BYTES_COL,
+        );
+
+        match self
+            .client()
+            .await?
+            .query_opt(&stmt, &[&client, &day])
+            .await
+        {
+            Err(e) => Err(Error::DatabaseOperationFailed(e.to_string())),
+            Ok(None) => Ok(0),
+            Ok(Some(row)) => Ok(row.get(0)),
+        }
+    }
+}
+
+/// Runs against the database of `DATABASE_URL` and is skipped when it isn't
+/// set: `DATABASE_URL=postgres://... cargo test --features postgres`. Rows
+/// are named after a fresh uuid, so the database doesn't need to be empty.
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    async fn datasource() -> Option<PostgresDataSource> {
+        let Ok(url) = std::env::var("DATABASE_URL") else {
+            eprintln!("DATABASE_URL is not set, skipping the Postgres tests");
+            return None;
+        };
+        let datasource = PostgresDataSource::new(&url, 2).unwrap();
+        datasource.init_database().await.unwrap();
+        Some(datasource)
+    }
+
+    #[tokio::test]
+    async fn migrations_are_applied_once() {
+        let Some(datasource) = datasource().await else {
+            return;
+        };
+        datasource.ping().await.unwrap();
+        // A second run finds nothing pending.
+        datasource.init_database().await.unwrap();
+
+        let applied = datasource.applied_migrations().await.unwrap();
+        let versions: Vec<u32> = applied.iter().map(|m| m.version).collect();
+        let expected: Vec<u32> = migrations::MIGRATIONS.iter().map(|m| m.version).collect();
+        assert_eq!(versions, expected);
+        assert!(applied.iter().all(|m| m.applied_at.is_some()));
+    }
+
+    #[tokio::test]
+    async fn file_entries() {
+        let Some(datasource) = datasource().await else {
+            return;
+        };
+        let id = datasource
+            .add_file_entry(NewUploadEntry {
+                file_path: Path::new("./data_/test.csv"),
+                original_name: "test.csv",
+                size: 42,
+                owner: Some("owner"),
+            })
+            .await
+            .unwrap();
+
+        let entry = datasource.get_file_entry(id.to_string()).await.unwrap();
+        assert_eq!(entry.id, id.to_string());
+        assert_eq!(entry.file_path, "./data_/test.csv");
+        assert_eq!(entry.original_name.as_deref(), Some("test.csv"));
+        assert_eq!(entry.size, Some(42));
+        assert_eq!(entry.owner.as_deref(), Some("owner"));
+        assert!(entry.created_at.is_some());
+
+        datasource.remove_file_entry(id.to_string()).await.unwrap();
+        assert!(matches!(
+            datasource.get_file_entry(id.to_string()).await,
+            Err(Error::NoEntryFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn users_sessions_and_tokens() {
+        let Some(datasource) = datasource().await else {
+            return;
+        };
+        let username = format!("user-{}", Uuid::now_v7());
+        let user_id = datasource
+            .add_user(NewUser {
+                username: &username,
+                password_hash: "hash",
+            })
+            .await
+            .unwrap()
+            .to_string();
+        let user = datasource.get_user(username.clone()).await.unwrap();
+        assert_eq!(user.id, user_id);
+        assert_eq!(user.password_hash, "hash");
+        assert!(matches!(
+            datasource.get_user(format!("{username}-missing")).await,
+            Err(Error::NoEntryFound(_))
+        ));
+
+        let session_hash = format!("session-{}", Uuid::now_v7());
+        datasource
+            .add_session(NewSession {
+                token_hash: &session_hash,
+                user_id: &user_id,
+                expires_at: "2100-01-01T00:00:00+00:00",
+            })
+            .await
+            .unwrap();
+        let session = datasource.get_session(session_hash.clone()).await.unwrap();
+        assert_eq!(session.user.username, username);
+        assert_eq!(session.expires_at, "2100-01-01T00:00:00+00:00");
+        datasource
+            .remove_session(session_hash.clone())
+            .await
+            .unwrap();
+        assert!(matches!(
+            datasource.get_session(session_hash).await,
+            Err(Error::NoEntryFound(_))
+        ));
+
+        let token_hash = format!("token-{}", Uuid::now_v7());
+        let token = datasource
+            .add_api_token(NewApiToken {
+                user_id: &user_id,
+                name: "ci",
+                token_hash: &token_hash,
+                scopes: &[Scope::Upload, Scope::Run],
+                expires_at: "2100-01-01T00:00:00+00:00",
+            })
+            .await
+            .unwrap();
+        let (found, owner) = datasource.get_api_token(token_hash.clone()).await.unwrap();
+        assert_eq!(found.id, token.id);
+        assert_eq!(found.scopes, vec![Scope::Upload, Scope::Run]);
+        assert_eq!(owner.username, username);
+
+        let listed = datasource.list_api_tokens(user_id.clone()).await.unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].name, "ci");
+
+        assert!(matches!(
+            datasource
+                .remove_api_token(token.id.clone(), "someone else".into())
+                .await,
+            Err(Error::NoEntryFound(_))
+        ));
+        datasource
+            .remove_api_token(token.id, user_id.clone())
+            .await
+            .unwrap();
+        assert!(datasource
+            .list_api_tokens(user_id)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn upload_usage() {
+        let Some(datasource) = datasource().await else {
+            return;
+        };
+        let client = format!("ip:{}", Uuid::now_v7());
+        let usage = |day: &str| datasource.get_upload_usage(client.clone(), day.into());
+
+        assert_eq!(usage("2024-01-01").await.unwrap(), 0);
+        datasource
+            .add_upload_usage(client.clone(), "2024-01-01".into(), 10)
+            .await
+            .unwrap();
+        datasource
+            .add_upload_usage(client.clone(), "2024-01-01".into(), 5)
+            .await
+            .unwrap();
+        assert_eq!(usage("2024-01-01").await.unwrap(), 15);
+        assert_eq!(usage("2024-01-02").await.unwrap(), 0);
+
+        let reserve =
+            |bytes| datasource.reserve_upload_usage(client.clone(), "2024-01-01".into(), bytes, 20);
+        assert!(reserve(5).await.unwrap());
+        assert!(!reserve(1).await.unwrap());
+        assert_eq!(usage("2024-01-01").await.unwrap(), 20);
+        datasource
+            .add_upload_usage(client.clone(), "2024-01-01".into(), -5)
+            .await
+            .unwrap();
+        assert!(reserve(5).await.unwrap());
+        assert!(!datasource
+            .reserve_upload_usage(client.clone(), "2024-01-02".into(), 21, 20)
+            .await
+            .unwrap());
+        assert_eq!(usage("2024-01-02").await.unwrap(), 0);
+    }
+}
@@ -1,59 +1,307 @@
-use std::{path::Path, sync::Arc};
+use std::{path::Path, time::Duration};
 
-use super::{model::UploadFileEntry, DataSource};
+use super::{
+    migrations::{self, MigrationStatus},
+    model::{
+        ApiTokenEntry, NewApiToken, NewSession, NewUploadEntry, NewUser, Scope, SessionEntry,
+        UploadFileEntry, UserEntry,
+    },
+    DataSource,
+};
 use crate::{
     error::{self, Error},
     Result,
 };
 use async_trait::async_trait;
-use rusqlite::Connection;
-use tokio::sync::Mutex;
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{Connection, OpenFlags};
 use uuid::Uuid;
 
 #[derive(Clone, Debug)]
-pub struct SqliteDataSource(Arc<Mutex<Connection>>);
+pub struct SqliteDataSource(Pool<SqliteConnectionManager>);
 
 impl SqliteDataSource {
     const UPLOAD_TABLE_NAME: &'static str = "UploadEntriesTable";
     const UPLOAD_T_ID_COL: &'static str = "ID";
     const UPLOAD_T_FILE_NAME_COL: &'static str = "FILE_NAME";
+    const UPLOAD_T_ORIGINAL_NAME_COL: &'static str = "ORIGINAL_NAME";
+    const UPLOAD_T_SIZE_COL: &'static str = "SIZE";
+    const UPLOAD_T_CREATED_AT_COL: &'static str = "CREATED_AT";
+    const UPLOAD_T_OWNER_COL: &'static str = "OWNER";
+    const USER_TABLE_NAME: &'static str = "UsersTable";
+    const USER_T_ID_COL: &'static str = "ID";
+    const USER_T_USERNAME_COL: &'static str = "USERNAME";
+    const USER_T_PASSWORD_HASH_COL: &'static str = "PASSWORD_HASH";
+    const USER_T_CREATED_AT_COL: &'static str = "CREATED_AT";
+    const SESSION_TABLE_NAME: &'static str = "SessionsTable";
+    const SESSION_T_TOKEN_HASH_COL: &'static str = "TOKEN_HASH";
+    const SESSION_T_USER_ID_COL: &'static str = "USER_ID";
+    const SESSION_T_EXPIRES_AT_COL: &'static str = "EXPIRES_AT";
+    const TOKEN_TABLE_NAME: &'static str = "ApiTokensTable";
+    const TOKEN_T_ID_COL: &'static str = "ID";
+    const TOKEN_T_USER_ID_COL: &'static str = "USER_ID";
+    const TOKEN_T_NAME_COL: &'static str = "NAME";
+    const TOKEN_T_TOKEN_HASH_COL: &'static str = "TOKEN_HASH";
+    const TOKEN_T_SCOPES_COL: &'static str = "SCOPES";
+    const TOKEN_T_CREATED_AT_COL: &'static str = "CREATED_AT";
+    const TOKEN_T_EXPIRES_AT_COL: &'static str = "EXPIRES_AT";
+    const USAGE_TABLE_NAME: &'static str = "UploadUsageTable";
+    const USAGE_T_CLIENT_COL: &'static str = "CLIENT";
+    const USAGE_T_DAY_COL: &'static str = "DAY";
+    const USAGE_T_BYTES_COL: &'static str = "BYTES";
+    const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+    pub fn new(path: &Path, pool_size: usize) -> Result<Self> {
+        // WAL lets readers carry on while a writer holds the database, which
+        // is what makes more than one pooled connection worth having.
+        let manager = SqliteConnectionManager::file(path).with_init(|c| {
+            c.busy_timeout(Self::BUSY_TIMEOUT)?;
+            // Off by default in SQLite, sessions and tokens rely on it to go
+            // along with their user.
+            c.pragma_update(None, "foreign_keys", "ON")?;
+            c.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))?;
+            c.pragma_update(None, "synchronous", "NORMAL")
+        });
+        Self::pool(manager, pool_size)
+    }
 
-    pub fn new(c: Connection) -> Self {
-        Self(Arc::new(Mutex::from(c)))
+    /// Opens an existing database without creating or changing anything,
+    /// for reporting on it.
+    pub fn open_read_only(path: &Path, pool_size: usize) -> Result<Self> {
+        let manager = SqliteConnectionManager::file(path)
+            .with_flags(OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX)
+            .with_init(|c| c.busy_timeout(Self::BUSY_TIMEOUT));
+        Self::pool(manager, pool_size)
+    }
+
+    fn pool(manager: SqliteConnectionManager, pool_size: usize) -> Result<Self> {
+        match Pool::builder().max_size(pool_size as u32).build(manager) {
+            Err(e) => Err(Error::DatabaseOperationFailed(e.to_string())),
+            Ok(pool) => Ok(Self(pool)),
+        }
+    }
+
+    /// Runs `f` with a pooled connection on tokio's blocking thread pool, so
+    /// the synchronous rusqlite calls never stall the async workers.
+    async fn with_connection<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T> + Send + 'static,
+    {
+        let pool = self.0.clone();
+        let task = tokio::task::spawn_blocking(move || {
+            let mut con = match pool.get() {
+                Err(e) => return Err(Error::DatabaseOperationFailed(e.to_string())),
+                Ok(c) => c,
+            };
+            f(&mut con)
+        });
+        match task.await {
+            Err(e) => Err(Error::DatabaseOperationFailed(e.to_string())),
+            Ok(result) => result,
+        }
+    }
+
+    /// Token columns in the order `token_row` reads them, prefixed with
+    /// `alias.`.
+    fn token_columns(alias: &str) -> String {
+        [
+            Self::TOKEN_T_ID_COL,
+            Self::TOKEN_T_USER_ID_COL,
+            Self::TOKEN_T_NAME_COL,
+            Self::TOKEN_T_SCOPES_COL,
+            Self::TOKEN_T_CREATED_AT_COL,
+            Self::TOKEN_T_EXPIRES_AT_COL,
+        ]
+        .map(|col| format!("{alias}.{col}"))
+        .join(", ")
+    }
+
+    /// Reads the columns listed by `token_columns`, starting at the first
+    /// one. The scopes are parsed afterwards, see `TokenRow::into_entry`.
+    fn token_row(row: &rusqlite::Row) -> rusqlite::Result<TokenRow> {
+        Ok(TokenRow {
+            id: row.get(0)?,
+            user_id: row.get(1)?,
+            name: row.get(2)?,
+            scopes: row.get(3)?,
+            created_at: row.get(4)?,
+            expires_at: row.get(5)?,
+        })
+    }
+
+    /// Adds the columns the table doesn't have yet, so a migration whose
+    /// version row went missing can run again.
+    fn add_columns(con: &Connection, columns: &[migrations::AddColumn]) -> rusqlite::Result<()> {
+        for column in columns {
+            let exists: i64 = con.query_row(
+                "SELECT COUNT(*) FROM pragma_table_info(?1) WHERE name = ?2 COLLATE NOCASE;",
+                [column.table, column.column],
+                |row| row.get(0),
+            )?;
+            if exists == 0 {
+                con.execute_batch(&column.sql())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An API token as stored, with its scopes as text.
+struct TokenRow {
+    id: String,
+    user_id: String,
+    name: String,
+    scopes: String,
+    created_at: String,
+    expires_at: String,
+}
+
+impl TokenRow {
+    fn into_entry(self) -> Result<ApiTokenEntry> {
+        Ok(ApiTokenEntry {
+            scopes: Scope::split(&self.scopes)
+                .map_err(|e| Error::DatabaseOperationFailed(e.to_string()))?,
+            id: self.id,
+            user_id: self.user_id,
+            name: self.name,
+            created_at: self.created_at,
+            expires_at: self.expires_at,
+        })
     }
 }
 
 #[async_trait]
 impl DataSource for SqliteDataSource {
     async fn init_database(&self) -> Result<()> {
-        let stmt = format!("CREATE TABLE IF NOT EXISTS {t_name} ({id_col} TEXT PRIMARY KEY, {fname_col} TEXT NOT NULL);", t_name=Self::UPLOAD_TABLE_NAME, id_col=Self::UPLOAD_T_ID_COL, fname_col=Self::UPLOAD_T_FILE_NAME_COL);
+        self.with_connection(|con| {
+            if let Err(e) = con.execute(migrations::CREATE_SCHEMA_VERSION_TABLE, ()) {
+                return Err(error::Error::DatabaseOperationFailed(e.to_string()));
+            };
 
-        if let Err(e) = self.0.lock().await.execute(&stmt, ()) {
-            return Err(error::Error::DatabaseOperationFailed(e.to_string()));
-        };
+            let stmt = format!(
+                "SELECT COALESCE(MAX(version), 0) FROM {t_name};",
+                t_name = migrations::SCHEMA_VERSION_TABLE_NAME
+            );
+            let current_version = match con.query_row(&stmt, (), |row| row.get::<usize, u32>(0)) {
+                Err(e) => return Err(Error::DatabaseOperationFailed(e.to_string())),
+                Ok(v) => v,
+            };
 
-        Ok(())
+            let stmt = format!(
+                "INSERT INTO {t_name} (version, name, applied_at) values (?1, ?2, ?3);",
+                t_name = migrations::SCHEMA_VERSION_TABLE_NAME
+            );
+            for migration in migrations::pending(current_version) {
+                // Each migration is applied along with its version row, so a
+                // failure never leaves the schema half way through a version.
+                let tx = match con.transaction() {
+                    Err(e) => return Err(Error::DatabaseOperationFailed(e.to_string())),
+                    Ok(tx) => tx,
+                };
+                if let Err(e) = tx
+                    .execute_batch(migration.sql)
+                    .and_then(|_| Self::add_columns(&tx, migration.add_columns))
+                {
+                    return Err(Error::DatabaseOperationFailed(format!(
+                        "Migration {} ({}) failed: {}",
+                        migration.version, migration.name, e
+                    )));
+                }
+                if let Err(e) = tx.execute(
+                    &stmt,
+                    (migration.version, migration.name, migrations::applied_at()),
+                ) {
+                    return Err(Error::DatabaseOperationFailed(e.to_string()));
+                }
+                if let Err(e) = tx.commit() {
+                    return Err(Error::DatabaseOperationFailed(e.to_string()));
+                }
+            }
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn ping(&self) -> Result<()> {
+        self.with_connection(|con| {
+            con.query_row("SELECT 1;", (), |row| row.get::<_, i64>(0))
+                .map(|_| ())
+                .map_err(|e| Error::DatabaseOperationFailed(e.to_string()))
+        })
+        .await
+    }
+
+    async fn applied_migrations(&self) -> Result<Vec<MigrationStatus>> {
+        self.with_connection(|con| {
+            // Databases no migration ran on yet have no version table, which
+            // is left for `init_database` to create.
+            let has_versions = con.query_row(
+                "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?1;",
+                [migrations::SCHEMA_VERSION_TABLE_NAME],
+                |row| row.get::<_, i64>(0),
+            );
+            match has_versions {
+                Err(e) => return Err(Error::DatabaseOperationFailed(e.to_string())),
+                Ok(0) => return Ok(Vec::new()),
+                Ok(_) => {}
+            }
+
+            let stmt = format!(
+                "SELECT version, name, applied_at FROM {t_name} ORDER BY version;",
+                t_name = migrations::SCHEMA_VERSION_TABLE_NAME
+            );
+            let mut stmt = match con.prepare(&stmt) {
+                Err(e) => return Err(Error::DatabaseOperationFailed(e.to_string())),
+                Ok(s) => s,
+            };
+            let rows = stmt.query_map((), |row| {
+                Ok(MigrationStatus {
+                    version: row.get(0)?,
+                    name: row.get(1)?,
+                    applied_at: Some(row.get(2)?),
+                })
+            });
+            match rows {
+                Err(e) => Err(Error::DatabaseOperationFailed(e.to_string())),
+                Ok(rows) => rows
+                    .collect::<std::result::Result<Vec<_>, _>>()
+                    .map_err(|e| Error::DatabaseOperationFailed(e.to_string())),
+            }
+        })
+        .await
     }
 
-    async fn add_file_entry(&self, file_path: &Path) -> Result<Uuid> {
+    async fn add_file_entry(&self, entry: NewUploadEntry<'_>) -> Result<Uuid> {
         let id_val = Uuid::now_v7();
         let stmt = format!(
-            "INSERT INTO {t_name} ({id_col}, {fname_col}) values (?1, ?2);",
+            "INSERT INTO {t_name} ({id_col}, {fname_col}, {oname_col}, {size_col}, {created_col}, {owner_col}) values (?1, ?2, ?3, ?4, ?5, ?6);",
             t_name = Self::UPLOAD_TABLE_NAME,
             id_col = Self::UPLOAD_T_ID_COL,
             fname_col = Self::UPLOAD_T_FILE_NAME_COL,
+            oname_col = Self::UPLOAD_T_ORIGINAL_NAME_COL,
+            size_col = Self::UPLOAD_T_SIZE_COL,
+            created_col = Self::UPLOAD_T_CREATED_AT_COL,
+            owner_col = Self::UPLOAD_T_OWNER_COL,
+        );
+        let params = (
+            id_val.to_string(),
+            entry.file_path.to_string_lossy().to_string(),
+            entry.original_name.to_string(),
+            entry.size,
+            chrono::Utc::now().to_rfc3339(),
+            entry.owner.map(str::to_string),
         );
 
-        if let Err(e) = self
-            .0
-            .lock()
-            .await
-            .execute(&stmt, (id_val.to_string(), file_path.to_string_lossy()))
-        {
-            return Err(error::Error::DatabaseOperationFailed(e.to_string()));
-        }
-
-        Ok(id_val)
+        self.with_connection(move |con| {
+            if let Err(e) = con.execute(&stmt, params) {
+                return Err(error::Error::DatabaseOperationFailed(e.to_string()));
+            }
+            Ok(id_val)
+        })
+        .await
     }
 
     async fn remove_file_entry(&self, id: String) -> Result<()> {
@@ -63,32 +311,502 @@ impl DataSource for SqliteDataSource {
             id_col = Self::UPLOAD_T_ID_COL
         );
 
-        let _ = self.0.lock().await.execute(&stmt, (id,));
-
-        Ok(())
+        self.with_connection(move |con| {
+            let _ = con.execute(&stmt, (id,));
+            Ok(())
+        })
+        .await
     }
 
     async fn get_file_entry(&self, id: String) -> Result<UploadFileEntry> {
         let stmt = format!(
-            "SELECT {id_col}, {fname_col} FROM {t_name} where id = ?1;",
+            "SELECT {id_col}, {fname_col}, {oname_col}, {size_col}, {created_col}, {owner_col} FROM {t_name} where id = ?1;",
             t_name = Self::UPLOAD_TABLE_NAME,
             id_col = Self::UPLOAD_T_ID_COL,
-            fname_col = Self::UPLOAD_T_FILE_NAME_COL
+            fname_col = Self::UPLOAD_T_FILE_NAME_COL,
+            oname_col = Self::UPLOAD_T_ORIGINAL_NAME_COL,
+            size_col = Self::UPLOAD_T_SIZE_COL,
+            created_col = Self::UPLOAD_T_CREATED_AT_COL,
+            owner_col = Self::UPLOAD_T_OWNER_COL,
         );
 
-        return match self.0.lock().await.query_row(&stmt, (&id,), |row| {
-            Ok((row.get::<usize, String>(0), row.get::<usize, String>(1)))
-        }) {
-            Err(e) => Err(Error::DatabaseOperationFailed(e.to_string())),
-            Ok(v) => {
-                if v.0.is_err() || v.1.is_err() {
-                    Err(Error::NoEntryFound(id))
-                } else {
-                    let id = v.0.unwrap();
-                    let file_path = v.1.unwrap();
-                    Ok(UploadFileEntry { id, file_path })
+        self.with_connection(move |con| {
+            match con.query_row(&stmt, (&id,), |row| {
+                Ok(UploadFileEntry {
+                    id: row.get(0)?,
+                    file_path: row.get(1)?,
+                    original_name: row.get(2)?,
+                    size: row.get(3)?,
+                    created_at: row.get(4)?,
+                    owner: row.get(5)?,
+                })
+            }) {
+                Err(rusqlite::Error::QueryReturnedNoRows) => Err(Error::NoEntryFound(id)),
+                Err(e) => Err(Error::DatabaseOperationFailed(e.to_string())),
+                Ok(entry) => Ok(entry),
+            }
+        })
+        .await
+    }
+
+    async fn add_user(&self, user: NewUser<'_>) -> Result<Uuid> {
+        let id_val = Uuid::now_v7();
+        let stmt = format!(
+            "INSERT INTO {t_name} ({id_col}, {name_col}, {hash_col}, {created_col}) values (?1, ?2, ?3, ?4);",
+            t_name = Self::USER_TABLE_NAME,
+            id_col = Self::USER_T_ID_COL,
+            name_col = Self::USER_T_USERNAME_COL,
+            hash_col = Self::USER_T_PASSWORD_HASH_COL,
+            created_col = Self::USER_T_CREATED_AT_COL,
+        );
+        let params = (
+            id_val.to_string(),
+            user.username.to_string(),
+            user.password_hash.to_string(),
+            chrono::Utc::now().to_rfc3339(),
+        );
+
+        self.with_connection(move |con| {
+            if let Err(e) = con.execute(&stmt, params) {
+                return Err(Error::DatabaseOperationFailed(e.to_string()));
+            }
+            Ok(id_val)
+        })
+        .await
+    }
+
+    async fn get_user(&self, username: String) -> Result<UserEntry> {
+        let stmt = format!(
+            "SELECT {id_col}, {name_col}, {hash_col} FROM {t_name} where {name_col} = ?1;",
+            t_name = Self::USER_TABLE_NAME,
+            id_col = Self::USER_T_ID_COL,
+            name_col = Self::USER_T_USERNAME_COL,
+            hash_col = Self::USER_T_PASSWORD_HASH_COL,
+        );
+
+        self.with_connection(move |con| {
+            match con.query_row(&stmt, (&username,), |row| {
+                Ok(UserEntry {
+                    id: row.get(0)?,
+                    username: row.get(1)?,
+                    password_hash: row.get(2)?,
+                })
+            }) {
+                Err(rusqlite::Error::QueryReturnedNoRows) => Err(Error::NoEntryFound(username)),
+                Err(e) => Err(Error::DatabaseOperationFailed(e.to_string())),
+                Ok(user) => Ok(user),
+            }
+        })
+        .await
+    }
+
+    async fn add_session(&self, session: NewSession<'_>) -> Result<()> {
+        let stmt = format!(
+            "INSERT INTO {t_name} ({token_col}, {user_col}, {expires_col}) values (?1, ?2, ?3);",
+            t_name = Self::SESSION_TABLE_NAME,
+            token_col = Self::SESSION_T_TOKEN_HASH_COL,
+            user_col = Self::SESSION_T_USER_ID_COL,
+            expires_col = Self::SESSION_T_EXPIRES_AT_COL,
+        );
+        let params = (
+            session.token_hash.to_string(),
+            session.user_id.to_string(),
+            session.expires_at.to_string(),
+        );
+
+        self.with_connection(move |con| {
+            if let Err(e) = con.execute(&stmt, params) {
+                return Err(Error::DatabaseOperationFailed(e.to_string()));
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_session(&self, token_hash: String) -> Result<SessionEntry> {
+        let stmt = format!(
+            "SELECT s.{token_col}, s.{expires_col}, u.{id_col}, u.{name_col}, u.{hash_col} FROM {s_name} s JOIN {u_name} u ON u.{id_col} = s.{user_col} where s.{token_col} = ?1;",
+            s_name = Self::SESSION_TABLE_NAME,
+            u_name = Self::USER_TABLE_NAME,
+            token_col = Self::SESSION_T_TOKEN_HASH_COL,
+            user_col = Self::SESSION_T_USER_ID_COL,
+            expires_col = Self::SESSION_T_EXPIRES_AT_COL,
+            id_col = Self::USER_T_ID_COL,
+            name_col = Self::USER_T_USERNAME_COL,
+            hash_col = Self::USER_T_PASSWORD_HASH_COL,
+        );
+
+        self.with_connection(move |con| {
+            match con.query_row(&stmt, (&token_hash,), |row| {
+                Ok(SessionEntry {
+                    token_hash: row.get(0)?,
+                    expires_at: row.get(1)?,
+                    user: UserEntry {
+                        id: row.get(2)?,
+                        username: row.get(3)?,
+                        password_hash: row.get(4)?,
+                    },
+                })
+            }) {
+                Err(rusqlite::Error::QueryReturnedNoRows) => {
+                    Err(Error::NoEntryFound("session".into()))
                 }
+                Err(e) => Err(Error::DatabaseOperationFailed(e.to_string())),
+                Ok(session) => Ok(session),
             }
+        })
+        .await
+    }
+
+    async fn remove_session(&self, token_hash: String) -> Result<()> {
+        let stmt = format!(
+            "DELETE FROM {t_name} where {token_col} = ?1",
+            t_name = Self::SESSION_TABLE_NAME,
+            token_col = Self::SESSION_T_TOKEN_HASH_COL
+        );
+
+        self.with_connection(move |con| {
+            let _ = con.execute(&stmt, (token_hash,));
+            Ok(())
+        })
+        .await
+    }
+
+    async fn add_api_token(&self, token: NewApiToken<'_>) -> Result<ApiTokenEntry> {
+        let entry = ApiTokenEntry {
+            id: Uuid::now_v7().to_string(),
+            user_id: token.user_id.to_string(),
+            name: token.name.to_string(),
+            scopes: token.scopes.to_vec(),
+            created_at: chrono::Utc::now().to_rfc3339(),
+            expires_at: token.expires_at.to_string(),
+        };
+        let stmt = format!(
+            "INSERT INTO {t_name} ({id_col}, {user_col}, {name_col}, {hash_col}, {scopes_col}, {created_col}, {expires_col}) values (?1, ?2, ?3, ?4, ?5, ?6, ?7);",
+            t_name = Self::TOKEN_TABLE_NAME,
+            id_col = Self::TOKEN_T_ID_COL,
+            user_col = Self::TOKEN_T_USER_ID_COL,
+            name_col = Self::TOKEN_T_NAME_COL,
+            hash_col = Self::TOKEN_T_TOKEN_HASH_COL,
+            scopes_col = Self::TOKEN_T_SCOPES_COL,
+            created_col = Self::TOKEN_T_CREATED_AT_COL,
+            expires_col = Self::TOKEN_T_EXPIRES_AT_COL,
+        );
+        let params = (
+            entry.id.clone(),
+            entry.user_id.clone(),
+            entry.name.clone(),
+            token.token_hash.to_string(),
+            Scope::join(token.scopes),
+            entry.created_at.clone(),
+            entry.expires_at.clone(),
+        );
+
+        self.with_connection(move |con| {
+            if let Err(e) = con.execute(&stmt, params) {
+                return Err(Error::DatabaseOperationFailed(e.to_string()));
+            }
+            Ok(entry)
+        })
+        .await
+    }
+
+    async fn get_api_token(&self, token_hash: String) -> Result<(ApiTokenEntry, UserEntry)> {
+        let stmt = format!(
+            "SELECT {token_cols}, u.{name_col}, u.{hash_col} FROM {t_name} t JOIN {u_name} u ON u.{id_col} = t.{user_col} where t.{token_hash_col} = ?1;",
+            token_cols = Self::token_columns("t"),
+            t_name = Self::TOKEN_TABLE_NAME,
+            u_name = Self::USER_TABLE_NAME,
+            user_col = Self::TOKEN_T_USER_ID_COL,
+            token_hash_col = Self::TOKEN_T_TOKEN_HASH_COL,
+            id_col = Self::USER_T_ID_COL,
+            name_col = Self::USER_T_USERNAME_COL,
+            hash_col = Self::USER_T_PASSWORD_HASH_COL,
+        );
+
+        self.with_connection(move |con| {
+            let (token, username, password_hash) =
+                match con.query_row(&stmt, (&token_hash,), |row| {
+                    Ok((Self::token_row(row)?, row.get(6)?, row.get(7)?))
+                }) {
+                    Err(rusqlite::Error::QueryReturnedNoRows) => {
+                        return Err(Error::NoEntryFound("api token".into()))
+                    }
+                    Err(e) => return Err(Error::DatabaseOperationFailed(e.to_string())),
+                    Ok(row) => row,
+                };
+            let user = UserEntry {
+                id: token.user_id.clone(),
+                username,
+                password_hash,
+            };
+            Ok((token.into_entry()?, user))
+        })
+        .await
+    }
+
+    async fn list_api_tokens(&self, user_id: String) -> Result<Vec<ApiTokenEntry>> {
+        let stmt = format!(
+            "SELECT {token_cols} FROM {t_name} t where t.{user_col} = ?1 ORDER BY t.{created_col};",
+            token_cols = Self::token_columns("t"),
+            t_name = Self::TOKEN_TABLE_NAME,
+            user_col = Self::TOKEN_T_USER_ID_COL,
+            created_col = Self::TOKEN_T_CREATED_AT_COL,
+        );
+
+        self.with_connection(move |con| {
+            let mut stmt = match con.prepare(&stmt) {
+                Err(e) => return Err(Error::DatabaseOperationFailed(e.to_string())),
+                Ok(s) => s,
+            };
+            let rows = match stmt.query_map((&user_id,), Self::token_row) {
+                Err(e) => return Err(Error::DatabaseOperationFailed(e.to_string())),
+                Ok(rows) => rows
+                    .collect::<std::result::Result<Vec<_>, _>>()
+                    .map_err(|e| Error::DatabaseOperationFailed(e.to_string()))?,
+            };
+            rows.into_iter().map(TokenRow::into_entry).collect()
+        })
+        .await
+    }
+
+    async fn remove_api_token(&self, id: String, user_id: String) -> Result<()> {
+        let stmt = format!(
+            "DELETE FROM {t_name} where {id_col} = ?1 AND {user_col} = ?2",
+            t_name = Self::TOKEN_TABLE_NAME,
+            id_col = Self::TOKEN_T_ID_COL,
+            user_col = Self::TOKEN_T_USER_ID_COL,
+        );
+
+        self.with_connection(move |con| match con.execute(&stmt, (&id, &user_id)) {
+            Err(e) => Err(Error::DatabaseOperationFailed(e.to_string())),
+            Ok(0) => Err(Error::NoEntryFound(id)),
+            Ok(_) => Ok(()),
+        })
+        .await
+    }
+
+    async fn add_upload_usage(&self, client: String, day: String, bytes: i64) -> Result<()> {
+        let stmt = format!(
+            "INSERT INTO {t_name} ({client_col}, {day_col}, {bytes_col}) values (?1, ?2, ?3) ON CONFLICT ({client_col}, {day_col}) DO UPDATE SET {bytes_col} = {t_name}.{bytes_col} + excluded.{bytes_col};",
+            t_name = Self::USAGE_TABLE_NAME,
+            client_col = Self::USAGE_T_CLIENT_COL,
+            day_col = Self::USAGE_T_DAY_COL,
+            bytes_col = Self::USAGE_T_BYTES_COL,
+        );
+
+        self.with_connection(move |con| {
+            if let Err(e) = con.execute(&stmt, (client, day, bytes)) {
+                return Err(Error::DatabaseOperationFailed(e.to_string()));
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn reserve_upload_usage(
+        &self,
+        client: String,
+        day: String,
+        bytes: i64,
+        quota: i64,
+    ) -> Result<bool> {
+        let stmt = format!(
+            "INSERT INTO {t_name} ({client_col}, {day_col}, {bytes_col}) SELECT ?1, ?2, ?3 WHERE ?3 <= ?4 ON CONFLICT ({client_col}, {day_col}) DO UPDATE SET {bytes_col} = {t_name}.{bytes_col} + excluded.{bytes_col} WHERE {t_name}.{bytes_col} + excluded.{bytes_col} <= ?4;",
+            t_name = Self::USAGE_TABLE_NAME,
+            client_col = Self::USAGE_T_CLIENT_COL,
+            day_col = Self::USAGE_T_DAY_COL,
+            bytes_col = Self::USAGE_T_BYTES_COL,
+        );
+
+        self.with_connection(
+            move |con| match con.execute(&stmt, (client, day, bytes, quota)) {
+                Err(e) => Err(Error::DatabaseOperationFailed(e.to_string())),
+                Ok(changed) => Ok(changed > 0),
+            },
+        )
+        .await
+    }
+
+    async fn get_upload_usage(&self, client: String, day: String) -> Result<i64> {
+        let stmt = format!(
+            "SELECT {bytes_col} FROM {t_name} where {client_col} = ?1 AND {day_col} = ?2;",
+            t_name = Self::USAGE_TABLE_NAME,
+            client_col = Self::USAGE_T_CLIENT_COL,
+            day_col = Self::USAGE_T_DAY_COL,
+            bytes_col = Self::USAGE_T_BYTES_COL,
+        );
+
+        self.with_connection(move |con| {
+            match con.query_row(&stmt, (client, day), |row| row.get(0)) {
+                Err(rusqlite::Error::QueryReturnedNoRows) => Ok(0),
+                Err(e) => Err(Error::DatabaseOperationFailed(e.to_string())),
+                Ok(bytes) => Ok(bytes),
+            }
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// A database file of its own, removed along with its WAL files when
+    /// dropped.
+    struct TestDb(PathBuf);
+
+    impl TestDb {
+        fn new() -> Self {
+            Self(std::env::temp_dir().join(format!("excel_app_test_{}.sqlite", Uuid::now_v7())))
+        }
+    }
+
+    impl Drop for TestDb {
+        fn drop(&mut self) {
+            for suffix in ["", "-wal", "-shm"] {
+                let mut path = self.0.clone().into_os_string();
+                path.push(suffix);
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+
+    fn versions(applied: &[MigrationStatus]) -> Vec<u32> {
+        applied.iter().map(|m| m.version).collect()
+    }
+
+    fn all_versions() -> Vec<u32> {
+        migrations::MIGRATIONS.iter().map(|m| m.version).collect()
+    }
+
+    #[tokio::test]
+    async fn migrations_are_applied_once() {
+        let db = TestDb::new();
+        let datasource = SqliteDataSource::new(&db.0, 1).unwrap();
+        assert!(datasource.applied_migrations().await.unwrap().is_empty());
+
+        datasource.init_database().await.unwrap();
+        datasource.init_database().await.unwrap();
+        assert_eq!(
+            versions(&datasource.applied_migrations().await.unwrap()),
+            all_versions()
+        );
+    }
+
+    #[tokio::test]
+    async fn added_columns_are_not_added_twice() {
+        let db = TestDb::new();
+        let datasource = SqliteDataSource::new(&db.0, 1).unwrap();
+        datasource.init_database().await.unwrap();
+        // As if the version rows were lost, the columns of migration 2 are
+        // already there when it runs again.
+        datasource
+            .with_connection(|con| {
+                con.execute("DELETE FROM schema_version WHERE version >= 2;", ())
+                    .map_err(|e| Error::DatabaseOperationFailed(e.to_string()))
+            })
+            .await
+            .unwrap();
+
+        datasource.init_database().await.unwrap();
+        assert_eq!(
+            versions(&datasource.applied_migrations().await.unwrap()),
+            all_versions()
+        );
+    }
+
+    #[tokio::test]
+    async fn status_does_not_create_the_version_table() {
+        let db = TestDb::new();
+        SqliteDataSource::new(&db.0, 1)
+            .unwrap()
+            .ping()
+            .await
+            .unwrap();
+
+        let datasource = SqliteDataSource::open_read_only(&db.0, 1).unwrap();
+        assert!(datasource.applied_migrations().await.unwrap().is_empty());
+        assert!(datasource.applied_migrations().await.unwrap().is_empty());
+        assert!(matches!(
+            datasource.init_database().await,
+            Err(Error::DatabaseOperationFailed(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn sessions_go_along_with_their_user() {
+        let db = TestDb::new();
+        let datasource = SqliteDataSource::new(&db.0, 1).unwrap();
+        datasource.init_database().await.unwrap();
+        let user_id = datasource
+            .add_user(NewUser {
+                username: "alice",
+                password_hash: "hash",
+            })
+            .await
+            .unwrap()
+            .to_string();
+        datasource
+            .add_session(NewSession {
+                token_hash: "session",
+                user_id: &user_id,
+                expires_at: "2100-01-01T00:00:00+00:00",
+            })
+            .await
+            .unwrap();
+
+        let deleted_user = user_id.clone();
+        datasource
+            .with_connection(move |con| {
+                con.execute("DELETE FROM UsersTable WHERE ID = ?1;", [deleted_user])
+                    .map_err(|e| Error::DatabaseOperationFailed(e.to_string()))
+            })
+            .await
+            .unwrap();
+        assert!(matches!(
+            datasource.get_session("session".into()).await,
+            Err(Error::NoEntryFound(_))
+        ));
+
+        let missing_user = datasource
+            .add_session(NewSession {
+                token_hash: "other",
+                user_id: &user_id,
+                expires_at: "2100-01-01T00:00:00+00:00",
+            })
+            .await;
+        assert!(matches!(
+            missing_user,
+            Err(Error::DatabaseOperationFailed(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn upload_usage_reservations() {
+        let db = TestDb::new();
+        let datasource = SqliteDataSource::new(&db.0, 1).unwrap();
+        datasource.init_database().await.unwrap();
+        let client = String::from("ip:127.0.0.1");
+        let usage = |day: &str| datasource.get_upload_usage(client.clone(), day.into());
+        let reserve = |day: &str, bytes| {
+            datasource.reserve_upload_usage(client.clone(), day.into(), bytes, 20)
         };
+
+        assert!(!reserve("2024-01-01", 21).await.unwrap());
+        assert_eq!(usage("2024-01-01").await.unwrap(), 0);
+        assert!(reserve("2024-01-01", 15).await.unwrap());
+        assert!(reserve("2024-01-01", 5).await.unwrap());
+        assert!(!reserve("2024-01-01", 1).await.unwrap());
+        assert_eq!(usage("2024-01-01").await.unwrap(), 20);
+
+        datasource
+            .add_upload_usage(client.clone(), "2024-01-01".into(), -5)
+            .await
+            .unwrap();
+        assert!(reserve("2024-01-01", 5).await.unwrap());
+        assert!(reserve("2024-01-02", 20).await.unwrap());
     }
 }
@@ -0,0 +1,86 @@
+//! Date serial numbers and the `mmddyy` dates checked by `checkDate`.
+
+use chrono::{Duration, NaiveDate, NaiveDateTime, NaiveTime};
+
+/// The `mmddyy` format checked by [`check_date`].
+pub const MMDDYY: &str = "mmddyy";
+
+/// Day 0 of the 1900 date system. Starting on the 30th rather than the 31st
+/// of December makes up for the 29th of February 1900 Excel counts.
+fn epoch() -> NaiveDateTime {
+    NaiveDate::from_ymd_opt(1899, 12, 30)
+        .unwrap()
+        .and_time(NaiveTime::MIN)
+}
+
+/// The serial number Excel stores `dt` as.
+pub fn to_serial(dt: NaiveDateTime) -> f64 {
+    (dt - epoch()).num_milliseconds() as f64 / 86_400_000.0
+}
+
+/// The date and time of an Excel serial number, to the millisecond.
+pub fn from_serial(serial: f64) -> Option<NaiveDateTime> {
+    if !serial.is_finite() {
+        return None;
+    }
+    let millis = (serial * 86_400_000.0).round() as i64;
+    epoch().checked_add_signed(Duration::milliseconds(millis))
+}
+
+/// `2024-01-31` for midnight, `2024-01-31T10:30:00` otherwise.
+pub fn to_iso(dt: NaiveDateTime) -> String {
+    if dt.time() == NaiveTime::MIN {
+        dt.format("%Y-%m-%d").to_string()
+    } else {
+        dt.format("%Y-%m-%dT%H:%M:%S").to_string()
+    }
+}
+
+/// Why `value` isn't a `mmddyy` date, `None` if it is one.
+pub fn check_date(value: &str, col_idx: u32, row_idx: u32) -> Option<String> {
+    if value.len() < 6 {
+        return Some(format!(
+            "Invalid date value at column: {}, row: {}",
+            col_idx, row_idx
+        ));
+    }
+    let month = value.get(0..2).unwrap_or_default();
+    match month.parse::<u32>() {
+        Ok(month_val) if (1..=12).contains(&month_val) => {}
+        _ => {
+            return Some(format!(
+                "Invalid month value for date field. Value = {}, column: {}, row: {}",
+                month, col_idx, row_idx
+            ))
+        }
+    }
+    let day = value.get(2..4).unwrap_or_default();
+    match day.parse::<u32>() {
+        Ok(day_val) if (1..=31).contains(&day_val) => {}
+        _ => {
+            return Some(format!(
+                "Invalid day value for date field. Value = {}, column: {}, row: {}",
+                day, col_idx, row_idx
+            ))
+        }
+    }
+    let year = value.get(4..6).unwrap_or_default();
+    if year.parse::<u32>().is_err() {
+        return Some(format!(
+            "Invalid year value for date field. Value = {}, column: {}, row: {}",
+            year, col_idx, row_idx
+        ));
+    }
+    None
+}
+
+/// Parses a `mmddyy` date [`check_date`] accepts, or a `yyyy-mm-dd` one.
+pub fn parse_date(text: &str) -> Option<NaiveDate> {
+    if text.len() == 6 && text.bytes().all(|b| b.is_ascii_digit()) {
+        if check_date(text, 0, 0).is_some() {
+            return None;
+        }
+        return NaiveDate::parse_from_str(text, "%m%d%y").ok();
+    }
+    NaiveDate::parse_from_str(text, "%Y-%m-%d").ok()
+}
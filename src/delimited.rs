@@ -0,0 +1,484 @@
+//! Delimiter separated text files, CSV and TSV.
+//!
+//! Files come from all sorts of upstream systems, so the encoding, the
+//! delimiter and the quote character are detected from the content rather
+//! than from the file name. The name only matters for files without a
+//! consistent delimiter, which are taken for text only with a text
+//! extension.
+
+use std::{
+    ffi::OsStr,
+    fs::File,
+    io::{BufReader, Cursor, Read, Write},
+    path::Path,
+};
+
+use encoding_rs::{Encoding, UTF_8};
+use encoding_rs_io::{DecodeReaderBytes, DecodeReaderBytesBuilder};
+
+use crate::{
+    error::Error,
+    xlsx::{Row, MAX_COLUMNS, MAX_ROWS},
+    Result,
+};
+
+const CANDIDATE_DELIMITERS: [u8; 4] = [b',', b'\t', b';', b'|'];
+const CANDIDATE_QUOTES: [u8; 2] = [b'"', b'\''];
+/// Bytes read ahead to detect the encoding, the delimiter and the quote
+/// character.
+const SAMPLE_BYTES: u64 = 64 * 1024;
+/// Records looked at to detect the delimiter and quote character.
+const SAMPLE_RECORDS: usize = 50;
+/// Files with these extensions are read as delimited text even when no
+/// delimiter is found on every record, single column files for one.
+const TEXT_EXTENSIONS: [&str; 3] = ["csv", "tsv", "txt"];
+
+/// How the values of a delimited file are separated and quoted. Output is
+/// always written as UTF-8, whatever the encoding of the input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Dialect {
+    pub delimiter: u8,
+    pub quote: u8,
+}
+
+impl Default for Dialect {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            quote: b'"',
+        }
+    }
+}
+
+/// A delimited file read into rows, the first record being the header.
+pub struct DelimitedFile {
+    pub dialect: Dialect,
+    pub header: Row,
+    pub rows: Vec<Row>,
+}
+
+pub fn read(path: &Path) -> Result<DelimitedFile> {
+    read_from(open(path)?, extension(path))
+}
+
+/// [`read`] for text in memory or in a stream, with the extension of the
+/// file it came from if there was one.
+pub fn read_from<R: Read>(input: R, extension: Option<&str>) -> Result<DelimitedFile> {
+    let (mut records, dialect) = records(input, extension)?;
+
+    let header = match records.next() {
+        None => Row::default(),
+        Some(record) => to_row(record)?,
+    };
+    let rows = records
+        .take(MAX_ROWS as usize)
+        .map(to_row)
+        .collect::<Result<Vec<Row>>>()?;
+    if rows.len() == MAX_ROWS as usize {
+        return Err(invalid(format!(
+            "The file has more records than the {MAX_ROWS} rows of a sheet"
+        )));
+    }
+
+    Ok(DelimitedFile {
+        dialect,
+        header,
+        rows,
+    })
+}
+
+/// Reads only the first record of the file.
+pub fn read_header(path: &Path) -> Result<Row> {
+    match records(open(path)?, extension(path))?.0.next() {
+        None => Ok(Row::default()),
+        Some(record) => to_row(record),
+    }
+}
+
+/// How the text is written, failing for text that doesn't look delimited.
+/// Only the start of the input is read, `extension` is the one of the file
+/// it came from if there was one.
+pub fn dialect_from<R: Read>(input: &mut R, extension: Option<&str>) -> Result<Dialect> {
+    Sample::read(input)?.dialect(extension)
+}
+
+/// A record as a row, refusing records with more values than a sheet has
+/// columns.
+fn to_row(record: csv::Result<csv::StringRecord>) -> Result<Row> {
+    let record = record.map_err(invalid)?;
+    if record.len() > MAX_COLUMNS as usize {
+        return Err(invalid(format!(
+            "A record has {} values, more than the {MAX_COLUMNS} columns of a sheet",
+            record.len()
+        )));
+    }
+    Ok(Row::from_text(record.iter()))
+}
+
+/// Writes `header` and `rows` as UTF-8 text with the delimiter and quote
+/// character of `dialect`, quoting values only where needed.
+pub fn write<W: Write>(
+    header: &Row,
+    rows: &[Row],
+    last_col: u32,
+    dialect: &Dialect,
+    out: W,
+) -> Result<()> {
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(dialect.delimiter)
+        .quote(dialect.quote)
+        .from_writer(out);
+    for row in std::iter::once(header).chain(rows) {
+        writer
+            .write_record((1..=last_col).map(|col| row.value(col).into_owned()))
+            .map_err(|e| Error::IOError(e.to_string()))?;
+    }
+    writer.flush().map_err(|e| Error::IOError(e.to_string()))
+}
+
+type Decoded<R> = DecodeReaderBytes<std::io::Chain<Cursor<Vec<u8>>, R>, Vec<u8>>;
+
+/// The records of the input, decoded to UTF-8 as they are read.
+fn records<R: Read>(
+    mut input: R,
+    extension: Option<&str>,
+) -> Result<(csv::StringRecordsIntoIter<Decoded<R>>, Dialect)> {
+    let sample = Sample::read(&mut input)?;
+    let dialect = sample.dialect(extension)?;
+    let records = reader(sample.decoder(input), &dialect).into_records();
+    Ok((records, dialect))
+}
+
+fn reader<R: Read>(input: R, dialect: &Dialect) -> csv::Reader<R> {
+    csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .delimiter(dialect.delimiter)
+        .quote(dialect.quote)
+        .from_reader(input)
+}
+
+fn open(path: &Path) -> Result<BufReader<File>> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|e| Error::IOError(e.to_string()))
+}
+
+fn extension(path: &Path) -> Option<&str> {
+    path.extension().and_then(OsStr::to_str)
+}
+
+/// The start of a file, read ahead to tell how the rest is written.
+struct Sample {
+    bytes: Vec<u8>,
+    /// The whole file fits in the sample.
+    complete: bool,
+    encoding: &'static Encoding,
+    text: String,
+}
+
+impl Sample {
+    fn read<R: Read>(input: &mut R) -> Result<Self> {
+        let mut bytes = Vec::new();
+        input
+            .take(SAMPLE_BYTES)
+            .read_to_end(&mut bytes)
+            .map_err(|e| Error::IOError(e.to_string()))?;
+        let complete = (bytes.len() as u64) < SAMPLE_BYTES;
+        let encoding = detect_encoding(&bytes, complete);
+        let text = encoding.decode_with_bom_removal(&bytes).0.into_owned();
+        if text.contains('\0') {
+            return Err(invalid("Not a workbook or a delimited text file"));
+        }
+        Ok(Self {
+            bytes,
+            complete,
+            encoding,
+            text,
+        })
+    }
+
+    /// Decodes the sample followed by the rest of the input.
+    fn decoder<R: Read>(self, rest: R) -> Decoded<R> {
+        DecodeReaderBytesBuilder::new()
+            .encoding(Some(self.encoding))
+            .bom_override(true)
+            .build(Cursor::new(self.bytes).chain(rest))
+    }
+
+    /// The dialect of the sample. Without a delimiter found the same number
+    /// of times on every sampled record, only files with a text extension
+    /// are taken for delimited text.
+    fn dialect(&self, extension: Option<&str>) -> Result<Dialect> {
+        if let Some(dialect) = self.detect() {
+            return Ok(dialect);
+        }
+        let is_text = extension.is_some_and(|extension| {
+            TEXT_EXTENSIONS
+                .iter()
+                .any(|text| extension.eq_ignore_ascii_case(text))
+        });
+        if !is_text {
+            return Err(invalid("Not a workbook or a delimited text file"));
+        }
+
+        // Falls back to the candidate splitting off the most values, then to
+        // the delimiter of the extension.
+        let delimiter = CANDIDATE_DELIMITERS
+            .into_iter()
+            .filter_map(|d| {
+                let counts = self.field_counts(d, b'"');
+                let total: usize = counts.iter().sum();
+                (total > counts.len()).then_some((d, total))
+            })
+            .max_by_key(|(_, total)| *total)
+            .map(|(d, _)| d);
+        let delimiter = match delimiter {
+            Some(delimiter) => delimiter,
+            None if extension.is_some_and(|e| e.eq_ignore_ascii_case("tsv")) => b'\t',
+            None => b',',
+        };
+        Ok(Dialect {
+            delimiter,
+            quote: self.detect_quote(delimiter),
+        })
+    }
+
+    /// Picks the candidate delimiter splitting every sampled record into the
+    /// same number of values, more than one, preferring the one splitting
+    /// off the most. When both quote characters give one, the quote
+    /// character more values start with wins.
+    fn detect(&self) -> Option<Dialect> {
+        let consistent = |quote: u8| {
+            CANDIDATE_DELIMITERS
+                .into_iter()
+                .filter_map(|delimiter| {
+                    let counts = self.field_counts(delimiter, quote);
+                    let first = *counts.first()?;
+                    (first > 1 && counts.iter().all(|n| *n == first)).then_some((delimiter, first))
+                })
+                .max_by_key(|(_, fields)| *fields)
+                .map(|(delimiter, _)| Dialect { delimiter, quote })
+        };
+        let [double, single] = CANDIDATE_QUOTES.map(consistent);
+        match (double, single) {
+            (Some(double), Some(single)) => {
+                if self.detect_quote(single.delimiter) == b'\'' {
+                    Some(single)
+                } else {
+                    Some(double)
+                }
+            }
+            (double, single) => double.or(single),
+        }
+    }
+
+    /// Number of values of each sampled record. The last record of a sample
+    /// that doesn't hold the whole file may be cut short, it is left out.
+    fn field_counts(&self, delimiter: u8, quote: u8) -> Vec<usize> {
+        let mut counts: Vec<usize> = reader(self.text.as_bytes(), &Dialect { delimiter, quote })
+            .into_records()
+            .map_while(|record| record.ok())
+            .take(SAMPLE_RECORDS + 1)
+            .map(|record| record.len())
+            .collect();
+        if !self.complete && counts.len() <= SAMPLE_RECORDS {
+            counts.pop();
+        }
+        counts.truncate(SAMPLE_RECORDS);
+        counts
+    }
+
+    /// Single quotes are used when more values start with one than with a
+    /// double quote.
+    fn detect_quote(&self, delimiter: u8) -> u8 {
+        let mut raw = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .delimiter(delimiter)
+            .quoting(false)
+            .from_reader(self.text.as_bytes());
+        let (mut double, mut single) = (0, 0);
+        for record in raw.byte_records().map_while(|record| record.ok()) {
+            for field in record.iter() {
+                match field.trim_ascii_start().first() {
+                    Some(b'"') => double += 1,
+                    Some(b'\'') => single += 1,
+                    _ => {}
+                }
+            }
+        }
+        if single > double {
+            b'\''
+        } else {
+            b'"'
+        }
+    }
+}
+
+/// The encoding of its byte order mark, UTF-8 if the bytes are valid UTF-8,
+/// or else the most likely legacy encoding. A sample cut short may end in
+/// the middle of a character.
+fn detect_encoding(bytes: &[u8], complete: bool) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+        return encoding;
+    }
+    match std::str::from_utf8(bytes) {
+        Ok(_) => return UTF_8,
+        Err(e) if !complete && e.error_len().is_none() => return UTF_8,
+        Err(_) => {}
+    }
+    let mut detector = chardetng::EncodingDetector::new();
+    detector.feed(bytes, complete);
+    detector.guess(None, true)
+}
+
+fn invalid(e: impl std::fmt::Display) -> Error {
+    Error::InValidExcelFile(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// A file removed when dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(extension: &str, bytes: &[u8]) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "excel_app_test_{}{extension}",
+                uuid::Uuid::now_v7()
+            ));
+            std::fs::write(&path, bytes).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn dialect(path: &Path) -> Result<Dialect> {
+        dialect_from(&mut open(path)?, extension(path))
+    }
+
+    fn values(row: &Row, last_col: u32) -> Vec<String> {
+        (1..=last_col)
+            .map(|col| row.value(col).into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn quoted_newlines_are_part_of_the_value() {
+        let file = TempFile::new("", b"id;note\n1;\"first\nsecond; line\"\n2;plain\n");
+        let read = read(&file.0).unwrap();
+        assert_eq!(
+            read.dialect,
+            Dialect {
+                delimiter: b';',
+                quote: b'"'
+            }
+        );
+        assert_eq!(values(&read.header, 2), ["id", "note"]);
+        assert_eq!(read.rows.len(), 2);
+        assert_eq!(values(&read.rows[0], 2), ["1", "first\nsecond; line"]);
+    }
+
+    #[test]
+    fn delimiters() {
+        for delimiter in CANDIDATE_DELIMITERS {
+            let d = delimiter as char;
+            let text = format!("a{d}b{d}c\n1{d}2{d}3\n");
+            let file = TempFile::new("", text.as_bytes());
+            assert_eq!(dialect(&file.0).unwrap().delimiter, delimiter);
+        }
+    }
+
+    #[test]
+    fn single_quotes() {
+        let file = TempFile::new("", b"'a,b',c\n'1,2',3\n");
+        let read = read(&file.0).unwrap();
+        assert_eq!(
+            read.dialect,
+            Dialect {
+                delimiter: b',',
+                quote: b'\''
+            }
+        );
+        assert_eq!(values(&read.header, 2), ["a,b", "c"]);
+    }
+
+    #[test]
+    fn encodings() {
+        let mut utf16 = vec![0xFF, 0xFE];
+        utf16.extend(
+            "név,ár\nkés,120\n"
+                .encode_utf16()
+                .flat_map(u16::to_le_bytes),
+        );
+        let file = TempFile::new("", &utf16);
+        assert_eq!(values(&read_header(&file.0).unwrap(), 2), ["név", "ár"]);
+
+        let (latin, _, _) = encoding_rs::WINDOWS_1252.encode("name,café\nthé,crème brûlée\n");
+        let file = TempFile::new("", &latin);
+        let read = read(&file.0).unwrap();
+        assert_eq!(values(&read.header, 2), ["name", "café"]);
+        assert_eq!(values(&read.rows[0], 2), ["thé", "crème brûlée"]);
+    }
+
+    #[test]
+    fn records_past_the_sample_are_decoded() {
+        let mut text = String::from("id,name\n");
+        while text.len() < 2 * SAMPLE_BYTES as usize {
+            text.push_str("1,Ünal\n");
+        }
+        let file = TempFile::new("", text.as_bytes());
+        let read = read(&file.0).unwrap();
+        assert!(read.rows.iter().all(|row| values(row, 2) == ["1", "Ünal"]));
+    }
+
+    #[test]
+    fn inconsistent_text_needs_a_text_extension() {
+        let text = b"a single column\nwith, a comma\n";
+        assert!(matches!(
+            dialect(&TempFile::new(".bin", text).0),
+            Err(Error::InValidExcelFile(_))
+        ));
+        let file = TempFile::new(".CSV", text);
+        assert_eq!(dialect(&file.0).unwrap(), Dialect::default());
+        assert_eq!(read(&file.0).unwrap().rows.len(), 1);
+        let file = TempFile::new(".tsv", b"one\ntwo\n");
+        assert_eq!(dialect(&file.0).unwrap().delimiter, b'\t');
+    }
+
+    #[test]
+    fn binary_files_are_refused() {
+        let file = TempFile::new(".csv", b"a,b\n\x00\x01\x02,c\n");
+        assert!(matches!(dialect(&file.0), Err(Error::InValidExcelFile(_))));
+    }
+
+    #[test]
+    fn refuses_records_past_the_last_column() {
+        let text = format!("{}\n", vec!["x"; MAX_COLUMNS as usize + 1].join(","));
+        let file = TempFile::new(".csv", text.as_bytes());
+        assert!(matches!(read(&file.0), Err(Error::InValidExcelFile(_))));
+    }
+
+    #[test]
+    fn writes_with_the_quote_of_the_dialect() {
+        let header = Row::from_text(["a", "b"]);
+        let rows = [Row::from_text(["x;y", "it's"])];
+        let mut out = Vec::new();
+        let dialect = Dialect {
+            delimiter: b';',
+            quote: b'\'',
+        };
+        write(&header, &rows, 2, &dialect, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "a;b\n'x;y';'it''s'\n");
+    }
+}
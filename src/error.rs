@@ -1,6 +1,6 @@
 use axum::{
     body::Body,
-    http::{Response, StatusCode},
+    http::{header::RETRY_AFTER, Response, StatusCode},
     response::IntoResponse,
 };
 use thiserror::Error;
@@ -24,17 +24,45 @@ pub enum Error {
     InvalidPayload(String),
     #[error("IO Error: {0}")]
     IOError(String),
+    #[error("Unsupported feature: {0}")]
+    Unsupported(String),
+    #[error("Too many jobs are running, try again later")]
+    ServerBusy,
+    #[error("{message}")]
+    RateLimited {
+        message: String,
+        /// Seconds until the request may succeed, sent as `Retry-After`.
+        retry_after: u64,
+    },
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
     #[error("{0}")]
     Generic(String),
 }
 
 impl IntoResponse for Error {
     fn into_response(self) -> axum::response::Response {
-        Response::builder()
-            .status(StatusCode::INTERNAL_SERVER_ERROR)
-            .header("Content-Type", "text/plain")
-            .body(Body::from(format!("{self}")))
-            .unwrap()
+        let status = match self {
+            Error::ServerBusy | Error::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+            Error::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Error::Forbidden(_) => StatusCode::FORBIDDEN,
+            Error::NoEntryFound(_) => StatusCode::NOT_FOUND,
+            Error::MultipartFormError(_)
+            | Error::NoFileUploaded
+            | Error::InValidExcelFile(_)
+            | Error::InvalidPayload(_)
+            | Error::Unsupported(_) => StatusCode::BAD_REQUEST,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        let mut response = Response::builder()
+            .status(status)
+            .header("Content-Type", "text/plain");
+        if let Error::RateLimited { retry_after, .. } = &self {
+            response = response.header(RETRY_AFTER, retry_after.to_string());
+        }
+        response.body(Body::from(format!("{self}"))).unwrap()
     }
 }
 
@@ -43,3 +71,40 @@ impl From<axum::extract::multipart::MultipartError> for Error {
         Self::MultipartFormError(format!("Error parsing multipart formdata: {}", value).to_string())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn statuses() {
+        let status = |error: Error| error.into_response().status();
+        assert_eq!(
+            status(Error::NoEntryFound("id".into())),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            status(Error::InvalidPayload("sortCol".into())),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            status(Error::InValidExcelFile("dates".into())),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            status(Error::Forbidden("scope".into())),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status(Error::Generic("panicked".into())),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+        let response = Error::RateLimited {
+            message: "slow down".into(),
+            retry_after: 7,
+        }
+        .into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[RETRY_AFTER], "7");
+    }
+}
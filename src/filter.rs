@@ -0,0 +1,234 @@
+//! Row filters of a job, applied before the rows are sorted and highlighted.
+
+use chrono::NaiveDate;
+
+use crate::{
+    dates,
+    preview::StyleCache,
+    processor::{FilterAction, FilterCondition, FilterLogic, JobSpec, RowFilter},
+    xlsx::{Cell, CellValue, Row, Workbook},
+    Result,
+};
+
+/// Name of the sheet rows that don't pass the filters are moved to.
+const FILTERED_SHEET_NAME: &str = "Filtered";
+
+/// Drops, moves or hides the rows that don't pass the filters of the job.
+/// Hidden rows stay in the sheet and are sorted and highlighted with the
+/// others.
+pub fn apply(workbook: &mut Workbook, spec: &JobSpec) -> Result<()> {
+    let filters = spec.filters();
+    if filters.is_empty() {
+        return Ok(());
+    }
+
+    let keep = {
+        let mut styles = StyleCache::new(&workbook.styles);
+        workbook
+            .rows
+            .iter()
+            .map(|row| passes(row, filters, spec.filter_logic(), &mut styles))
+            .collect::<Result<Vec<bool>>>()?
+    };
+
+    match spec.filter_action() {
+        FilterAction::Drop => {
+            let mut keep = keep.into_iter();
+            workbook.rows.retain(|_| keep.next().unwrap_or(true));
+        }
+        FilterAction::Sheet => {
+            let mut filtered = Vec::new();
+            let mut keep = keep.into_iter();
+            workbook.rows.retain_mut(|row| {
+                if keep.next().unwrap_or(true) {
+                    return true;
+                }
+                filtered.push(std::mem::take(row));
+                false
+            });
+            if !filtered.is_empty() {
+                let header = workbook.header.clone();
+                workbook.add_sheet(FILTERED_SHEET_NAME, header, filtered);
+            }
+        }
+        FilterAction::Hide => {
+            for (row, keep) in workbook.rows.iter_mut().zip(keep) {
+                if !keep {
+                    row.set_hidden(true);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn passes(
+    row: &Row,
+    filters: &[RowFilter],
+    logic: FilterLogic,
+    styles: &mut StyleCache,
+) -> Result<bool> {
+    for filter in filters {
+        let passed = matches(row.cell(filter.column_index), &filter.condition, styles)?;
+        match logic {
+            FilterLogic::And if !passed => return Ok(false),
+            FilterLogic::Or if passed => return Ok(true),
+            _ => {}
+        }
+    }
+    Ok(logic == FilterLogic::And)
+}
+
+fn matches(
+    cell: Option<&Cell>,
+    condition: &FilterCondition,
+    styles: &mut StyleCache,
+) -> Result<bool> {
+    let text = cell.map(|cell| cell.value.text()).unwrap_or_default();
+    let matched = match condition {
+        FilterCondition::Equals(value) => text == value.as_str(),
+        FilterCondition::Contains(value) => text.contains(value.as_str()),
+        FilterCondition::Range { min, max } => match text.trim().parse::<f64>() {
+            Err(_) => false,
+            Ok(number) => {
+                min.is_none_or(|min| number >= min) && max.is_none_or(|max| number <= max)
+            }
+        },
+        FilterCondition::Regex(regex) => regex.is_match(&text),
+        FilterCondition::Before(date) => cell_date(cell, styles)?.is_some_and(|d| d < *date),
+        FilterCondition::After(date) => cell_date(cell, styles)?.is_some_and(|d| d > *date),
+        FilterCondition::Blank => text.trim().is_empty(),
+        FilterCondition::NotBlank => !text.trim().is_empty(),
+    };
+    Ok(matched)
+}
+
+/// The date of Excel dates, ISO dates and text dates in the formats filters
+/// take.
+fn cell_date(cell: Option<&Cell>, styles: &mut StyleCache) -> Result<Option<NaiveDate>> {
+    let Some(cell) = cell else {
+        return Ok(None);
+    };
+    let date = match &cell.value {
+        CellValue::Number(text) if styles.get(cell.style)?.0 => text
+            .parse()
+            .ok()
+            .and_then(dates::from_serial)
+            .map(|dt| dt.date()),
+        CellValue::Date(text) => text
+            .get(..10)
+            .and_then(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()),
+        other => dates::parse_date(other.text().trim()),
+    };
+    Ok(date)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xlsx::DATE_STYLE;
+
+    fn workbook() -> Workbook {
+        let rows = [
+            ["north", "12", "2024-01-15"],
+            ["south", "7.5", "031524"],
+            ["north east", "", "not a date"],
+            ["west", "n/a", ""],
+        ]
+        .into_iter()
+        .map(Row::from_text)
+        .collect();
+        Workbook::from_rows(Row::from_text(["region", "amount", "date"]), rows).unwrap()
+    }
+
+    /// Values of the first column of the rows passing `filters`.
+    fn passing(filters: &[&str], logic: FilterLogic) -> Vec<String> {
+        let workbook = workbook();
+        let filters: Vec<RowFilter> = filters
+            .iter()
+            .map(|f| RowFilter::parse(f).unwrap())
+            .collect();
+        let mut styles = StyleCache::new(&workbook.styles);
+        workbook
+            .rows
+            .iter()
+            .filter(|row| passes(row, &filters, logic, &mut styles).unwrap())
+            .map(|row| row.value(1).into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn conditions() {
+        let and = FilterLogic::And;
+        assert_eq!(passing(&["1,eq,north"], and), ["north"]);
+        assert_eq!(passing(&["1,contains,north"], and), ["north", "north east"]);
+        assert_eq!(passing(&["2,range,7.5,"], and), ["north", "south"]);
+        assert_eq!(passing(&["2,range,,10"], and), ["south"]);
+        assert_eq!(passing(&["1,regex,^(south|west)$"], and), ["south", "west"]);
+        assert_eq!(passing(&["3,before,2024-02-01"], and), ["north"]);
+        assert_eq!(passing(&["3,after,013124"], and), ["south"]);
+        assert_eq!(passing(&["2,blank,"], and), ["north east"]);
+        assert_eq!(
+            passing(&["3,notblank,"], and),
+            ["north", "south", "north east"]
+        );
+    }
+
+    #[test]
+    fn logic() {
+        let filters = ["1,contains,north", "2,range,10,"];
+        assert_eq!(passing(&filters, FilterLogic::And), ["north"]);
+        assert_eq!(passing(&filters, FilterLogic::Or), ["north", "north east"]);
+    }
+
+    #[test]
+    fn excel_dates() {
+        let workbook = workbook();
+        let mut styles = StyleCache::new(&workbook.styles);
+        let date = Cell::new(1, DATE_STYLE, CellValue::Number("45306".into()));
+        let number = Cell::new(1, 0, CellValue::Number("45306".into()));
+        let after = RowFilter::parse("1,after,2024-01-01").unwrap().condition;
+        assert!(matches(Some(&date), &after, &mut styles).unwrap());
+        assert!(!matches(Some(&number), &after, &mut styles).unwrap());
+        assert!(!matches(None, &after, &mut styles).unwrap());
+    }
+
+    #[test]
+    fn actions() {
+        let spec = |filter: &str, action| {
+            JobSpec::builder()
+                .filter(RowFilter::parse(filter).unwrap())
+                .filter_action(action)
+                .build()
+                .unwrap()
+        };
+
+        let mut dropped = workbook();
+        apply(&mut dropped, &spec("1,contains,north", FilterAction::Drop)).unwrap();
+        assert_eq!(dropped.rows.len(), 2);
+
+        let mut moved = workbook();
+        apply(&mut moved, &spec("1,contains,north", FilterAction::Sheet)).unwrap();
+        assert_eq!(moved.rows.len(), 2);
+        assert!(has_filtered_sheet(&moved));
+
+        // Nothing to move, no sheet is added.
+        let mut kept = workbook();
+        apply(&mut kept, &spec("1,notblank,", FilterAction::Sheet)).unwrap();
+        assert_eq!(kept.rows.len(), 4);
+        assert!(!has_filtered_sheet(&kept));
+
+        let mut hidden = workbook();
+        apply(&mut hidden, &spec("1,contains,north", FilterAction::Hide)).unwrap();
+        assert_eq!(hidden.rows.len(), 4);
+    }
+
+    fn has_filtered_sheet(workbook: &Workbook) -> bool {
+        let cursor = workbook.write(std::io::Cursor::new(Vec::new())).unwrap();
+        let mut archive = zip::ZipArchive::new(cursor).unwrap();
+        let mut xml = String::new();
+        std::io::Read::read_to_string(&mut archive.by_name("xl/workbook.xml").unwrap(), &mut xml)
+            .unwrap();
+        xml.contains(&format!("name=\"{FILTERED_SHEET_NAME}\""))
+    }
+}
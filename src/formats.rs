@@ -0,0 +1,221 @@
+//! Detects the format of an uploaded file and reads it into a workbook.
+
+use std::{
+    ffi::OsStr,
+    fs::File,
+    io::{BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use crate::{
+    convert,
+    delimited::{self, Dialect},
+    error::Error,
+    processor::Macros,
+    xlsx::{self, Row, Workbook},
+    Result,
+};
+
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+/// Compound file binary, the container of xls workbooks.
+const CFB_MAGIC: &[u8] = b"\xD0\xCF\x11\xE0\xA1\xB1\x1A\xE1";
+/// An OpenDocument package starts with its uncompressed `mimetype` entry,
+/// the file name at offset 30 followed by the media type.
+const ODS_MAGIC: &[u8] = b"mimetypeapplication/vnd.oasis.opendocument.spreadsheet";
+const ODS_MAGIC_OFFSET: usize = 30;
+/// The name of the only sheet of delimited files, as in the workbooks made
+/// from them.
+const DELIMITED_SHEET_NAME: &str = "Sheet1";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileFormat {
+    /// Xlsx and xlsm, with or without macros.
+    Xlsx,
+    Xls,
+    Ods,
+    /// CSV, TSV and other delimiter separated text.
+    Delimited,
+}
+
+impl FileFormat {
+    /// Detects the format from the first bytes of the file. Zip archives
+    /// other than OpenDocument packages are taken for xlsx packages. Anything
+    /// without a known signature is taken for delimited text when it has a
+    /// consistent delimiter or a text extension, and refused otherwise.
+    pub fn detect(path: &Path) -> Result<Self> {
+        let mut file = File::open(path)
+            .map(BufReader::new)
+            .map_err(|e| Error::IOError(e.to_string()))?;
+        Self::detect_from(&mut file, path.extension().and_then(OsStr::to_str))
+    }
+
+    /// [`FileFormat::detect`] for a file in memory or in a stream, with the
+    /// extension of its name if it has one. The input is rewound to its
+    /// start.
+    pub fn detect_from<R: Read + Seek>(input: &mut R, extension: Option<&str>) -> Result<Self> {
+        let mut magic = Vec::with_capacity(ODS_MAGIC_OFFSET + ODS_MAGIC.len());
+        input
+            .by_ref()
+            .take((ODS_MAGIC_OFFSET + ODS_MAGIC.len()) as u64)
+            .read_to_end(&mut magic)
+            .map_err(|e| Error::IOError(e.to_string()))?;
+        rewind(input)?;
+        let format = if magic.starts_with(CFB_MAGIC) {
+            Self::Xls
+        } else if magic.starts_with(ZIP_MAGIC) && magic.ends_with(ODS_MAGIC) {
+            Self::Ods
+        } else if magic.starts_with(ZIP_MAGIC) {
+            Self::Xlsx
+        } else {
+            delimited::dialect_from(input, extension)?;
+            rewind(input)?;
+            Self::Delimited
+        };
+        Ok(format)
+    }
+}
+
+/// An uploaded file read for processing.
+pub struct Source {
+    pub workbook: Workbook,
+    /// How the file was written, for delimited text.
+    pub dialect: Option<Dialect>,
+}
+
+/// Reads the file into a workbook, keeping or stripping the macros of xlsm
+/// workbooks as `macros` says. Macros of xls and ods workbooks can't be
+/// converted, those are refused unless the macros are to be stripped.
+pub fn open(path: &Path, macros: Macros) -> Result<Source> {
+    match FileFormat::detect(path)? {
+        FileFormat::Xlsx => Ok(xlsx_source(Workbook::open(path)?, macros)),
+        FileFormat::Delimited => delimited_source(delimited::read(path)?),
+        FileFormat::Xls => converted_source(convert::read_xls(path, None)?, macros),
+        FileFormat::Ods => converted_source(convert::read_ods(path, None)?, macros),
+    }
+}
+
+/// [`open`] for a file in memory or in a stream. There's no extension to go
+/// by, delimited text needs a consistent delimiter to be recognized. Xlsx
+/// workbooks are read into memory, the rest of the package is written back
+/// from there.
+pub fn read<R: Read + Seek>(mut input: R, macros: Macros) -> Result<Source> {
+    match FileFormat::detect_from(&mut input, None)? {
+        FileFormat::Xlsx => {
+            let mut bytes = Vec::new();
+            input
+                .read_to_end(&mut bytes)
+                .map_err(|e| Error::IOError(e.to_string()))?;
+            Ok(xlsx_source(Workbook::from_bytes(bytes)?, macros))
+        }
+        FileFormat::Delimited => delimited_source(delimited::read_from(input, None)?),
+        FileFormat::Xls => converted_source(convert::read_xls_from(input, None)?, macros),
+        FileFormat::Ods => converted_source(convert::read_ods_from(input, None)?, macros),
+    }
+}
+
+fn xlsx_source(mut workbook: Workbook, macros: Macros) -> Source {
+    if macros == Macros::Strip {
+        workbook.strip_macros();
+    }
+    Source {
+        workbook,
+        dialect: None,
+    }
+}
+
+fn delimited_source(file: delimited::DelimitedFile) -> Result<Source> {
+    Ok(Source {
+        workbook: Workbook::from_rows(file.header, file.rows)?,
+        dialect: Some(file.dialect),
+    })
+}
+
+fn converted_source(converted: convert::Converted, macros: Macros) -> Result<Source> {
+    if converted.has_macros && macros == Macros::Keep {
+        return Err(Error::Unsupported(
+            "The workbook has macros, which can't be carried over from xls or ods files. \
+             Run the job with macros set to strip to process it without them"
+                .into(),
+        ));
+    }
+    Ok(Source {
+        workbook: Workbook::from_rows(converted.header, converted.rows)?,
+        dialect: None,
+    })
+}
+
+fn rewind<R: Seek>(input: &mut R) -> Result<()> {
+    input
+        .seek(SeekFrom::Start(0))
+        .map(|_| ())
+        .map_err(|e| Error::IOError(e.to_string()))
+}
+
+/// Reads the header row of the file, whatever its format.
+pub fn read_header(path: &Path) -> Result<Row> {
+    match FileFormat::detect(path)? {
+        FileFormat::Xlsx => xlsx::read_header(path),
+        FileFormat::Xls => Ok(convert::read_xls(path, None)?.header),
+        FileFormat::Ods => Ok(convert::read_ods(path, None)?.header),
+        FileFormat::Delimited => delimited::read_header(path),
+    }
+}
+
+/// A sheet of an uploaded file, read for a preview rather than a job.
+pub struct Sheet {
+    /// Names of all the sheets of the file.
+    pub sheet_names: Vec<String>,
+    pub sheet_idx: usize,
+    pub workbook: Workbook,
+}
+
+/// Reads the sheet named or numbered `sheet`, the first one if `None`.
+/// Delimited files have a single sheet.
+pub fn open_sheet(path: &Path, sheet: Option<&str>) -> Result<Sheet> {
+    let converted = match FileFormat::detect(path)? {
+        FileFormat::Xlsx => {
+            let sheet_names = xlsx::sheet_names(path)?;
+            let sheet_idx = sheet_index(&sheet_names, sheet)?;
+            return Ok(Sheet {
+                workbook: Workbook::open_sheet(path, sheet_idx)?,
+                sheet_names,
+                sheet_idx,
+            });
+        }
+        FileFormat::Delimited => {
+            let sheet_names = vec![DELIMITED_SHEET_NAME.to_string()];
+            let sheet_idx = sheet_index(&sheet_names, sheet)?;
+            let file = delimited::read(path)?;
+            return Ok(Sheet {
+                workbook: Workbook::from_rows(file.header, file.rows)?,
+                sheet_names,
+                sheet_idx,
+            });
+        }
+        FileFormat::Xls => convert::read_xls(path, sheet)?,
+        FileFormat::Ods => convert::read_ods(path, sheet)?,
+    };
+    Ok(Sheet {
+        sheet_idx: sheet_index(&converted.sheet_names, sheet)?,
+        workbook: Workbook::from_rows(converted.header, converted.rows)?,
+        sheet_names: converted.sheet_names,
+    })
+}
+
+/// Index of the sheet named `sheet`, or numbered from 0 if no sheet has that
+/// name. The first sheet for `None`.
+pub fn sheet_index(sheet_names: &[String], sheet: Option<&str>) -> Result<usize> {
+    let Some(sheet) = sheet else {
+        return Ok(0);
+    };
+    if let Some(idx) = sheet_names.iter().position(|name| name == sheet) {
+        return Ok(idx);
+    }
+    match sheet.parse::<usize>() {
+        Ok(idx) if idx < sheet_names.len() => Ok(idx),
+        _ => Err(Error::InvalidPayload(format!(
+            "No sheet {sheet}, the sheets are {}",
+            sheet_names.join(", ")
+        ))),
+    }
+}
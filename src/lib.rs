@@ -1,47 +1,258 @@
 use axum::Router;
-use std::path::MAIN_SEPARATOR;
+use std::{path::MAIN_SEPARATOR, sync::Arc};
 
-use axum::{extract::DefaultBodyLimit, http::Method};
+use axum::{
+    extract::DefaultBodyLimit,
+    http::{header::RETRY_AFTER, HeaderName},
+};
+use config::{AllowedOrigins, Config, CorsConfig, DatabaseConfig};
 use data::{sqlite_ds::SqliteDataSource, DataSource};
-use rusqlite::Connection;
-use tokio::fs;
+use tokio::{fs, sync::Semaphore};
 use tower::ServiceBuilder;
 use tower_http::{
-    cors::{Any, CorsLayer},
+    cors::{AllowOrigin, CorsLayer},
     services::ServeDir,
     trace::TraceLayer,
 };
 
+mod auth;
 mod colors;
+pub mod config;
+mod convert;
 mod data;
+mod dates;
+mod delimited;
 pub mod error;
+mod filter;
+mod formats;
+mod limits;
+pub mod logging;
+mod metrics;
+mod pivot;
+mod preview;
+pub mod processor;
+mod profile;
+#[cfg(feature = "otel")]
+pub mod telemetry;
 mod web;
+mod xlsx;
 
 const DATA_DIR_NAME: &str = "data_";
 
 pub type Result<T> = std::result::Result<T, error::Error>;
 
-pub async fn get_app_router() -> Result<Router> {
+pub use data::migrations::MigrationStatus;
+
+/// The jobs of `/runJob` requests, which run on threads of their own and
+/// outlive requests whose client went away.
+#[derive(Clone)]
+pub struct RunningJobs {
+    permits: Arc<Semaphore>,
+    slots: usize,
+}
+
+impl RunningJobs {
+    fn new(slots: usize) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(slots)),
+            slots,
+        }
+    }
+
+    /// Waits for the running jobs to finish. Every job slot is kept
+    /// afterwards, later jobs are turned away as if the server was busy.
+    pub async fn drain(&self) {
+        if let Ok(permits) = self.permits.acquire_many(self.slots as u32).await {
+            permits.forget();
+        }
+    }
+}
+
+pub async fn get_app_router(config: &Config) -> Result<Router> {
+    Ok(get_app(config).await?.0)
+}
+
+/// The router along with its running jobs, to wait for on shutdown.
+pub async fn get_app(config: &Config) -> Result<(Router, RunningJobs)> {
+    let jobs = RunningJobs::new(config.max_concurrent_jobs);
+    let router = match &config.database {
+        DatabaseConfig::Sqlite { path, pool_size } => {
+            build_router(open_sqlite(path, *pool_size).await?, config, &jobs).await
+        }
+        #[cfg(feature = "postgres")]
+        DatabaseConfig::Postgres { url, pool_size } => {
+            build_router(
+                data::postgres_ds::PostgresDataSource::new(url, *pool_size)?,
+                config,
+                &jobs,
+            )
+            .await
+        }
+        #[cfg(not(feature = "postgres"))]
+        DatabaseConfig::Postgres { .. } => Err(postgres_not_enabled()),
+    }?;
+    Ok((router, jobs))
+}
+
+/// Reports every migration known to this build or recorded in the database,
+/// without applying anything.
+pub async fn migration_status(config: &Config) -> Result<Vec<MigrationStatus>> {
+    match &config.database {
+        // Nothing is created for a database that doesn't exist yet, all of
+        // its migrations are pending.
+        DatabaseConfig::Sqlite { path, .. } if !path.exists() => {
+            Ok(data::migrations::status(Vec::new()))
+        }
+        DatabaseConfig::Sqlite { path, pool_size } => {
+            get_migration_status(&SqliteDataSource::open_read_only(path, *pool_size)?).await
+        }
+        #[cfg(feature = "postgres")]
+        DatabaseConfig::Postgres { url, pool_size } => {
+            get_migration_status(&data::postgres_ds::PostgresDataSource::new(
+                url, *pool_size,
+            )?)
+            .await
+        }
+        #[cfg(not(feature = "postgres"))]
+        DatabaseConfig::Postgres { .. } => Err(postgres_not_enabled()),
+    }
+}
+
+/// Applies pending migrations and returns the resulting status.
+pub async fn apply_migrations(config: &Config) -> Result<Vec<MigrationStatus>> {
+    match &config.database {
+        DatabaseConfig::Sqlite { path, pool_size } => {
+            let datasource = open_sqlite(path, *pool_size).await?;
+            datasource.init_database().await?;
+            get_migration_status(&datasource).await
+        }
+        #[cfg(feature = "postgres")]
+        DatabaseConfig::Postgres { url, pool_size } => {
+            let datasource = data::postgres_ds::PostgresDataSource::new(url, *pool_size)?;
+            datasource.init_database().await?;
+            get_migration_status(&datasource).await
+        }
+        #[cfg(not(feature = "postgres"))]
+        DatabaseConfig::Postgres { .. } => Err(postgres_not_enabled()),
+    }
+}
+
+/// Creates a user who can log in with `password`, applying pending
+/// migrations first.
+pub async fn add_user(config: &Config, username: &str, password: &str) -> Result<()> {
+    match &config.database {
+        DatabaseConfig::Sqlite { path, pool_size } => {
+            create_user(&open_sqlite(path, *pool_size).await?, username, password).await
+        }
+        #[cfg(feature = "postgres")]
+        DatabaseConfig::Postgres { url, pool_size } => {
+            create_user(
+                &data::postgres_ds::PostgresDataSource::new(url, *pool_size)?,
+                username,
+                password,
+            )
+            .await
+        }
+        #[cfg(not(feature = "postgres"))]
+        DatabaseConfig::Postgres { .. } => Err(postgres_not_enabled()),
+    }
+}
+
+async fn create_user<D: DataSource>(datasource: &D, username: &str, password: &str) -> Result<()> {
+    let username = username.trim();
+    if username.is_empty() {
+        return Err(error::Error::InvalidPayload(
+            "The user name is empty".into(),
+        ));
+    }
+    if password.chars().count() < auth::MIN_PASSWORD_LENGTH {
+        return Err(error::Error::InvalidPayload(format!(
+            "The password must be at least {} characters long",
+            auth::MIN_PASSWORD_LENGTH
+        )));
+    }
+    datasource.init_database().await?;
+    match datasource.get_user(username.to_string()).await {
+        Ok(_) => {
+            return Err(error::Error::InvalidPayload(format!(
+                "The user {username} already exists"
+            )))
+        }
+        Err(error::Error::NoEntryFound(_)) => {}
+        Err(e) => return Err(e),
+    }
+    let password_hash = auth::hash_password(password);
+    datasource
+        .add_user(data::model::NewUser {
+            username,
+            password_hash: &password_hash,
+        })
+        .await?;
+    Ok(())
+}
+
+async fn open_sqlite(path: &std::path::Path, pool_size: usize) -> Result<SqliteDataSource> {
     fs::create_dir_all(format!(".{MAIN_SEPARATOR}{DATA_DIR_NAME}"))
         .await
         .unwrap();
-    let sqlite_con = Connection::open(format!(
-        ".{MAIN_SEPARATOR}{DATA_DIR_NAME}{MAIN_SEPARATOR}db.sqlite"
+    SqliteDataSource::new(path, pool_size)
+}
+
+#[cfg(not(feature = "postgres"))]
+fn postgres_not_enabled() -> error::Error {
+    error::Error::Generic(
+        "A postgres database was configured but the server was built without the postgres feature"
+            .into(),
+    )
+}
+
+async fn get_migration_status<D: DataSource>(datasource: &D) -> Result<Vec<MigrationStatus>> {
+    Ok(data::migrations::status(
+        datasource.applied_migrations().await?,
     ))
-    .unwrap();
-    let datasource = SqliteDataSource::new(sqlite_con);
+}
+
+/// The CORS policy of the server. Responses expose the match report of jobs,
+/// how long to wait after a 429 and the request id to scripts of allowed
+/// origins.
+pub fn cors_layer(cors: &CorsConfig) -> CorsLayer {
+    let origins = match &cors.allowed_origins {
+        AllowedOrigins::Any => AllowOrigin::any(),
+        AllowedOrigins::List(origins) => AllowOrigin::list(origins.iter().cloned()),
+    };
+    CorsLayer::new()
+        .allow_origin(origins)
+        .allow_methods(cors.allowed_methods.clone())
+        .allow_headers(cors.allowed_headers.clone())
+        .allow_credentials(cors.allow_credentials)
+        .max_age(cors.max_age)
+        .expose_headers([
+            HeaderName::from_static(web::MATCH_REPORT_HEADER),
+            RETRY_AFTER,
+            HeaderName::from_static(logging::REQUEST_ID_HEADER),
+        ])
+}
+
+async fn build_router<D: DataSource>(
+    datasource: D,
+    config: &Config,
+    jobs: &RunningJobs,
+) -> Result<Router> {
     datasource.init_database().await?;
-    let cors = CorsLayer::new()
-        .allow_methods([Method::GET, Method::POST])
-        .allow_origin(Any);
+    let cors = cors_layer(&config.cors);
 
     Ok(Router::new()
-        .merge(crate::web::get_routes(datasource))
+        .merge(crate::web::get_routes(
+            datasource,
+            config,
+            jobs.permits.clone(),
+        ))
         .nest_service("/", ServeDir::new(format!(".{MAIN_SEPARATOR}frontend")))
         .layer(
             ServiceBuilder::new()
+                .layer(axum::middleware::from_fn(logging::request_id))
                 .layer(TraceLayer::new_for_http())
                 .layer(cors)
-                .layer(DefaultBodyLimit::max(10000000)),
+                .layer(DefaultBodyLimit::max(config.max_upload_bytes)),
         ))
 }
@@ -0,0 +1,313 @@
+//! Per client rate limits of `/upload` and `/runJob`, caps on the jobs a
+//! client runs at the same time and daily upload quotas. Clients are users,
+//! or the address of requests made without authentication.
+
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    middleware::Next,
+    response::Response,
+};
+use chrono::{Days, Utc};
+use tracing::{event, Level};
+
+use crate::{
+    auth::Principal, config::LimitsConfig, data::DataSource, error::Error, web::AppState, Result,
+};
+
+/// Buckets are dropped once full past this many clients, they'd be
+/// recreated full anyway.
+const MAX_IDLE_BUCKETS: usize = 10_000;
+
+/// Who limits and quotas are counted for, put in the request extensions by
+/// [`limit_uploads`] and [`limit_jobs`].
+#[derive(Debug, Clone)]
+pub struct Client(pub String);
+
+impl Client {
+    fn of(request: &Request) -> Self {
+        let principal = request.extensions().get::<Principal>();
+        if let Some(Principal::User { id, .. }) = principal {
+            return Self(format!("user:{id}"));
+        }
+        match request.extensions().get::<ConnectInfo<SocketAddr>>() {
+            Some(ConnectInfo(addr)) => Self(format!("ip:{}", addr.ip())),
+            None => Self("unknown".into()),
+        }
+    }
+}
+
+pub struct Limits {
+    config: LimitsConfig,
+    uploads: RateLimiter,
+    jobs: RateLimiter,
+    /// Jobs running per client.
+    running: Mutex<HashMap<String, usize>>,
+}
+
+impl Limits {
+    pub fn new(config: &LimitsConfig) -> Self {
+        Self {
+            uploads: RateLimiter::new(config.uploads_per_minute),
+            jobs: RateLimiter::new(config.jobs_per_minute),
+            running: Mutex::new(HashMap::new()),
+            config: config.clone(),
+        }
+    }
+
+    /// Takes one of the job slots of `client`, given back when the guard
+    /// is dropped.
+    fn start_job(self: &Arc<Self>, client: &Client) -> Result<RunningJob> {
+        let mut running = self.running.lock().unwrap();
+        let count = running.entry(client.0.clone()).or_default();
+        if *count >= self.config.max_jobs_per_client {
+            return Err(Error::RateLimited {
+                message: format!(
+                    "At most {} jobs may run at the same time, try again once one is done",
+                    self.config.max_jobs_per_client
+                ),
+                retry_after: 1,
+            });
+        }
+        *count += 1;
+        Ok(RunningJob {
+            limits: self.clone(),
+            client: client.0.clone(),
+        })
+    }
+
+    /// Fails once `client` uploaded its daily quota.
+    pub async fn check_quota<D: DataSource>(&self, datasource: &D, client: &Client) -> Result<()> {
+        let used = datasource
+            .get_upload_usage(client.0.clone(), today())
+            .await?;
+        if used.max(0) as usize >= self.config.daily_upload_quota_bytes {
+            return Err(self.quota_exceeded(used));
+        }
+        Ok(())
+    }
+
+    /// Counts `bytes` against the daily quota of `client` before an upload is
+    /// stored, failing if they'd take it past the quota. The reservation is
+    /// to be released if the upload isn't stored after all.
+    pub async fn reserve_upload<D: DataSource>(
+        &self,
+        datasource: &D,
+        client: &Client,
+        bytes: usize,
+    ) -> Result<UploadReservation> {
+        let day = today();
+        let quota = i64::try_from(self.config.daily_upload_quota_bytes).unwrap_or(i64::MAX);
+        let bytes = i64::try_from(bytes).unwrap_or(i64::MAX);
+        let reserved = datasource
+            .reserve_upload_usage(client.0.clone(), day.clone(), bytes, quota)
+            .await?;
+        if !reserved {
+            let used = datasource.get_upload_usage(client.0.clone(), day).await?;
+            return Err(self.quota_exceeded(used));
+        }
+        Ok(UploadReservation {
+            client: client.0.clone(),
+            day,
+            bytes,
+        })
+    }
+
+    /// Gives the bytes of an upload that failed back to the quota.
+    pub async fn release_upload<D: DataSource>(
+        &self,
+        datasource: &D,
+        reservation: UploadReservation,
+    ) -> Result<()> {
+        datasource
+            .add_upload_usage(reservation.client, reservation.day, -reservation.bytes)
+            .await
+    }
+
+    fn quota_exceeded(&self, used: i64) -> Error {
+        Error::RateLimited {
+            message: format!(
+                "The upload exceeds the daily upload quota of {} bytes, {used} bytes were uploaded today",
+                self.config.daily_upload_quota_bytes
+            ),
+            retry_after: seconds_to_midnight(),
+        }
+    }
+}
+
+/// Bytes of the daily quota set aside for an upload by
+/// [`Limits::reserve_upload`]. They're released on the day they were
+/// reserved, even if the upload fails after midnight.
+pub struct UploadReservation {
+    client: String,
+    day: String,
+    bytes: i64,
+}
+
+struct RunningJob {
+    limits: Arc<Limits>,
+    client: String,
+}
+
+impl Drop for RunningJob {
+    fn drop(&mut self) {
+        let mut running = self.limits.running.lock().unwrap();
+        if let Some(count) = running.get_mut(&self.client) {
+            *count -= 1;
+            if *count == 0 {
+                running.remove(&self.client);
+            }
+        }
+    }
+}
+
+/// Token buckets holding a minute worth of requests per client, refilled
+/// continuously.
+struct RateLimiter {
+    per_minute: f64,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    fn new(per_minute: usize) -> Self {
+        Self {
+            per_minute: per_minute as f64,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes a token of `client`, failing with the seconds until one is
+    /// refilled.
+    fn acquire(&self, client: &Client, route: &str) -> Result<()> {
+        let now = Instant::now();
+        let per_second = self.per_minute / 60.0;
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() > MAX_IDLE_BUCKETS {
+            buckets.retain(|_, bucket| {
+                bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * per_second
+                    < self.per_minute
+            });
+        }
+        let bucket = buckets.entry(client.0.clone()).or_insert(Bucket {
+            tokens: self.per_minute,
+            updated: now,
+        });
+        let refilled = now.duration_since(bucket.updated).as_secs_f64() * per_second;
+        bucket.tokens = (bucket.tokens + refilled).min(self.per_minute);
+        bucket.updated = now;
+        if bucket.tokens < 1.0 {
+            event!(Level::WARN, "Rate limiting {} on {}", client.0, route);
+            return Err(Error::RateLimited {
+                message: format!(
+                    "Too many {route} requests, at most {} are allowed per minute",
+                    self.per_minute
+                ),
+                retry_after: ((1.0 - bucket.tokens) / per_second).ceil() as u64,
+            });
+        }
+        bucket.tokens -= 1.0;
+        Ok(())
+    }
+}
+
+/// Rate limits `/upload` requests. Runs after `auth::authenticate`, which
+/// resolves the user requests are counted for.
+pub async fn limit_uploads<D: DataSource>(
+    State(state): State<AppState<D>>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response> {
+    let client = Client::of(&request);
+    state.limits.uploads.acquire(&client, "/upload")?;
+    request.extensions_mut().insert(client);
+    Ok(next.run(request).await)
+}
+
+/// Rate limits `/runJob` requests and holds one of the job slots of the
+/// client until the job is done.
+pub async fn limit_jobs<D: DataSource>(
+    State(state): State<AppState<D>>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response> {
+    let client = Client::of(&request);
+    state.limits.jobs.acquire(&client, "/runJob")?;
+    let job = state.limits.start_job(&client)?;
+    request.extensions_mut().insert(client);
+    let response = next.run(request).await;
+    drop(job);
+    Ok(response)
+}
+
+fn today() -> String {
+    Utc::now().format("%Y-%m-%d").to_string()
+}
+
+fn seconds_to_midnight() -> u64 {
+    let now = Utc::now();
+    now.date_naive()
+        .checked_add_days(Days::new(1))
+        .and_then(|day| day.and_hms_opt(0, 0, 0))
+        .map_or(1, |midnight| {
+            (midnight - now.naive_utc()).num_seconds().max(1) as u64
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_hold_a_minute_of_requests() {
+        let limiter = RateLimiter::new(3);
+        let client = Client("ip:127.0.0.1".into());
+        for _ in 0..3 {
+            limiter.acquire(&client, "/upload").unwrap();
+        }
+        match limiter.acquire(&client, "/upload") {
+            Err(Error::RateLimited { retry_after, .. }) => assert!((1..=20).contains(&retry_after)),
+            other => panic!("expected a rate limit, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn clients_have_buckets_of_their_own() {
+        let limiter = RateLimiter::new(1);
+        let first = Client("user:first".into());
+        let second = Client("user:second".into());
+        limiter.acquire(&first, "/runJob").unwrap();
+        assert!(limiter.acquire(&first, "/runJob").is_err());
+        limiter.acquire(&second, "/runJob").unwrap();
+    }
+
+    #[test]
+    fn buckets_are_refilled() {
+        let limiter = RateLimiter::new(60);
+        let client = Client("ip:127.0.0.1".into());
+        for _ in 0..60 {
+            limiter.acquire(&client, "/upload").unwrap();
+        }
+        assert!(limiter.acquire(&client, "/upload").is_err());
+        // A second ago, the bucket would have been refilled by one token.
+        limiter
+            .buckets
+            .lock()
+            .unwrap()
+            .get_mut(&client.0)
+            .unwrap()
+            .updated -= std::time::Duration::from_secs(1);
+        limiter.acquire(&client, "/upload").unwrap();
+        assert!(limiter.acquire(&client, "/upload").is_err());
+    }
+}
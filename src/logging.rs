@@ -0,0 +1,242 @@
+//! Log output of the server and the request ids tying log events to the
+//! request they happened in.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use axum::{
+    extract::Request,
+    http::{HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use tracing::{span, Instrument, Level};
+use tracing_appender::{
+    non_blocking::WorkerGuard,
+    rolling::{RollingFileAppender, Rotation},
+};
+use tracing_subscriber::{
+    fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer, Registry,
+};
+
+use crate::{
+    config::{LogConfig, LogFormat, LogRotation},
+    error::Error,
+    Result,
+};
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+const LOG_FILE_NAME: &str = "server.log";
+/// Filter used without `RUST_LOG`.
+const DEFAULT_FILTER: &str = "excel_app=info";
+/// Request ids taken from clients are at most this long.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Keeps the log output going. Events and spans still buffered are written
+/// out when it is dropped.
+pub struct LogGuard {
+    _writers: Vec<WorkerGuard>,
+    #[cfg(feature = "otel")]
+    provider: Option<opentelemetry_sdk::trace::TracerProvider>,
+}
+
+impl Drop for LogGuard {
+    fn drop(&mut self) {
+        #[cfg(feature = "otel")]
+        if let Some(provider) = self.provider.take() {
+            if let Err(e) = provider.shutdown() {
+                eprintln!("Error exporting the last spans, {e}");
+            }
+        }
+    }
+}
+
+/// Installs the global subscriber. Events are written on a thread of their
+/// own, and spans exported to the OTLP collector configured, if any. Must
+/// be called within the runtime.
+pub fn init(config: &LogConfig) -> Result<LogGuard> {
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_FILTER));
+    let mut guards = Vec::new();
+    let mut layers = Vec::new();
+
+    let (file_writer, guard) = match config.rotation {
+        LogRotation::Hourly => {
+            tracing_appender::non_blocking(time_appender(config, Rotation::HOURLY)?)
+        }
+        LogRotation::Daily => {
+            tracing_appender::non_blocking(time_appender(config, Rotation::DAILY)?)
+        }
+        LogRotation::Size(max_bytes) => tracing_appender::non_blocking(
+            SizeRollingFile::new(&config.directory, max_bytes, config.max_files)
+                .map_err(log_file_error)?,
+        ),
+    };
+    guards.push(guard);
+    layers.push(format_layer(config.format, file_writer));
+    if config.stdout {
+        let (stdout_writer, guard) = tracing_appender::non_blocking(io::stdout());
+        guards.push(guard);
+        layers.push(format_layer(config.format, stdout_writer));
+    }
+    #[cfg(feature = "otel")]
+    let provider = match &config.otlp_endpoint {
+        None => None,
+        Some(endpoint) => {
+            let provider = crate::telemetry::otlp_provider(endpoint)?;
+            layers.push(crate::telemetry::layer(&provider).boxed());
+            Some(provider)
+        }
+    };
+    #[cfg(not(feature = "otel"))]
+    if config.otlp_endpoint.is_some() {
+        return Err(Error::Generic(
+            "An OTLP endpoint was configured but the server was built without the otel feature"
+                .into(),
+        ));
+    }
+
+    tracing_subscriber::registry()
+        .with(layers.with_filter(filter))
+        .try_init()
+        .map_err(|e| Error::Generic(format!("Error setting up logging, {e}")))?;
+    Ok(LogGuard {
+        _writers: guards,
+        #[cfg(feature = "otel")]
+        provider,
+    })
+}
+
+fn format_layer<W>(format: LogFormat, writer: W) -> Box<dyn Layer<Registry> + Send + Sync>
+where
+    W: for<'w> fmt::MakeWriter<'w> + Send + Sync + 'static,
+{
+    match format {
+        LogFormat::Json => fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .with_writer(writer)
+            .boxed(),
+        LogFormat::Pretty => fmt::layer()
+            .pretty()
+            .with_ansi(false)
+            .with_writer(writer)
+            .boxed(),
+    }
+}
+
+fn time_appender(config: &LogConfig, rotation: Rotation) -> Result<RollingFileAppender> {
+    RollingFileAppender::builder()
+        .rotation(rotation)
+        .filename_prefix(LOG_FILE_NAME)
+        .max_log_files(config.max_files)
+        .build(&config.directory)
+        .map_err(log_file_error)
+}
+
+fn log_file_error(e: impl std::fmt::Display) -> Error {
+    Error::IOError(format!("Error opening the log file, {e}"))
+}
+
+/// `server.log`, moved to `server.log.1` once it holds `max_bytes`, the
+/// older files shifted up to `server.log.<max_files - 1>`.
+struct SizeRollingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    file: File,
+    written: u64,
+}
+
+impl SizeRollingFile {
+    fn new(directory: &Path, max_bytes: u64, max_files: usize) -> io::Result<Self> {
+        fs::create_dir_all(directory)?;
+        let path = directory.join(LOG_FILE_NAME);
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let written = file.metadata()?.len();
+        Ok(Self {
+            path,
+            max_bytes,
+            max_files,
+            file,
+            written,
+        })
+    }
+
+    fn numbered(&self, n: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{n}"));
+        PathBuf::from(path)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        // The current file counts as one of the files kept.
+        let _ = fs::remove_file(self.numbered(self.max_files));
+        for n in (1..self.max_files).rev() {
+            let from = if n == 1 {
+                self.path.clone()
+            } else {
+                self.numbered(n - 1)
+            };
+            if from.exists() {
+                fs::rename(from, self.numbered(n))?;
+            }
+        }
+        if self.max_files == 1 {
+            fs::remove_file(&self.path)?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.written = 0;
+        Ok(())
+    }
+}
+
+impl Write for SizeRollingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.written > 0 && self.written + buf.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Runs every request in a `request` span holding its id, so the events
+/// logged while serving it carry the id. The id is taken from the
+/// `X-Request-Id` header when the client sent one, and sent back in it.
+pub async fn request_id(mut request: Request, next: Next) -> Response {
+    let header = HeaderName::from_static(REQUEST_ID_HEADER);
+    let id = request
+        .headers()
+        .get(&header)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN)
+        .map(str::to_string)
+        .unwrap_or_else(|| uuid::Uuid::now_v7().to_string());
+    let value = HeaderValue::from_str(&id).unwrap();
+    request.headers_mut().insert(header.clone(), value.clone());
+
+    let span = span!(
+        Level::INFO,
+        "request",
+        request_id = %id,
+        method = %request.method(),
+        path = request.uri().path()
+    );
+    let mut response = next.run(request).instrument(span).await;
+    response.headers_mut().insert(header, value);
+    response
+}
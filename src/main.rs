@@ -1,20 +1,189 @@
-use std::path::MAIN_SEPARATOR;
+use std::{net::SocketAddr, time::Duration};
+
+use axum_server::{tls_rustls::RustlsConfig, Handle};
+use excel_app::{config::Config, MigrationStatus, RunningJobs};
+use tokio::{task::JoinHandle, time::Instant};
+use tracing::{event, Level};
+
+const PASSWORD_ENV: &str = "EXCEL_APP_PASSWORD";
 
 #[tokio::main]
 async fn main() {
-    let f_appender = tracing_appender::rolling::hourly(format!(".{MAIN_SEPARATOR}"), "server.log");
-    let (non_blk, _guard) = tracing_appender::non_blocking(f_appender);
-    tracing_subscriber::fmt::fmt()
-        .with_env_filter("excel_app=trace")
-        .event_format(tracing_subscriber::fmt::format().pretty())
-        .with_writer(non_blk)
-        .init();
-    let app = excel_app::get_app_router().await.unwrap();
-
-    println!("Running on http://127.0.0.1:6070");
-    println!("Swagger ui at http://127.0.0.1:6070/swagger-ui");
-    let listener = tokio::net::TcpListener::bind("127.0.0.1:6070")
-        .await
-        .unwrap();
-    axum::serve(listener, app).await.unwrap();
+    let config = Config::from_env().unwrap();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    if args.first().map(String::as_str) == Some("migrate") {
+        run_migrate_command(&config, args.get(1).map(String::as_str)).await;
+        return;
+    }
+    if args.first().map(String::as_str) == Some("user") {
+        run_user_command(&config, &args[1..]).await;
+        return;
+    }
+
+    let log_guard = excel_app::logging::init(&config.log).unwrap();
+    let (app, jobs) = excel_app::get_app(&config).await.unwrap();
+
+    let addr = SocketAddr::from(([127, 0, 0, 1], 6070));
+    let scheme = if config.tls.is_some() {
+        "https"
+    } else {
+        "http"
+    };
+    println!("Running on {scheme}://{addr}");
+    println!("Swagger ui at {scheme}://{addr}/swagger-ui");
+    // Clients without a user are rate limited by their address.
+    let app = app.into_make_service_with_connect_info::<SocketAddr>();
+    let handle = Handle::new();
+    let mut server = match &config.tls {
+        None => tokio::spawn(axum_server::bind(addr).handle(handle.clone()).serve(app)),
+        Some(tls) => {
+            let rustls = RustlsConfig::from_pem_file(&tls.cert_path, &tls.key_path)
+                .await
+                .unwrap();
+            tokio::spawn(
+                axum_server::bind_rustls(addr, rustls)
+                    .handle(handle.clone())
+                    .serve(app),
+            )
+        }
+    };
+
+    tokio::select! {
+        result = &mut server => {
+            result.unwrap().unwrap();
+            return;
+        }
+        _ = shutdown_signal() => {}
+    }
+    let drained = shut_down(handle, server, &jobs, config.shutdown_timeout).await;
+    if !drained {
+        // Blocking job threads would otherwise hold the runtime up.
+        drop(log_guard);
+        std::process::exit(1);
+    }
+}
+
+/// Resolves on ctrl-c or SIGTERM.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c().await.unwrap();
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .unwrap()
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+/// Stops accepting connections and waits up to `timeout` for the requests
+/// being served and the jobs running to finish. Returns whether they did.
+async fn shut_down(
+    handle: Handle,
+    server: JoinHandle<std::io::Result<()>>,
+    jobs: &RunningJobs,
+    timeout: Duration,
+) -> bool {
+    println!(
+        "Shutting down, waiting up to {}s for running jobs",
+        timeout.as_secs()
+    );
+    event!(
+        Level::INFO,
+        "Shutting down with {} open connections",
+        handle.connection_count()
+    );
+    let deadline = Instant::now() + timeout;
+    handle.graceful_shutdown(Some(timeout));
+    let drained = tokio::time::timeout_at(deadline, async {
+        let _ = server.await;
+        jobs.drain().await;
+    })
+    .await
+    .is_ok();
+    if !drained {
+        event!(
+            Level::WARN,
+            "Jobs still running after {}s, dropping them",
+            timeout.as_secs()
+        );
+        eprintln!(
+            "Jobs still running after {}s, dropping them",
+            timeout.as_secs()
+        );
+    }
+    drained
+}
+
+/// `excel_app migrate [status|apply]`
+async fn run_migrate_command(config: &Config, sub_command: Option<&str>) {
+    let result = match sub_command.unwrap_or("status") {
+        "status" => excel_app::migration_status(config).await,
+        "apply" => excel_app::apply_migrations(config).await,
+        other => {
+            eprintln!("Unknown migrate command: {other}, expected status / apply");
+            std::process::exit(2);
+        }
+    };
+
+    match result {
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+        Ok(statuses) => print_migration_statuses(&statuses),
+    }
+}
+
+/// `excel_app user add <username>`, with the password read from
+/// `EXCEL_APP_PASSWORD` or the first line of stdin.
+async fn run_user_command(config: &Config, args: &[String]) {
+    let username = match args {
+        [command, username] if command == "add" => username,
+        _ => {
+            eprintln!("Usage: excel_app user add <username>");
+            std::process::exit(2);
+        }
+    };
+    let password = match std::env::var(PASSWORD_ENV) {
+        Ok(password) => password,
+        Err(_) => {
+            eprintln!("Password of {username}:");
+            let mut line = String::new();
+            if let Err(e) = std::io::stdin().read_line(&mut line) {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }
+            line.trim_end_matches(['\r', '\n']).to_string()
+        }
+    };
+
+    match excel_app::add_user(config, username, &password).await {
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+        Ok(()) => println!("Added user {username}"),
+    }
+}
+
+fn print_migration_statuses(statuses: &[MigrationStatus]) {
+    println!("{:<8} {:<32} APPLIED AT", "VERSION", "NAME");
+    for status in statuses {
+        println!(
+            "{:<8} {:<32} {}",
+            status.version,
+            status.name,
+            status.applied_at.as_deref().unwrap_or("pending")
+        );
+    }
 }
@@ -0,0 +1,210 @@
+//! Prometheus metrics of the server, served by `/metrics`: requests and
+//! their latency per route, how long the phases of jobs take, the rows
+//! processed and the bytes uploaded.
+
+use std::{sync::Arc, time::Instant};
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    middleware::Next,
+    response::Response,
+};
+use prometheus::{
+    exponential_buckets, histogram_opts, opts, Encoder, HistogramVec, IntCounter, IntCounterVec,
+    Registry, TextEncoder,
+};
+use tracing::{span, span::EnteredSpan, Level};
+
+use crate::{error::Error, Result};
+
+/// Content type of the text exposition format.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// The phases jobs are timed in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Opening the upload.
+    Read,
+    /// Removing duplicates, filtering and validating the rows.
+    Validate,
+    /// Sorting the rows, reading the contraction file alongside.
+    Sort,
+    /// Highlighting matches and adding summaries and subtotals.
+    Highlight,
+    /// Writing the result.
+    Write,
+}
+
+impl Phase {
+    pub const READ: &'static str = "read";
+    pub const VALIDATE: &'static str = "validate";
+    pub const SORT: &'static str = "sort";
+    pub const HIGHLIGHT: &'static str = "highlight";
+    pub const WRITE: &'static str = "write";
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Phase::Read => Self::READ,
+            Phase::Validate => Self::VALIDATE,
+            Phase::Sort => Self::SORT,
+            Phase::Highlight => Self::HIGHLIGHT,
+            Phase::Write => Self::WRITE,
+        }
+    }
+}
+
+pub struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    request_seconds: HistogramVec,
+    job_phase_seconds: HistogramVec,
+    rows_processed: IntCounter,
+    upload_bytes: IntCounter,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let requests = IntCounterVec::new(
+            opts!("excel_app_http_requests_total", "Requests answered"),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let request_seconds = HistogramVec::new(
+            histogram_opts!(
+                "excel_app_http_request_duration_seconds",
+                "Time taken to answer requests"
+            ),
+            &["method", "route"],
+        )
+        .unwrap();
+        // Jobs on large sheets take minutes.
+        let job_phase_seconds = HistogramVec::new(
+            histogram_opts!(
+                "excel_app_job_phase_duration_seconds",
+                "Time taken by the phases of jobs",
+                exponential_buckets(0.005, 2.0, 16).unwrap()
+            ),
+            &["phase"],
+        )
+        .unwrap();
+        let rows_processed =
+            IntCounter::new("excel_app_rows_processed_total", "Data rows read by jobs").unwrap();
+        let upload_bytes =
+            IntCounter::new("excel_app_upload_bytes_total", "Bytes of files uploaded").unwrap();
+
+        let registry = Registry::new();
+        registry.register(Box::new(requests.clone())).unwrap();
+        registry
+            .register(Box::new(request_seconds.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(job_phase_seconds.clone()))
+            .unwrap();
+        registry.register(Box::new(rows_processed.clone())).unwrap();
+        registry.register(Box::new(upload_bytes.clone())).unwrap();
+        Self {
+            registry,
+            requests,
+            request_seconds,
+            job_phase_seconds,
+            rows_processed,
+            upload_bytes,
+        }
+    }
+
+    pub fn add_rows_processed(&self, rows: usize) {
+        self.rows_processed.inc_by(rows as u64);
+    }
+
+    pub fn add_upload_bytes(&self, bytes: usize) {
+        self.upload_bytes.inc_by(bytes as u64);
+    }
+
+    /// Every metric in the text exposition format.
+    pub fn render(&self) -> Result<String> {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .map_err(|e| Error::Generic(format!("Error encoding the metrics, {e}")))?;
+        String::from_utf8(buffer).map_err(|e| Error::Generic(e.to_string()))
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Records how long each phase of a job took, a phase lasting until the
+/// next one starts or the timer is dropped. Phases run in a `phase` span
+/// of their own, named after them when exported.
+pub struct PhaseTimer<'a> {
+    metrics: Option<&'a Metrics>,
+    current: Option<(Phase, Instant, EnteredSpan)>,
+}
+
+impl<'a> PhaseTimer<'a> {
+    /// Times the phases of a job in `metrics`, starting with none. Without
+    /// metrics, only the spans of the phases are made.
+    pub fn new(metrics: Option<&'a Metrics>) -> Self {
+        Self {
+            metrics,
+            current: None,
+        }
+    }
+
+    pub fn start(&mut self, phase: Phase) {
+        self.finish();
+        let span = span!(
+            Level::INFO,
+            "phase",
+            otel.name = phase.as_str(),
+            phase = phase.as_str()
+        );
+        self.current = Some((phase, Instant::now(), span.entered()));
+    }
+
+    fn finish(&mut self) {
+        let Some((phase, started, _span)) = self.current.take() else {
+            return;
+        };
+        if let Some(metrics) = self.metrics {
+            metrics
+                .job_phase_seconds
+                .with_label_values(&[phase.as_str()])
+                .observe(started.elapsed().as_secs_f64());
+        }
+    }
+}
+
+impl Drop for PhaseTimer<'_> {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+/// Counts and times the requests of every route, labelled with the route
+/// pattern rather than the path so ids don't make new series.
+pub async fn track_requests(
+    State(metrics): State<Arc<Metrics>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let started = Instant::now();
+    let method = request.method().to_string();
+    let route = match request.extensions().get::<MatchedPath>() {
+        Some(path) => path.as_str().to_string(),
+        None => "unmatched".to_string(),
+    };
+    let response = next.run(request).await;
+    metrics
+        .requests
+        .with_label_values(&[&method, &route, response.status().as_str()])
+        .inc();
+    metrics
+        .request_seconds
+        .with_label_values(&[&method, &route])
+        .observe(started.elapsed().as_secs_f64());
+    response
+}
@@ -0,0 +1,352 @@
+//! The `Summary` sheet of a job, a pivot table of the processed rows with
+//! the search term and contraction matches of every group.
+
+use std::{borrow::Cow, collections::BTreeMap};
+
+use crate::{
+    processor::{Aggregate, AggregateFunction, JobSpec},
+    xlsx::{Cell, CellValue, Row, Workbook},
+};
+
+/// Name of the sheet the pivot table is written to.
+const SUMMARY_SHEET_NAME: &str = "Summary";
+const GRAND_TOTAL_LABEL: &str = "Grand Total";
+/// Shown for blank keys, as in Excel pivot tables.
+const BLANK_KEY: &str = "(blank)";
+const ROWS_HEADER: &str = "Rows";
+const SEARCH_TERM_MATCHES_HEADER: &str = "Search term matches";
+const CONTRACTION_MATCHES_HEADER: &str = "Contraction matches";
+
+/// Rows grouped by the values of the pivot row columns, then by those of
+/// the pivot columns. Keys sort as text.
+type Groups = BTreeMap<Vec<String>, BTreeMap<Vec<String>, Vec<usize>>>;
+
+/// Adds a `Summary` sheet with a row per distinct value of the pivot row
+/// columns and the pivot values of its rows, in a column per distinct value
+/// of the pivot columns. `matches` gives the number of search term matches
+/// of a cell text and whether it matches a contraction. Nothing is added
+/// without pivot row columns.
+pub fn add_summary<F>(workbook: &mut Workbook, spec: &JobSpec, mut matches: F)
+where
+    F: FnMut(&str) -> (usize, bool),
+{
+    let row_cols = spec.pivot_rows();
+    if row_cols.is_empty() {
+        return;
+    }
+    let col_cols = spec.pivot_cols();
+    let values = spec.pivot_values();
+    let rows = &workbook.rows;
+
+    let mut groups = Groups::new();
+    for (pos, row) in rows.iter().enumerate() {
+        // Rows missing from the sheet aren't records.
+        if row.cells.is_empty() {
+            continue;
+        }
+        groups
+            .entry(keys(row, row_cols))
+            .or_default()
+            .entry(keys(row, col_cols))
+            .or_default()
+            .push(pos);
+    }
+    let mut col_keys: Vec<&Vec<String>> = groups.values().flat_map(BTreeMap::keys).collect();
+    col_keys.sort();
+    col_keys.dedup();
+
+    // Search term matches and contraction cells of every row.
+    let row_matches: Vec<(usize, usize)> = rows
+        .iter()
+        .map(|row| {
+            row.cells
+                .iter()
+                .fold((0, 0), |(terms, contractions), cell| {
+                    let (found, contraction) = matches(&cell.value.text());
+                    (terms + found, contractions + usize::from(contraction))
+                })
+        })
+        .collect();
+
+    let mut header: Vec<String> = row_cols
+        .iter()
+        .map(|col_idx| workbook.header.value(*col_idx).into_owned())
+        .collect();
+    for col_key in &col_keys {
+        let prefix = match col_cols.is_empty() {
+            true => String::new(),
+            false => format!("{} - ", col_key.join(" / ")),
+        };
+        if values.is_empty() {
+            header.push(format!("{prefix}{ROWS_HEADER}"));
+        }
+        for value in values {
+            header.push(format!(
+                "{prefix}{} of {}",
+                function_label(value.function),
+                workbook.header.value(value.column_index)
+            ));
+        }
+    }
+    header.push(SEARCH_TERM_MATCHES_HEADER.into());
+    header.push(CONTRACTION_MATCHES_HEADER.into());
+
+    let mut summary_rows = Vec::with_capacity(groups.len() + 1);
+    for (row_key, by_col) in &groups {
+        let mut cells = texts(row_key);
+        for col_key in &col_keys {
+            let positions = by_col.get(*col_key).map_or(&[][..], Vec::as_slice);
+            cells.extend(pivot_values(rows, positions, values));
+        }
+        let positions = by_col.values().flatten().copied();
+        cells.extend(match_counts(&row_matches, positions));
+        summary_rows.push(cells);
+    }
+
+    let mut grand_total = vec![CellValue::Text(GRAND_TOTAL_LABEL.into())];
+    grand_total.resize(row_cols.len(), CellValue::Empty);
+    for col_key in &col_keys {
+        let positions: Vec<usize> = groups
+            .values()
+            .filter_map(|by_col| by_col.get(*col_key))
+            .flatten()
+            .copied()
+            .collect();
+        grand_total.extend(pivot_values(rows, &positions, values));
+    }
+    let positions = groups
+        .values()
+        .flat_map(BTreeMap::values)
+        .flatten()
+        .copied();
+    grand_total.extend(match_counts(&row_matches, positions));
+    summary_rows.push(grand_total);
+
+    let summary_rows = summary_rows.into_iter().map(cells_row).collect();
+    workbook.add_sheet(SUMMARY_SHEET_NAME, Row::from_text(header), summary_rows);
+}
+
+/// The result of `function` over the values of a column, blank when there
+/// is nothing to average or compare.
+pub fn aggregate<'a, I>(function: AggregateFunction, values: I) -> CellValue
+where
+    I: Iterator<Item = Cow<'a, str>>,
+{
+    if function == AggregateFunction::Count {
+        let count = values.filter(|value| !value.trim().is_empty()).count();
+        return CellValue::Number(count.to_string().into());
+    }
+    let numbers: Vec<f64> = values
+        .filter_map(|value| value.trim().parse::<f64>().ok())
+        .filter(|number| number.is_finite())
+        .collect();
+    // Folded from 0 rather than summed, the sum of no floats is -0.
+    let sum = || numbers.iter().fold(0.0, |a, b| a + b);
+    let result = match function {
+        AggregateFunction::Count | AggregateFunction::Sum => Some(sum()),
+        AggregateFunction::Average => (!numbers.is_empty()).then(|| sum() / numbers.len() as f64),
+        AggregateFunction::Min => numbers.iter().copied().reduce(f64::min),
+        AggregateFunction::Max => numbers.iter().copied().reduce(f64::max),
+    };
+    result.map_or(CellValue::Empty, |result| {
+        CellValue::Number(result.to_string().into())
+    })
+}
+
+fn function_label(function: AggregateFunction) -> &'static str {
+    match function {
+        AggregateFunction::Count => "Count",
+        AggregateFunction::Sum => "Sum",
+        AggregateFunction::Average => "Average",
+        AggregateFunction::Min => "Min",
+        AggregateFunction::Max => "Max",
+    }
+}
+
+fn keys(row: &Row, cols: &[u32]) -> Vec<String> {
+    cols.iter()
+        .map(|col_idx| match row.value(*col_idx).trim() {
+            "" => BLANK_KEY.to_string(),
+            value => value.to_string(),
+        })
+        .collect()
+}
+
+fn texts(values: &[String]) -> Vec<CellValue> {
+    values
+        .iter()
+        .map(|value| CellValue::Text(value.as_str().into()))
+        .collect()
+}
+
+/// The pivot values of the rows at `positions`, their count without any.
+/// Blank without rows, as Excel leaves the empty cells of pivot tables.
+fn pivot_values(rows: &[Row], positions: &[usize], values: &[Aggregate]) -> Vec<CellValue> {
+    if positions.is_empty() {
+        return vec![CellValue::Empty; values.len().max(1)];
+    }
+    if values.is_empty() {
+        return vec![CellValue::Number(positions.len().to_string().into())];
+    }
+    values
+        .iter()
+        .map(|value| {
+            aggregate(
+                value.function,
+                positions
+                    .iter()
+                    .map(|pos| rows[*pos].value(value.column_index)),
+            )
+        })
+        .collect()
+}
+
+fn match_counts<I>(row_matches: &[(usize, usize)], positions: I) -> [CellValue; 2]
+where
+    I: Iterator<Item = usize>,
+{
+    let (terms, contractions) = positions
+        .map(|pos| row_matches[pos])
+        .fold((0, 0), |(terms, contractions), (t, c)| {
+            (terms + t, contractions + c)
+        });
+    [
+        CellValue::Number(terms.to_string().into()),
+        CellValue::Number(contractions.to_string().into()),
+    ]
+}
+
+/// A row of `values` from column 1, without cells for blank values.
+fn cells_row(values: Vec<CellValue>) -> Row {
+    Row::from_cells(
+        (1..)
+            .zip(values)
+            .filter(|(_, value)| !matches!(value, CellValue::Empty))
+            .map(|(col, value)| Cell::new(col, 0, value))
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+
+    use super::*;
+
+    fn values<'a>(values: &'a [&'static str]) -> impl Iterator<Item = Cow<'static, str>> + 'a {
+        values.iter().copied().map(Cow::Borrowed)
+    }
+
+    fn aggregate_text(function: AggregateFunction, texts: &[&'static str]) -> String {
+        aggregate(function, values(texts)).text().into_owned()
+    }
+
+    #[test]
+    fn aggregates() {
+        let texts = ["2", " 4 ", "", "n/a", "inf", "-1.5"];
+        assert_eq!(aggregate_text(AggregateFunction::Count, &texts), "5");
+        assert_eq!(aggregate_text(AggregateFunction::Sum, &texts), "4.5");
+        assert_eq!(aggregate_text(AggregateFunction::Average, &texts), "1.5");
+        assert_eq!(aggregate_text(AggregateFunction::Min, &texts), "-1.5");
+        assert_eq!(aggregate_text(AggregateFunction::Max, &texts), "4");
+    }
+
+    #[test]
+    fn aggregates_without_numbers() {
+        let texts = ["", "n/a"];
+        assert_eq!(aggregate_text(AggregateFunction::Count, &texts), "1");
+        assert_eq!(aggregate_text(AggregateFunction::Sum, &texts), "0");
+        for function in [
+            AggregateFunction::Average,
+            AggregateFunction::Min,
+            AggregateFunction::Max,
+        ] {
+            assert!(matches!(
+                aggregate(function, values(&texts)),
+                CellValue::Empty
+            ));
+        }
+    }
+
+    #[test]
+    fn blank_keys() {
+        let row = Row::from_text(["east", " ", "", " west "]);
+        assert_eq!(
+            keys(&row, &[1, 2, 3, 4]),
+            ["east", BLANK_KEY, BLANK_KEY, "west"]
+        );
+    }
+
+    #[test]
+    fn values_of_groups() {
+        let rows = vec![Row::from_text(["a", "2"]), Row::from_text(["a", "3"])];
+        let sum = [Aggregate::new("pivotValue", "sum", 2).unwrap()];
+        assert_eq!(pivot_values(&rows, &[0, 1], &sum)[0].text(), "5");
+        assert_eq!(pivot_values(&rows, &[0, 1], &[])[0].text(), "2");
+        let empty = pivot_values(&rows, &[], &[]);
+        assert!(matches!(empty.as_slice(), [CellValue::Empty]));
+    }
+
+    #[test]
+    fn summary_sheet() {
+        let rows = [
+            ["east", "x", "1"],
+            ["", "y", "2"],
+            [" ", "x", "3"],
+            ["east", "y", "4"],
+        ];
+        let mut workbook = Workbook::from_rows(
+            Row::from_text(["region", "kind", "amount"]),
+            rows.iter().map(Row::from_text).collect(),
+        )
+        .unwrap();
+        let spec = JobSpec::builder()
+            .pivot_row(1)
+            .pivot_col(2)
+            .pivot_value(Aggregate::new("pivotValue", "sum", 3).unwrap())
+            .build()
+            .unwrap();
+        add_summary(&mut workbook, &spec, |text| {
+            (usize::from(text == "x"), false)
+        });
+
+        let cursor = workbook.write(Cursor::new(Vec::new())).unwrap();
+        let mut archive = zip::ZipArchive::new(cursor).unwrap();
+        let mut sheet = String::new();
+        archive
+            .by_name("xl/worksheets/sheet2.xml")
+            .unwrap()
+            .read_to_string(&mut sheet)
+            .unwrap();
+        let texts: Vec<&str> = sheet
+            .split(r#"<t xml:space="preserve">"#)
+            .skip(1)
+            .filter_map(|part| part.split_once("</t>"))
+            .map(|(text, _)| text)
+            .collect();
+        // Blank and whitespace keys share a group, sorting as text.
+        assert_eq!(
+            texts,
+            [
+                "region",
+                "x - Sum of amount",
+                "y - Sum of amount",
+                SEARCH_TERM_MATCHES_HEADER,
+                CONTRACTION_MATCHES_HEADER,
+                BLANK_KEY,
+                "east",
+                GRAND_TOTAL_LABEL,
+            ]
+        );
+        let numbers: Vec<&str> = sheet
+            .split("<v>")
+            .skip(1)
+            .filter_map(|part| part.split_once("</v>"))
+            .map(|(number, _)| number)
+            .collect();
+        assert_eq!(
+            numbers,
+            ["3", "2", "1", "0", "1", "4", "1", "0", "4", "6", "2", "0"]
+        );
+    }
+}
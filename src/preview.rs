@@ -0,0 +1,207 @@
+//! Pages of the rows of an uploaded sheet with typed values, for clients to
+//! show the data before running a job on it.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+use serde_json::Value;
+
+use crate::{
+    data::model::{
+        CellType, ColumnStats, PreviewCell, PreviewRow, PreviewStyle, RowsPreview, RowsPreviewQuery,
+    },
+    dates, formats,
+    xlsx::{CellStyle, CellValue, Styles},
+    Result,
+};
+
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1000;
+
+/// Reads the sheet asked for by `query` and returns the requested page of
+/// its data rows along with statistics over all of them.
+pub fn rows_preview(path: &Path, query: &RowsPreviewQuery) -> Result<RowsPreview> {
+    let formats::Sheet {
+        sheet_names,
+        sheet_idx,
+        workbook,
+    } = formats::open_sheet(path, query.sheet.as_deref())?;
+    let offset = query.offset.unwrap_or(0);
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+    let last_col_idx = workbook.last_column();
+    let mut styles = StyleCache::new(&workbook.styles);
+
+    let mut rows = Vec::new();
+    for (row_idx, row) in (2..).zip(&workbook.rows).skip(offset).take(limit) {
+        let mut cells = Vec::with_capacity(row.cells.len());
+        for cell in &row.cells {
+            let (is_date, style) = styles.get(cell.style)?;
+            let Some(kind) = cell_type(&cell.value, *is_date) else {
+                continue;
+            };
+            let value = match kind {
+                CellType::Date => date_value(&cell.value),
+                _ => json_value(&cell.value),
+            };
+            cells.push(PreviewCell {
+                column: cell.col,
+                value,
+                kind,
+                style: style.clone(),
+            });
+        }
+        rows.push(PreviewRow {
+            row: row_idx,
+            cells,
+        });
+    }
+
+    let mut columns: Vec<ColumnStats> = (1..=last_col_idx)
+        .map(|col_idx| ColumnStats {
+            column: col_idx,
+            name: workbook.header.value(col_idx).into_owned(),
+            ..Default::default()
+        })
+        .collect();
+    let mut distinct: Vec<HashSet<String>> = vec![HashSet::new(); columns.len()];
+    for row in &workbook.rows {
+        for cell in &row.cells {
+            let (is_date, _) = styles.get(cell.style)?;
+            let Some(kind) = cell_type(&cell.value, *is_date) else {
+                continue;
+            };
+            let Some(stats) = columns.get_mut(cell.col as usize - 1) else {
+                continue;
+            };
+            let count = match kind {
+                CellType::String => &mut stats.types.string,
+                CellType::Number => &mut stats.types.number,
+                CellType::Boolean => &mut stats.types.boolean,
+                CellType::Date => &mut stats.types.date,
+                CellType::Error => &mut stats.types.error,
+            };
+            *count += 1;
+            distinct[cell.col as usize - 1].insert(cell.value.text().into_owned());
+        }
+    }
+    for (stats, values) in columns.iter_mut().zip(&distinct) {
+        let filled = stats.types.string
+            + stats.types.number
+            + stats.types.boolean
+            + stats.types.date
+            + stats.types.error;
+        stats.blank = workbook.rows.len() - filled;
+        stats.distinct = values.len();
+    }
+
+    Ok(RowsPreview {
+        sheet: sheet_names[sheet_idx].clone(),
+        sheets: sheet_names,
+        offset,
+        limit,
+        total_rows: workbook.rows.len(),
+        header: (1..=last_col_idx)
+            .map(|col_idx| workbook.header.value(col_idx).into_owned())
+            .collect(),
+        rows,
+        columns,
+    })
+}
+
+/// Numbers and booleans as such, empty cells as null and anything else as
+/// its text.
+pub fn json_value(value: &CellValue) -> Value {
+    match value {
+        CellValue::Empty => Value::Null,
+        CellValue::Bool(v) => Value::Bool(*v),
+        CellValue::Number(text) => match text.parse::<i64>() {
+            Ok(v) => Value::from(v),
+            Err(_) => text
+                .parse::<f64>()
+                .ok()
+                .and_then(serde_json::Number::from_f64)
+                .map_or_else(|| Value::String(text.to_string()), Value::Number),
+        },
+        other => Value::String(other.text().into_owned()),
+    }
+}
+
+/// The type of a value, `None` for blank cells. Numbers count as dates when
+/// their number format shows a date or a time.
+fn cell_type(value: &CellValue, is_date: bool) -> Option<CellType> {
+    if value.text().is_empty() {
+        return None;
+    }
+    match value {
+        CellValue::Empty => None,
+        CellValue::Number(_) if is_date => Some(CellType::Date),
+        CellValue::Number(_) => Some(CellType::Number),
+        CellValue::Date(_) => Some(CellType::Date),
+        CellValue::Bool(_) => Some(CellType::Boolean),
+        CellValue::Error(_) => Some(CellType::Error),
+        CellValue::SharedString { .. }
+        | CellValue::Text(_)
+        | CellValue::FormulaString(_)
+        | CellValue::RichText(_) => Some(CellType::String),
+    }
+}
+
+/// Date serial numbers as `2024-01-31` or `2024-01-31T10:30:00`, ISO dates
+/// as written.
+fn date_value(value: &CellValue) -> Value {
+    let CellValue::Number(text) = value else {
+        return json_value(value);
+    };
+    match text.parse::<f64>().ok().and_then(dates::from_serial) {
+        None => json_value(value),
+        Some(dt) => Value::String(dates::to_iso(dt)),
+    }
+}
+
+/// Cell formats resolved once per style index, most cells share a handful.
+pub struct StyleCache<'a> {
+    styles: &'a Styles,
+    resolved: HashMap<u32, (bool, Option<PreviewStyle>)>,
+}
+
+impl<'a> StyleCache<'a> {
+    pub fn new(styles: &'a Styles) -> Self {
+        Self {
+            styles,
+            resolved: HashMap::new(),
+        }
+    }
+
+    /// Whether the format shows dates, and how it differs from the default.
+    pub fn get(&mut self, idx: u32) -> Result<&(bool, Option<PreviewStyle>)> {
+        if !self.resolved.contains_key(&idx) {
+            let style = self.styles.cell_style(idx)?;
+            self.resolved
+                .insert(idx, (style.is_date, preview_style(style)));
+        }
+        Ok(&self.resolved[&idx])
+    }
+}
+
+fn preview_style(style: CellStyle) -> Option<PreviewStyle> {
+    let CellStyle {
+        bold,
+        italic,
+        color,
+        background,
+        number_format,
+        is_date: _,
+    } = style;
+    if !bold && !italic && color.is_none() && background.is_none() && number_format.is_none() {
+        return None;
+    }
+    Some(PreviewStyle {
+        bold,
+        italic,
+        color,
+        background,
+        number_format,
+    })
+}
@@ -0,0 +1,1858 @@
+//! The workbook engine behind `/runJob`: validates, dedupes, filters and
+//! sorts the rows of a workbook or delimited file, highlights the search
+//! terms and contractions found in them and writes the result. It doesn't
+//! depend on the web layer, services can run jobs with [`process`] and a
+//! [`JobSpec`] made with [`JobSpec::builder`].
+//!
+//! Processing is CPU bound and blocking, async callers should run it on
+//! the blocking thread pool.
+
+use crate::{
+    colors::{self, CellColorProfile},
+    dates::check_date,
+    delimited::{self, Dialect},
+    error::Error,
+    filter, formats,
+    metrics::{Metrics, Phase, PhaseTimer},
+    pivot,
+    preview::json_value,
+    xlsx::{self, CellValue, Row, Styles, TextRun, Workbook},
+    Result as CrateRes,
+};
+use aho_corasick::AhoCorasick;
+use serde_json::{json, Value};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    io::{Cursor, Read, Seek, Write},
+    path::Path,
+};
+use tracing::{event, span, Level, Span};
+use zip::{write::FileOptions, ZipWriter};
+
+mod report;
+mod spec;
+
+pub use report::{
+    CellAnnotation, CellMatches, ContractionMatch, JobResult, MatchReport, MatchStats, TextRange,
+};
+pub use spec::{
+    Aggregate, AggregateFunction, DedupeAction, DedupeStrategy, FilterAction, FilterCondition,
+    FilterLogic, Macros, OutputFormat, RowFilter, SortInfo,
+};
+
+const XLSX_CONTENT_TYPE: &str = "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet";
+const XLSM_CONTENT_TYPE: &str = "application/vnd.ms-excel.sheet.macroEnabled.12";
+/// Names of the files in the zip returned for csv output.
+const CSV_RESULT_NAME: &str = "result.csv";
+const CSV_MATCHES_NAME: &str = "matches.json";
+/// Name of the sheet the match report is written to.
+const REPORT_SHEET_NAME: &str = "Report";
+/// Span name of reading the contraction file, done while the rows are
+/// sorted.
+const CONTRACTION_LOAD_PHASE: &str = "contraction_load";
+
+/// What a job does to a workbook. Columns are 1 based indexes.
+pub struct JobSpec {
+    contraction_file: Option<Vec<u8>>,
+    search_terms: Vec<String>,
+    check_date_cols: Vec<u32>,
+    sort_cols_info: Vec<SortInfo>,
+    output_format: OutputFormat,
+    macros: Macros,
+    filters: Vec<RowFilter>,
+    filter_logic: FilterLogic,
+    filter_action: FilterAction,
+    dedupe_cols: Vec<u32>,
+    dedupe_strategy: DedupeStrategy,
+    dedupe_action: DedupeAction,
+    subtotals: Vec<Aggregate>,
+    pivot_rows: Vec<u32>,
+    pivot_cols: Vec<u32>,
+    pivot_values: Vec<Aggregate>,
+    report_sheet: bool,
+}
+
+impl std::fmt::Debug for JobSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JobSpec")
+            .field(
+                "contraction_file not None",
+                &self.contraction_file.is_some(),
+            )
+            .field("search_terms", &self.search_terms)
+            .field("check_date_cols", &self.check_date_cols)
+            .field("sort_cols_info", &self.sort_cols_info)
+            .field("output_format", &self.output_format)
+            .field("macros", &self.macros)
+            .field("filters", &self.filters)
+            .field("filter_logic", &self.filter_logic)
+            .field("filter_action", &self.filter_action)
+            .field("dedupe_cols", &self.dedupe_cols)
+            .field("dedupe_strategy", &self.dedupe_strategy)
+            .field("dedupe_action", &self.dedupe_action)
+            .field("subtotals", &self.subtotals)
+            .field("pivot_rows", &self.pivot_rows)
+            .field("pivot_cols", &self.pivot_cols)
+            .field("pivot_values", &self.pivot_values)
+            .field("report_sheet", &self.report_sheet)
+            .finish()
+    }
+}
+
+impl JobSpec {
+    /// A job sorting nothing and finding nothing, writing an xlsx result.
+    pub fn builder() -> JobSpecBuilder {
+        JobSpecBuilder::default()
+    }
+
+    pub fn contraction_file(&self) -> Option<&[u8]> {
+        self.contraction_file.as_deref()
+    }
+
+    pub fn sort_infos(&self) -> &[SortInfo] {
+        &self.sort_cols_info
+    }
+
+    pub fn search_terms(&self) -> &Vec<String> {
+        &self.search_terms
+    }
+
+    pub fn check_date_cols(&self) -> &Vec<u32> {
+        &self.check_date_cols
+    }
+
+    pub fn output_format(&self) -> OutputFormat {
+        self.output_format
+    }
+
+    pub fn macros(&self) -> Macros {
+        self.macros
+    }
+
+    pub fn filters(&self) -> &[RowFilter] {
+        &self.filters
+    }
+
+    pub fn filter_logic(&self) -> FilterLogic {
+        self.filter_logic
+    }
+
+    pub fn filter_action(&self) -> FilterAction {
+        self.filter_action
+    }
+
+    pub fn dedupe_cols(&self) -> &[u32] {
+        &self.dedupe_cols
+    }
+
+    pub fn dedupe_strategy(&self) -> DedupeStrategy {
+        self.dedupe_strategy
+    }
+
+    pub fn dedupe_action(&self) -> DedupeAction {
+        self.dedupe_action
+    }
+
+    pub fn subtotals(&self) -> &[Aggregate] {
+        &self.subtotals
+    }
+
+    pub fn pivot_rows(&self) -> &[u32] {
+        &self.pivot_rows
+    }
+
+    pub fn pivot_cols(&self) -> &[u32] {
+        &self.pivot_cols
+    }
+
+    pub fn pivot_values(&self) -> &[Aggregate] {
+        &self.pivot_values
+    }
+
+    pub fn report_sheet(&self) -> bool {
+        self.report_sheet
+    }
+}
+
+/// Builds a [`JobSpec`], options left out keep their default. Columns
+/// given more than once are sorted, checked or grouped by in the order
+/// they were added.
+#[derive(Default)]
+pub struct JobSpecBuilder {
+    contraction_file: Option<Vec<u8>>,
+    search_terms: Vec<String>,
+    check_date_cols: Vec<u32>,
+    sort_cols_info: Vec<SortInfo>,
+    output_format: OutputFormat,
+    macros: Macros,
+    filters: Vec<RowFilter>,
+    filter_logic: FilterLogic,
+    filter_action: FilterAction,
+    dedupe_cols: Vec<u32>,
+    dedupe_strategy: DedupeStrategy,
+    dedupe_action: DedupeAction,
+    subtotals: Vec<Aggregate>,
+    pivot_rows: Vec<u32>,
+    pivot_cols: Vec<u32>,
+    pivot_values: Vec<Aggregate>,
+    report_sheet: bool,
+}
+
+impl JobSpecBuilder {
+    /// A workbook or delimited file whose values are highlighted as
+    /// contractions, each column in turn.
+    pub fn contraction_file(mut self, bytes: impl Into<Vec<u8>>) -> Self {
+        self.contraction_file = Some(bytes.into());
+        self
+    }
+
+    pub fn search_term(mut self, term: impl Into<String>) -> Self {
+        self.search_terms.push(term.into());
+        self
+    }
+
+    /// A column whose values have to be `mmddyy` dates.
+    pub fn check_date(mut self, column_index: u32) -> Self {
+        self.check_date_cols.push(column_index);
+        self
+    }
+
+    /// Sorts by a column, after the columns added before it.
+    pub fn sort(mut self, sort_info: SortInfo) -> Self {
+        self.sort_cols_info.push(sort_info);
+        self
+    }
+
+    pub fn output_format(mut self, output_format: OutputFormat) -> Self {
+        self.output_format = output_format;
+        self
+    }
+
+    pub fn macros(mut self, macros: Macros) -> Self {
+        self.macros = macros;
+        self
+    }
+
+    pub fn filter(mut self, filter: RowFilter) -> Self {
+        self.filters.push(filter);
+        self
+    }
+
+    pub fn filter_logic(mut self, filter_logic: FilterLogic) -> Self {
+        self.filter_logic = filter_logic;
+        self
+    }
+
+    pub fn filter_action(mut self, filter_action: FilterAction) -> Self {
+        self.filter_action = filter_action;
+        self
+    }
+
+    /// A column identifying a record, rows with the same values in all of
+    /// them are duplicates.
+    pub fn dedupe_col(mut self, column_index: u32) -> Self {
+        self.dedupe_cols.push(column_index);
+        self
+    }
+
+    pub fn dedupe_strategy(mut self, dedupe_strategy: DedupeStrategy) -> Self {
+        self.dedupe_strategy = dedupe_strategy;
+        self
+    }
+
+    pub fn dedupe_action(mut self, dedupe_action: DedupeAction) -> Self {
+        self.dedupe_action = dedupe_action;
+        self
+    }
+
+    pub fn subtotal(mut self, subtotal: Aggregate) -> Self {
+        self.subtotals.push(subtotal);
+        self
+    }
+
+    pub fn pivot_row(mut self, column_index: u32) -> Self {
+        self.pivot_rows.push(column_index);
+        self
+    }
+
+    pub fn pivot_col(mut self, column_index: u32) -> Self {
+        self.pivot_cols.push(column_index);
+        self
+    }
+
+    pub fn pivot_value(mut self, value: Aggregate) -> Self {
+        self.pivot_values.push(value);
+        self
+    }
+
+    /// Adds a `Report` sheet with the match report to xlsx results.
+    pub fn report_sheet(mut self, report_sheet: bool) -> Self {
+        self.report_sheet = report_sheet;
+        self
+    }
+
+    /// Fails for columns of 0 and for options the output format doesn't
+    /// support.
+    pub fn build(self) -> CrateRes<JobSpec> {
+        let columns = self
+            .check_date_cols
+            .iter()
+            .chain(&self.dedupe_cols)
+            .chain(&self.pivot_rows)
+            .chain(&self.pivot_cols)
+            .copied()
+            .chain(self.sort_cols_info.iter().map(SortInfo::column_index))
+            .chain(self.filters.iter().map(|filter| filter.column_index))
+            .chain(self.subtotals.iter().map(|subtotal| subtotal.column_index))
+            .chain(self.pivot_values.iter().map(|value| value.column_index));
+        for column_index in columns {
+            if column_index == 0 {
+                return Err(Error::InvalidPayload(
+                    "Invalid column index: 0, columns are numbered from 1".into(),
+                ));
+            }
+        }
+        let spec = JobSpec {
+            contraction_file: self.contraction_file,
+            search_terms: self.search_terms,
+            check_date_cols: self.check_date_cols,
+            sort_cols_info: self.sort_cols_info,
+            output_format: self.output_format,
+            macros: self.macros,
+            filters: self.filters,
+            filter_logic: self.filter_logic,
+            filter_action: self.filter_action,
+            dedupe_cols: self.dedupe_cols,
+            dedupe_strategy: self.dedupe_strategy,
+            dedupe_action: self.dedupe_action,
+            subtotals: self.subtotals,
+            pivot_rows: self.pivot_rows,
+            pivot_cols: self.pivot_cols,
+            pivot_values: self.pivot_values,
+            report_sheet: self.report_sheet,
+        };
+
+        check_subtotals(&spec)?;
+        let xlsx_only = [
+            (
+                !spec.pivot_rows.is_empty(),
+                "Summaries can only be written to xlsx results",
+            ),
+            (
+                spec.report_sheet,
+                "The report sheet can only be written to xlsx results",
+            ),
+            (
+                spec.dedupe_action == DedupeAction::Highlight,
+                "Duplicate rows can only be highlighted in xlsx results",
+            ),
+            (
+                spec.filter_action != FilterAction::Drop,
+                "Filtered rows can only be moved to a sheet or hidden in xlsx results",
+            ),
+        ];
+        if spec.output_format != OutputFormat::Xlsx {
+            if let Some((_, message)) = xlsx_only.iter().find(|(used, _)| *used) {
+                return Err(Error::Unsupported(message.to_string()));
+            }
+        }
+        Ok(spec)
+    }
+}
+
+/// The result of a job along with how often the search terms and
+/// contractions matched.
+pub struct ProcessOutput {
+    pub file: OutputFile,
+    pub report: MatchReport,
+}
+
+/// The file produced by a job.
+pub enum OutputFile {
+    Xlsx(Vec<u8>),
+    /// An xlsx workbook that kept its macros.
+    Xlsm(Vec<u8>),
+    /// The csv and the json file of matches, zipped.
+    CsvZip(Vec<u8>),
+    /// A serialized `JobResult`.
+    Json(Vec<u8>),
+}
+
+impl OutputFile {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            OutputFile::Xlsx(_) => XLSX_CONTENT_TYPE,
+            OutputFile::Xlsm(_) => XLSM_CONTENT_TYPE,
+            OutputFile::CsvZip(_) => "application/zip",
+            OutputFile::Json(_) => "application/json",
+        }
+    }
+
+    /// Appended to the download's file name, xlsx downloads have none.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFile::Xlsx(_) => "",
+            OutputFile::Xlsm(_) => ".xlsm",
+            OutputFile::CsvZip(_) => ".zip",
+            OutputFile::Json(_) => ".json",
+        }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        match self {
+            OutputFile::Xlsx(bytes)
+            | OutputFile::Xlsm(bytes)
+            | OutputFile::CsvZip(bytes)
+            | OutputFile::Json(bytes) => bytes,
+        }
+    }
+}
+
+/// Runs the whole read, validate, sort, highlight and write pipeline of
+/// `spec` on the first sheet of `workbook`, a workbook or delimited file
+/// read from its start, such as a `File` or a `Cursor` over its bytes.
+/// There's no file name to go by, delimited text is recognized by a
+/// consistent delimiter.
+pub fn process<R: Read + Seek>(workbook: R, spec: &JobSpec) -> CrateRes<ProcessOutput> {
+    run(|macros| formats::read(workbook, macros), spec, None)
+}
+
+/// [`process`], timing the phases of the job and counting the rows read in
+/// `metrics`.
+pub(crate) fn process_with_metrics(
+    workbook: &Path,
+    spec: &JobSpec,
+    metrics: &Metrics,
+) -> CrateRes<ProcessOutput> {
+    run(
+        |macros| formats::open(workbook, macros),
+        spec,
+        Some(metrics),
+    )
+}
+
+fn run(
+    read: impl FnOnce(Macros) -> CrateRes<formats::Source>,
+    spec: &JobSpec,
+    metrics: Option<&Metrics>,
+) -> CrateRes<ProcessOutput> {
+    let mut timer = PhaseTimer::new(metrics);
+    timer.start(Phase::Read);
+    // Only the first sheet of a workbook is read, as compact rows. The other
+    // parts of the package are copied over untouched when writing.
+    let formats::Source {
+        mut workbook,
+        dialect,
+    } = read(spec.macros())?;
+    if let Some(metrics) = metrics {
+        metrics.add_rows_processed(workbook.rows.len());
+    }
+    timer.start(Phase::Validate);
+
+    let (first_col_idx, first_row_idx) = (1, 1);
+    let last_col_idx = workbook.last_column();
+
+    // Before removing or filtering any rows, while the positions of the rows
+    // still give their numbers in the sheet.
+    event!(Level::TRACE, "Validating sheet");
+    if spec.output_format() == OutputFormat::Json {
+        // Invalid dates are reported with the cells of json results, the
+        // job doesn't fail on them.
+        validate_header(first_col_idx, last_col_idx, &workbook.header)?;
+    } else {
+        validate_sheet(
+            first_col_idx,
+            last_col_idx,
+            &workbook.header,
+            &workbook.rows,
+            first_row_idx,
+            spec,
+        )?;
+    }
+    event!(Level::TRACE, "Sheet is valid");
+
+    // Before filtering, for the same reason.
+    event!(Level::TRACE, "Removing duplicate rows");
+    remove_duplicates(&mut workbook, spec);
+
+    event!(Level::TRACE, "Filtering rows");
+    filter::apply(&mut workbook, spec)?;
+
+    // Duplicates to highlight, by position before sorting.
+    let mut duplicates = match spec.dedupe_action() {
+        DedupeAction::Highlight if !spec.dedupe_cols().is_empty() => {
+            find_duplicates(&workbook.rows, spec.dedupe_cols(), spec.dedupe_strategy())
+        }
+        _ => Vec::new(),
+    };
+
+    timer.start(Phase::Sort);
+    let span = Span::current();
+    let contraction_f_bytes = spec.contraction_file();
+    let contraction_str = std::thread::scope(|scope| {
+        let contraction_task = scope.spawn(move || {
+            let _entered = span!(
+                parent: &span,
+                Level::INFO,
+                "phase",
+                otel.name = CONTRACTION_LOAD_PHASE,
+                phase = CONTRACTION_LOAD_PHASE
+            )
+            .entered();
+            get_contraction_texts(contraction_f_bytes)
+        });
+
+        event!(Level::TRACE, "Collecting sort keys");
+        let mut sort_rows = get_sort_rows(&workbook.rows, spec.sort_infos());
+        event!(
+            Level::TRACE,
+            "Done collecting sort keys, Row count: {}, Key count: {}",
+            sort_rows.len(),
+            spec.sort_infos().len()
+        );
+
+        sort_cells(sort_rows.as_mut_slice(), spec.sort_infos());
+
+        event!(Level::TRACE, "Moving rows into sorted order");
+        move_rows(&mut workbook.rows, &sort_rows);
+        if !duplicates.is_empty() {
+            duplicates = sort_rows
+                .iter()
+                .map(|sort_row| duplicates[sort_row.src_pos as usize])
+                .collect();
+        }
+        drop(sort_rows);
+        event!(Level::TRACE, "Done moving rows");
+
+        match contraction_task.join() {
+            Err(_) => Err(Error::Generic(
+                "Reading the contraction file panicked".into(),
+            )),
+            Ok(contraction_str) => contraction_str,
+        }
+    })?;
+    let matcher = CellMatcher::new(spec.search_terms(), &contraction_str);
+
+    match spec.output_format() {
+        OutputFormat::Xlsx => {}
+        OutputFormat::Csv => {
+            timer.start(Phase::Write);
+            event!(Level::TRACE, "Writing csv and matches");
+            return write_csv_with_matches(&workbook, &dialect.unwrap_or_default(), &matcher).map(
+                |(bytes, report)| ProcessOutput {
+                    file: OutputFile::CsvZip(bytes),
+                    report,
+                },
+            );
+        }
+        OutputFormat::Json => {
+            timer.start(Phase::Write);
+            event!(Level::TRACE, "Writing json");
+            let date_errors = date_errors(&workbook.rows, first_row_idx, spec).collect();
+            return write_json(&workbook, &matcher, &contraction_str, date_errors).map(
+                |(bytes, report)| ProcessOutput {
+                    file: OutputFile::Json(bytes),
+                    report,
+                },
+            );
+        }
+    }
+
+    timer.start(Phase::Highlight);
+    // Before highlighting turns the cells with matches into rich text and
+    // subtotals add rows that aren't records.
+    event!(Level::TRACE, "Adding summary");
+    pivot::add_summary(&mut workbook, spec, |text| {
+        let findings = matcher.find(text);
+        (
+            findings.search_findings.len(),
+            findings.contraction_idx.is_some(),
+        )
+    });
+
+    event!(Level::TRACE, "Highlighting search terms and contractions");
+    let report = highlight_search_terms_and_contractions(
+        &mut workbook.rows,
+        &mut workbook.styles,
+        &matcher,
+    )?;
+    if spec.report_sheet() {
+        add_report_sheet(&mut workbook, &report);
+    }
+    event!(
+        Level::TRACE,
+        "Done highlighting search terms and contractions"
+    );
+    highlight_duplicates(&mut workbook, &duplicates)?;
+
+    event!(Level::TRACE, "Adding subtotals");
+    add_subtotals(&mut workbook, spec.sort_infos(), spec.subtotals());
+
+    timer.start(Phase::Write);
+    event!(Level::TRACE, "Writing to in memory file");
+    let cursor = match workbook.write(Cursor::new(Vec::new())) {
+        Err(e) => {
+            event!(
+                Level::ERROR,
+                message = "Error writing final contraction result to memory",
+                error = e.to_string()
+            );
+            return Err(e);
+        }
+        Ok(cursor) => cursor,
+    };
+    event!(Level::TRACE, "Done writting");
+
+    let file = if workbook.has_macros() {
+        OutputFile::Xlsm(cursor.into_inner())
+    } else {
+        OutputFile::Xlsx(cursor.into_inner())
+    };
+    Ok(ProcessOutput { file, report })
+}
+
+/// The values a data row is sorted by. Sorting shuffles these small records
+/// instead of the rows, which are moved once the final order is known.
+#[derive(Debug)]
+struct SortRow {
+    /// Position of the row among the data rows before sorting.
+    src_pos: u32,
+    /// One value per sort column, in the order of the sort infos.
+    keys: Box<[Box<str>]>,
+}
+
+fn get_sort_rows(rows: &[Row], sort_infos: &[SortInfo]) -> Vec<SortRow> {
+    rows.iter()
+        .enumerate()
+        .map(|(pos, row)| SortRow {
+            src_pos: pos as u32,
+            keys: sort_infos
+                .iter()
+                .map(|sort_info| row.value(sort_info.column_index()).into())
+                .collect(),
+        })
+        .collect()
+}
+
+/// Puts the data rows in the order of `sort_rows`. Rows are moved, not
+/// cloned.
+fn move_rows(rows: &mut Vec<Row>, sort_rows: &[SortRow]) {
+    let mut old_rows = std::mem::take(rows);
+    rows.extend(
+        sort_rows
+            .iter()
+            .map(|sort_row| std::mem::take(&mut old_rows[sort_row.src_pos as usize])),
+    );
+}
+
+fn sort_cells(rows: &mut [SortRow], sort_infos: &[SortInfo]) {
+    event!(Level::TRACE, "Sorting cells");
+    if sort_infos.is_empty() || rows.is_empty() {
+        event!(Level::TRACE, "No columns to sort");
+        return;
+    }
+
+    let mut sortable_rows: Vec<std::ops::Range<usize>> = Vec::new();
+
+    sort_cells_by_range(rows, 0..rows.len(), &sort_infos[0], 0);
+    event!(Level::TRACE, "First sort done...");
+
+    sort_infos
+        .iter()
+        .enumerate()
+        .skip(1)
+        .for_each(|(key_idx, sort_info)| {
+            event!(Level::TRACE, "Finding sortable rows");
+            clear_build_sortable_rows(rows, key_idx, &mut sortable_rows);
+            event!(Level::TRACE, "Sortable rows found");
+
+            for row_range in sortable_rows.iter().filter(|r| r.len() > 1) {
+                event!(Level::TRACE, "Performing sub sort in iter #{}", key_idx);
+                sort_cells_by_range(rows, row_range.clone(), sort_info, key_idx);
+                event!(Level::TRACE, "Sub sort in iter #{} done", key_idx);
+            }
+        });
+
+    event!(Level::TRACE, "Done sorting...");
+}
+
+#[inline(always)]
+fn sort_cells_by_range(
+    rows: &mut [SortRow],
+    row_range: std::ops::Range<usize>,
+    sort_info: &SortInfo,
+    key_idx: usize,
+) {
+    rows[row_range].sort_unstable_by(|s1, s2| match sort_info {
+        SortInfo::Asc { .. } => s1.keys[key_idx].cmp(&s2.keys[key_idx]),
+        SortInfo::Desc { .. } => s2.keys[key_idx].cmp(&s1.keys[key_idx]),
+    });
+}
+
+/// Splits the rows into runs sharing the same values for the first
+/// `key_count` sort columns. Those runs are what the next sort column is
+/// allowed to reorder without disturbing the preceding columns.
+#[inline(always)]
+fn clear_build_sortable_rows(
+    rows: &[SortRow],
+    key_count: usize,
+    sortable_rows: &mut Vec<std::ops::Range<usize>>,
+) {
+    sortable_rows.clear();
+    let mut start = 0;
+    for idx in 1..=rows.len() {
+        if idx == rows.len() || rows[idx].keys[..key_count] != rows[start].keys[..key_count] {
+            sortable_rows.push(start..idx);
+            start = idx;
+        }
+    }
+}
+
+/// Name of the sheet removed duplicate rows are moved to.
+const DUPLICATES_SHEET_NAME: &str = "Duplicates";
+/// Header of the column of the `Duplicates` sheet giving the row numbers.
+const ORIGINAL_ROW_HEADER: &str = "Original row";
+/// Light red fill with dark red text, as Excel highlights duplicate values.
+const DUPLICATE_BACKGROUND: &str = "FFFFC7CE";
+const DUPLICATE_TEXT_COLOR: &str = "FF9C0006";
+
+/// Moves the rows sharing the values of the dedupe columns with a row kept
+/// by the dedupe strategy to a `Duplicates` sheet, after their row number
+/// in the uploaded sheet.
+fn remove_duplicates(workbook: &mut Workbook, spec: &JobSpec) {
+    let key_cols = spec.dedupe_cols();
+    if key_cols.is_empty() || spec.dedupe_action() != DedupeAction::Remove {
+        return;
+    }
+    let mut duplicates =
+        find_duplicates(&workbook.rows, key_cols, spec.dedupe_strategy()).into_iter();
+
+    let mut removed = Vec::new();
+    let mut row_idx = 1;
+    workbook.rows.retain_mut(|row| {
+        row_idx += 1;
+        if !duplicates.next().unwrap_or(false) {
+            return true;
+        }
+        removed.push(numbered_row(
+            CellValue::Number(row_idx.to_string().into()),
+            std::mem::take(row),
+        ));
+        false
+    });
+    if !removed.is_empty() {
+        let header = numbered_row(
+            CellValue::Text(ORIGINAL_ROW_HEADER.into()),
+            workbook.header.clone(),
+        );
+        workbook.add_sheet(DUPLICATES_SHEET_NAME, header, removed);
+    }
+}
+
+/// Fills the rows flagged in `duplicates` over their whole width, on top of
+/// the search term and contraction highlighting.
+fn highlight_duplicates(workbook: &mut Workbook, duplicates: &[bool]) -> CrateRes<()> {
+    let last_col_idx = workbook.last_column();
+    let mut highlight_styles: HashMap<u32, u32> = HashMap::new();
+    for (row, _) in workbook
+        .rows
+        .iter_mut()
+        .zip(duplicates)
+        .filter(|(_, duplicate)| **duplicate)
+    {
+        // Blank columns get empty cells so the whole row is filled.
+        let mut cells = std::mem::take(&mut row.cells).into_iter().peekable();
+        let mut filled = Vec::with_capacity(last_col_idx as usize);
+        for col_idx in 1..=last_col_idx {
+            match cells.next_if(|cell| cell.col == col_idx) {
+                Some(cell) => filled.push(cell),
+                None => filled.push(xlsx::Cell::new(col_idx, 0, CellValue::Empty)),
+            }
+        }
+        for cell in filled.iter_mut() {
+            cell.style = match highlight_styles.get(&cell.style) {
+                Some(style) => *style,
+                None => {
+                    let style = workbook.styles.add_highlight(
+                        cell.style,
+                        DUPLICATE_BACKGROUND,
+                        DUPLICATE_TEXT_COLOR,
+                    )?;
+                    highlight_styles.insert(cell.style, style);
+                    style
+                }
+            };
+        }
+        row.cells = filled;
+    }
+    Ok(())
+}
+
+/// Whether each row is a duplicate. Rows are grouped the way sorting groups
+/// them, by the runs of equal keys [`clear_build_sortable_rows`] finds once
+/// the keys are in order. Rows blank in all the key columns are never
+/// duplicates.
+fn find_duplicates(rows: &[Row], key_cols: &[u32], strategy: DedupeStrategy) -> Vec<bool> {
+    let mut key_rows: Vec<SortRow> = rows
+        .iter()
+        .enumerate()
+        .map(|(pos, row)| SortRow {
+            src_pos: pos as u32,
+            keys: key_cols
+                .iter()
+                .map(|col_idx| row.value(*col_idx).into())
+                .collect(),
+        })
+        .filter(|key_row| key_row.keys.iter().any(|key| !key.trim().is_empty()))
+        .collect();
+    // Stable, the rows of a group stay in sheet order.
+    key_rows.sort_by(|r1, r2| r1.keys.cmp(&r2.keys));
+
+    let mut groups: Vec<std::ops::Range<usize>> = Vec::new();
+    clear_build_sortable_rows(&key_rows, key_cols.len(), &mut groups);
+
+    let mut duplicates = vec![false; rows.len()];
+    for group in groups.into_iter().filter(|group| group.len() > 1) {
+        let group = &key_rows[group];
+        let kept = match strategy {
+            DedupeStrategy::First => group[0].src_pos,
+            DedupeStrategy::Last => group[group.len() - 1].src_pos,
+            DedupeStrategy::MostComplete => group
+                .iter()
+                .max_by_key(|key_row| {
+                    (
+                        filled_cells(&rows[key_row.src_pos as usize]),
+                        std::cmp::Reverse(key_row.src_pos),
+                    )
+                })
+                .map_or(group[0].src_pos, |key_row| key_row.src_pos),
+        };
+        for key_row in group.iter().filter(|key_row| key_row.src_pos != kept) {
+            duplicates[key_row.src_pos as usize] = true;
+        }
+    }
+    duplicates
+}
+
+fn filled_cells(row: &Row) -> usize {
+    row.cells
+        .iter()
+        .filter(|cell| !cell.value.text().trim().is_empty())
+        .count()
+}
+
+/// `row` moved one column to the right, after a cell holding `first`.
+fn numbered_row(first: CellValue, row: Row) -> Row {
+    let mut cells = Vec::with_capacity(row.cells.len() + 1);
+    cells.push(xlsx::Cell::new(1, 0, first));
+    cells.extend(row.cells.into_iter().map(|mut cell| {
+        cell.col += 1;
+        cell
+    }));
+    Row::from_cells(cells)
+}
+
+/// Deepest outline level of Excel, the data rows take the one below the
+/// subtotals of the last sort column.
+const MAX_OUTLINE_LEVEL: usize = 7;
+const TOTAL_LABEL: &str = "Total";
+const GRAND_TOTAL_LABEL: &str = "Grand Total";
+
+fn check_subtotals(spec: &JobSpec) -> CrateRes<()> {
+    let subtotals = spec.subtotals();
+    if subtotals.is_empty() {
+        return Ok(());
+    }
+    if spec.output_format() != OutputFormat::Xlsx {
+        return Err(Error::Unsupported(
+            "Subtotals can only be written to xlsx results".into(),
+        ));
+    }
+    let key_count = spec.sort_infos().len();
+    if key_count == 0 {
+        return Err(Error::InvalidPayload(
+            "Subtotals need a sortCol to group the rows by".into(),
+        ));
+    }
+    if key_count >= MAX_OUTLINE_LEVEL {
+        return Err(Error::InvalidPayload(format!(
+            "Subtotals group by at most {} sortCol columns, got {}",
+            MAX_OUTLINE_LEVEL - 1,
+            key_count
+        )));
+    }
+    for (idx, subtotal) in subtotals.iter().enumerate() {
+        if subtotals[..idx]
+            .iter()
+            .any(|other| other.column_index == subtotal.column_index)
+        {
+            return Err(Error::InvalidPayload(format!(
+                "Column {} has more than one subtotal",
+                subtotal.column_index
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// A row of the sheet once subtotals are added.
+enum OutlineRow {
+    /// The data row at this position.
+    Data(usize),
+    Total(Row),
+}
+
+/// Adds a row of subtotals below every group of sorted rows sharing the
+/// values of the sort columns, nested the way the sort columns are, and a
+/// grand total below all of them. The rows are put in outline levels so
+/// Excel collapses every group down to its subtotals.
+fn add_subtotals(workbook: &mut Workbook, sort_infos: &[SortInfo], subtotals: &[Aggregate]) {
+    if subtotals.is_empty() || sort_infos.is_empty() {
+        return;
+    }
+    let key_rows = get_sort_rows(&workbook.rows, sort_infos);
+    let mut outline = Vec::with_capacity(workbook.rows.len() + 1);
+    group_subtotals(
+        &mut outline,
+        &workbook.rows,
+        &key_rows,
+        0..key_rows.len(),
+        0,
+        sort_infos,
+        subtotals,
+    );
+    outline.push(OutlineRow::Total(subtotal_row(
+        &workbook.rows,
+        sort_infos[0].column_index(),
+        GRAND_TOTAL_LABEL.into(),
+        subtotals,
+    )));
+    drop(key_rows);
+
+    let data_level = sort_infos.len() as u8 + 1;
+    let mut old_rows = std::mem::take(&mut workbook.rows);
+    workbook
+        .rows
+        .extend(outline.into_iter().map(|outline_row| match outline_row {
+            OutlineRow::Data(pos) => {
+                let mut row = std::mem::take(&mut old_rows[pos]);
+                row.set_outline_level(data_level);
+                row
+            }
+            OutlineRow::Total(row) => row,
+        }));
+}
+
+/// Lists the rows in `range`, which share the values of the sort columns
+/// before `key_idx`, grouped by the values of the next one with the
+/// subtotals of every group below it. The groups are the runs
+/// [`clear_build_sortable_rows`] finds, as when sorting.
+fn group_subtotals(
+    outline: &mut Vec<OutlineRow>,
+    rows: &[Row],
+    key_rows: &[SortRow],
+    range: std::ops::Range<usize>,
+    key_idx: usize,
+    sort_infos: &[SortInfo],
+    subtotals: &[Aggregate],
+) {
+    let Some(sort_info) = sort_infos.get(key_idx) else {
+        outline.extend(range.map(OutlineRow::Data));
+        return;
+    };
+    let mut groups: Vec<std::ops::Range<usize>> = Vec::new();
+    clear_build_sortable_rows(&key_rows[range.clone()], key_idx + 1, &mut groups);
+    for group in groups {
+        let group = range.start + group.start..range.start + group.end;
+        group_subtotals(
+            outline,
+            rows,
+            key_rows,
+            group.clone(),
+            key_idx + 1,
+            sort_infos,
+            subtotals,
+        );
+        let label = format!("{} {TOTAL_LABEL}", key_rows[group.start].keys[key_idx]);
+        let mut row = subtotal_row(
+            &rows[group],
+            sort_info.column_index(),
+            label.trim_start().to_string(),
+            subtotals,
+        );
+        row.set_outline_level(key_idx as u8 + 1);
+        outline.push(OutlineRow::Total(row));
+    }
+}
+
+/// The totals of `rows`, with `label` in the column of the sort column they
+/// group by unless that column has a subtotal too.
+fn subtotal_row(rows: &[Row], label_col: u32, label: String, subtotals: &[Aggregate]) -> Row {
+    let mut cells: Vec<xlsx::Cell> = subtotals
+        .iter()
+        .map(|subtotal| {
+            let values = rows.iter().map(|row| row.value(subtotal.column_index));
+            xlsx::Cell::new(
+                subtotal.column_index,
+                0,
+                pivot::aggregate(subtotal.function, values),
+            )
+        })
+        .filter(|cell| !matches!(cell.value, CellValue::Empty))
+        .collect();
+    if subtotals
+        .iter()
+        .all(|subtotal| subtotal.column_index != label_col)
+    {
+        cells.push(xlsx::Cell::new(label_col, 0, CellValue::Text(label.into())));
+    }
+    cells.sort_by_key(|cell| cell.col);
+    Row::from_cells(cells)
+}
+
+#[derive(Clone, Copy, Debug)]
+struct FoundSubTextPosInfo {
+    start_idx: usize,
+    end_idx: usize,
+    /// Index of the search term found.
+    term_idx: usize,
+}
+
+/// The default profile followed by the ones contractions cycle through.
+fn color_profiles() -> [Box<dyn CellColorProfile>; 6] {
+    let default_color: colors::White = colors::White { color_pool_pos: 0 };
+    let black: colors::Black = colors::Black { color_pool_pos: 0 };
+    let yellow: colors::Yellow = colors::Yellow { color_pool_pos: 0 };
+    let beige: colors::Beige = colors::Beige { color_pool_pos: 0 };
+    let lavender: colors::Lavender = colors::Lavender { color_pool_pos: 0 };
+    let navy_blue: colors::NavyBlue = colors::NavyBlue { color_pool_pos: 0 };
+
+    [
+        Box::from(default_color),
+        Box::from(yellow),
+        Box::from(beige),
+        Box::from(lavender),
+        Box::from(navy_blue),
+        Box::from(black),
+    ]
+}
+
+/// Finds the search terms and contractions in cell values.
+struct CellMatcher<'a> {
+    search_terms: AhoCorasick,
+    search_terms_str: &'a [String],
+    contraction_str: &'a [String],
+}
+
+/// What [`CellMatcher::find`] found in a cell.
+struct CellFindings {
+    /// The search term findings with the overlapping rule applied.
+    search_findings: Vec<FoundSubTextPosInfo>,
+    /// The index of the search term of every finding, before the
+    /// overlapping rule merged any.
+    search_term_idxs: Vec<usize>,
+    /// The index of the contraction the text matches.
+    contraction_idx: Option<usize>,
+}
+
+impl<'a> CellMatcher<'a> {
+    fn new(search_terms: &'a [String], contraction_str: &'a [String]) -> Self {
+        Self {
+            search_terms: AhoCorasick::new(search_terms).unwrap(),
+            search_terms_str: search_terms,
+            contraction_str,
+        }
+    }
+
+    fn find(&self, cell_text: &str) -> CellFindings {
+        let mut search_term_idxs = Vec::new();
+        let mut search_findings: Vec<FoundSubTextPosInfo> = self
+            .search_terms
+            .find_overlapping_iter(cell_text)
+            .map(|finding| {
+                search_term_idxs.push(finding.pattern().as_usize());
+                FoundSubTextPosInfo {
+                    start_idx: finding.start(),
+                    end_idx: finding.end() - 1,
+                    term_idx: finding.pattern().as_usize(),
+                }
+            })
+            .collect();
+
+        search_findings.sort_by(|f1, f2| f1.start_idx.partial_cmp(&f2.start_idx).unwrap());
+
+        let new_search_findings = apply_overlapping_rule(search_findings);
+
+        let contraction_idx = self
+            .contraction_str
+            .iter()
+            .position(|contraction| cell_text.trim().eq_ignore_ascii_case(contraction));
+        CellFindings {
+            search_findings: new_search_findings,
+            search_term_idxs,
+            contraction_idx,
+        }
+    }
+
+    fn report(&self) -> MatchCounter {
+        MatchCounter {
+            search_terms: (0..self.search_terms_str.len())
+                .map(|_| HitCounts::default())
+                .collect(),
+            contractions: BTreeMap::new(),
+        }
+    }
+}
+
+/// Counts the hits of the search terms and contractions cell by cell, the
+/// cells of a row one after the other.
+struct MatchCounter {
+    search_terms: Vec<HitCounts>,
+    /// By contraction index.
+    contractions: BTreeMap<usize, HitCounts>,
+}
+
+#[derive(Default)]
+struct HitCounts {
+    hits: usize,
+    rows: usize,
+    last_row_idx: Option<u32>,
+    columns: BTreeSet<u32>,
+}
+
+impl HitCounts {
+    fn add(&mut self, row_idx: u32, col_idx: u32) {
+        self.hits += 1;
+        if self.last_row_idx != Some(row_idx) {
+            self.rows += 1;
+            self.last_row_idx = Some(row_idx);
+        }
+        self.columns.insert(col_idx);
+    }
+
+    fn into_stats(self, text: &str) -> MatchStats {
+        MatchStats {
+            text: text.to_string(),
+            hits: self.hits,
+            rows: self.rows,
+            columns: self.columns.into_iter().collect(),
+        }
+    }
+}
+
+impl MatchCounter {
+    fn add(&mut self, row_idx: u32, col_idx: u32, findings: &CellFindings) {
+        for idx in &findings.search_term_idxs {
+            self.search_terms[*idx].add(row_idx, col_idx);
+        }
+        if let Some(idx) = findings.contraction_idx {
+            self.contractions
+                .entry(idx)
+                .or_default()
+                .add(row_idx, col_idx);
+        }
+    }
+
+    fn into_report(self, matcher: &CellMatcher) -> MatchReport {
+        MatchReport {
+            search_terms: self
+                .search_terms
+                .into_iter()
+                .zip(matcher.search_terms_str)
+                .map(|(counts, text)| counts.into_stats(text))
+                .collect(),
+            contractions: self
+                .contractions
+                .into_iter()
+                .map(|(idx, counts)| counts.into_stats(&matcher.contraction_str[idx]))
+                .collect(),
+        }
+    }
+}
+
+/// Adds a `Report` sheet listing the match report, with the names of the
+/// columns rather than their indexes.
+fn add_report_sheet(workbook: &mut Workbook, report: &MatchReport) {
+    let header = Row::from_text(["Kind", "Text", "Hits", "Rows", "Columns"]);
+    let kinds = std::iter::repeat("Search term")
+        .zip(&report.search_terms)
+        .chain(std::iter::repeat("Contraction").zip(&report.contractions));
+    let rows = kinds
+        .map(|(kind, stats)| {
+            let columns: Vec<String> = stats
+                .columns
+                .iter()
+                .map(|col_idx| match workbook.header.value(*col_idx).trim() {
+                    "" => col_idx.to_string(),
+                    name => name.to_string(),
+                })
+                .collect();
+            Row::from_cells(vec![
+                xlsx::Cell::new(1, 0, CellValue::Text(kind.into())),
+                xlsx::Cell::new(2, 0, CellValue::Text(stats.text.as_str().into())),
+                xlsx::Cell::new(3, 0, CellValue::Number(stats.hits.to_string().into())),
+                xlsx::Cell::new(4, 0, CellValue::Number(stats.rows.to_string().into())),
+                xlsx::Cell::new(5, 0, CellValue::Text(columns.join(", ").into())),
+            ])
+        })
+        .collect();
+    workbook.add_sheet(REPORT_SHEET_NAME, header, rows);
+}
+
+/// Index of the color profile of cells matching the contraction at
+/// `contraction_idx`. Index 0 is the default profile, for cells matching
+/// none, contractions past the last profile start over at the first one.
+fn color_idx(contraction_idx: Option<usize>) -> usize {
+    contraction_idx.map_or(0, |idx| idx % 5 + 1)
+}
+
+/// The byte ranges of the findings, end exclusive.
+fn text_ranges(findings: &[FoundSubTextPosInfo]) -> Vec<TextRange> {
+    findings
+        .iter()
+        .map(|finding| TextRange {
+            start: finding.start_idx,
+            end: finding.end_idx + 1,
+            term: finding.term_idx,
+        })
+        .collect()
+}
+
+/// Highlights the cells and returns how often the search terms and
+/// contractions matched.
+fn highlight_search_terms_and_contractions(
+    rows: &mut [Row],
+    styles: &mut Styles,
+    matcher: &CellMatcher,
+) -> CrateRes<MatchReport> {
+    let mut color_profiles = color_profiles();
+    let mut counter = matcher.report();
+
+    // Cells sharing a format and a color profile share the highlighted
+    // format too, keyed by (original format, color profile).
+    let mut highlight_styles: HashMap<(u32, usize), u32> = HashMap::new();
+
+    let cells = (2..)
+        .zip(rows.iter_mut())
+        .flat_map(|(row_idx, row)| row.cells.iter_mut().map(move |cell| (row_idx, cell)));
+    for (row_idx, cell) in cells {
+        let findings = matcher.find(&cell.value.text());
+        counter.add(row_idx, cell.col, &findings);
+        let color_idx = color_idx(findings.contraction_idx);
+        let color_profile = &mut color_profiles[color_idx];
+
+        cell.style = match highlight_styles.get(&(cell.style, color_idx)) {
+            Some(style) => *style,
+            None => {
+                let style = styles.add_highlight(
+                    cell.style,
+                    &colors::to_argb(&color_profile.get_background_color()),
+                    &colors::to_argb(&color_profile.get_default_text_color()),
+                )?;
+                highlight_styles.insert((cell.style, color_idx), style);
+                style
+            }
+        };
+        apply_formatting(cell, color_profile, findings.search_findings);
+    }
+
+    Ok(counter.into_report(matcher))
+}
+
+/// The sorted rows with typed values, along with the matches of every cell
+/// and the `date_errors` of the sorted rows, in place of highlighting them.
+fn write_json(
+    workbook: &Workbook,
+    matcher: &CellMatcher,
+    contraction_str: &[String],
+    date_errors: Vec<DateError>,
+) -> CrateRes<(Vec<u8>, MatchReport)> {
+    let color_profiles = color_profiles();
+    let mut counter = matcher.report();
+    let last_col_idx = workbook.last_column();
+    let mut annotations: BTreeMap<(u32, u32), CellAnnotation> = BTreeMap::new();
+    let mut rows = Vec::with_capacity(workbook.rows.len());
+    for (row_idx, row) in (2..).zip(&workbook.rows) {
+        rows.push(
+            (1..=last_col_idx)
+                .map(|col_idx| {
+                    row.cell(col_idx)
+                        .map_or(Value::Null, |cell| json_value(&cell.value))
+                })
+                .collect(),
+        );
+        for cell in &row.cells {
+            let findings = matcher.find(&cell.value.text());
+            counter.add(row_idx, cell.col, &findings);
+            let CellFindings {
+                search_findings,
+                contraction_idx,
+                ..
+            } = findings;
+            if search_findings.is_empty() && contraction_idx.is_none() {
+                continue;
+            }
+            let contraction = contraction_idx.map(|idx| {
+                let color_profile = &color_profiles[color_idx(Some(idx))];
+                ContractionMatch {
+                    contraction: contraction_str[idx].clone(),
+                    color_profile: color_idx(Some(idx)),
+                    background: color_profile.get_background_color(),
+                    text_color: color_profile.get_default_text_color(),
+                }
+            });
+            annotations.insert(
+                (row_idx, cell.col),
+                CellAnnotation {
+                    row: row_idx,
+                    column: cell.col,
+                    search_terms: text_ranges(&search_findings),
+                    contraction,
+                    validation_error: None,
+                },
+            );
+        }
+    }
+    for error in date_errors {
+        annotations
+            .entry((error.row_idx, error.col_idx))
+            .or_insert_with(|| CellAnnotation {
+                row: error.row_idx,
+                column: error.col_idx,
+                search_terms: Vec::new(),
+                contraction: None,
+                validation_error: None,
+            })
+            .validation_error = Some(error.message);
+    }
+
+    let result = JobResult {
+        header: (1..=last_col_idx)
+            .map(|col_idx| workbook.header.value(col_idx).into_owned())
+            .collect(),
+        rows,
+        annotations: annotations.into_values().collect(),
+        report: counter.into_report(matcher),
+    };
+    let bytes = serde_json::to_vec(&result).map_err(|e| Error::IOError(e.to_string()))?;
+    Ok((bytes, result.report))
+}
+
+/// Zips the sorted rows as csv along with a json file listing the matches
+/// of every cell that has any, in place of highlighting them, and the match
+/// report.
+fn write_csv_with_matches(
+    workbook: &Workbook,
+    dialect: &Dialect,
+    matcher: &CellMatcher,
+) -> CrateRes<(Vec<u8>, MatchReport)> {
+    let color_profiles = color_profiles();
+    let mut counter = matcher.report();
+    let mut matches: Vec<CellMatches> = Vec::new();
+    for (row_idx, row) in (2..).zip(&workbook.rows) {
+        for cell in &row.cells {
+            let findings = matcher.find(&cell.value.text());
+            counter.add(row_idx, cell.col, &findings);
+            let CellFindings {
+                search_findings,
+                contraction_idx,
+                ..
+            } = findings;
+            if search_findings.is_empty() && contraction_idx.is_none() {
+                continue;
+            }
+            matches.push(CellMatches {
+                row: row_idx,
+                column: cell.col,
+                search_terms: text_ranges(&search_findings),
+                contraction_background: contraction_idx
+                    .is_some()
+                    .then(|| color_profiles[color_idx(contraction_idx)].get_background_color()),
+            });
+        }
+    }
+
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = FileOptions::default();
+    let zip_error = |e: zip::result::ZipError| Error::IOError(e.to_string());
+    zip.start_file(CSV_RESULT_NAME, options)
+        .map_err(zip_error)?;
+    delimited::write(
+        &workbook.header,
+        &workbook.rows,
+        workbook.last_column(),
+        dialect,
+        &mut zip,
+    )?;
+    zip.start_file(CSV_MATCHES_NAME, options)
+        .map_err(zip_error)?;
+    let report = counter.into_report(matcher);
+    if let Err(e) =
+        serde_json::to_writer(&mut zip, &json!({ "matches": matches, "report": report }))
+    {
+        return Err(Error::IOError(e.to_string()));
+    }
+    zip.flush().map_err(|e| Error::IOError(e.to_string()))?;
+    match zip.finish() {
+        Err(e) => Err(zip_error(e)),
+        Ok(cursor) => Ok((cursor.into_inner(), report)),
+    }
+}
+
+/// Splits the cell text into rich text runs, search findings get a bold
+/// font in a color of the profile. The background and default text color
+/// come from the cell format.
+#[inline(always)]
+fn apply_formatting(
+    cell: &mut xlsx::Cell,
+    color_profile: &mut Box<dyn CellColorProfile>,
+    new_search_findings: Vec<FoundSubTextPosInfo>,
+) {
+    // Formula cells keep their formula, a rich text value would replace it.
+    if new_search_findings.is_empty() || cell.has_formula() {
+        color_profile.reset_color_pool_pos();
+        return;
+    }
+
+    let cell_text = cell.value.text().into_owned();
+    let plain_run = |text: &str| TextRun {
+        text: text.into(),
+        color: None,
+        bold: false,
+    };
+    let mut runs = Vec::with_capacity(new_search_findings.len() * 2 + 1);
+    let mut pos = 0;
+    for finding in new_search_findings {
+        let start = finding.start_idx.max(pos);
+        let end = finding.end_idx + 1;
+        if start >= end || !cell_text.is_char_boundary(start) || !cell_text.is_char_boundary(end) {
+            continue;
+        }
+        if pos < start {
+            runs.push(plain_run(&cell_text[pos..start]));
+        }
+        runs.push(TextRun {
+            text: cell_text[start..end].into(),
+            color: Some(colors::to_argb(&color_profile.get_color())),
+            bold: true,
+        });
+        pos = end;
+    }
+    if pos < cell_text.len() {
+        runs.push(plain_run(&cell_text[pos..]));
+    }
+    cell.value = CellValue::RichText(runs.into_boxed_slice());
+    color_profile.reset_color_pool_pos();
+}
+
+#[inline(always)]
+fn apply_overlapping_rule(search_findings: Vec<FoundSubTextPosInfo>) -> Vec<FoundSubTextPosInfo> {
+    let mut new_search_findings = Vec::clone(&search_findings);
+
+    for i in 0..search_findings.len() {
+        let f1 = search_findings[i];
+        for j in i + 1..search_findings.len() {
+            let f2 = search_findings[j];
+            let new_f2 = &mut new_search_findings[j];
+            let range = f1.start_idx..=f1.end_idx;
+            if range.contains(&f2.start_idx) {
+                if range.contains(&f2.end_idx) {
+                    new_f2.end_idx = 0;
+                    new_f2.start_idx = 0;
+                    continue;
+                }
+                let new_start = f1.end_idx + 1;
+                if new_start > new_f2.start_idx && new_start < new_f2.end_idx {
+                    new_f2.start_idx = new_start;
+                } else {
+                    new_f2.start_idx = new_f2.end_idx
+                }
+            }
+        }
+    }
+    let new_search_findings: Vec<FoundSubTextPosInfo> = new_search_findings
+        .into_iter()
+        .filter(|finding| finding.start_idx != 0 || finding.end_idx != 0)
+        .collect();
+    event!(
+        Level::DEBUG,
+        message = "Final findings",
+        findings = format!("{:?}", new_search_findings),
+        previous = format!("{:?}", search_findings)
+    );
+    new_search_findings
+}
+
+/// The values of the contraction file, read in memory, column by column.
+fn get_contraction_texts(contraction_f_bytes: Option<&[u8]>) -> CrateRes<Vec<String>> {
+    let mut contraction_str: Vec<String> = Vec::new();
+    if let Some(contraction_f_bytes) = contraction_f_bytes {
+        // Only the values are needed, whatever the format of the file.
+        let contraction_wkbook =
+            formats::read(Cursor::new(contraction_f_bytes), Macros::Strip)?.workbook;
+
+        for col_idx in 1..=contraction_wkbook.last_column() {
+            for row in &contraction_wkbook.rows {
+                let cell_text = row.value(col_idx);
+                let cell_text = cell_text.trim();
+                if !cell_text.is_empty() {
+                    contraction_str.push(cell_text.into());
+                }
+            }
+        }
+    }
+    Ok(contraction_str)
+}
+
+fn validate_sheet(
+    first_col_idx: u32,
+    last_col_idx: u32,
+    header: &Row,
+    rows: &[Row],
+    first_row_idx: u32,
+    spec: &JobSpec,
+) -> CrateRes<()> {
+    validate_header(first_col_idx, last_col_idx, header)?;
+    match date_errors(rows, first_row_idx, spec).next() {
+        Some(error) => Err(Error::InValidExcelFile(error.message)),
+        None => Ok(()),
+    }
+}
+
+fn validate_header(first_col_idx: u32, last_col_idx: u32, header: &Row) -> CrateRes<()> {
+    // verify header row has no empty values
+    for col_idx in first_col_idx..=last_col_idx {
+        let row_val = header.value(col_idx);
+        if row_val.trim().is_empty() {
+            return Err(Error::InValidExcelFile("Incomplete title bar".into()));
+        }
+    }
+    Ok(())
+}
+
+struct DateError {
+    row_idx: u32,
+    col_idx: u32,
+    message: String,
+}
+
+/// The values of the date columns that aren't `mmddyy` dates, column by
+/// column. Rows are numbered from `first_row_idx + 1`.
+fn date_errors<'a>(
+    rows: &'a [Row],
+    first_row_idx: u32,
+    spec: &'a JobSpec,
+) -> impl Iterator<Item = DateError> + 'a {
+    spec.check_date_cols().iter().flat_map(move |col_idx| {
+        (first_row_idx + 1..)
+            .zip(rows)
+            .filter_map(move |(row_idx, row)| {
+                check_date(&row.value(*col_idx), *col_idx, row_idx).map(|message| DateError {
+                    row_idx,
+                    col_idx: *col_idx,
+                    message,
+                })
+            })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_message(result: CrateRes<ProcessOutput>) -> String {
+        match result {
+            Err(Error::InValidExcelFile(message)) => message,
+            Err(e) => panic!("unexpected error {e:?}"),
+            Ok(_) => panic!("the job didn't fail"),
+        }
+    }
+
+    #[test]
+    fn date_errors_are_numbered_as_in_the_sheet() {
+        let file = "name,date\na,010124\nb,010224\nc,13xx24\n";
+        let spec = JobSpec::builder()
+            .check_date(2)
+            .filter(RowFilter::parse("1,eq,c").unwrap())
+            .output_format(OutputFormat::Csv)
+            .build()
+            .unwrap();
+        assert!(error_message(process(Cursor::new(file), &spec)).ends_with("row: 4"));
+    }
+
+    #[test]
+    fn date_errors_of_removed_duplicates_are_numbered_as_in_the_sheet() {
+        let file = "name,date\na,010124\na,010124\nb,13xx24\n";
+        let spec = JobSpec::builder()
+            .check_date(2)
+            .dedupe_col(1)
+            .output_format(OutputFormat::Csv)
+            .build()
+            .unwrap();
+        assert!(error_message(process(Cursor::new(file), &spec)).ends_with("row: 4"));
+    }
+
+    fn rows(values: &[[&str; 3]]) -> Vec<Row> {
+        values.iter().map(Row::from_text).collect()
+    }
+
+    #[test]
+    fn dedupe_strategies() {
+        let rows = rows(&[
+            ["a", "1", ""],
+            ["b", "2", "x"],
+            ["a", "3", "x"],
+            ["", "", "blank key"],
+            ["a", "4", ""],
+            ["", "", "blank key"],
+        ]);
+        let duplicates = |strategy| find_duplicates(&rows, &[1], strategy);
+        assert_eq!(
+            duplicates(DedupeStrategy::First),
+            [false, false, true, false, true, false]
+        );
+        assert_eq!(
+            duplicates(DedupeStrategy::Last),
+            [true, false, true, false, false, false]
+        );
+        assert_eq!(
+            duplicates(DedupeStrategy::MostComplete),
+            [true, false, false, false, true, false]
+        );
+    }
+
+    #[test]
+    fn dedupe_keys_span_columns() {
+        let rows = rows(&[["a", "1", "x"], ["a", "2", "x"], ["a", "1", "y"]]);
+        assert_eq!(
+            find_duplicates(&rows, &[1, 2], DedupeStrategy::First),
+            [false, false, true]
+        );
+        assert_eq!(
+            find_duplicates(&rows, &[3], DedupeStrategy::First),
+            [false, true, false]
+        );
+    }
+
+    #[test]
+    fn removed_duplicates_keep_their_row_number() {
+        let header = Row::from_text(["key", "value"]);
+        let mut workbook = Workbook::from_rows(
+            header,
+            ["a", "b", "a", "a"]
+                .into_iter()
+                .map(|key| Row::from_text([key, "v"]))
+                .collect(),
+        )
+        .unwrap();
+        let spec = JobSpec::builder().dedupe_col(1).build().unwrap();
+        remove_duplicates(&mut workbook, &spec);
+
+        assert_eq!(workbook.rows.len(), 2);
+        let cursor = workbook.write(Cursor::new(Vec::new())).unwrap();
+        let mut archive = zip::ZipArchive::new(cursor).unwrap();
+        let mut sheet = String::new();
+        std::io::Read::read_to_string(
+            &mut archive.by_name("xl/worksheets/sheet2.xml").unwrap(),
+            &mut sheet,
+        )
+        .unwrap();
+        assert!(sheet.contains(ORIGINAL_ROW_HEADER));
+        assert!(sheet.contains("<v>4</v>") && sheet.contains("<v>5</v>"));
+    }
+
+    /// Level and values of the first `cols` columns of every row.
+    fn outline(workbook: &Workbook, cols: u32) -> Vec<(u8, Vec<String>)> {
+        workbook
+            .rows
+            .iter()
+            .map(|row| {
+                let values = (1..=cols).map(|col| row.value(col).into_owned()).collect();
+                (row.outline_level(), values)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn subtotal_outline_levels() {
+        let rows = [
+            ["east", "a", "1"],
+            ["east", "b", "2"],
+            ["east", "b", "3"],
+            ["west", "a", "4"],
+        ];
+        let mut workbook = Workbook::from_rows(
+            Row::from_text(["region", "city", "amount"]),
+            rows.iter().map(Row::from_text).collect(),
+        )
+        .unwrap();
+        let sort_infos = [
+            SortInfo::new("asc", 1).unwrap(),
+            SortInfo::new("asc", 2).unwrap(),
+        ];
+        let subtotals = [Aggregate::new("subtotal", "sum", 3).unwrap()];
+        add_subtotals(&mut workbook, &sort_infos, &subtotals);
+
+        let row = |level, values: [&str; 3]| (level, values.map(String::from).to_vec());
+        assert_eq!(
+            outline(&workbook, 3),
+            [
+                row(3, ["east", "a", "1"]),
+                row(2, ["", "a Total", "1"]),
+                row(3, ["east", "b", "2"]),
+                row(3, ["east", "b", "3"]),
+                row(2, ["", "b Total", "5"]),
+                row(1, ["east Total", "", "6"]),
+                row(3, ["west", "a", "4"]),
+                row(2, ["", "a Total", "4"]),
+                row(1, ["west Total", "", "4"]),
+                row(0, ["Grand Total", "", "10"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn subtotals_of_blank_keys_and_label_columns() {
+        let mut workbook = Workbook::from_rows(
+            Row::from_text(["region", "amount"]),
+            vec![Row::from_text(["", "2"]), Row::from_text(["x", "n/a"])],
+        )
+        .unwrap();
+        let sort_infos = [SortInfo::new("asc", 1).unwrap()];
+        let subtotals = [
+            Aggregate::new("subtotal", "count", 1).unwrap(),
+            Aggregate::new("subtotal", "avg", 2).unwrap(),
+        ];
+        add_subtotals(&mut workbook, &sort_infos, &subtotals);
+
+        // The counts take the place of the labels, averages of no numbers
+        // are left blank.
+        let row = |level, values: [&str; 2]| (level, values.map(String::from).to_vec());
+        assert_eq!(
+            outline(&workbook, 2),
+            [
+                row(2, ["", "2"]),
+                row(1, ["0", "2"]),
+                row(2, ["x", "n/a"]),
+                row(1, ["1", ""]),
+                row(0, ["1", "2"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn subtotals_need_sort_columns() {
+        let spec = JobSpec::builder()
+            .subtotal(Aggregate::new("subtotal", "sum", 2).unwrap())
+            .build();
+        assert!(matches!(spec, Err(Error::InvalidPayload(_))));
+
+        let mut builder =
+            JobSpec::builder().subtotal(Aggregate::new("subtotal", "sum", 2).unwrap());
+        for col in 1..=MAX_OUTLINE_LEVEL as u32 {
+            builder = builder.sort(SortInfo::new("asc", col).unwrap());
+        }
+        assert!(matches!(builder.build(), Err(Error::InvalidPayload(_))));
+    }
+
+    fn strings(texts: &[&str]) -> Vec<String> {
+        texts.iter().map(|text| text.to_string()).collect()
+    }
+
+    #[test]
+    fn overlapping_search_terms() {
+        let terms = strings(&["an", "nan"]);
+        let matcher = CellMatcher::new(&terms, &[]);
+        let findings = matcher.find("banana");
+        // Every occurrence counts, the highlighted ranges don't overlap.
+        assert_eq!(findings.search_term_idxs, [0, 1, 0]);
+        let ranges: Vec<(usize, usize, usize)> = text_ranges(&findings.search_findings)
+            .iter()
+            .map(|range| (range.start, range.end, range.term))
+            .collect();
+        // What is left of "nan" after "an" still reports "nan".
+        assert_eq!(ranges, [(1, 3, 0), (3, 5, 1)]);
+    }
+
+    #[test]
+    fn contractions_match_whole_cells() {
+        let contractions = strings(&["can't", "won't"]);
+        let matcher = CellMatcher::new(&[], &contractions);
+        assert_eq!(matcher.find(" WON'T ").contraction_idx, Some(1));
+        assert_eq!(matcher.find("can't stop").contraction_idx, None);
+    }
+
+    #[test]
+    fn match_report() {
+        let terms = strings(&["x", "zzz"]);
+        let contractions = strings(&["can't", "won't"]);
+        let matcher = CellMatcher::new(&terms, &contractions);
+        let mut counter = matcher.report();
+        for (row_idx, col_idx, text) in [
+            (2, 1, "xx"),
+            (2, 3, "x"),
+            (3, 2, "can't"),
+            (4, 2, "can't"),
+            (4, 3, "b"),
+        ] {
+            counter.add(row_idx, col_idx, &matcher.find(text));
+        }
+        let report = counter.into_report(&matcher);
+
+        let stats = |stats: &MatchStats| {
+            (
+                stats.text.clone(),
+                stats.hits,
+                stats.rows,
+                stats.columns.clone(),
+            )
+        };
+        assert_eq!(
+            report.search_terms.iter().map(stats).collect::<Vec<_>>(),
+            [
+                ("x".to_string(), 3, 1, vec![1, 3]),
+                ("zzz".to_string(), 0, 0, vec![])
+            ]
+        );
+        // Only the contractions with hits are listed.
+        assert_eq!(
+            report.contractions.iter().map(stats).collect::<Vec<_>>(),
+            [("can't".to_string(), 2, 2, vec![2])]
+        );
+    }
+
+    fn xlsx(header: &[&str], rows: &[&[&str]]) -> Vec<u8> {
+        let rows = rows.iter().map(|row| Row::from_text(row.iter())).collect();
+        Workbook::from_rows(Row::from_text(header), rows)
+            .unwrap()
+            .write(Cursor::new(Vec::new()))
+            .unwrap()
+            .into_inner()
+    }
+
+    #[test]
+    fn workbooks_and_contractions_are_read_in_memory() {
+        let workbook = xlsx(&["name", "status"], &[&["b", "closed"], &["a", "open"]]);
+        let spec = JobSpec::builder()
+            .contraction_file(xlsx(&["status"], &[&["closed"]]))
+            .search_term("open")
+            .sort(SortInfo::Asc { column_index: 1 })
+            .build()
+            .unwrap();
+        let output = process(Cursor::new(workbook), &spec).unwrap();
+        assert!(matches!(output.file, OutputFile::Xlsx(_)));
+        assert_eq!(output.report.search_terms[0].hits, 1);
+        assert_eq!(output.report.contractions[0].text, "closed");
+        assert_eq!(output.report.contractions[0].hits, 1);
+    }
+
+    #[test]
+    fn delimited_text_needs_a_delimiter_in_memory() {
+        let spec = JobSpec::builder().build().unwrap();
+        assert!(process(Cursor::new("name;score\na;1\n"), &spec).is_ok());
+        assert!(matches!(
+            process(Cursor::new("name\na\n"), &spec),
+            Err(Error::InValidExcelFile(_))
+        ));
+    }
+}
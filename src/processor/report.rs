@@ -0,0 +1,95 @@
+//! Where the search terms and contractions of a job matched, reported with
+//! its result.
+
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// Search term and contraction matches of a cell, as listed in the json
+/// file of csv results.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CellMatches {
+    /// 1 based row number in the result, the header being row 1.
+    pub row: u32,
+    pub column: u32,
+    /// Byte ranges of the search terms in the value, end exclusive.
+    pub search_terms: Vec<TextRange>,
+    /// Background color of the contraction the value matched.
+    pub contraction_background: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TextRange {
+    pub start: usize,
+    pub end: usize,
+    /// Index of the search term matched, in the order they were given.
+    pub term: usize,
+}
+
+/// The processed sheet, returned by `/runJob` for the json output format.
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct JobResult {
+    pub header: Vec<String>,
+    /// The sorted rows, one value per column of the header. Numbers and
+    /// booleans are json numbers and booleans, empty cells are null and
+    /// everything else is a string.
+    #[schema(value_type = Vec<Vec<Object>>)]
+    pub rows: Vec<Vec<serde_json::Value>>,
+    /// The cells with anything to report, ordered by row then column.
+    pub annotations: Vec<CellAnnotation>,
+    pub report: MatchReport,
+}
+
+/// How often the search terms and contractions of a job matched, sent with
+/// every result in the `X-Match-Report` header.
+#[derive(Debug, Default, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MatchReport {
+    /// Every search term, in the order they were given.
+    pub search_terms: Vec<MatchStats>,
+    /// The contractions matching any cell, in the order of the contraction
+    /// file.
+    pub contractions: Vec<MatchStats>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MatchStats {
+    pub text: String,
+    /// Occurrences of a search term, overlapping ones included, or cells
+    /// matching a contraction.
+    pub hits: usize,
+    /// Rows with a hit.
+    pub rows: usize,
+    /// 1 based columns with a hit.
+    pub columns: Vec<u32>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CellAnnotation {
+    /// 1 based row number in the result, the header being row 1.
+    pub row: u32,
+    pub column: u32,
+    /// Byte ranges of the search terms in the value, end exclusive.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub search_terms: Vec<TextRange>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contraction: Option<ContractionMatch>,
+    /// Why the value of a date column isn't a valid date.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub validation_error: Option<String>,
+}
+
+/// The contraction a cell matched and the colors it is highlighted with.
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ContractionMatch {
+    pub contraction: String,
+    /// Index of the color profile, contractions cycle through profiles 1
+    /// to 5.
+    pub color_profile: usize,
+    pub background: String,
+    pub text_color: String,
+}
@@ -0,0 +1,397 @@
+//! What a job does, the options of a [`JobSpec`](super::JobSpec) along with
+//! how they are parsed from the text `/runJob` takes.
+
+use chrono::NaiveDate;
+use regex::Regex;
+
+use crate::{dates, error::Error, Result};
+
+#[derive(Debug)]
+pub enum SortInfo {
+    Asc { column_index: u32 },
+    Desc { column_index: u32 },
+}
+
+impl SortInfo {
+    pub(crate) const ASC: &'static str = "asc";
+    const DESC: &'static str = "desc";
+
+    /// Sorts the column in `order`, `asc` or `desc`.
+    pub fn new(order: &str, column_index: u32) -> Result<Self> {
+        match order.trim().to_lowercase().as_str() {
+            SortInfo::ASC => Ok(SortInfo::Asc { column_index }),
+            SortInfo::DESC => Ok(SortInfo::Desc { column_index }),
+            other => Err(Error::InvalidPayload(format!(
+                "Invalid sort order value: Got {}, Expected: asc / desc",
+                other
+            ))),
+        }
+    }
+
+    /// The 1 based index of the column to sort.
+    pub fn column_index(&self) -> u32 {
+        match self {
+            SortInfo::Asc { column_index } | SortInfo::Desc { column_index } => *column_index,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Xlsx,
+    /// The sorted rows as csv, with the match positions in a json file
+    /// alongside.
+    Csv,
+    /// The sorted rows as a [`JobResult`](super::JobResult).
+    Json,
+}
+
+impl OutputFormat {
+    const XLSX: &'static str = "xlsx";
+    const CSV: &'static str = "csv";
+    const JSON: &'static str = "json";
+
+    /// Parses the option, empty text giving the default.
+    pub fn parse(text: &str) -> Result<Self> {
+        match text.trim().to_lowercase().as_str() {
+            "" | OutputFormat::XLSX => Ok(OutputFormat::Xlsx),
+            OutputFormat::CSV => Ok(OutputFormat::Csv),
+            OutputFormat::JSON => Ok(OutputFormat::Json),
+            other => Err(Error::InvalidPayload(format!(
+                "Invalid output format: Got {}, Expected: xlsx / csv / json",
+                other
+            ))),
+        }
+    }
+}
+
+/// What happens to the macros of a workbook when it is processed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Macros {
+    /// Xlsm workbooks come back as xlsm with their macros, xls and ods
+    /// workbooks with macros are refused.
+    #[default]
+    Keep,
+    /// The result is a plain xlsx without the macros.
+    Strip,
+}
+
+impl Macros {
+    const KEEP: &'static str = "keep";
+    const STRIP: &'static str = "strip";
+
+    /// Parses the option, empty text giving the default.
+    pub fn parse(text: &str) -> Result<Self> {
+        match text.trim().to_lowercase().as_str() {
+            "" | Macros::KEEP => Ok(Macros::Keep),
+            Macros::STRIP => Ok(Macros::Strip),
+            other => Err(Error::InvalidPayload(format!(
+                "Invalid macros option: Got {}, Expected: keep / strip",
+                other
+            ))),
+        }
+    }
+}
+
+/// A condition on the value of a column, see [`FilterCondition`].
+#[derive(Debug)]
+pub struct RowFilter {
+    /// The 1 based index of the column.
+    pub column_index: u32,
+    pub condition: FilterCondition,
+}
+
+#[derive(Debug)]
+pub enum FilterCondition {
+    Equals(String),
+    Contains(String),
+    /// Numbers between the bounds, both included.
+    Range {
+        min: Option<f64>,
+        max: Option<f64>,
+    },
+    Regex(Regex),
+    Before(NaiveDate),
+    After(NaiveDate),
+    Blank,
+    NotBlank,
+}
+
+impl RowFilter {
+    const EQ: &'static str = "eq";
+    const CONTAINS: &'static str = "contains";
+    const RANGE: &'static str = "range";
+    const REGEX: &'static str = "regex";
+    const BEFORE: &'static str = "before";
+    const AFTER: &'static str = "after";
+    const BLANK: &'static str = "blank";
+    const NOT_BLANK: &'static str = "notblank";
+
+    /// Parses `column,operator,value`. The value is the rest of the text,
+    /// commas included.
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut parts = text.splitn(3, ',');
+        let (index, operator) = match (parts.next(), parts.next()) {
+            (Some(index), Some(operator)) => (index.trim(), operator),
+            _ => {
+                return Err(Error::InvalidPayload(format!(
+                    "filter has to be of form column,operator,value. Got: {}",
+                    text
+                )))
+            }
+        };
+        let column_index = match index.parse::<u32>() {
+            Ok(column_index) if column_index > 0 => column_index,
+            _ => {
+                return Err(Error::InvalidPayload(format!(
+                    "Invalid value passed as filter column index. Got {}, expected a valid number",
+                    index
+                )))
+            }
+        };
+        Self::new(column_index, operator, parts.next().unwrap_or_default())
+    }
+
+    /// A filter of the column at `column_index` with `operator` and its
+    /// value, empty for `blank` and `notblank`.
+    pub fn new(column_index: u32, operator: &str, value: &str) -> Result<Self> {
+        let date = |value: &str| {
+            dates::parse_date(value.trim()).ok_or_else(|| {
+                Error::InvalidPayload(format!(
+                    "Invalid filter date: Got {}, Expected a mmddyy or yyyy-mm-dd date",
+                    value
+                ))
+            })
+        };
+        let bound = |value: &str| -> Result<Option<f64>> {
+            let value = value.trim();
+            if value.is_empty() {
+                return Ok(None);
+            }
+            match value.parse::<f64>() {
+                Ok(bound) => Ok(Some(bound)),
+                Err(_) => Err(Error::InvalidPayload(format!(
+                    "Invalid filter range bound: Got {}, expected a number",
+                    value
+                ))),
+            }
+        };
+
+        let condition = match operator.trim().to_lowercase().as_str() {
+            RowFilter::EQ => FilterCondition::Equals(value.to_string()),
+            RowFilter::CONTAINS => FilterCondition::Contains(value.to_string()),
+            RowFilter::RANGE => match value.split_once(',') {
+                Some((min, max)) => FilterCondition::Range {
+                    min: bound(min)?,
+                    max: bound(max)?,
+                },
+                None => {
+                    return Err(Error::InvalidPayload(format!(
+                        "A range filter takes two bounds, min,max. Got: {}",
+                        value
+                    )))
+                }
+            },
+            RowFilter::REGEX => match Regex::new(value) {
+                Ok(regex) => FilterCondition::Regex(regex),
+                Err(e) => return Err(Error::InvalidPayload(format!("Invalid filter regex: {}", e))),
+            },
+            RowFilter::BEFORE => FilterCondition::Before(date(value)?),
+            RowFilter::AFTER => FilterCondition::After(date(value)?),
+            RowFilter::BLANK => FilterCondition::Blank,
+            RowFilter::NOT_BLANK => FilterCondition::NotBlank,
+            other => {
+                return Err(Error::InvalidPayload(format!(
+                    "Invalid filter operator: Got {}, Expected: eq / contains / range / regex / before / after / blank / notblank",
+                    other
+                )))
+            }
+        };
+        Ok(Self {
+            column_index,
+            condition,
+        })
+    }
+}
+
+/// How the filters of a job combine.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FilterLogic {
+    /// Rows have to pass every filter.
+    #[default]
+    And,
+    /// Rows have to pass any of the filters.
+    Or,
+}
+
+impl FilterLogic {
+    const AND: &'static str = "and";
+    const OR: &'static str = "or";
+
+    /// Parses the option, empty text giving the default.
+    pub fn parse(text: &str) -> Result<Self> {
+        match text.trim().to_lowercase().as_str() {
+            "" | FilterLogic::AND => Ok(FilterLogic::And),
+            FilterLogic::OR => Ok(FilterLogic::Or),
+            other => Err(Error::InvalidPayload(format!(
+                "Invalid filter logic: Got {}, Expected: and / or",
+                other
+            ))),
+        }
+    }
+}
+
+/// What happens to the rows that don't pass the filters.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FilterAction {
+    #[default]
+    Drop,
+    /// The rows are moved to a sheet of their own.
+    Sheet,
+    /// The rows stay in the sheet, hidden.
+    Hide,
+}
+
+impl FilterAction {
+    const DROP: &'static str = "drop";
+    const SHEET: &'static str = "sheet";
+    const HIDE: &'static str = "hide";
+
+    /// Parses the option, empty text giving the default.
+    pub fn parse(text: &str) -> Result<Self> {
+        match text.trim().to_lowercase().as_str() {
+            "" | FilterAction::DROP => Ok(FilterAction::Drop),
+            FilterAction::SHEET => Ok(FilterAction::Sheet),
+            FilterAction::HIDE => Ok(FilterAction::Hide),
+            other => Err(Error::InvalidPayload(format!(
+                "Invalid filter action: Got {}, Expected: drop / sheet / hide",
+                other
+            ))),
+        }
+    }
+}
+
+/// Which of the rows sharing the dedupe columns is kept.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DedupeStrategy {
+    #[default]
+    First,
+    Last,
+    /// The row with the most non blank cells, the first of those on ties.
+    MostComplete,
+}
+
+impl DedupeStrategy {
+    const FIRST: &'static str = "first";
+    const LAST: &'static str = "last";
+    const COMPLETE: &'static str = "complete";
+
+    /// Parses the option, empty text giving the default.
+    pub fn parse(text: &str) -> Result<Self> {
+        match text.trim().to_lowercase().as_str() {
+            "" | DedupeStrategy::FIRST => Ok(DedupeStrategy::First),
+            DedupeStrategy::LAST => Ok(DedupeStrategy::Last),
+            DedupeStrategy::COMPLETE => Ok(DedupeStrategy::MostComplete),
+            other => Err(Error::InvalidPayload(format!(
+                "Invalid dedupe strategy: Got {}, Expected: first / last / complete",
+                other
+            ))),
+        }
+    }
+}
+
+/// What happens to duplicate rows.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DedupeAction {
+    /// The rows are moved to a sheet listing them with their row numbers.
+    #[default]
+    Remove,
+    /// The rows stay, highlighted.
+    Highlight,
+}
+
+impl DedupeAction {
+    const REMOVE: &'static str = "remove";
+    const HIGHLIGHT: &'static str = "highlight";
+
+    /// Parses the option, empty text giving the default.
+    pub fn parse(text: &str) -> Result<Self> {
+        match text.trim().to_lowercase().as_str() {
+            "" | DedupeAction::REMOVE => Ok(DedupeAction::Remove),
+            DedupeAction::HIGHLIGHT => Ok(DedupeAction::Highlight),
+            other => Err(Error::InvalidPayload(format!(
+                "Invalid dedupe action: Got {}, Expected: remove / highlight",
+                other
+            ))),
+        }
+    }
+}
+
+/// A function over the values of a column, for subtotals and pivot values.
+#[derive(Debug)]
+pub struct Aggregate {
+    pub function: AggregateFunction,
+    /// The 1 based index of the column.
+    pub column_index: u32,
+}
+
+impl Aggregate {
+    /// Parses `function,column` given as the `field` field.
+    pub fn parse(field: &str, text: &str) -> Result<Self> {
+        let Some((function, index)) = text.split_once(',') else {
+            return Err(Error::InvalidPayload(format!(
+                "{} has to be of form function,column. Got: {}",
+                field, text
+            )));
+        };
+        match index.trim().parse::<u32>() {
+            Ok(column_index) if column_index > 0 => Self::new(field, function, column_index),
+            _ => Err(Error::InvalidPayload(format!(
+                "Invalid value passed as {} column index. Got {}, expected a valid number",
+                field, index
+            ))),
+        }
+    }
+
+    /// `function` of the column at `column_index`, given as the `field`
+    /// field.
+    pub fn new(field: &str, function: &str, column_index: u32) -> Result<Self> {
+        let function = match function.trim().to_lowercase().as_str() {
+            AggregateFunction::COUNT => AggregateFunction::Count,
+            AggregateFunction::SUM => AggregateFunction::Sum,
+            AggregateFunction::AVG => AggregateFunction::Average,
+            AggregateFunction::MIN => AggregateFunction::Min,
+            AggregateFunction::MAX => AggregateFunction::Max,
+            other => {
+                return Err(Error::InvalidPayload(format!(
+                    "Invalid {} function: Got {}, Expected: count / sum / avg / min / max",
+                    field, other
+                )))
+            }
+        };
+        Ok(Self {
+            function,
+            column_index,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AggregateFunction {
+    /// Non blank cells.
+    Count,
+    /// The numbers, text that doesn't parse as one is left out of these.
+    Sum,
+    Average,
+    Min,
+    Max,
+}
+
+impl AggregateFunction {
+    const COUNT: &'static str = "count";
+    const SUM: &'static str = "sum";
+    const AVG: &'static str = "avg";
+    const MIN: &'static str = "min";
+    const MAX: &'static str = "max";
+}
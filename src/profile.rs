@@ -0,0 +1,447 @@
+//! Column profiles of an uploaded sheet: the type the values of every column
+//! have, how many are blank and what they range over. Helps picking the
+//! `checkDate` columns of a job.
+
+use std::{collections::HashMap, path::Path};
+
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use serde_json::Value;
+
+use crate::{
+    data::model::{ColumnProfile, InferredType, SheetProfile, ValueCount},
+    dates::{self, check_date, MMDDYY},
+    formats,
+    preview::StyleCache,
+    xlsx::CellValue,
+    Result,
+};
+
+const TOP_VALUES: usize = 5;
+/// Share of the non blank values of a column that must follow a date format
+/// for it to be suggested.
+const SUGGESTED_DATE_RATIO: f64 = 0.8;
+/// Name of the format of ISO 8601 date cells.
+const ISO_DATE_FORMAT: &str = "yyyy-mm-dd";
+/// Text dates recognized besides `mmddyy` ones, by name and chrono format.
+/// Days first only counts where the values can't be months first.
+const TEXT_DATE_FORMATS: [(&str, &str); 3] = [
+    (ISO_DATE_FORMAT, "%Y-%m-%d"),
+    ("mm/dd/yyyy", "%m/%d/%Y"),
+    ("dd/mm/yyyy", "%d/%m/%Y"),
+];
+
+/// Reads the sheet named or numbered `sheet` and profiles its columns.
+pub fn profile(path: &Path, sheet: Option<&str>) -> Result<SheetProfile> {
+    let formats::Sheet {
+        sheet_names,
+        sheet_idx,
+        workbook,
+    } = formats::open_sheet(path, sheet)?;
+    let last_col_idx = workbook.last_column();
+    let mut styles = StyleCache::new(&workbook.styles);
+
+    let mut columns: Vec<ColumnValues> = (1..=last_col_idx)
+        .map(|_| ColumnValues::default())
+        .collect();
+    for row in &workbook.rows {
+        for cell in &row.cells {
+            let Some(column) = columns.get_mut(cell.col as usize - 1) else {
+                continue;
+            };
+            let (is_date, style) = styles.get(cell.style)?;
+            let date_format = match style {
+                Some(style) if *is_date => style.number_format.as_deref(),
+                _ => None,
+            };
+            if let Some(value) = classify(&cell.value, *is_date, date_format) {
+                column.add(value);
+            }
+        }
+    }
+
+    let total_rows = workbook.rows.len();
+    Ok(SheetProfile {
+        sheet: sheet_names[sheet_idx].clone(),
+        sheets: sheet_names,
+        total_rows,
+        columns: (1..=last_col_idx)
+            .zip(columns)
+            .map(|(col_idx, values)| {
+                values.into_profile(
+                    col_idx,
+                    workbook.header.value(col_idx).into_owned(),
+                    total_rows,
+                )
+            })
+            .collect(),
+    })
+}
+
+/// A non blank value as far as its type goes.
+enum Classified {
+    Integer(i64),
+    Decimal(f64),
+    Boolean(bool),
+    Date {
+        format: String,
+        /// `None` for dates `check_date` accepts that don't exist, such as
+        /// the 31st of February.
+        value: Option<NaiveDateTime>,
+        /// The value as listed in the top values, text dates as written.
+        shown: String,
+    },
+    Text(String),
+}
+
+/// Excel dates are numbers in a date format, text values are typed by what
+/// they parse as. `None` for blank values.
+fn classify(value: &CellValue, is_date: bool, date_format: Option<&str>) -> Option<Classified> {
+    let classified = match value {
+        CellValue::Empty => return None,
+        CellValue::Bool(v) => Classified::Boolean(*v),
+        CellValue::Number(text) if is_date => {
+            let value = text.parse().ok().and_then(dates::from_serial);
+            Classified::Date {
+                format: date_format.unwrap_or(ISO_DATE_FORMAT).to_string(),
+                shown: value.map_or_else(|| text.to_string(), dates::to_iso),
+                value,
+            }
+        }
+        CellValue::Number(text) => match text.parse::<i64>() {
+            Ok(v) => Classified::Integer(v),
+            Err(_) => match text.parse::<f64>() {
+                Ok(v) => Classified::Decimal(v),
+                Err(_) => Classified::Text(text.to_string()),
+            },
+        },
+        CellValue::Date(text) => Classified::Date {
+            format: ISO_DATE_FORMAT.to_string(),
+            value: parse_iso(text),
+            shown: text.to_string(),
+        },
+        CellValue::Error(text) => Classified::Text(text.to_string()),
+        other => return classify_text(other.text().trim()),
+    };
+    Some(classified)
+}
+
+fn classify_text(text: &str) -> Option<Classified> {
+    if text.is_empty() {
+        return None;
+    }
+    if text.eq_ignore_ascii_case("true") || text.eq_ignore_ascii_case("false") {
+        return Some(Classified::Boolean(text.eq_ignore_ascii_case("true")));
+    }
+    // Six digits `checkDate` accepts are taken for dates rather than for
+    // numbers, those are the dates jobs validate.
+    if text.len() == 6
+        && text.bytes().all(|b| b.is_ascii_digit())
+        && check_date(text, 0, 0).is_none()
+    {
+        return Some(Classified::Date {
+            format: MMDDYY.to_string(),
+            value: NaiveDate::parse_from_str(text, "%m%d%y")
+                .ok()
+                .map(|date| date.and_time(NaiveTime::MIN)),
+            shown: text.to_string(),
+        });
+    }
+    if let Ok(v) = text.parse::<i64>() {
+        return Some(Classified::Integer(v));
+    }
+    if let Ok(v) = text.parse::<f64>() {
+        if v.is_finite() {
+            return Some(Classified::Decimal(v));
+        }
+    }
+    for (name, format) in TEXT_DATE_FORMATS {
+        if let Ok(date) = NaiveDate::parse_from_str(text, format) {
+            return Some(Classified::Date {
+                format: name.to_string(),
+                value: Some(date.and_time(NaiveTime::MIN)),
+                shown: text.to_string(),
+            });
+        }
+    }
+    Some(Classified::Text(text.to_string()))
+}
+
+fn parse_iso(text: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M:%S%.f")
+        .ok()
+        .or_else(|| {
+            NaiveDate::parse_from_str(text, "%Y-%m-%d")
+                .ok()
+                .map(|date| date.and_time(NaiveTime::MIN))
+        })
+}
+
+/// What was seen of the non blank values of a column.
+#[derive(Default)]
+struct ColumnValues {
+    integers: usize,
+    decimals: usize,
+    booleans: usize,
+    texts: usize,
+    /// Dates by format.
+    date_formats: HashMap<String, usize>,
+    numbers: Option<(f64, f64)>,
+    dates: Option<(NaiveDateTime, NaiveDateTime)>,
+    bools: Option<(bool, bool)>,
+    text_range: Option<(String, String)>,
+    /// Count and first position of every value.
+    counts: HashMap<String, (usize, usize)>,
+}
+
+impl ColumnValues {
+    fn add(&mut self, value: Classified) {
+        let shown = match value {
+            Classified::Integer(v) => {
+                self.integers += 1;
+                self.numbers = Some(min_max(self.numbers, v as f64));
+                v.to_string()
+            }
+            Classified::Decimal(v) => {
+                self.decimals += 1;
+                self.numbers = Some(min_max(self.numbers, v));
+                v.to_string()
+            }
+            Classified::Boolean(v) => {
+                self.booleans += 1;
+                self.bools = Some(min_max(self.bools, v));
+                if v { "TRUE" } else { "FALSE" }.to_string()
+            }
+            Classified::Date {
+                format,
+                value,
+                shown,
+            } => {
+                *self.date_formats.entry(format).or_default() += 1;
+                if let Some(dt) = value {
+                    self.dates = Some(min_max(self.dates, dt));
+                }
+                shown
+            }
+            Classified::Text(text) => {
+                self.texts += 1;
+                self.text_range = Some(match self.text_range.take() {
+                    None => (text.clone(), text.clone()),
+                    Some((min, max)) if text < min => (text.clone(), max),
+                    Some((min, max)) if text > max => (min, text.clone()),
+                    Some(range) => range,
+                });
+                text
+            }
+        };
+        let seen = self.counts.len();
+        self.counts.entry(shown).or_insert((0, seen)).0 += 1;
+    }
+
+    fn into_profile(self, column: u32, name: String, total_rows: usize) -> ColumnProfile {
+        let dates: usize = self.date_formats.values().sum();
+        let non_blank = self.integers + self.decimals + self.booleans + self.texts + dates;
+        let kind = match (
+            self.integers,
+            self.decimals,
+            self.booleans,
+            self.texts,
+            dates,
+        ) {
+            _ if non_blank == 0 => InferredType::Empty,
+            (_, 0, 0, 0, 0) => InferredType::Integer,
+            (_, _, 0, 0, 0) => InferredType::Decimal,
+            (0, 0, _, 0, 0) => InferredType::Boolean,
+            (0, 0, 0, _, 0) => InferredType::Text,
+            (0, 0, 0, 0, _) => InferredType::Date,
+            _ => InferredType::Mixed,
+        };
+
+        // Ties go to the name sorting first, not to whichever one hashing
+        // happens to list last.
+        let main_date_format = self
+            .date_formats
+            .iter()
+            .max_by(|(a_name, a), (b_name, b)| a.cmp(b).then(b_name.cmp(a_name)))
+            .map(|(name, count)| (name.clone(), *count));
+        let suggested_date_format = main_date_format
+            .as_ref()
+            .filter(|(_, count)| *count as f64 >= non_blank as f64 * SUGGESTED_DATE_RATIO)
+            .map(|(name, _)| name.clone());
+
+        let (min, max) = match kind {
+            InferredType::Integer => unzip(self.numbers.map(|(min, max)| (min as i64, max as i64))),
+            InferredType::Decimal => unzip(self.numbers),
+            InferredType::Boolean => unzip(self.bools),
+            InferredType::Date => unzip(
+                self.dates
+                    .map(|(min, max)| (dates::to_iso(min), dates::to_iso(max))),
+            ),
+            InferredType::Text => unzip(self.text_range),
+            InferredType::Mixed | InferredType::Empty => (None, None),
+        };
+
+        let mut top_values: Vec<(String, (usize, usize))> = self.counts.into_iter().collect();
+        top_values.sort_by(|(_, (a_count, a_seen)), (_, (b_count, b_seen))| {
+            b_count.cmp(a_count).then(a_seen.cmp(b_seen))
+        });
+        top_values.truncate(TOP_VALUES);
+
+        ColumnProfile {
+            column,
+            name,
+            kind,
+            date_format: (kind == InferredType::Date)
+                .then_some(main_date_format.map(|(name, _)| name))
+                .flatten(),
+            check_date: suggested_date_format.as_deref() == Some(MMDDYY),
+            suggested_date_format,
+            null_ratio: match total_rows {
+                0 => 0.0,
+                _ => (total_rows - non_blank) as f64 / total_rows as f64,
+            },
+            min,
+            max,
+            top_values: top_values
+                .into_iter()
+                .map(|(value, (count, _))| ValueCount { value, count })
+                .collect(),
+        }
+    }
+}
+
+fn min_max<T: PartialOrd + Copy>(range: Option<(T, T)>, value: T) -> (T, T) {
+    match range {
+        None => (value, value),
+        Some((min, max)) => (
+            if value < min { value } else { min },
+            if value > max { value } else { max },
+        ),
+    }
+}
+
+fn unzip<T: Into<Value>>(range: Option<(T, T)>) -> (Option<Value>, Option<Value>) {
+    match range {
+        None => (None, None),
+        Some((min, max)) => (Some(min.into()), Some(max.into())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use serde_json::json;
+
+    use super::*;
+
+    /// A file removed when dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn csv(text: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("excel_app_test_{}.csv", uuid::Uuid::now_v7()));
+            std::fs::write(&path, text).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    /// Profiles the first column of a csv file, holding `values`.
+    fn column(values: &[&str]) -> ColumnProfile {
+        let rows: String = (1..)
+            .zip(values)
+            .map(|(row, value)| format!("{value},{row}\n"))
+            .collect();
+        let file = TempFile::csv(&format!("value,row\n{rows}"));
+        profile(&file.0, None).unwrap().columns.remove(0)
+    }
+
+    #[test]
+    fn mmddyy_dates() {
+        let profile = column(&["010124", "123124", "022924"]);
+        assert_eq!(profile.kind, InferredType::Date);
+        assert_eq!(profile.date_format.as_deref(), Some(MMDDYY));
+        assert!(profile.check_date);
+        assert_eq!(profile.min, Some(json!("2024-01-01")));
+        assert_eq!(profile.max, Some(json!("2024-12-31")));
+    }
+
+    #[test]
+    fn mostly_dates_are_suggested() {
+        let profile = column(&["010124", "020124", "030124", "040124", "n/a"]);
+        assert_eq!(profile.kind, InferredType::Mixed);
+        assert_eq!(profile.date_format, None);
+        assert_eq!(profile.suggested_date_format.as_deref(), Some(MMDDYY));
+        assert!(profile.check_date);
+
+        let profile = column(&["010124", "020124", "030124", "n/a", "n/a"]);
+        assert_eq!(profile.suggested_date_format, None);
+        assert!(!profile.check_date);
+    }
+
+    #[test]
+    fn text_date_formats() {
+        let profile = column(&["2024-01-31", "01/31/2024"]);
+        assert_eq!(profile.kind, InferredType::Date);
+        // Ties go to the format sorting first.
+        assert_eq!(profile.date_format.as_deref(), Some("mm/dd/yyyy"));
+        assert!(!profile.check_date);
+
+        let profile = column(&["31/01/2024", "15/02/2024"]);
+        assert_eq!(profile.date_format.as_deref(), Some("dd/mm/yyyy"));
+        assert_eq!(profile.min, Some(json!("2024-01-31")));
+    }
+
+    #[test]
+    fn six_digits_that_are_no_dates() {
+        let profile = column(&["999999", "123456"]);
+        assert_eq!(profile.kind, InferredType::Integer);
+        // Accepted by `checkDate` though there is no 31st of February.
+        let profile = column(&["023124"]);
+        assert_eq!(profile.kind, InferredType::Date);
+        assert_eq!(profile.min, None);
+    }
+
+    #[test]
+    fn blanks_and_other_types() {
+        let profile = column(&["", "1.5", "2", ""]);
+        assert_eq!(profile.kind, InferredType::Decimal);
+        assert_eq!(profile.null_ratio, 0.5);
+        assert_eq!(
+            (profile.min, profile.max),
+            (Some(json!(1.5)), Some(json!(2.0)))
+        );
+        assert_eq!(column(&["TRUE", "false"]).kind, InferredType::Boolean);
+        assert_eq!(column(&["", ""]).kind, InferredType::Empty);
+    }
+
+    #[test]
+    fn excel_dates() {
+        let Some(Classified::Date {
+            format,
+            value,
+            shown,
+        }) = classify(
+            &CellValue::Number("45322.5".into()),
+            true,
+            Some("dd.mm.yyyy"),
+        )
+        else {
+            panic!("not classified as a date");
+        };
+        assert_eq!(format, "dd.mm.yyyy");
+        assert!(value.is_some());
+        assert_eq!(shown, "2024-01-31T12:00:00");
+        assert!(matches!(
+            classify(&CellValue::Number("45322".into()), false, None),
+            Some(Classified::Integer(45322))
+        ));
+        assert!(classify(&CellValue::Empty, true, None).is_none());
+    }
+}
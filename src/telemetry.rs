@@ -0,0 +1,55 @@
+//! Export of the spans of requests and of the phases of jobs to an
+//! OpenTelemetry collector, over OTLP. Only built with the `otel` feature.
+
+use opentelemetry::{trace::TracerProvider as _, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{
+    export::trace::SpanExporter,
+    runtime,
+    trace::{Tracer, TracerProvider},
+    Resource,
+};
+use tracing::Subscriber;
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::{error::Error, Result};
+
+/// Name the spans are reported under.
+pub const SERVICE_NAME: &str = "excel_app";
+
+/// Exports spans in batches to the OTLP/gRPC `endpoint` of a collector,
+/// such as `http://localhost:4317`. Must be called within the runtime.
+pub fn otlp_provider(endpoint: &str) -> Result<TracerProvider> {
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_tonic()
+        .with_endpoint(endpoint)
+        .build()
+        .map_err(|e| Error::Generic(format!("Error setting up the OTLP exporter, {e}")))?;
+    Ok(TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_resource(resource())
+        .build())
+}
+
+/// Exports every span as soon as it ends, to an exporter of the process
+/// itself such as one collecting spans in tests.
+pub fn simple_provider<E: SpanExporter + 'static>(exporter: E) -> TracerProvider {
+    TracerProvider::builder()
+        .with_simple_exporter(exporter)
+        .with_resource(resource())
+        .build()
+}
+
+/// The layer turning the `tracing` spans of the server into OpenTelemetry
+/// spans of `provider`.
+pub fn layer<S>(provider: &TracerProvider) -> OpenTelemetryLayer<S, Tracer>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    tracing_opentelemetry::layer().with_tracer(provider.tracer(SERVICE_NAME))
+}
+
+fn resource() -> Resource {
+    Resource::new([KeyValue::new("service.name", SERVICE_NAME)])
+}
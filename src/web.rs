@@ -1,239 +1,468 @@
 use crate::{
-    colors::{self, CellColorProfile},
+    auth::{self, Principal},
+    config::{AuthConfig, Config},
     data::{
-        model::{JobDetails, RowsPayload, SortInfo, UploadFileEntry, ExcelFileForm, RunJobRequest, RunJobResponse},
-        sqlite_ds::SqliteDataSource,
+        model::{
+            ApiTokenEntry, CellType, ColumnAggregate, ColumnFilter, ColumnProfile, ColumnRef,
+            ColumnStats, CreateApiTokenRequest, CreatedApiToken, ExcelFileForm, InferredType,
+            JobDetails, LoginRequest, LoginResponse, NewApiToken, NewSession, NewUploadEntry,
+            PreviewCell, PreviewRow, PreviewStyle, ProfileQuery, RowsPayload, RowsPreview,
+            RowsPreviewQuery, RunJobForm, RunJobRequest, RunJobResponse, Scope, SheetProfile,
+            SortColumn, TypeCounts, UploadFileEntry, ValueCount,
+        },
         DataSource,
     },
     error::Error,
+    formats,
+    limits::{self, Client, Limits},
+    metrics::{self, Metrics},
+    preview,
+    processor::{
+        self, CellAnnotation, ContractionMatch, JobResult, MatchReport, MatchStats, TextRange,
+    },
+    profile,
+    xlsx::Row,
     Result as CrateRes, DATA_DIR_NAME,
 };
-use aho_corasick::AhoCorasick;
 use axum::{
-    body::{self, Bytes},
-    extract::{Multipart, Path, State},
-    http::{header::{CONTENT_DISPOSITION, CONTENT_TYPE, HeaderMap}, StatusCode},
+    async_trait, body,
+    extract::{FromRequest, Multipart, Path, Query, Request, State},
+    http::{
+        header::{HeaderMap, HeaderValue, CONTENT_DISPOSITION, CONTENT_TYPE, SET_COOKIE},
+        StatusCode,
+    },
+    middleware,
     response::IntoResponse,
-    routing::{get, post},
-    Json, Router,
+    routing::{delete, get, post},
+    Extension, Json, Router,
 };
+use chrono::Local;
 use serde_json::json;
 use serde_json::Value;
+use std::io::Cursor;
 use std::{
-    collections::HashMap,
     path::{PathBuf, MAIN_SEPARATOR},
+    sync::Arc,
 };
-use tokio::fs;
+use tokio::{fs, sync::Semaphore};
 use tokio_util::io::ReaderStream;
-use tracing::{event, Level};
-use umya_spreadsheet::{reader, writer, Cell};
-use utoipa::OpenApi;
+use tracing::{event, span, Level};
+use utoipa::{
+    openapi::{
+        path::PathItemType,
+        security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme},
+        Content, Ref,
+    },
+    Modify, OpenApi,
+};
 use utoipa_swagger_ui::SwaggerUi;
-use chrono::Local;
-use std::io::Cursor;
+
+/// Response header holding the match report of a job as json.
+pub const MATCH_REPORT_HEADER: &str = "x-match-report";
+/// Reports above this size are left out of the header, proxies and clients
+/// commonly refuse headers much larger.
+const MAX_MATCH_REPORT_HEADER_BYTES: usize = 8 * 1024;
 
 #[derive(OpenApi)]
 #[openapi(
-    paths(get_header_row, upload_file, run_job),
+    paths(
+        get_header_row, get_rows_preview, get_profile, upload_file, run_job, login, logout,
+        create_api_token, list_api_tokens, revoke_api_token, healthz, readyz, get_metrics
+    ),
     components(
         schemas(UploadFileEntry),
+        schemas(LoginRequest, LoginResponse),
+        schemas(Scope, ApiTokenEntry, CreateApiTokenRequest, CreatedApiToken),
         schemas(RowsPayload),
         schemas(ExcelFileForm),
         schemas(Error),
-        schemas(RunJobRequest),
+        schemas(RunJobRequest, RunJobForm, ColumnRef, SortColumn, ColumnFilter, ColumnAggregate),
         schemas(RunJobResponse),
-    )
+        schemas(JobResult, CellAnnotation, ContractionMatch, TextRange, MatchReport, MatchStats),
+        schemas(RowsPreview, PreviewRow, PreviewCell, PreviewStyle, CellType, ColumnStats, TypeCounts),
+        schemas(SheetProfile, ColumnProfile, InferredType, ValueCount),
+    ),
+    modifiers(&SecuritySchemes, &RunJobFormContent)
 )]
 pub struct APIDoc;
 
+/// API tokens and login sessions, the credentials `auth::authenticate`
+/// takes.
+struct SecuritySchemes;
 
-pub fn get_routes(datasource: SqliteDataSource) -> Router {
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer_token",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+        components.add_security_scheme(
+            "session_cookie",
+            SecurityScheme::ApiKey(ApiKey::Cookie(ApiKeyValue::new(auth::SESSION_COOKIE))),
+        );
+    }
+}
+
+/// The multipart form of `/runJob`, taken along with json. The path
+/// attribute only documents one content type.
+struct RunJobFormContent;
+
+impl Modify for RunJobFormContent {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let request_body = openapi
+            .paths
+            .paths
+            .get_mut("/runJob")
+            .and_then(|path| path.operations.get_mut(&PathItemType::Post))
+            .and_then(|operation| operation.request_body.as_mut());
+        if let Some(request_body) = request_body {
+            request_body.content.insert(
+                "multipart/form-data".into(),
+                Content::new(Ref::from_schema_name("RunJobForm")),
+            );
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct AppState<D: DataSource> {
+    pub datasource: D,
+    /// One permit per job allowed to run at the same time.
+    pub job_permits: Arc<Semaphore>,
+    pub auth: AuthConfig,
+    /// Whether session cookies are marked `Secure`, set when the server
+    /// serves https.
+    pub secure_cookies: bool,
+    pub limits: Arc<Limits>,
+    pub metrics: Arc<Metrics>,
+}
+
+pub fn get_routes<D: DataSource>(
+    datasource: D,
+    config: &Config,
+    job_permits: Arc<Semaphore>,
+) -> Router {
+    let state = AppState {
+        datasource,
+        job_permits,
+        auth: config.auth.clone(),
+        secure_cookies: config.tls.is_some(),
+        limits: Arc::new(Limits::new(&config.limits)),
+        metrics: Arc::new(Metrics::new()),
+    };
+    // Routes working on uploads act for the principal `authenticate` puts
+    // in the request.
+    let uploads = Router::new()
+        .route(
+            "/upload",
+            post(upload_file::<D>).route_layer(middleware::from_fn_with_state(
+                state.clone(),
+                limits::limit_uploads::<D>,
+            )),
+        )
+        .route("/getHeader/:entry_uuid", get(get_header_row::<D>))
+        .route("/uploads/:entry_uuid/rows", get(get_rows_preview::<D>))
+        .route("/uploads/:entry_uuid/profile", get(get_profile::<D>))
+        .route(
+            "/runJob",
+            post(run_job::<D>).route_layer(middleware::from_fn_with_state(
+                state.clone(),
+                limits::limit_jobs::<D>,
+            )),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::authenticate::<D>,
+        ));
+    let tokens = Router::new()
+        .route(
+            "/tokens",
+            get(list_api_tokens::<D>).post(create_api_token::<D>),
+        )
+        .route("/tokens/:token_id", delete(revoke_api_token::<D>))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::authenticate_user::<D>,
+        ));
     Router::new()
         .merge(SwaggerUi::new("/swagger-ui").url("/api-docs/openapi.json", APIDoc::openapi()))
-        .route("/upload", post(upload_file))
-        .route("/getHeader/:entry_uuid", get(get_header_row))
-        .route("/runJob", post(run_job))
-        .with_state(datasource)
+        .route("/login", post(login::<D>))
+        .route("/logout", post(logout::<D>))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz::<D>))
+        .route("/metrics", get(get_metrics::<D>))
+        .merge(uploads)
+        .merge(tokens)
+        .layer(middleware::from_fn_with_state(
+            state.metrics.clone(),
+            metrics::track_requests,
+        ))
+        .with_state(state)
 }
 
 #[utoipa::path(
-    post,
-    path = "/runJob",
+    get,
+    path = "/healthz",
     responses(
-        (status = 200, body=RunJobResponse, description="Contraction excel file to download"),
-        (status = 501, body=Error, description="An error message")
-    ),
-    request_body(
-        content = RunJobRequest, content_type = "multipart/form-data"
+        (status = 200, description = "The server is up"),
     )
 )]
-async fn run_job(
-    State(datasource): State<SqliteDataSource>,
-    multipart: Multipart,
-) -> impl IntoResponse {
-    let mut job_detail = JobDetails::try_from(multipart).await?;
-    event!(Level::DEBUG, "Job details: {:?}", job_detail);
-    let file_entry = datasource
-        .get_file_entry(job_detail.file_id().to_owned())
-        .await?;
-    let spreadsheet = reader::xlsx::read(&file_entry.file_path);
-    if spreadsheet.is_err() {
-        return Err(Error::IOError(spreadsheet.err().unwrap().to_string()));
-    }
-    let mut spreadsheet = spreadsheet.unwrap();
-    let worksheet = spreadsheet.get_sheet_mut(&0usize);
-    if worksheet.is_err() {
-        return Err(Error::InValidExcelFile(worksheet.err().unwrap().into()));
-    }
-    let worksheet = worksheet.unwrap();
-
-    let (first_col_idx, first_row_idx) = (1, 1);
-    let (last_col_idx, last_row_idx) = worksheet.get_highest_column_and_row();
-
-    event!(Level::TRACE, "Validating sheet");
-    validate_sheet(
-        first_col_idx,
-        last_col_idx,
-        worksheet,
-        first_row_idx,
-        &job_detail,
-        last_row_idx,
-    )?;
-    event!(Level::TRACE, "Sheet is valid");
-
-    let contraction_f_bytes = job_detail.pop_contraction_file();
-
-    let contraction_task = tokio::spawn(async move {
-        let mut contraction_f_path = PathBuf::from(format!(".{MAIN_SEPARATOR}{DATA_DIR_NAME}"));
-        let contraction_f_name = format!("contraction_{}.xlsx", uuid::Uuid::now_v7());
-        contraction_f_path.push(contraction_f_name);
-        get_contraction_texts(contraction_f_bytes, &contraction_f_path).await
+async fn healthz() -> impl IntoResponse {
+    Json(json!({"status": "ok"}))
+}
+
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    responses(
+        (status = 200, description = "The database can be reached and the data directory written to"),
+        (status = 503, description = "A check failed, the body tells which"),
+    )
+)]
+async fn readyz<D: DataSource>(State(state): State<AppState<D>>) -> impl IntoResponse {
+    let database = match state.datasource.ping().await {
+        Err(e) => Err(e.to_string()),
+        Ok(()) => Ok(()),
+    };
+    let data_directory = check_data_dir_writable().await;
+    let status = match (&database, &data_directory) {
+        (Ok(()), Ok(())) => StatusCode::OK,
+        _ => {
+            event!(
+                Level::WARN,
+                "Not ready, database: {:?}, data directory: {:?}",
+                database,
+                data_directory
+            );
+            StatusCode::SERVICE_UNAVAILABLE
+        }
+    };
+    let check = |result: &Result<(), String>| match result {
+        Err(e) => e.clone(),
+        Ok(()) => "ok".to_string(),
+    };
+    let body = json!({
+        "status": if status == StatusCode::OK { "ready" } else { "unavailable" },
+        "database": check(&database),
+        "dataDirectory": check(&data_directory),
     });
+    (status, Json(body))
+}
 
-    event!(Level::TRACE, "Copying cell values into Vec<Vec<Cell>>");
-    let mut cells = get_cells(worksheet, last_row_idx as usize, last_col_idx as usize);
-    if cells.len() > 1 {
-        event!(
-            Level::TRACE,
-            "Done copying, Row count: {}, expected: {}. Col count: {}, expected: {}",
-            cells.len(),
-            // Minus header row
-            last_row_idx - 1,
-            cells[0].len(),
-            last_col_idx
-        );
-    } else {
-        event!(
-            Level::TRACE,
-            "No values in sheet to copy, Row count: {}, Col count: {}",
-            last_row_idx,
-            last_col_idx
-        );
+/// Writes and removes a file in the data directory, where uploads go.
+async fn check_data_dir_writable() -> Result<(), String> {
+    let mut probe_path = PathBuf::from(format!(".{MAIN_SEPARATOR}{DATA_DIR_NAME}"));
+    probe_path.push(format!(".ready_{}", uuid::Uuid::now_v7()));
+    if let Err(e) = fs::write(&probe_path, b"").await {
+        return Err(format!("Error writing to the data directory, {e}"));
     }
+    let _ = fs::remove_file(&probe_path).await;
+    Ok(())
+}
 
-    sort_cells(cells.as_mut_slice(), job_detail.sort_infos());
-
-    let contraction_str = contraction_task.await.unwrap()?;
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    responses(
+        (status = 200, description = "The metrics of the server in the Prometheus text format", content_type = "text/plain"),
+    )
+)]
+async fn get_metrics<D: DataSource>(State(state): State<AppState<D>>) -> impl IntoResponse {
+    let text = state.metrics.render()?;
+    Ok::<_, Error>(([(CONTENT_TYPE, metrics::CONTENT_TYPE)], text))
+}
 
-    event!(Level::TRACE, "Highlighting search terms and contractions");
-    highlight_search_terms_and_contractions(cells.as_mut_slice(), &job_detail, &contraction_str)?;
+#[utoipa::path(
+    post,
+    path = "/runJob",
+    responses(
+        (status = 200, description="Contraction excel file to download, a zip with the csv and its matches for outputFormat csv, or the processed rows for outputFormat json", content(
+            ("application/vnd.openxmlformats-officedocument.spreadsheetml.sheet" = RunJobResponse),
+            ("application/zip" = RunJobResponse),
+            ("application/json" = JobResult),
+        )),
+        (status = 401, body=Error, description="Login required"),
+        (status = 403, body=Error, description="The API token lacks the run scope"),
+        (status = 429, body=Error, description="Too many jobs are running, or the client made too many requests or runs too many jobs, retry after the `Retry-After` seconds"),
+        (status = 400, body=Error, description="Invalid job options, or a sheet with an incomplete header or invalid dates"),
+        (status = 404, body=Error, description="Unknown file id"),
+        (status = 500, body=Error, description="An error message")
+    ),
+    request_body(
+        content = RunJobRequest, content_type = "application/json",
+        description = "The job as json, or as a multipart form"
+    ),
+    security(("bearer_token" = []), ("session_cookie" = []))
+)]
+async fn run_job<D: DataSource>(
+    State(state): State<AppState<D>>,
+    Extension(principal): Extension<Principal>,
+    body: RunJobBody,
+) -> impl IntoResponse {
+    let file_id = match &body {
+        RunJobBody::Form(job_detail) => job_detail.file_id().to_owned(),
+        RunJobBody::Json(request) => request.file_id.clone(),
+    };
+    principal.require(Scope::Run)?;
+    let file_entry = state.datasource.get_file_entry(file_id).await?;
+    principal.authorize(&file_entry)?;
+    let job_detail = match body {
+        RunJobBody::Form(job_detail) => {
+            let header = read_header_row(PathBuf::from(&file_entry.file_path)).await?;
+            job_detail.check_columns(&header)?;
+            job_detail
+        }
+        RunJobBody::Json(request) => {
+            let header = read_header_row(PathBuf::from(&file_entry.file_path)).await?;
+            let contraction_file = match &request.contraction_file_id {
+                None => None,
+                Some(id) => {
+                    let entry = state.datasource.get_file_entry(id.clone()).await?;
+                    principal.authorize(&entry)?;
+                    match fs::read(&entry.file_path).await {
+                        Err(e) => {
+                            return Err(Error::IOError(format!(
+                                "Error reading contraction file, {e}"
+                            )))
+                        }
+                        Ok(bytes) => Some(bytes.into()),
+                    }
+                }
+            };
+            JobDetails::from_json(request, &header, contraction_file)?
+        }
+    };
     event!(
-        Level::TRACE,
-        "Done highlighting search terms and contractions"
+        Level::DEBUG,
+        "Job details of {}: {:?}",
+        principal.name(),
+        job_detail
     );
 
-    // We read the cells from row 2 and col 1
-    // Hence we have to offset the indexes below
-    // by them, to set them at the right place.
-    event!(Level::TRACE, "Mutating spreadsheet");
-    const ROW_OFFSET: usize = 2;
-    const COL_OFFSET: usize = 1;
-    cells.into_iter().enumerate().for_each(|(row_idx, row)| {
-        row.into_iter()
-            .enumerate()
-            .for_each(|(col_idx, mut col_cell)| {
-                let coordinate = col_cell.get_coordinate_mut();
-                coordinate.set_row_num((row_idx + ROW_OFFSET) as u32);
-                coordinate.set_col_num((col_idx + COL_OFFSET) as u32);
-                worksheet.set_cell(col_cell);
-            });
-    });
-    event!(Level::TRACE, "Done mutating spreadsheet");
-
-    event!(Level::TRACE, "Writing to in memory file");
-    let mut cursor: Cursor<Vec<u8>> = Cursor::new(Vec::new());
+    // Jobs beyond the limit are turned away instead of queued, a queue would
+    // only hold on to uploads and connections the client is likely to retry.
+    let permit = match state.job_permits.clone().try_acquire_owned() {
+        Err(_) => {
+            event!(Level::WARN, "Rejecting job, all job slots are in use");
+            return Err(Error::ServerBusy);
+        }
+        Ok(p) => p,
+    };
 
-    if let Err(e) = writer::xlsx::write_writer(&spreadsheet, &mut cursor) {
-        event!(
-            Level::ERROR,
-            message = "Error writing final contraction result to memory",
-            error = e.to_string()
-        );
-        return Err(Error::IOError(e.to_string()));
+    let file_path = PathBuf::from(&file_entry.file_path);
+    let metrics = state.metrics.clone();
+    // A child of the span of the request, so events of the job carry the
+    // id of the request too.
+    let job_id = uuid::Uuid::now_v7();
+    let span = span!(Level::INFO, "job", job_id = %job_id, file_id = %file_entry.id);
+    let job = tokio::task::spawn_blocking(move || {
+        let _entered = span.enter();
+        let result = processor::process_with_metrics(&file_path, job_detail.spec(), &metrics);
+        drop(permit);
+        result
+    });
+    let processor::ProcessOutput {
+        file: output,
+        report,
+    } = match job.await {
+        Err(e) => return Err(Error::Generic(format!("Job failed to complete: {e}"))),
+        Ok(output) => output?,
     };
-    event!(Level::TRACE, "Done writting");
 
-    cursor.set_position(0);
-    let stream = ReaderStream::new(cursor);
+    let (content_type, extension) = (output.content_type(), output.extension());
+    let stream = ReaderStream::new(Cursor::new(output.into_bytes()));
     let stream = body::Body::from_stream(stream);
 
     let mut headers = HeaderMap::new();
-    let file_name = file_entry.file_path;
-    let last_slash_pos = file_name.rfind(MAIN_SEPARATOR);
-    let file_name = &file_name[last_slash_pos.unwrap_or(0)+1..];
-    let full_stop_pos = file_name.rfind('.');
-    let full_stop_pos = full_stop_pos.unwrap_or(file_name.len());
-    let file_name = &file_name[0..full_stop_pos];
+    let file_name = download_stem(&file_entry);
     let dt = Local::now();
     let formatted_dt = format!("{}", dt.format("%m%d%Y%H%M"));
     event!(Level::TRACE, "Sending file");
-    
-    headers.insert(CONTENT_TYPE, "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet".parse().unwrap());
-    headers.insert(CONTENT_DISPOSITION, format!("attachment; filename=\"{file_name} basic process-{formatted_dt}\"").parse().unwrap());
+
+    headers.insert(CONTENT_TYPE, content_type.parse().unwrap());
+    headers.insert(
+        CONTENT_DISPOSITION,
+        HeaderValue::from_bytes(
+            format!("attachment; filename=\"{file_name} basic process-{formatted_dt}{extension}\"")
+                .as_bytes(),
+        )
+        .unwrap(),
+    );
+    let report =
+        ascii_json(&serde_json::to_value(&report).map_err(|e| Error::Generic(e.to_string()))?);
+    if report.len() <= MAX_MATCH_REPORT_HEADER_BYTES {
+        headers.insert(MATCH_REPORT_HEADER, report.parse().unwrap());
+    } else {
+        event!(
+            Level::WARN,
+            "Leaving out a match report of {} bytes",
+            report.len()
+        );
+    }
 
     Ok((headers, stream))
 }
 
+/// The body of a `/runJob` request, a json `RunJobRequest` when sent as
+/// `application/json` and the multipart form otherwise.
+enum RunJobBody {
+    Form(JobDetails),
+    Json(RunJobRequest),
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequest<S> for RunJobBody {
+    type Rejection = Error;
+
+    async fn from_request(request: Request, state: &S) -> CrateRes<Self> {
+        let is_json = request
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| {
+                value
+                    .trim_start()
+                    .to_lowercase()
+                    .starts_with("application/json")
+            });
+        if is_json {
+            match Json::<RunJobRequest>::from_request(request, state).await {
+                Err(e) => Err(Error::InvalidPayload(e.body_text())),
+                Ok(Json(request)) => Ok(RunJobBody::Json(request)),
+            }
+        } else {
+            match Multipart::from_request(request, state).await {
+                Err(e) => Err(Error::MultipartFormError(e.body_text())),
+                Ok(multipart) => Ok(RunJobBody::Form(JobDetails::try_from(multipart).await?)),
+            }
+        }
+    }
+}
 
 #[utoipa::path(
-    get, 
-    path = "/getHeader/{entry_uuid}", 
+    get,
+    path = "/getHeader/{entry_uuid}",
     responses(
-        (status = 200, description = "The header row of the excel file, with each string representing a column", body = RowsPayload)
-    )
+        (status = 200, description = "The header row of the excel file, with each string representing a column", body = RowsPayload),
+        (status = 404, body = Error, description = "Unknown file id")
+    ),
+    security(("bearer_token" = []), ("session_cookie" = []))
 )]
-async fn get_header_row(
-    State(datasource): State<SqliteDataSource>,
+async fn get_header_row<D: DataSource>(
+    State(state): State<AppState<D>>,
+    Extension(principal): Extension<Principal>,
     Path(entry_uuid): Path<String>,
 ) -> CrateRes<Json<Value>> {
-    let result = match datasource.get_file_entry(entry_uuid).await {
-        Ok(r) => r,
-        Err(e) => return Err(Error::DatabaseOperationFailed(e.to_string())),
-    };
-
-    let spreadsheet = match reader::xlsx::read(result.file_path) {
-        Err(e) => return Err(Error::InValidExcelFile(e.to_string())),
-        Ok(ss) => ss,
-    };
-
-    let first_sheet = spreadsheet.get_sheet(&0);
+    principal.require(Scope::Read)?;
+    let result = state.datasource.get_file_entry(entry_uuid).await?;
+    principal.authorize(&result)?;
 
-    if first_sheet.is_err() {
-        return Err(Error::Generic("No sheet found in excel file".into()));
-    }
-
-    let first_sheet = first_sheet.unwrap();
+    let header = read_header_row(PathBuf::from(result.file_path)).await?;
 
-    let mut columns: Vec<String>;
-    if first_sheet.get_highest_row() < 1 {
-        columns = Vec::with_capacity(0);
-    }else {
-        let col_count = first_sheet.get_highest_column();
-        columns = Vec::with_capacity((col_count - 1) as usize);
-        for col_idx in 1..=col_count {
-            columns.push(first_sheet.get_value((col_idx, 1)));
-        }
-    }
+    let columns: Vec<String> = (1..=header.last_column())
+        .map(|col_idx| header.value(col_idx).into_owned())
+        .collect();
 
     let rows = RowsPayload { columns };
     let rows = json!(rows);
@@ -241,6 +470,61 @@ async fn get_header_row(
     Ok(Json(rows))
 }
 
+#[utoipa::path(
+    get,
+    path = "/uploads/{entry_uuid}/rows",
+    params(RowsPreviewQuery),
+    responses(
+        (status = 200, description = "A page of the data rows of a sheet with typed values and basic styles, and statistics of every column", body = RowsPreview),
+        (status = 400, body = Error, description = "Unknown sheet"),
+        (status = 404, body = Error, description = "Unknown file id")
+    ),
+    security(("bearer_token" = []), ("session_cookie" = []))
+)]
+async fn get_rows_preview<D: DataSource>(
+    State(state): State<AppState<D>>,
+    Extension(principal): Extension<Principal>,
+    Path(entry_uuid): Path<String>,
+    Query(query): Query<RowsPreviewQuery>,
+) -> CrateRes<Json<RowsPreview>> {
+    principal.require(Scope::Read)?;
+    let entry = state.datasource.get_file_entry(entry_uuid).await?;
+    principal.authorize(&entry)?;
+    let file_path = PathBuf::from(entry.file_path);
+    match tokio::task::spawn_blocking(move || preview::rows_preview(&file_path, &query)).await {
+        Err(e) => Err(Error::Generic(format!("Reading workbook failed: {e}"))),
+        Ok(preview) => Ok(Json(preview?)),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/uploads/{entry_uuid}/profile",
+    params(ProfileQuery),
+    responses(
+        (status = 200, description = "The inferred type of every column of a sheet, with its blank ratio, range, most frequent values and date format", body = SheetProfile),
+        (status = 400, body = Error, description = "Unknown sheet"),
+        (status = 404, body = Error, description = "Unknown file id")
+    ),
+    security(("bearer_token" = []), ("session_cookie" = []))
+)]
+async fn get_profile<D: DataSource>(
+    State(state): State<AppState<D>>,
+    Extension(principal): Extension<Principal>,
+    Path(entry_uuid): Path<String>,
+    Query(query): Query<ProfileQuery>,
+) -> CrateRes<Json<SheetProfile>> {
+    principal.require(Scope::Read)?;
+    let entry = state.datasource.get_file_entry(entry_uuid).await?;
+    principal.authorize(&entry)?;
+    let file_path = PathBuf::from(entry.file_path);
+    match tokio::task::spawn_blocking(move || profile::profile(&file_path, query.sheet.as_deref()))
+        .await
+    {
+        Err(e) => Err(Error::Generic(format!("Reading workbook failed: {e}"))),
+        Ok(profile) => Ok(Json(profile?)),
+    }
+}
 
 #[utoipa::path(
     post,
@@ -248,13 +532,19 @@ async fn get_header_row(
     request_body(content_type = "multipart/form-data", content = ExcelFileForm),
     responses(
         (status=201, body = UploadFileEntry, description = "id for referencing the uploaded file for subsequent operations"),
-        (status=500, body = Error, description = "Error in multipart form data or no file found error")
-    )
+        (status=429, body = Error, description = "Too many uploads or the daily upload quota is used up, retry after the `Retry-After` seconds"),
+        (status=400, body = Error, description = "Error in multipart form data, no file found, or not a workbook or delimited text file")
+    ),
+    security(("bearer_token" = []), ("session_cookie" = []))
 )]
-async fn upload_file(
-    State(datasource): State<SqliteDataSource>,
+async fn upload_file<D: DataSource>(
+    State(state): State<AppState<D>>,
+    Extension(principal): Extension<Principal>,
+    Extension(client): Extension<Client>,
     mut multipart: Multipart,
 ) -> impl IntoResponse {
+    principal.require(Scope::Upload)?;
+    state.limits.check_quota(&state.datasource, &client).await?;
     let result = multipart.next_field().await;
 
     if result.is_err() {
@@ -274,401 +564,385 @@ async fn upload_file(
         return Err(Error::NoFileUploaded);
     }
     let fname = fname.unwrap().to_string();
-    let bytes = field.bytes().await.unwrap();
+    let bytes = field.bytes().await?;
+    let size = bytes.len();
+    let reservation = state
+        .limits
+        .reserve_upload(&state.datasource, &client, size)
+        .await?;
 
+    let id = match store_upload(&state.datasource, &principal, &fname, bytes).await {
+        Ok(id) => id,
+        Err(e) => {
+            if let Err(release_error) = state
+                .limits
+                .release_upload(&state.datasource, reservation)
+                .await
+            {
+                event!(
+                    Level::WARN,
+                    "Releasing the upload quota of {} failed: {}",
+                    client.0,
+                    release_error
+                );
+            }
+            return Err(e);
+        }
+    };
+    state.metrics.add_upload_bytes(size);
+
+    event!(
+        Level::INFO,
+        "{} uploaded {} as {}",
+        principal.name(),
+        fname,
+        id
+    );
+    let f_entry = state.datasource.get_file_entry(id.into()).await?;
+
+    Ok((StatusCode::CREATED, Json(json!(f_entry))))
+}
+
+/// The name of the upload without its extension, as results are downloaded
+/// under it. Quotes, backslashes and control characters would break the
+/// header and are left out. Entries without an uploaded name go by the name
+/// they are stored under.
+fn download_stem(entry: &UploadFileEntry) -> String {
+    let clean = |stem: &str| -> String {
+        stem.chars()
+            .filter(|c| !c.is_control() && *c != '"' && *c != '\\')
+            .collect()
+    };
+    let original = entry
+        .original_name
+        .as_deref()
+        .map(|name| clean(name.rsplit_once('.').map_or(name, |(stem, _)| stem)))
+        .filter(|stem| !stem.trim().is_empty());
+    original.unwrap_or_else(|| {
+        let stored = std::path::Path::new(&entry.file_path).file_stem();
+        clean(&stored.unwrap_or_default().to_string_lossy())
+    })
+}
+
+/// Writes an upload to the data directory and adds its entry, removing the
+/// file again if it isn't a readable workbook or the entry can't be added.
+async fn store_upload<D: DataSource>(
+    datasource: &D,
+    principal: &Principal,
+    fname: &str,
+    bytes: axum::body::Bytes,
+) -> CrateRes<uuid::Uuid> {
     let mut file_path = PathBuf::from(format!(".{MAIN_SEPARATOR}{DATA_DIR_NAME}"));
     if !file_path.exists() {
         let _ = fs::create_dir_all(&file_path).await;
     }
-    file_path.push(&fname);
+    file_path.push(stored_file_name(fname));
+    let size = bytes.len();
 
     if let Err(e) = fs::write(&file_path, bytes).await {
-        println!("Error writing file");
-        eprintln!("{} : {:?}", e, file_path);
-        return Err(Error::WritingToDisk(fname));
+        event!(Level::ERROR, "Error writing {}: {e}", file_path.display());
+        return Err(Error::WritingToDisk(fname.to_string()));
     };
 
-    if let Err(e) = reader::xlsx::read(&file_path) {
+    if let Err(e) = read_header_row(file_path.clone()).await {
         let _ = fs::remove_file(file_path).await;
-        return Err(Error::InValidExcelFile(e.to_string()));
+        return Err(e);
     };
 
-    let id = datasource.add_file_entry(&file_path).await;
-
-    if id.is_err() {
-        return Err(id.err().unwrap());
+    let added = datasource
+        .add_file_entry(NewUploadEntry {
+            file_path: &file_path,
+            original_name: fname,
+            size: size as i64,
+            owner: principal.owner(),
+        })
+        .await;
+    if added.is_err() {
+        let _ = fs::remove_file(file_path).await;
     }
-
-    let f_entry = UploadFileEntry {
-        id: id.unwrap().into(),
-        file_path: file_path.to_string_lossy().to_string(),
-    };
-
-    Ok((StatusCode::CREATED ,Json(json!(f_entry))))
+    added
 }
 
-fn get_cells(
-    work_sheet: &mut umya_spreadsheet::Worksheet,
-    last_row_idx: usize,
-    last_col_idx: usize,
-) -> Vec<Vec<Cell>> {
-    let mut result: Vec<Vec<Cell>> = Vec::new();
-    for row in 2..=last_row_idx {
-        let mut cur_row: Vec<Cell> = Vec::new();
-        for col in 1..=last_col_idx {
-            cur_row.push(
-                work_sheet
-                    .get_cell((col as u32, row as u32))
-                    .unwrap()
-                    .to_owned(),
-            )
-        }
-        result.push(cur_row);
+/// Uploads are stored under a name of their own, so uploads of the same
+/// name by different users don't overwrite each other and the name sent by
+/// the client never makes it into a path. Only the extension is kept, the
+/// format of delimited text files can depend on it.
+fn stored_file_name(original_name: &str) -> String {
+    let extension = std::path::Path::new(original_name)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .filter(|ext| !ext.is_empty() && ext.bytes().all(|b| b.is_ascii_alphanumeric()));
+    match extension {
+        Some(ext) => format!("{}.{}", uuid::Uuid::now_v7(), ext.to_ascii_lowercase()),
+        None => uuid::Uuid::now_v7().to_string(),
     }
-    result
 }
 
-fn sort_cells(cells: &mut [Vec<Cell>], sort_infos: &[SortInfo]) {
-    event!(Level::TRACE, "Sorting cells");
-    if sort_infos.is_empty() {
-        event!(Level::TRACE, "No columns to sort");
-        return;
-    }
-
-    let mut sortable_rows: HashMap<String, Vec<usize>> = HashMap::new();
-    let sort_info = &sort_infos[0];
-    let mut col_idx: usize = 0;
-
-    sort_cells_by_range(cells, 0..=(cells.len() - 1), sort_info, &mut col_idx);
-    event!(Level::TRACE, "First sort done...");
-
-    let sort_infos = sort_infos.iter().skip(1).collect::<Vec<_>>();
-    sort_infos.iter().enumerate().for_each(|(i, sort_info)| {
-        event!(Level::TRACE, "Finding sortable rows");
-        clear_build_sortable_rows(cells, col_idx, &mut sortable_rows);
-        event!(Level::TRACE, "Sortable rows found");
-
-        for row_range in sortable_rows
-            .values()
-            .filter(|r| r.len() > 1)
-            .map(|row| row[0]..=row[row.len() - 1])
-        {
-            event!(Level::TRACE, "Performing sub sort in iter #{}", i);
-            sort_cells_by_range(cells, row_range, sort_info, &mut col_idx);
-            event!(Level::TRACE, "Sub sort in iter #{} done", i);
+#[utoipa::path(
+    post,
+    path = "/login",
+    request_body = LoginRequest,
+    responses(
+        (status = 200, body = LoginResponse, description = "Logged in, the session is kept in the `excel_app_session` cookie"),
+        (status = 401, body = Error, description = "Unknown user or wrong password")
+    )
+)]
+async fn login<D: DataSource>(
+    State(state): State<AppState<D>>,
+    Json(request): Json<LoginRequest>,
+) -> CrateRes<impl IntoResponse> {
+    let user = match state.datasource.get_user(request.username).await {
+        Err(Error::NoEntryFound(_)) => None,
+        Err(e) => return Err(e),
+        Ok(user) => Some(user),
+    };
+    // Hashing is slow on purpose, so it is kept off the async workers.
+    // Unknown users are checked against a dummy hash, so they can't be told
+    // apart from wrong passwords by the time taken.
+    let user = match tokio::task::spawn_blocking(move || match user {
+        Some(user) => auth::verify_password(&request.password, &user.password_hash).then_some(user),
+        None => {
+            auth::verify_password(&request.password, auth::DUMMY_PASSWORD_HASH);
+            None
         }
-    });
-    
-    event!(Level::TRACE, "Done sorting...");
-}
+    })
+    .await
+    {
+        Err(e) => return Err(Error::Generic(format!("Checking password failed: {e}"))),
+        Ok(None) => return Err(Error::Unauthorized("Invalid username or password".into())),
+        Ok(Some(user)) => user,
+    };
 
-#[inline(always)]
-fn sort_cells_by_range(
-    cells: &mut [Vec<Cell>],
-    row_range: std::ops::RangeInclusive<usize>,
-    sort_info: &SortInfo,
-    col_idx: &mut usize,
-) {
-    cells[row_range].sort_unstable_by(|s1, s2| match sort_info {
-        crate::data::model::SortInfo::Asc { column_index } => {
-            // The column index we are receiving from the user
-            // doesn't start counting from 0, hence the -1 here
-            *col_idx = (column_index.to_owned() - 1) as usize;
-            return s1[*col_idx]
-                .get_value()
-                .as_ref()
-                .partial_cmp(s2[*col_idx].get_value().as_ref())
-                .unwrap();
-        }
-        crate::data::model::SortInfo::Desc { column_index } => {
-            // The column index we are receiving from the user
-            // doesn't start counting from 0, hence the -1 here
-            *col_idx = (column_index.to_owned() - 1) as usize;
-            return s2[*col_idx]
-                .get_value()
-                .as_ref()
-                .partial_cmp(s1[*col_idx].get_value().as_ref())
-                .unwrap();
-        }
-    });
+    let token = auth::new_token();
+    let expires_at = (chrono::Utc::now() + state.auth.session_ttl).to_rfc3339();
+    state
+        .datasource
+        .add_session(NewSession {
+            token_hash: &auth::token_hash(&token),
+            user_id: &user.id,
+            expires_at: &expires_at,
+        })
+        .await?;
+    event!(Level::INFO, "User {} logged in", user.username);
+
+    Ok((
+        [(
+            SET_COOKIE,
+            auth::session_cookie(&token, state.auth.session_ttl, state.secure_cookies),
+        )],
+        Json(LoginResponse {
+            username: user.username,
+            expires_at,
+        }),
+    ))
 }
 
-#[inline(always)]
-fn clear_build_sortable_rows(
-    cells: &mut [Vec<Cell>],
-    col_idx: usize,
-    sortable_rows: &mut HashMap<String, Vec<usize>>,
-) {
-    for (idx, row) in cells.iter().enumerate() {
-        let key = row[col_idx].get_value();
-        let value = sortable_rows.get_mut(key.as_ref());
-        if value.is_some() {
-            let value = value.unwrap();
-            value.push(idx);
-        } else {
-            sortable_rows.insert(key.to_string(), vec![idx]);
-        }
+#[utoipa::path(
+    post,
+    path = "/logout",
+    responses(
+        (status = 204, description = "The session is ended and its cookie removed")
+    )
+)]
+async fn logout<D: DataSource>(
+    State(state): State<AppState<D>>,
+    headers: HeaderMap,
+) -> CrateRes<impl IntoResponse> {
+    if let Some(token) = auth::session_token(&headers) {
+        state
+            .datasource
+            .remove_session(auth::token_hash(token))
+            .await?;
     }
+    Ok((
+        StatusCode::NO_CONTENT,
+        [(
+            SET_COOKIE,
+            auth::cleared_session_cookie(state.secure_cookies),
+        )],
+    ))
 }
 
-#[derive(Clone, Copy, Debug)]
-struct FoundSubTextPosInfo {
-    start_idx: usize,
-    end_idx: usize,
-}
+#[utoipa::path(
+    post,
+    path = "/tokens",
+    request_body = CreateApiTokenRequest,
+    responses(
+        (status = 201, body = CreatedApiToken, description = "The new token, shown only in this response"),
+        (status = 401, body = Error, description = "Login required"),
+        (status = 403, body = Error, description = "The API token lacks the admin scope"),
+        (status = 400, body = Error, description = "Empty name, unknown scope or expiry out of range")
+    ),
+    security(("bearer_token" = []), ("session_cookie" = []))
+)]
+async fn create_api_token<D: DataSource>(
+    State(state): State<AppState<D>>,
+    Extension(principal): Extension<Principal>,
+    Json(request): Json<CreateApiTokenRequest>,
+) -> CrateRes<impl IntoResponse> {
+    principal.require(Scope::Admin)?;
+    let user_id = principal.user_id()?;
+
+    let name = request.name.trim();
+    if name.is_empty() {
+        return Err(Error::InvalidPayload("The token name is empty".into()));
+    }
+    let mut scopes: Vec<Scope> = Vec::new();
+    for scope in &request.scopes {
+        let scope = Scope::parse(scope)?;
+        if !scopes.contains(&scope) {
+            scopes.push(scope);
+        }
+    }
+    if scopes.is_empty() {
+        return Err(Error::InvalidPayload(
+            "A token needs at least one scope".into(),
+        ));
+    }
+    let days = request.expires_in_days.unwrap_or(auth::DEFAULT_TOKEN_DAYS);
+    if !(1..=auth::MAX_TOKEN_DAYS).contains(&days) {
+        return Err(Error::InvalidPayload(format!(
+            "Invalid expiresInDays: Got {}, Expected: 1 to {}",
+            days,
+            auth::MAX_TOKEN_DAYS
+        )));
+    }
 
-fn highlight_search_terms_and_contractions(
-    cells: &mut [Vec<Cell>],
-    job_detail: &JobDetails,
-    contraction_str: &[String],
-) -> CrateRes<()> {
-    let default_color: colors::White = colors::White { color_pool_pos: 0 };
-    let black: colors::Black = colors::Black { color_pool_pos: 0 };
-    let yellow: colors::Yellow = colors::Yellow { color_pool_pos: 0 };
-    let beige: colors::Beige = colors::Beige { color_pool_pos: 0 };
-    let lavender: colors::Lavender = colors::Lavender { color_pool_pos: 0 };
-    let navy_blue: colors::NavyBlue = colors::NavyBlue { color_pool_pos: 0 };
-
-    let mut color_profiles: [Box<dyn CellColorProfile>; 6] = [
-        Box::from(default_color),
-        Box::from(yellow),
-        Box::from(beige),
-        Box::from(lavender),
-        Box::from(navy_blue),
-        Box::from(black),
-    ];
-
-    let search_terms = job_detail.search_terms();
-    let ac = AhoCorasick::new(search_terms).unwrap();
-
-    cells.iter_mut().for_each(|row| {
-        row.iter_mut().for_each(|cell| {
-            let cell_text = cell.get_value().to_string();
-            
-            let mut search_findings: Vec<FoundSubTextPosInfo> = ac
-                .find_overlapping_iter(&cell_text)
-                .map(|finding| FoundSubTextPosInfo {
-                    start_idx: finding.start(),
-                    end_idx: finding.end() - 1,
-                })
-                .collect();
-
-            search_findings.sort_by(|f1, f2| f1.start_idx.partial_cmp(&f2.start_idx).unwrap());
-
-            let new_search_findings = apply_overlapping_rule(search_findings);
-
-            let mut color_profile: &mut Box<dyn CellColorProfile> = &mut color_profiles[0];
-            for (idx, contraction) in contraction_str.iter().enumerate() {
-                if cell_text.trim().eq_ignore_ascii_case(contraction) {
-                    let color_idx = idx + 1 % color_profiles.len();
-                    color_profile = &mut color_profiles[color_idx];
-                    break;
-                }
-            }
-            apply_formatting(cell, color_profile, new_search_findings);
+    let token = auth::new_token();
+    let expires_at = (chrono::Utc::now() + chrono::Duration::days(days.into())).to_rfc3339();
+    let entry = state
+        .datasource
+        .add_api_token(NewApiToken {
+            user_id,
+            name,
+            token_hash: &auth::token_hash(&token),
+            scopes: &scopes,
+            expires_at: &expires_at,
         })
-    });
+        .await?;
+    event!(
+        Level::INFO,
+        "{} created the API token {}",
+        principal.name(),
+        entry.id
+    );
 
-    Ok(())
+    Ok((StatusCode::CREATED, Json(CreatedApiToken { token, entry })))
 }
 
-#[inline(always)]
-fn apply_formatting(
-    cell: &mut Cell,
-    color_profile: &mut Box<dyn CellColorProfile>,
-    new_search_findings: Vec<FoundSubTextPosInfo>,
-) {
-    let mut cell_text = cell.get_value().to_string();
-    let cell_style = cell.get_style_mut();
-
-    cell_style.set_background_color(colors::to_argb(
-        &color_profile.as_ref().get_background_color(),
-    ));
-
-    let font = cell_style.get_font_mut();
-    font.get_color_mut().set_argb(&colors::to_argb(
-        &color_profile.as_ref().get_default_text_color(),
-    ));
-
-    let mut offset = 0;
-    for mut finding in new_search_findings {
-        // Adding the html font tags,etc changes the position of the texts
-        // we would have to update the position of the findings.
-        // luckily they're in ascending order so we just add offset
-        finding.start_idx += offset;
-        finding.end_idx += offset;
-        cell_text.replace_range(
-            finding.start_idx..=finding.end_idx,
-            &format!(
-                r##"<font color="{txtcolor}"><b>{value}</b></font>"##,
-                txtcolor = color_profile.get_color(),
-                value = &cell_text[finding.start_idx..=finding.end_idx]
-            ),
-        );
-        // why 29, the new characters added to the old text sum up to 28
-        // 29 = html instructions (including the quote surrounding the color hex), color hex = 7
-        // The text value is not part, because they've been accounted for already
-        offset += 36;
-    }
-    cell.set_rich_text(umya_spreadsheet::helper::html::html_to_richtext(&cell_text).unwrap());
-    color_profile.reset_color_pool_pos();
+#[utoipa::path(
+    get,
+    path = "/tokens",
+    responses(
+        (status = 200, body = [ApiTokenEntry], description = "The API tokens of the user, oldest first, expired ones included"),
+        (status = 401, body = Error, description = "Login required"),
+        (status = 403, body = Error, description = "The API token lacks the admin scope")
+    ),
+    security(("bearer_token" = []), ("session_cookie" = []))
+)]
+async fn list_api_tokens<D: DataSource>(
+    State(state): State<AppState<D>>,
+    Extension(principal): Extension<Principal>,
+) -> CrateRes<Json<Vec<ApiTokenEntry>>> {
+    principal.require(Scope::Admin)?;
+    let user_id = principal.user_id()?.to_string();
+    Ok(Json(state.datasource.list_api_tokens(user_id).await?))
 }
 
-#[inline(always)]
-fn apply_overlapping_rule(search_findings: Vec<FoundSubTextPosInfo>) -> Vec<FoundSubTextPosInfo> {
-    let mut new_search_findings = Vec::clone(&search_findings);
-
-    for i in 0..search_findings.len() {
-        let f1 = search_findings[i];
-
-        for j in i + 1..search_findings.len() {
-            let f2 = search_findings[j];
-            let new_f2 = &mut new_search_findings[j];
-
-            let range = f1.start_idx..=f1.end_idx;
-
-            if range.contains(&f2.start_idx) {
-
-                if range.contains(&f2.end_idx) {
-
-                    new_f2.end_idx = 0;
-                    new_f2.start_idx = 0;
-                    continue;
-                }
-
-                let new_start = f1.end_idx + 1;
-                if new_start > new_f2.start_idx && new_start < new_f2.end_idx {
-                    new_f2.start_idx = new_start;
-                } else {
-                    new_f2.start_idx = new_f2.end_idx
-                }
-
-            }
-        }
-    }
-    let new_search_findings: Vec<FoundSubTextPosInfo> = new_search_findings
-        .into_iter()
-        .filter(|finding| finding.start_idx != 0 || finding.end_idx != 0)
-        .collect();
+#[utoipa::path(
+    delete,
+    path = "/tokens/{token_id}",
+    responses(
+        (status = 204, description = "The token is revoked"),
+        (status = 401, body = Error, description = "Login required"),
+        (status = 403, body = Error, description = "The API token lacks the admin scope"),
+        (status = 404, body = Error, description = "The user has no token with the id")
+    ),
+    security(("bearer_token" = []), ("session_cookie" = []))
+)]
+async fn revoke_api_token<D: DataSource>(
+    State(state): State<AppState<D>>,
+    Extension(principal): Extension<Principal>,
+    Path(token_id): Path<String>,
+) -> CrateRes<StatusCode> {
+    principal.require(Scope::Admin)?;
+    let user_id = principal.user_id()?.to_string();
+    state
+        .datasource
+        .remove_api_token(token_id.clone(), user_id)
+        .await?;
     event!(
-        Level::DEBUG,
-        message = "Final findings",
-        findings = format!("{:?}", new_search_findings),
-        previous = format!("{:?}",search_findings)
+        Level::INFO,
+        "{} revoked the API token {}",
+        principal.name(),
+        token_id
     );
-    new_search_findings
+    Ok(StatusCode::NO_CONTENT)
 }
 
-async fn get_contraction_texts(
-    contraction_f_bytes: Option<Bytes>,
-    contraction_f_path: &PathBuf,
-) -> CrateRes<Vec<String>> {
-    let mut contraction_str: Vec<String> = Vec::new();
-    if contraction_f_bytes.is_some() {
-        if let Err(e) = fs::write(&contraction_f_path, contraction_f_bytes.unwrap()).await {
-            return Err(Error::IOError(format!(
-                "Error writing contraction file to disk, {}",
-                e
-            )));
-        };
-
-        let contraction_wkbook = reader::xlsx::read(contraction_f_path);
-
-        if contraction_wkbook.is_err() {
-            return Err(Error::IOError(
-                contraction_wkbook.err().unwrap().to_string(),
-            ));
-        }
+/// Reads the header row of a workbook's first sheet or of a delimited file
+/// on the blocking thread pool. Fails for files in neither format.
+async fn read_header_row(file_path: PathBuf) -> CrateRes<Row> {
+    match tokio::task::spawn_blocking(move || formats::read_header(&file_path)).await {
+        Err(e) => Err(Error::Generic(format!("Reading workbook failed: {e}"))),
+        Ok(result) => result,
+    }
+}
 
-        let contraction_wkbook = contraction_wkbook.unwrap();
-        let first_contra_sheet = contraction_wkbook.get_sheet(&0usize);
-        if first_contra_sheet.is_err() {
-            return Err(Error::Generic(format!(
-                "Contraction file contains no sheet: {}",
-                first_contra_sheet.err().unwrap()
-            )));
-        }
-        let first_contra_sheet = first_contra_sheet.unwrap();
-        let (max_col, max_row) = first_contra_sheet.get_highest_column_and_row();
-        for col_idx in 1..=max_col {
-            for row_idx in 2..=max_row {
-                let cell_text = first_contra_sheet.get_value((col_idx, row_idx));
-                let cell_text = cell_text.trim();
-                if !cell_text.is_empty() {
-                    contraction_str.push(cell_text.into());
-                }
+/// `value` serialized with the characters outside of ASCII escaped, as
+/// header values can only hold ASCII.
+fn ascii_json(value: &Value) -> String {
+    let mut escaped = String::new();
+    for c in value.to_string().chars() {
+        if c.is_ascii() {
+            escaped.push(c);
+        } else {
+            for unit in c.encode_utf16(&mut [0; 2]) {
+                escaped.push_str(&format!("\\u{:04x}", unit));
             }
         }
-        let _ = fs::remove_file(contraction_f_path).await;
     }
-    Ok(contraction_str)
+    escaped
 }
 
-fn validate_sheet(
-    first_col_idx: u32,
-    last_col_idx: u32,
-    first_sheet: &umya_spreadsheet::Worksheet,
-    first_row_idx: u32,
-    job_detail: &JobDetails,
-    last_row_idx: u32,
-) -> CrateRes<()> {
-    // verify header row has no empty values
-    for col_idx in first_col_idx..=last_col_idx {
-        let row_val = first_sheet.get_value((col_idx, first_row_idx));
-        if row_val.trim().is_empty() {
-            return Err(Error::Generic("Incomplete title bar".into()));
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stored_file_names() {
+        let name = stored_file_name("../../etc/Report 2024.XLSX");
+        let (stem, ext) = name.split_once('.').unwrap();
+        assert!(uuid::Uuid::parse_str(stem).is_ok());
+        assert_eq!(ext, "xlsx");
+        assert_ne!(stored_file_name("a.csv"), stored_file_name("a.csv"));
+
+        for name in ["noextension", "trailing.", "odd.c/sv", "../..", "dots.x y"] {
+            let stored = stored_file_name(name);
+            assert!(
+                uuid::Uuid::parse_str(&stored).is_ok(),
+                "{name} stored as {stored}"
+            );
         }
     }
 
-    // verify cols with date
-    for col_idx in job_detail.check_date_cols() {
-        for row_idx in first_row_idx + 1..=last_row_idx {
-            let value = first_sheet.get_value((col_idx, &row_idx));
-            if value.len() < 6 {
-                return Err(Error::Generic(format!(
-                    "Invalid date value at column: {}, row: {}",
-                    col_idx, row_idx
-                )));
-            }
-            let month = &value[0..2];
-            let month_val = month.parse::<u32>();
-            if month_val.is_err() {
-                return Err(Error::Generic(format!(
-                    "Invalid month value for date field. Value = {}, column: {}, row: {}",
-                    month, col_idx, row_idx
-                )));
-            }
-            let month_val = month_val.unwrap();
-            if !(1..=12).contains(&month_val) {
-                return Err(Error::Generic(format!(
-                    "Invalid month value for date field. Value = {}, column: {}, row: {}",
-                    month, col_idx, row_idx
-                )));
-            }
-            let day = &value[2..4];
-            let day_val = day.parse::<u32>();
-            if day_val.is_err() {
-                return Err(Error::Generic(format!(
-                    "Invalid day value for date field. Value = {}, column: {}, row: {}",
-                    day, col_idx, row_idx
-                )));
-            }
-            let day_val = day_val.unwrap();
-            if !(1..=31).contains(&day_val) {
-                return Err(Error::Generic(format!(
-                    "Invalid day value for date field. Value = {}, column: {}, row: {}",
-                    day, col_idx, row_idx
-                )));
-            }
-            let year = &value[4..6];
-            let year_val = year.parse::<u32>();
-            if year_val.is_err() {
-                return Err(Error::Generic(format!(
-                    "Invalid year value for date field. Value = {}, column: {}, row: {}",
-                    year, col_idx, row_idx
-                )));
-            }
-        }
+    #[test]
+    fn download_stems() {
+        let entry = |original_name: Option<&str>| UploadFileEntry {
+            id: "id".into(),
+            file_path: format!(".{MAIN_SEPARATOR}data_{MAIN_SEPARATOR}0190a.xlsx"),
+            original_name: original_name.map(String::from),
+            size: None,
+            created_at: None,
+            owner: None,
+        };
+        assert_eq!(
+            download_stem(&entry(Some("Q1 report.v2.xlsx"))),
+            "Q1 report.v2"
+        );
+        assert_eq!(download_stem(&entry(Some("na\"me\r\n.csv"))), "name");
+        assert_eq!(download_stem(&entry(Some("Übersicht"))), "Übersicht");
+        assert_eq!(download_stem(&entry(Some("\"\".xlsx"))), "0190a");
+        assert_eq!(download_stem(&entry(None)), "0190a");
     }
-    Ok(())
 }
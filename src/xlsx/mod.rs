@@ -0,0 +1,499 @@
+//! Streaming access to the first sheet of an xlsx workbook.
+//!
+//! umya-spreadsheet builds a full object model for every cell, a few
+//! kilobytes each, which runs out of memory on sheets with hundreds of
+//! thousands of rows. This module only keeps the value, style index and
+//! formula of the cells of the first sheet, in compact rows. Every other part
+//! of the package is copied to the output untouched when writing.
+
+use std::{borrow::Cow, io::Write, sync::Arc};
+
+use crate::{error::Error, Result};
+
+mod read;
+mod styles;
+mod template;
+mod write;
+
+pub use read::{read_header, sheet_names};
+pub use styles::{CellStyle, Styles};
+pub use template::{DATE_STYLE, DATE_TIME_STYLE, DURATION_STYLE};
+
+/// The rows and columns of an Excel sheet, files with cells past them are
+/// refused rather than read into memory.
+pub const MAX_ROWS: u32 = 1_048_576;
+pub const MAX_COLUMNS: u32 = 16_384;
+
+/// A sheet of a workbook, the first one unless asked otherwise, read into
+/// memory, and what is needed to write it back into the rest of the package.
+pub struct Workbook {
+    /// The package the workbook was read from, `None` for a new workbook.
+    source: Option<read::Package>,
+    parts: Parts,
+    sheet: SheetXml,
+    pub styles: Styles,
+    /// Row 1 of the sheet, empty if the sheet has no such row.
+    pub header: Row,
+    /// Row 2 and below. Rows missing from the file are kept as empty rows,
+    /// so a row's position in this list always maps back to its number.
+    pub rows: Vec<Row>,
+    /// Leave the macros out when writing.
+    strip_macros: bool,
+    /// Sheets written after the existing ones.
+    added_sheets: Vec<AddedSheet>,
+}
+
+impl Workbook {
+    /// A new single sheet workbook holding `header` and `rows`, for data that
+    /// didn't come from an xlsx file.
+    pub fn from_rows(header: Row, rows: Vec<Row>) -> Result<Self> {
+        Ok(Self {
+            source: None,
+            parts: template::parts(),
+            sheet: template::sheet_xml(),
+            styles: Styles::parse(template::STYLES.as_bytes().to_vec())?,
+            header,
+            rows,
+            strip_macros: false,
+            added_sheets: Vec::new(),
+        })
+    }
+
+    /// Whether the workbook is written with a vba project, making it an
+    /// xlsm workbook.
+    pub fn has_macros(&self) -> bool {
+        !self.parts.macros.is_empty() && !self.strip_macros
+    }
+
+    /// Writes the workbook as a plain xlsx, without its vba project.
+    pub fn strip_macros(&mut self) {
+        self.strip_macros = true;
+    }
+
+    /// Adds a sheet after the existing ones, written from `header` and
+    /// `rows`. Formulas are left out, only their results are kept, as they
+    /// point at cells of the sheet the rows came from. A number is appended
+    /// to `name` if a sheet already has that name.
+    pub fn add_sheet(&mut self, name: &str, header: Row, mut rows: Vec<Row>) {
+        for cell in rows.iter_mut().flat_map(|row| row.cells.iter_mut()) {
+            if cell.formula.take().is_some() {
+                if let CellValue::FormulaString(text) = &mut cell.value {
+                    cell.value = CellValue::Text(std::mem::take(text));
+                }
+            }
+        }
+        self.added_sheets.push(AddedSheet {
+            name: name.to_string(),
+            header,
+            rows,
+        });
+    }
+
+    /// The highest column index used by any row, header included.
+    pub fn last_column(&self) -> u32 {
+        self.rows
+            .iter()
+            .map(Row::last_column)
+            .max()
+            .unwrap_or(0)
+            .max(self.header.last_column())
+    }
+}
+
+/// A sheet added to the workbook, see [`Workbook::add_sheet`].
+#[derive(Debug)]
+struct AddedSheet {
+    name: String,
+    header: Row,
+    rows: Vec<Row>,
+}
+
+/// Paths of the package parts that are read or rewritten.
+#[derive(Debug)]
+struct Parts {
+    workbook: String,
+    sheet: String,
+    styles: String,
+    shared_strings: Option<String>,
+    calc_chain: Option<String>,
+    workbook_rels: String,
+    /// The vba project and the parts that belong to it, empty for workbooks
+    /// without macros.
+    macros: Vec<String>,
+}
+
+/// The xml of the sheet around `<sheetData>`, written back the way it was
+/// read.
+#[derive(Debug, Default)]
+struct SheetXml {
+    prefix: Vec<u8>,
+    suffix: Vec<u8>,
+    /// Namespace prefix of the sheet elements including the colon, usually
+    /// empty.
+    ns_prefix: String,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Row {
+    /// Attributes of the `<row>` element other than its number, as escaped
+    /// in the file.
+    attrs: Box<[(Box<str>, Box<str>)]>,
+    /// Cells ordered by column.
+    pub cells: Vec<Cell>,
+}
+
+impl Row {
+    /// A row of inline strings, one cell per value starting at column 1.
+    /// Empty values get no cell.
+    pub fn from_text<I, S>(values: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        Self::from_cells(
+            values
+                .into_iter()
+                .zip(1..)
+                .filter(|(value, _)| !value.as_ref().is_empty())
+                .map(|(value, col)| Cell::new(col, 0, CellValue::Text(value.as_ref().into())))
+                .collect(),
+        )
+    }
+
+    /// A row of `cells`, which have to be ordered by column.
+    pub fn from_cells(mut cells: Vec<Cell>) -> Self {
+        cells.shrink_to_fit();
+        Self {
+            attrs: Box::default(),
+            cells,
+        }
+    }
+
+    pub fn cell(&self, col: u32) -> Option<&Cell> {
+        self.cells
+            .binary_search_by_key(&col, |c| c.col)
+            .ok()
+            .map(|idx| &self.cells[idx])
+    }
+
+    /// Text of the cell at `col`, empty for missing cells.
+    pub fn value(&self, col: u32) -> Cow<'_, str> {
+        self.cell(col)
+            .map(|cell| cell.value.text())
+            .unwrap_or(Cow::Borrowed(""))
+    }
+
+    pub fn last_column(&self) -> u32 {
+        self.cells.last().map_or(0, |c| c.col)
+    }
+
+    /// Hides or shows the row in Excel.
+    pub fn set_hidden(&mut self, hidden: bool) {
+        self.set_attr("hidden", hidden.then_some("1"));
+    }
+
+    /// The outline level of the row, 0 outside of any group.
+    pub fn outline_level(&self) -> u8 {
+        self.attrs
+            .iter()
+            .find(|(key, _)| &**key == "outlineLevel")
+            .and_then(|(_, value)| value.parse().ok())
+            .unwrap_or(0)
+    }
+
+    /// Puts the row in the groups Excel collapses up to `level`, 0 takes it
+    /// out of any group.
+    pub fn set_outline_level(&mut self, level: u8) {
+        let level = level.to_string();
+        self.set_attr("outlineLevel", (level != "0").then_some(&level));
+    }
+
+    fn set_attr(&mut self, name: &str, value: Option<&str>) {
+        let mut attrs: Vec<(Box<str>, Box<str>)> = std::mem::take(&mut self.attrs)
+            .into_vec()
+            .into_iter()
+            .filter(|(key, _)| &**key != name)
+            .collect();
+        if let Some(value) = value {
+            attrs.push((name.into(), value.into()));
+        }
+        self.attrs = attrs.into_boxed_slice();
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Cell {
+    /// 1 based column index.
+    pub col: u32,
+    /// Index into the `cellXfs` of the styles part.
+    pub style: u32,
+    pub value: CellValue,
+    /// The raw `<f>` element, written back as it was read.
+    formula: Option<Box<str>>,
+}
+
+impl Cell {
+    /// A cell without a formula.
+    pub fn new(col: u32, style: u32, value: CellValue) -> Self {
+        Self {
+            col,
+            style,
+            value,
+            formula: None,
+        }
+    }
+
+    pub fn has_formula(&self) -> bool {
+        self.formula.is_some()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum CellValue {
+    Empty,
+    /// An entry of the shared string table, the index is kept so the cell
+    /// is written back pointing at the same entry.
+    SharedString {
+        idx: u32,
+        text: Arc<str>,
+    },
+    /// An inline string.
+    Text(Box<str>),
+    /// The cached string result of a formula.
+    FormulaString(Box<str>),
+    /// A number as written in the file.
+    Number(Box<str>),
+    Bool(bool),
+    Error(Box<str>),
+    /// An ISO 8601 date.
+    Date(Box<str>),
+    /// An inline string made of differently formatted runs.
+    RichText(Box<[TextRun]>),
+}
+
+impl CellValue {
+    /// The text of the value, numbers and dates as written in the file and
+    /// booleans as `TRUE` or `FALSE`.
+    pub fn text(&self) -> Cow<'_, str> {
+        match self {
+            CellValue::Empty => Cow::Borrowed(""),
+            CellValue::SharedString { text, .. } => Cow::Borrowed(text),
+            CellValue::Text(text)
+            | CellValue::FormulaString(text)
+            | CellValue::Number(text)
+            | CellValue::Error(text)
+            | CellValue::Date(text) => Cow::Borrowed(text),
+            CellValue::Bool(true) => Cow::Borrowed("TRUE"),
+            CellValue::Bool(false) => Cow::Borrowed("FALSE"),
+            CellValue::RichText(runs) => Cow::Owned(runs.iter().map(|r| &*r.text).collect()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TextRun {
+    pub text: Box<str>,
+    /// ARGB font color, the cell's font color when `None`.
+    pub color: Option<String>,
+    pub bold: bool,
+}
+
+/// Parses the column part of a cell reference such as `AB12`, `None` past
+/// the last column of a sheet.
+fn column_index(cell_ref: &[u8]) -> Option<u32> {
+    let mut col: u32 = 0;
+    for b in cell_ref.iter().take_while(|b| b.is_ascii_alphabetic()) {
+        col = col
+            .checked_mul(26)?
+            .checked_add((b.to_ascii_uppercase() - b'A' + 1) as u32)
+            .filter(|col| *col <= MAX_COLUMNS)?;
+    }
+    (col > 0).then_some(col)
+}
+
+/// Writes the letters of a 1 based column index, `28` becomes `AB`.
+fn write_column_name<W: Write>(w: &mut W, mut col: u32) -> std::io::Result<()> {
+    let mut name = [0u8; 8];
+    let mut start = name.len();
+    while col > 0 {
+        col -= 1;
+        start -= 1;
+        name[start] = b'A' + (col % 26) as u8;
+        col /= 26;
+    }
+    w.write_all(&name[start..])
+}
+
+fn invalid(e: impl std::fmt::Display) -> Error {
+    Error::InValidExcelFile(e.to_string())
+}
+
+fn io_error(e: impl std::fmt::Display) -> Error {
+    Error::IOError(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Cursor, path::PathBuf};
+
+    use zip::{write::FileOptions, ZipWriter};
+
+    use super::*;
+
+    const SHEET_START: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"><sheetData>"#;
+    const SHEET_END: &str = "</sheetData></worksheet>";
+
+    /// A file removed when dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(bytes: &[u8]) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("excel_app_test_{}.xlsx", uuid::Uuid::now_v7()));
+            std::fs::write(&path, bytes).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    /// The template package with its sheet holding `rows`, the xml of its
+    /// `<sheetData>`.
+    fn package(rows: &str) -> Vec<u8> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        let sheet = format!("{SHEET_START}{rows}{SHEET_END}");
+        let parts = template::PACKAGE_PARTS.iter().copied().chain([
+            ("xl/styles.xml", template::STYLES),
+            ("xl/worksheets/sheet1.xml", sheet.as_str()),
+        ]);
+        for (name, xml) in parts {
+            zip.start_file(name, FileOptions::default()).unwrap();
+            zip.write_all(xml.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    fn open(bytes: &[u8]) -> Result<Workbook> {
+        Workbook::open(&TempFile::new(bytes).0)
+    }
+
+    fn values(row: &Row) -> Vec<String> {
+        (1..=row.last_column())
+            .map(|col| row.value(col).into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn column_references() {
+        assert_eq!(column_index(b"A1"), Some(1));
+        assert_eq!(column_index(b"ab12"), Some(28));
+        assert_eq!(column_index(b"XFD1048576"), Some(MAX_COLUMNS));
+        assert_eq!(column_index(b"XFE1"), None);
+        assert_eq!(column_index(b"ZZZZZZZZZZZZ1"), None);
+        assert_eq!(column_index(b"12"), None);
+    }
+
+    #[test]
+    fn column_names() {
+        for (col, name) in [(1, "A"), (26, "Z"), (28, "AB"), (MAX_COLUMNS, "XFD")] {
+            let mut out = Vec::new();
+            write_column_name(&mut out, col).unwrap();
+            assert_eq!(String::from_utf8(out).unwrap(), name);
+        }
+    }
+
+    #[test]
+    fn reads_rows_at_their_position() {
+        let workbook = open(&package(
+            r#"<row r="1"><c r="A1" t="inlineStr"><is><t>Name</t></is></c><c r="C1" t="inlineStr"><is><t>Note</t></is></c></row><row r="3" hidden="1"><c r="A3"><v>1.5</v></c><c t="b"><v>1</v></c></row>"#,
+        ))
+        .unwrap();
+        assert_eq!(values(&workbook.header), ["Name", "", "Note"]);
+        assert_eq!(workbook.rows.len(), 2);
+        assert!(workbook.rows[0].cells.is_empty());
+        // Cells without a reference follow the one before them.
+        assert_eq!(values(&workbook.rows[1]), ["1.5", "TRUE"]);
+        assert_eq!(workbook.last_column(), 3);
+    }
+
+    #[test]
+    fn refuses_rows_and_columns_past_the_sheet() {
+        let past_last_row = format!(r#"<row r="{}"><c><v>1</v></c></row>"#, MAX_ROWS + 1);
+        assert!(matches!(
+            open(&package(&past_last_row)),
+            Err(Error::InValidExcelFile(_))
+        ));
+        assert!(matches!(
+            open(&package(r#"<row r="2"><c r="XFE2"><v>1</v></c></row>"#)),
+            Err(Error::InValidExcelFile(_))
+        ));
+        assert!(matches!(
+            open(&package(
+                r#"<row r="2"><c r="XFD2"><v>1</v></c><c><v>2</v></c></row>"#
+            )),
+            Err(Error::InValidExcelFile(_))
+        ));
+        assert!(matches!(
+            open(&package(r#"<row r="4294967296"/>"#)),
+            Err(Error::InValidExcelFile(_))
+        ));
+    }
+
+    #[test]
+    fn refuses_rows_out_of_order() {
+        assert!(matches!(
+            open(&package(
+                r#"<row r="3"><c><v>1</v></c></row><row r="2"><c><v>2</v></c></row>"#
+            )),
+            Err(Error::InValidExcelFile(_))
+        ));
+    }
+
+    #[test]
+    fn writes_rows_back() {
+        let mut rows = vec![
+            Row::from_text(["carol", "apple pie"]),
+            Row::default(),
+            Row::from_text(["alice", "", "banana"]),
+        ];
+        rows[0].set_outline_level(2);
+        rows[2].set_hidden(true);
+        let mut workbook = Workbook::from_rows(Row::from_text(["Name", "Note"]), rows).unwrap();
+        workbook.add_sheet(
+            "Report",
+            Row::from_text(["Term"]),
+            vec![Row::from_text(["pie"])],
+        );
+
+        let written = workbook
+            .write(Cursor::new(Vec::new()))
+            .unwrap()
+            .into_inner();
+        let read = open(&written).unwrap();
+        assert_eq!(values(&read.header), ["Name", "Note"]);
+        assert_eq!(read.rows.len(), 3);
+        assert_eq!(values(&read.rows[0]), ["carol", "apple pie"]);
+        assert_eq!(read.rows[0].outline_level(), 2);
+        assert!(read.rows[1].cells.is_empty());
+        assert_eq!(values(&read.rows[2]), ["alice", "", "banana"]);
+        assert!(read.rows[2].attrs.iter().any(|(key, _)| &**key == "hidden"));
+
+        let names = read::sheet_names(&TempFile::new(&written).0).unwrap();
+        assert_eq!(names, ["Sheet1", "Report"]);
+    }
+
+    #[test]
+    fn outline_levels() {
+        let mut row = Row::default();
+        assert_eq!(row.outline_level(), 0);
+        row.set_outline_level(3);
+        assert_eq!(row.outline_level(), 3);
+        row.set_outline_level(0);
+        assert!(row.attrs.is_empty());
+    }
+}
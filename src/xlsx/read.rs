@@ -0,0 +1,699 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Cursor, Read, Seek, SeekFrom},
+    ops::ControlFlow,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use quick_xml::{
+    escape::unescape,
+    events::{attributes::Attribute, BytesStart, Event},
+    Reader, Writer,
+};
+use zip::ZipArchive;
+
+use super::{
+    column_index, invalid, io_error, styles::Styles, Cell, CellValue, Parts, Row, SheetXml,
+    Workbook, MAX_COLUMNS, MAX_ROWS,
+};
+use crate::Result;
+
+pub(super) type Archive = ZipArchive<PackageReader>;
+
+/// Where the package of a workbook is read from, and read again from when
+/// the workbook is written.
+pub(super) enum Package {
+    File(PathBuf),
+    Bytes(Arc<[u8]>),
+}
+
+pub(super) enum PackageReader {
+    File(BufReader<File>),
+    Bytes(Cursor<Arc<[u8]>>),
+}
+
+impl Read for PackageReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            PackageReader::File(file) => file.read(buf),
+            PackageReader::Bytes(bytes) => bytes.read(buf),
+        }
+    }
+}
+
+impl Seek for PackageReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        match self {
+            PackageReader::File(file) => file.seek(pos),
+            PackageReader::Bytes(bytes) => bytes.seek(pos),
+        }
+    }
+}
+
+impl Workbook {
+    /// Reads the first sheet of the workbook at `path`. The sheet is parsed
+    /// as a stream, only the compact rows are kept in memory.
+    pub fn open(path: &Path) -> Result<Self> {
+        Self::open_sheet(path, 0)
+    }
+
+    /// Reads the sheet at `sheet_idx`, in workbook order, the same way as
+    /// [`Workbook::open`] does the first one.
+    pub fn open_sheet(path: &Path, sheet_idx: usize) -> Result<Self> {
+        Self::read_package(Package::File(path.to_path_buf()), sheet_idx)
+    }
+
+    /// Reads the first sheet of the workbook held in `bytes`, which are kept
+    /// to write the rest of the package back from.
+    pub fn from_bytes(bytes: impl Into<Arc<[u8]>>) -> Result<Self> {
+        Self::read_package(Package::Bytes(bytes.into()), 0)
+    }
+
+    fn read_package(package: Package, sheet_idx: usize) -> Result<Self> {
+        let mut archive = open_archive(&package)?;
+        let parts = find_parts(&mut archive, sheet_idx)?;
+        let shared_strings = read_shared_strings(&mut archive, parts.shared_strings.as_deref())?;
+        let styles = Styles::read(&mut archive, &parts.styles)?;
+
+        let mut header = Row::default();
+        let mut rows: Vec<Row> = Vec::new();
+        let mut out_of_order = None;
+        let sheet = read_sheet(
+            &mut archive,
+            &parts.sheet,
+            &shared_strings,
+            |row_idx, row| {
+                if row_idx == 1 {
+                    header = row;
+                    return ControlFlow::Continue(());
+                }
+                let pos = row_idx as usize - 2;
+                if pos < rows.len() {
+                    out_of_order = Some(row_idx);
+                    return ControlFlow::Break(());
+                }
+                rows.resize_with(pos, Row::default);
+                rows.push(row);
+                ControlFlow::Continue(())
+            },
+        )?;
+        if let Some(row_idx) = out_of_order {
+            return Err(invalid(format!(
+                "Row {row_idx} of the first sheet is out of order"
+            )));
+        }
+
+        Ok(Self {
+            source: Some(package),
+            parts,
+            sheet,
+            styles,
+            header,
+            rows,
+            strip_macros: false,
+            added_sheets: Vec::new(),
+        })
+    }
+}
+
+/// Reads only row 1 of the first sheet, parsing stops at the first row of
+/// the sheet whichever it is. Cheap enough to validate uploads with.
+pub fn read_header(path: &Path) -> Result<Row> {
+    let mut archive = open_archive(&Package::File(path.to_path_buf()))?;
+    let parts = find_parts(&mut archive, 0)?;
+    let shared_strings = read_shared_strings(&mut archive, parts.shared_strings.as_deref())?;
+
+    let mut header = Row::default();
+    read_sheet(
+        &mut archive,
+        &parts.sheet,
+        &shared_strings,
+        |row_idx, row| {
+            if row_idx == 1 {
+                header = row;
+            }
+            ControlFlow::Break(())
+        },
+    )?;
+    Ok(header)
+}
+
+/// Names of the sheets, in workbook order.
+pub fn sheet_names(path: &Path) -> Result<Vec<String>> {
+    let mut archive = open_archive(&Package::File(path.to_path_buf()))?;
+    let workbook = workbook_part(&mut archive)?;
+    Ok(sheets(&mut archive, &workbook)?
+        .into_iter()
+        .map(|sheet| sheet.name)
+        .collect())
+}
+
+pub(super) fn open_archive(package: &Package) -> Result<Archive> {
+    let reader = match package {
+        Package::File(path) => {
+            PackageReader::File(BufReader::new(File::open(path).map_err(io_error)?))
+        }
+        Package::Bytes(bytes) => PackageReader::Bytes(Cursor::new(bytes.clone())),
+    };
+    ZipArchive::new(reader).map_err(invalid)
+}
+
+#[derive(Debug)]
+struct Relationship {
+    id: String,
+    kind: String,
+    target: String,
+}
+
+/// A `<sheet>` of the workbook.
+struct SheetEntry {
+    name: String,
+    /// `r:id`, not to be confused with `sheetId`.
+    id: Option<String>,
+}
+
+fn workbook_part(archive: &mut Archive) -> Result<String> {
+    read_relationships(archive, "_rels/.rels")?
+        .into_iter()
+        .find(|r| r.kind.ends_with("/officeDocument"))
+        .map(|r| resolve_target("", &r.target))
+        .ok_or_else(|| invalid("No workbook found in the package"))
+}
+
+fn find_parts(archive: &mut Archive, sheet_idx: usize) -> Result<Parts> {
+    let workbook = workbook_part(archive)?;
+    let base_dir = workbook.rsplit_once('/').map_or("", |(dir, _)| dir);
+    let workbook_rels = relationships_part(&workbook);
+
+    let relationships = read_relationships(archive, &workbook_rels)?;
+    let find_part = |kind: &str| {
+        relationships
+            .iter()
+            .find(|r| r.kind.ends_with(kind))
+            .map(|r| resolve_target(base_dir, &r.target))
+    };
+
+    let sheet_id = match sheets(archive, &workbook)?.into_iter().nth(sheet_idx) {
+        None if sheet_idx == 0 => return Err(invalid("The workbook has no sheet")),
+        None => return Err(invalid(format!("The workbook has no sheet {sheet_idx}"))),
+        Some(SheetEntry { id: None, name }) => {
+            return Err(invalid(format!("The sheet {name} has no relationship id")))
+        }
+        Some(SheetEntry { id: Some(id), .. }) => id,
+    };
+    let sheet = relationships
+        .iter()
+        .find(|r| r.id == sheet_id)
+        .map(|r| resolve_target(base_dir, &r.target))
+        .ok_or_else(|| invalid("The first sheet is missing from the package"))?;
+    let styles = find_part("/styles").ok_or_else(|| invalid("The workbook has no styles"))?;
+    let macros = match find_part("/vbaProject") {
+        None => Vec::new(),
+        Some(vba_project) => macro_parts(archive, vba_project)?,
+    };
+
+    let shared_strings = find_part("/sharedStrings");
+    let calc_chain = find_part("/calcChain");
+    Ok(Parts {
+        workbook,
+        sheet,
+        styles,
+        shared_strings,
+        calc_chain,
+        workbook_rels,
+        macros,
+    })
+}
+
+/// The vba project along with its relationships part and the parts it
+/// points to, its signatures.
+fn macro_parts(archive: &mut Archive, vba_project: String) -> Result<Vec<String>> {
+    let base_dir = vba_project.rsplit_once('/').map_or("", |(dir, _)| dir);
+    let rels = relationships_part(&vba_project);
+    let mut parts = Vec::new();
+    if archive.file_names().any(|name| name == rels) {
+        parts.extend(
+            read_relationships(archive, &rels)?
+                .iter()
+                .map(|r| resolve_target(base_dir, &r.target)),
+        );
+        parts.push(rels);
+    }
+    parts.push(vba_project);
+    Ok(parts)
+}
+
+/// The part holding the relationships of `part`.
+fn relationships_part(part: &str) -> String {
+    match part.rsplit_once('/') {
+        None => format!("_rels/{part}.rels"),
+        Some((dir, file_name)) => format!("{dir}/_rels/{file_name}.rels"),
+    }
+}
+
+/// Resolves a relationship target against the directory of its source part.
+fn resolve_target(base_dir: &str, target: &str) -> String {
+    if let Some(absolute) = target.strip_prefix('/') {
+        return absolute.to_string();
+    }
+    let mut segments: Vec<&str> = base_dir.split('/').filter(|s| !s.is_empty()).collect();
+    for segment in target.split('/') {
+        match segment {
+            ".." => {
+                segments.pop();
+            }
+            "." | "" => {}
+            s => segments.push(s),
+        }
+    }
+    segments.join("/")
+}
+
+fn part_reader<'a>(
+    archive: &'a mut Archive,
+    name: &str,
+) -> Result<Reader<BufReader<zip::read::ZipFile<'a>>>> {
+    let file = archive
+        .by_name(name)
+        .map_err(|e| invalid(format!("{name}: {e}")))?;
+    Ok(Reader::from_reader(BufReader::new(file)))
+}
+
+fn read_relationships(archive: &mut Archive, name: &str) -> Result<Vec<Relationship>> {
+    let mut xml = part_reader(archive, name)?;
+    let mut buf = Vec::new();
+    let mut relationships = Vec::new();
+    loop {
+        match xml.read_event_into(&mut buf).map_err(invalid)? {
+            Event::Eof => break,
+            Event::Start(e) | Event::Empty(e) if e.local_name().as_ref() == b"Relationship" => {
+                relationships.push(Relationship {
+                    id: attribute(&e, b"Id")?.unwrap_or_default(),
+                    kind: attribute(&e, b"Type")?.unwrap_or_default(),
+                    target: attribute(&e, b"Target")?.unwrap_or_default(),
+                });
+            }
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok(relationships)
+}
+
+/// The `<sheet>` elements of the workbook, in order.
+fn sheets(archive: &mut Archive, workbook: &str) -> Result<Vec<SheetEntry>> {
+    let mut xml = part_reader(archive, workbook)?;
+    let mut buf = Vec::new();
+    let mut sheets = Vec::new();
+    loop {
+        match xml.read_event_into(&mut buf).map_err(invalid)? {
+            Event::Eof => break,
+            Event::Start(e) | Event::Empty(e) if e.local_name().as_ref() == b"sheet" => {
+                let mut id = None;
+                for attr in e.attributes() {
+                    let attr = attr.map_err(invalid)?;
+                    if attr.key.local_name().as_ref() == b"id" {
+                        id = Some(attribute_value(&attr)?);
+                    }
+                }
+                sheets.push(SheetEntry {
+                    name: attribute(&e, b"name")?.unwrap_or_default(),
+                    id,
+                });
+            }
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok(sheets)
+}
+
+fn read_shared_strings(archive: &mut Archive, part: Option<&str>) -> Result<Vec<Arc<str>>> {
+    let Some(part) = part else {
+        return Ok(Vec::new());
+    };
+    let mut xml = part_reader(archive, part)?;
+    let mut buf = Vec::new();
+    let mut strings = Vec::new();
+    loop {
+        match xml.read_event_into(&mut buf).map_err(invalid)? {
+            Event::Eof => break,
+            Event::Start(e) if e.local_name().as_ref() == b"si" => {
+                strings.push(Arc::from(read_string_item(&mut xml, b"si")?));
+            }
+            Event::Empty(e) if e.local_name().as_ref() == b"si" => strings.push(Arc::from("")),
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok(strings)
+}
+
+/// Collects the text of a shared or inline string up to the end of
+/// `end_name`, rich text runs are concatenated and phonetic hints skipped.
+fn read_string_item<R: BufRead>(xml: &mut Reader<R>, end_name: &[u8]) -> Result<String> {
+    let mut buf = Vec::new();
+    let mut text = String::new();
+    let mut in_text = false;
+    let mut in_phonetic = false;
+    loop {
+        match xml.read_event_into(&mut buf).map_err(invalid)? {
+            Event::Eof => return Err(invalid("Unexpected end of a string item")),
+            Event::Start(e) => match e.local_name().as_ref() {
+                b"t" => in_text = !in_phonetic,
+                b"rPh" => in_phonetic = true,
+                _ => {}
+            },
+            Event::End(e) => match e.local_name().as_ref() {
+                b"t" => in_text = false,
+                b"rPh" => in_phonetic = false,
+                name if name == end_name => break,
+                _ => {}
+            },
+            Event::Text(t) if in_text => text.push_str(&t.unescape().map_err(invalid)?),
+            Event::CData(t) if in_text => text.push_str(&String::from_utf8_lossy(&t)),
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok(text)
+}
+
+/// Streams the sheet part, handing every row to `on_row` along with its 1
+/// based number. Everything around `<sheetData>` is kept as xml to write the
+/// sheet back. `on_row` can stop the parsing early, the returned xml is then
+/// incomplete.
+fn read_sheet<F>(
+    archive: &mut Archive,
+    part: &str,
+    shared_strings: &[Arc<str>],
+    mut on_row: F,
+) -> Result<SheetXml>
+where
+    F: FnMut(u32, Row) -> ControlFlow<()>,
+{
+    let mut xml = part_reader(archive, part)?;
+    let mut buf = Vec::new();
+    let mut out = Writer::new(Vec::new());
+    let mut sheet = SheetXml::default();
+    loop {
+        match xml.read_event_into(&mut buf).map_err(invalid)? {
+            Event::Eof => break,
+            Event::Start(e) if e.local_name().as_ref() == b"sheetData" => {
+                sheet.ns_prefix = ns_prefix(&e);
+                sheet.prefix = std::mem::take(out.get_mut());
+                if read_rows(&mut xml, shared_strings, &mut on_row)?.is_break() {
+                    return Ok(sheet);
+                }
+            }
+            Event::Empty(e) if e.local_name().as_ref() == b"sheetData" => {
+                sheet.ns_prefix = ns_prefix(&e);
+                sheet.prefix = std::mem::take(out.get_mut());
+            }
+            // Optional, and stale as soon as rows are added or removed.
+            Event::Empty(e) if e.local_name().as_ref() == b"dimension" => {}
+            e => out.write_event(e).map_err(invalid)?,
+        }
+        buf.clear();
+    }
+    sheet.suffix = out.into_inner();
+    Ok(sheet)
+}
+
+fn read_rows<R, F>(
+    xml: &mut Reader<R>,
+    shared_strings: &[Arc<str>],
+    on_row: &mut F,
+) -> Result<ControlFlow<()>>
+where
+    R: BufRead,
+    F: FnMut(u32, Row) -> ControlFlow<()>,
+{
+    let mut buf = Vec::new();
+    let mut last_row_idx = 0;
+    loop {
+        let row = match xml.read_event_into(&mut buf).map_err(invalid)? {
+            Event::Eof => return Err(invalid("Unexpected end of the sheet data")),
+            Event::End(e) if e.local_name().as_ref() == b"sheetData" => {
+                return Ok(ControlFlow::Continue(()))
+            }
+            Event::Start(e) if e.local_name().as_ref() == b"row" => {
+                let (row_idx, attrs) = row_attributes(&e, last_row_idx)?;
+                let cells = read_cells(xml, shared_strings)?;
+                Some((row_idx, Row { attrs, cells }))
+            }
+            Event::Empty(e) if e.local_name().as_ref() == b"row" => {
+                let (row_idx, attrs) = row_attributes(&e, last_row_idx)?;
+                Some((
+                    row_idx,
+                    Row {
+                        attrs,
+                        cells: Vec::new(),
+                    },
+                ))
+            }
+            _ => None,
+        };
+        buf.clear();
+        if let Some((row_idx, row)) = row {
+            last_row_idx = row_idx;
+            if on_row(row_idx, row).is_break() {
+                return Ok(ControlFlow::Break(()));
+            }
+        }
+    }
+}
+
+type RowAttributes = Box<[(Box<str>, Box<str>)]>;
+
+fn row_attributes(e: &BytesStart, last_row_idx: u32) -> Result<(u32, RowAttributes)> {
+    let mut row_idx = last_row_idx + 1;
+    let mut attrs = Vec::new();
+    for attr in e.attributes() {
+        let attr = attr.map_err(invalid)?;
+        match attr.key.as_ref() {
+            b"r" => {
+                row_idx = std::str::from_utf8(&attr.value)
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .filter(|v| *v > 0)
+                    .ok_or_else(|| invalid("Invalid row number"))?;
+            }
+            // Only a loading hint, recomputing it is not worth it.
+            b"spans" => {}
+            key => attrs.push((
+                String::from_utf8_lossy(key).into(),
+                String::from_utf8_lossy(&attr.value).into(),
+            )),
+        }
+    }
+    if row_idx > MAX_ROWS {
+        return Err(invalid(format!(
+            "Row {row_idx} is past the last row of a sheet, {MAX_ROWS}"
+        )));
+    }
+    Ok((row_idx, attrs.into_boxed_slice()))
+}
+
+#[derive(Clone, Copy)]
+enum CellType {
+    Number,
+    SharedString,
+    InlineString,
+    FormulaString,
+    Bool,
+    Error,
+    Date,
+}
+
+fn read_cells<R: BufRead>(xml: &mut Reader<R>, shared_strings: &[Arc<str>]) -> Result<Vec<Cell>> {
+    let mut buf = Vec::new();
+    let mut cells: Vec<Cell> = Vec::new();
+    loop {
+        match xml.read_event_into(&mut buf).map_err(invalid)? {
+            Event::Eof => return Err(invalid("Unexpected end of a row")),
+            Event::End(e) if e.local_name().as_ref() == b"row" => break,
+            Event::Start(e) if e.local_name().as_ref() == b"c" => {
+                let last_col = cells.last().map_or(0, |c| c.col);
+                let (col, style, cell_type) = cell_attributes(&e, last_col)?;
+                cells.push(read_cell(xml, col, style, cell_type, shared_strings)?);
+            }
+            Event::Empty(e) if e.local_name().as_ref() == b"c" => {
+                let last_col = cells.last().map_or(0, |c| c.col);
+                let (col, style, _) = cell_attributes(&e, last_col)?;
+                cells.push(Cell {
+                    col,
+                    style,
+                    value: CellValue::Empty,
+                    formula: None,
+                });
+            }
+            _ => {}
+        }
+        buf.clear();
+    }
+    if !cells.windows(2).all(|w| w[0].col < w[1].col) {
+        cells.sort_by_key(|c| c.col);
+        cells.dedup_by_key(|c| c.col);
+    }
+    cells.shrink_to_fit();
+    Ok(cells)
+}
+
+fn cell_attributes(e: &BytesStart, last_col: u32) -> Result<(u32, u32, CellType)> {
+    let mut col = last_col + 1;
+    let mut style = 0;
+    let mut cell_type = CellType::Number;
+    for attr in e.attributes() {
+        let attr = attr.map_err(invalid)?;
+        match attr.key.as_ref() {
+            b"r" => {
+                col = column_index(&attr.value).ok_or_else(|| {
+                    invalid(format!(
+                        "Invalid cell reference {}, columns go up to {MAX_COLUMNS}",
+                        String::from_utf8_lossy(&attr.value)
+                    ))
+                })?
+            }
+            b"s" => {
+                style = std::str::from_utf8(&attr.value)
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .ok_or_else(|| invalid("Invalid cell style"))?
+            }
+            b"t" => {
+                cell_type = match attr.value.as_ref() {
+                    b"s" => CellType::SharedString,
+                    b"inlineStr" => CellType::InlineString,
+                    b"str" => CellType::FormulaString,
+                    b"b" => CellType::Bool,
+                    b"e" => CellType::Error,
+                    b"d" => CellType::Date,
+                    _ => CellType::Number,
+                }
+            }
+            _ => {}
+        }
+    }
+    if col > MAX_COLUMNS {
+        return Err(invalid(format!(
+            "Column {col} is past the last column of a sheet, {MAX_COLUMNS}"
+        )));
+    }
+    Ok((col, style, cell_type))
+}
+
+fn read_cell<R: BufRead>(
+    xml: &mut Reader<R>,
+    col: u32,
+    style: u32,
+    cell_type: CellType,
+    shared_strings: &[Arc<str>],
+) -> Result<Cell> {
+    let mut buf = Vec::new();
+    let mut formula = None;
+    let mut raw_value: Option<String> = None;
+    loop {
+        match xml.read_event_into(&mut buf).map_err(invalid)? {
+            Event::Eof => return Err(invalid("Unexpected end of a cell")),
+            Event::End(e) if e.local_name().as_ref() == b"c" => break,
+            Event::Start(e) => match e.local_name().as_ref() {
+                b"f" => formula = Some(capture_element(xml, e.into_owned())?.into()),
+                b"v" => raw_value = Some(read_text(xml, b"v")?),
+                b"is" => raw_value = Some(read_string_item(xml, b"is")?),
+                _ => {}
+            },
+            Event::Empty(e) if e.local_name().as_ref() == b"f" => {
+                let mut out = Writer::new(Vec::new());
+                out.write_event(Event::Empty(e)).map_err(invalid)?;
+                formula = Some(String::from_utf8_lossy(&out.into_inner()).into());
+            }
+            _ => {}
+        }
+        buf.clear();
+    }
+
+    let value = match (cell_type, raw_value) {
+        (_, None) => CellValue::Empty,
+        (CellType::SharedString, Some(v)) => {
+            let idx: u32 = v.trim().parse().map_err(invalid)?;
+            let text = shared_strings
+                .get(idx as usize)
+                .cloned()
+                .ok_or_else(|| invalid(format!("Shared string {idx} does not exist")))?;
+            CellValue::SharedString { idx, text }
+        }
+        (CellType::InlineString, Some(v)) => CellValue::Text(v.into()),
+        (CellType::FormulaString, Some(v)) => CellValue::FormulaString(v.into()),
+        (CellType::Bool, Some(v)) => CellValue::Bool(v.trim() == "1"),
+        (CellType::Error, Some(v)) => CellValue::Error(v.into()),
+        (CellType::Date, Some(v)) => CellValue::Date(v.into()),
+        (CellType::Number, Some(v)) => CellValue::Number(v.into()),
+    };
+    Ok(Cell {
+        col,
+        style,
+        value,
+        formula,
+    })
+}
+
+fn read_text<R: BufRead>(xml: &mut Reader<R>, end_name: &[u8]) -> Result<String> {
+    let mut buf = Vec::new();
+    let mut text = String::new();
+    loop {
+        match xml.read_event_into(&mut buf).map_err(invalid)? {
+            Event::Eof => return Err(invalid("Unexpected end of a cell value")),
+            Event::End(e) if e.local_name().as_ref() == end_name => break,
+            Event::Text(t) => text.push_str(&t.unescape().map_err(invalid)?),
+            Event::CData(t) => text.push_str(&String::from_utf8_lossy(&t)),
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok(text)
+}
+
+/// Re-serializes the element started by `start` and everything in it.
+pub(super) fn capture_element<R: BufRead>(
+    xml: &mut Reader<R>,
+    start: BytesStart<'static>,
+) -> Result<String> {
+    let mut out = Writer::new(Vec::new());
+    out.write_event(Event::Start(start)).map_err(invalid)?;
+    let mut buf = Vec::new();
+    let mut depth = 1;
+    while depth > 0 {
+        let event = xml.read_event_into(&mut buf).map_err(invalid)?;
+        match &event {
+            Event::Eof => return Err(invalid("Unexpected end of an element")),
+            Event::Start(_) => depth += 1,
+            Event::End(_) => depth -= 1,
+            _ => {}
+        }
+        out.write_event(event).map_err(invalid)?;
+        buf.clear();
+    }
+    String::from_utf8(out.into_inner()).map_err(invalid)
+}
+
+pub(super) fn attribute(e: &BytesStart, key: &[u8]) -> Result<Option<String>> {
+    match e.try_get_attribute(key).map_err(invalid)? {
+        None => Ok(None),
+        Some(attr) => Ok(Some(attribute_value(&attr)?)),
+    }
+}
+
+/// Unescapes an attribute value, parts are taken to be UTF-8.
+fn attribute_value(attr: &Attribute) -> Result<String> {
+    let value = std::str::from_utf8(&attr.value).map_err(invalid)?;
+    Ok(unescape(value).map_err(invalid)?.into_owned())
+}
+
+fn ns_prefix(e: &BytesStart) -> String {
+    match e.name().prefix() {
+        None => String::new(),
+        Some(prefix) => format!("{}:", String::from_utf8_lossy(prefix.as_ref())),
+    }
+}
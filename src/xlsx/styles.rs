@@ -0,0 +1,565 @@
+use std::{collections::HashMap, io::Read};
+
+use quick_xml::{
+    events::{attributes::Attribute, BytesStart, Event},
+    Reader, Writer,
+};
+
+use super::{
+    invalid,
+    read::{attribute, capture_element, Archive},
+};
+use crate::Result;
+
+/// Codes of the built in number formats that are commonly used, by id.
+const BUILT_IN_NUMBER_FORMATS: [(u32, &str); 24] = [
+    (1, "0"),
+    (2, "0.00"),
+    (3, "#,##0"),
+    (4, "#,##0.00"),
+    (9, "0%"),
+    (10, "0.00%"),
+    (11, "0.00E+00"),
+    (12, "# ?/?"),
+    (13, "# ??/??"),
+    (14, "mm-dd-yy"),
+    (15, "d-mmm-yy"),
+    (16, "d-mmm"),
+    (17, "mmm-yy"),
+    (18, "h:mm AM/PM"),
+    (19, "h:mm:ss AM/PM"),
+    (20, "h:mm"),
+    (21, "h:mm:ss"),
+    (22, "m/d/yy h:mm"),
+    (45, "mm:ss"),
+    (46, "[h]:mm:ss"),
+    (47, "mmss.0"),
+    (37, "#,##0 ;(#,##0)"),
+    (48, "##0.0E+0"),
+    (49, "@"),
+];
+
+/// The styles part of the workbook. Only the number formats, fonts, fills
+/// and cell formats are parsed, new fonts, fills and cell formats are
+/// appended to those lists when the part is written back.
+pub struct Styles {
+    xml: Vec<u8>,
+    /// Namespace prefix of the style elements including the colon, usually
+    /// empty.
+    ns_prefix: String,
+    /// Codes of the custom number formats by id.
+    number_formats: HashMap<u32, String>,
+    fonts: Vec<String>,
+    /// The `<fill>` elements.
+    fills: Vec<String>,
+    cell_formats: Vec<BytesStart<'static>>,
+    cell_format_children: Vec<Vec<u8>>,
+    new_fonts: Vec<String>,
+    new_fills: Vec<String>,
+    new_cell_formats: Vec<String>,
+}
+
+impl Styles {
+    pub(super) fn read(archive: &mut Archive, part: &str) -> Result<Self> {
+        let mut xml = Vec::new();
+        archive
+            .by_name(part)
+            .map_err(invalid)?
+            .read_to_end(&mut xml)
+            .map_err(invalid)?;
+        Self::parse(xml)
+    }
+
+    pub(super) fn parse(xml: Vec<u8>) -> Result<Self> {
+        let mut styles = Self {
+            xml,
+            ns_prefix: String::new(),
+            number_formats: HashMap::new(),
+            fonts: Vec::new(),
+            fills: Vec::new(),
+            cell_formats: Vec::new(),
+            cell_format_children: Vec::new(),
+            new_fonts: Vec::new(),
+            new_fills: Vec::new(),
+            new_cell_formats: Vec::new(),
+        };
+
+        let mut reader = Reader::from_reader(styles.xml.as_slice());
+        let mut buf = Vec::new();
+        let mut parents: Vec<Vec<u8>> = Vec::new();
+        loop {
+            let event = reader.read_event_into(&mut buf).map_err(invalid)?;
+            let parent = parents.last().cloned().unwrap_or_default();
+            match event {
+                Event::Eof => break,
+                Event::Start(e) if e.local_name().as_ref() == b"styleSheet" => {
+                    if let Some(prefix) = e.name().prefix() {
+                        styles.ns_prefix = format!("{}:", String::from_utf8_lossy(prefix.as_ref()));
+                    }
+                    parents.push(b"styleSheet".to_vec());
+                }
+                Event::Start(e) if parent == b"fonts" && e.local_name().as_ref() == b"font" => {
+                    let element = capture_element(&mut reader, e.into_owned())?;
+                    styles.fonts.push(element_children(&element).to_string());
+                }
+                Event::Empty(e) if parent == b"fonts" && e.local_name().as_ref() == b"font" => {
+                    styles.fonts.push(String::new());
+                }
+                Event::Empty(e) if parent == b"numFmts" && e.local_name().as_ref() == b"numFmt" => {
+                    styles.number_formats.extend(number_format(&e)?);
+                }
+                Event::Start(e) if parent == b"numFmts" && e.local_name().as_ref() == b"numFmt" => {
+                    styles.number_formats.extend(number_format(&e)?);
+                    capture_element(&mut reader, e.into_owned())?;
+                }
+                Event::Start(e) if parent == b"fills" && e.local_name().as_ref() == b"fill" => {
+                    styles
+                        .fills
+                        .push(capture_element(&mut reader, e.into_owned())?);
+                }
+                Event::Empty(_) if parent == b"fills" => {
+                    styles.fills.push(String::new());
+                }
+                Event::Start(e) if parent == b"cellXfs" && e.local_name().as_ref() == b"xf" => {
+                    // Only the children are kept as xml, the start tag is
+                    // rebuilt with new attributes.
+                    let start = e.into_owned();
+                    let element = capture_element(&mut reader, start.clone())?;
+                    styles.cell_formats.push(start);
+                    styles
+                        .cell_format_children
+                        .push(element_children(&element).as_bytes().to_vec());
+                }
+                Event::Empty(e) if parent == b"cellXfs" && e.local_name().as_ref() == b"xf" => {
+                    styles.cell_formats.push(e.into_owned());
+                    styles.cell_format_children.push(Vec::new());
+                }
+                Event::Start(e) => parents.push(e.local_name().as_ref().to_vec()),
+                Event::End(_) => {
+                    parents.pop();
+                }
+                _ => {}
+            }
+            buf.clear();
+        }
+
+        Ok(styles)
+    }
+
+    /// The formatting of the cell format at index `idx` that matters for
+    /// showing a value, the default format for unknown indexes.
+    pub fn cell_style(&self, idx: u32) -> Result<CellStyle> {
+        let mut style = CellStyle::default();
+        let Some(format) = self.cell_formats.get(idx as usize) else {
+            return Ok(style);
+        };
+        let index_of = |key: &[u8]| -> Result<Option<usize>> {
+            Ok(attribute(format, key)?.and_then(|id| id.parse().ok()))
+        };
+
+        if let Some(font) = index_of(b"fontId")?.and_then(|id| self.fonts.get(id)) {
+            let mut reader = Reader::from_reader(font.as_bytes());
+            let mut buf = Vec::new();
+            loop {
+                match reader.read_event_into(&mut buf).map_err(invalid)? {
+                    Event::Eof => break,
+                    Event::Start(e) | Event::Empty(e) => match e.local_name().as_ref() {
+                        b"b" => style.bold = is_on(&e)?,
+                        b"i" => style.italic = is_on(&e)?,
+                        b"color" => style.color = attribute(&e, b"rgb")?.map(to_hex_color),
+                        _ => {}
+                    },
+                    _ => {}
+                }
+                buf.clear();
+            }
+        }
+
+        if let Some(fill) = index_of(b"fillId")?.and_then(|id| self.fills.get(id)) {
+            let mut reader = Reader::from_reader(fill.as_bytes());
+            let mut buf = Vec::new();
+            let mut solid = false;
+            loop {
+                match reader.read_event_into(&mut buf).map_err(invalid)? {
+                    Event::Eof => break,
+                    Event::Start(e) | Event::Empty(e) => match e.local_name().as_ref() {
+                        b"patternFill" => {
+                            solid = attribute(&e, b"patternType")?.as_deref() == Some("solid")
+                        }
+                        b"fgColor" if solid => {
+                            style.background = attribute(&e, b"rgb")?.map(to_hex_color)
+                        }
+                        _ => {}
+                    },
+                    _ => {}
+                }
+                buf.clear();
+            }
+        }
+
+        if let Some(id) = index_of(b"numFmtId")? {
+            let id = id as u32;
+            let code = match self.number_formats.get(&id) {
+                Some(code) => Some(code.as_str()),
+                None => BUILT_IN_NUMBER_FORMATS
+                    .iter()
+                    .find(|(built_in, _)| *built_in == id)
+                    .map(|(_, code)| *code),
+            };
+            style.is_date = is_date_format(id, code.unwrap_or_default());
+            style.number_format = code.map(str::to_string);
+        }
+        Ok(style)
+    }
+
+    /// Adds a cell format based on the format at index `base` with a solid
+    /// `background` and the font recolored to `font_color`, both ARGB hex.
+    /// Returns the index of the new format.
+    pub fn add_highlight(&mut self, base: u32, background: &str, font_color: &str) -> Result<u32> {
+        let p = &self.ns_prefix;
+
+        let fill = format!(
+            r#"<{p}fill><{p}patternFill patternType="solid"><{p}fgColor rgb="{background}"/><{p}bgColor indexed="64"/></{p}patternFill></{p}fill>"#
+        );
+        let fill_id = self.fills.len() + append_once(&mut self.new_fills, fill);
+
+        let base_idx = if (base as usize) < self.cell_formats.len() {
+            base as usize
+        } else {
+            0
+        };
+        let base_format = self.cell_formats.get(base_idx);
+        let base_font = match base_format {
+            None => None,
+            Some(format) => attribute(format, b"fontId")?.and_then(|id| id.parse::<usize>().ok()),
+        };
+        let font_children = match self.fonts.get(base_font.unwrap_or(0)) {
+            None => Vec::new(),
+            Some(children) => without_color(children)?,
+        };
+        let font = format!(
+            r#"<{p}font>{children}<{p}color rgb="{font_color}"/></{p}font>"#,
+            children = String::from_utf8_lossy(&font_children)
+        );
+        let font_id = self.fonts.len() + append_once(&mut self.new_fonts, font);
+
+        let p = &self.ns_prefix;
+        let mut start = BytesStart::new(format!("{p}xf"));
+        if let Some(format) = base_format {
+            for attr in format.attributes() {
+                let attr = attr.map_err(invalid)?;
+                if !matches!(
+                    attr.key.as_ref(),
+                    b"fontId" | b"fillId" | b"applyFont" | b"applyFill"
+                ) {
+                    start.push_attribute(attr);
+                }
+            }
+        } else {
+            start.push_attribute(("numFmtId", "0"));
+            start.push_attribute(("borderId", "0"));
+        }
+        start.push_attribute(("fontId", font_id.to_string().as_str()));
+        start.push_attribute(("fillId", fill_id.to_string().as_str()));
+        start.push_attribute(("applyFont", "1"));
+        start.push_attribute(("applyFill", "1"));
+
+        let children = self
+            .cell_format_children
+            .get(base_idx)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let mut out = Writer::new(Vec::new());
+        if children.is_empty() {
+            out.write_event(Event::Empty(start)).map_err(invalid)?;
+        } else {
+            let end = start.to_end().into_owned();
+            out.write_event(Event::Start(start)).map_err(invalid)?;
+            out.get_mut().extend_from_slice(children);
+            out.write_event(Event::End(end)).map_err(invalid)?;
+        }
+        self.new_cell_formats
+            .push(String::from_utf8(out.into_inner()).map_err(invalid)?);
+
+        Ok((self.cell_formats.len() + self.new_cell_formats.len() - 1) as u32)
+    }
+
+    /// The styles part with the added fonts, fills and cell formats.
+    pub(super) fn to_xml(&self) -> Result<Vec<u8>> {
+        if self.new_cell_formats.is_empty() {
+            return Ok(self.xml.clone());
+        }
+
+        let mut reader = Reader::from_reader(self.xml.as_slice());
+        let mut out = Writer::new(Vec::with_capacity(self.xml.len()));
+        let mut buf = Vec::new();
+        let mut depth = 0;
+        loop {
+            let event = reader.read_event_into(&mut buf).map_err(invalid)?;
+            match event {
+                Event::Eof => break,
+                Event::Start(e) => {
+                    depth += 1;
+                    match self.appended(depth, e.local_name().as_ref()) {
+                        None => out.write_event(Event::Start(e)).map_err(invalid)?,
+                        Some((count, _)) => out
+                            .write_event(Event::Start(with_count(&e, count)?))
+                            .map_err(invalid)?,
+                    }
+                }
+                Event::End(e) => {
+                    if let Some((_, added)) = self.appended(depth, e.local_name().as_ref()) {
+                        for element in added {
+                            out.get_mut().extend_from_slice(element.as_bytes());
+                        }
+                    }
+                    depth -= 1;
+                    out.write_event(Event::End(e)).map_err(invalid)?;
+                }
+                Event::Empty(e) => match self.appended(depth + 1, e.local_name().as_ref()) {
+                    None => out.write_event(Event::Empty(e)).map_err(invalid)?,
+                    Some((count, added)) => {
+                        let end = e.to_end().into_owned();
+                        out.write_event(Event::Start(with_count(&e, count)?))
+                            .map_err(invalid)?;
+                        for element in added {
+                            out.get_mut().extend_from_slice(element.as_bytes());
+                        }
+                        out.write_event(Event::End(end)).map_err(invalid)?;
+                    }
+                },
+                e => out.write_event(e).map_err(invalid)?,
+            }
+            buf.clear();
+        }
+        Ok(out.into_inner())
+    }
+
+    /// The new element count and the added elements of the list named
+    /// `name`, for the lists directly under the root that get new entries.
+    fn appended(&self, depth: usize, name: &[u8]) -> Option<(usize, &[String])> {
+        if depth != 2 {
+            return None;
+        }
+        match name {
+            b"fonts" => Some((self.fonts.len() + self.new_fonts.len(), &self.new_fonts)),
+            b"fills" => Some((self.fills.len() + self.new_fills.len(), &self.new_fills)),
+            b"cellXfs" => Some((
+                self.cell_formats.len() + self.new_cell_formats.len(),
+                &self.new_cell_formats,
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// The formatting of a cell as far as showing its value goes.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct CellStyle {
+    pub bold: bool,
+    pub italic: bool,
+    /// `#RRGGBB` font color, for explicit colors only. Theme and indexed
+    /// colors are left out.
+    pub color: Option<String>,
+    /// `#RRGGBB` color of a solid fill.
+    pub background: Option<String>,
+    /// Code of the number format, `None` for the general format and built
+    /// in formats without a well known code.
+    pub number_format: Option<String>,
+    /// Numbers in this format are date or time serial numbers.
+    pub is_date: bool,
+}
+
+/// The id and code of a `<numFmt>`.
+fn number_format(e: &BytesStart) -> Result<Option<(u32, String)>> {
+    let id = attribute(e, b"numFmtId")?.and_then(|id| id.parse().ok());
+    Ok(id.zip(attribute(e, b"formatCode")?))
+}
+
+/// Whether the number format with `id` and `code` shows dates or times.
+fn is_date_format(id: u32, code: &str) -> bool {
+    if matches!(id, 14..=22 | 27..=36 | 45..=47 | 50..=58) {
+        return true;
+    }
+    // Quoted text, escaped characters and bracketed colors or conditions
+    // don't count, elapsed time such as `[h]` does.
+    let mut in_quotes = false;
+    let mut in_brackets = false;
+    let mut escaped = false;
+    let mut bracket = String::new();
+    for c in code.chars() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match c {
+            '"' => in_quotes = !in_quotes,
+            _ if in_quotes => {}
+            '\\' => escaped = true,
+            '[' => {
+                in_brackets = true;
+                bracket.clear();
+            }
+            ']' => {
+                in_brackets = false;
+                if matches!(
+                    bracket.to_ascii_lowercase().as_str(),
+                    "h" | "hh" | "m" | "mm" | "s" | "ss"
+                ) {
+                    return true;
+                }
+            }
+            _ if in_brackets => bracket.push(c),
+            'y' | 'Y' | 'm' | 'M' | 'd' | 'D' | 'h' | 'H' | 's' | 'S' => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// `<b/>` and `<i/>` are on unless their `val` says otherwise.
+fn is_on(e: &BytesStart) -> Result<bool> {
+    Ok(!matches!(
+        attribute(e, b"val")?.as_deref(),
+        Some("0" | "false")
+    ))
+}
+
+/// ARGB hex to `#RRGGBB`.
+fn to_hex_color(argb: String) -> String {
+    match argb.len() {
+        8 => format!("#{}", &argb[2..]),
+        _ => format!("#{argb}"),
+    }
+}
+
+/// Index of `element` in `added`, pushing it first if it isn't there yet.
+fn append_once(added: &mut Vec<String>, element: String) -> usize {
+    match added.iter().position(|e| *e == element) {
+        Some(idx) => idx,
+        None => {
+            added.push(element);
+            added.len() - 1
+        }
+    }
+}
+
+fn with_count(e: &BytesStart, count: usize) -> Result<BytesStart<'static>> {
+    let mut start = e.to_owned();
+    start.clear_attributes();
+    for attr in e.attributes() {
+        let attr: Attribute = attr.map_err(invalid)?;
+        if attr.key.as_ref() != b"count" {
+            start.push_attribute(attr);
+        }
+    }
+    start.push_attribute(("count", count.to_string().as_str()));
+    Ok(start.into_owned())
+}
+
+/// The xml between the start and end tag of a serialized element.
+fn element_children(element: &str) -> &str {
+    element
+        .split_once('>')
+        .and_then(|(_, rest)| rest.rsplit_once("</"))
+        .map_or("", |(children, _)| children)
+}
+
+/// Font children with the `<color>` element left out.
+fn without_color(font_children: &str) -> Result<Vec<u8>> {
+    let mut reader = Reader::from_reader(font_children.as_bytes());
+    let mut out = Writer::new(Vec::new());
+    let mut buf = Vec::new();
+    let mut depth = 0;
+    let mut in_color = false;
+    loop {
+        let event = reader.read_event_into(&mut buf).map_err(invalid)?;
+        let skip = match &event {
+            Event::Eof => break,
+            Event::Empty(e) => in_color || (depth == 0 && e.local_name().as_ref() == b"color"),
+            Event::Start(e) => {
+                if depth == 0 && e.local_name().as_ref() == b"color" {
+                    in_color = true;
+                }
+                depth += 1;
+                in_color
+            }
+            Event::End(_) => {
+                depth -= 1;
+                let skip = in_color;
+                if depth == 0 {
+                    in_color = false;
+                }
+                skip
+            }
+            _ => in_color,
+        };
+        if !skip {
+            out.write_event(event).map_err(invalid)?;
+        }
+        buf.clear();
+    }
+    Ok(out.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xlsx::{template, DATE_STYLE, DURATION_STYLE};
+
+    fn template_styles() -> Styles {
+        Styles::parse(template::STYLES.as_bytes().to_vec()).unwrap()
+    }
+
+    #[test]
+    fn cell_styles() {
+        let styles = template_styles();
+        assert_eq!(styles.cell_style(0).unwrap(), CellStyle::default());
+        let date = styles.cell_style(DATE_STYLE).unwrap();
+        assert!(date.is_date);
+        assert_eq!(date.number_format.as_deref(), Some("mm-dd-yy"));
+        assert!(styles.cell_style(DURATION_STYLE).unwrap().is_date);
+        // Unknown indexes get the default format.
+        assert_eq!(styles.cell_style(999).unwrap(), CellStyle::default());
+    }
+
+    #[test]
+    fn highlights_are_appended() {
+        let mut styles = template_styles();
+        let first = styles
+            .add_highlight(DATE_STYLE, "FFFFFF00", "FFFF0000")
+            .unwrap();
+        let second = styles.add_highlight(0, "FFFFFF00", "FFFF0000").unwrap();
+        assert_eq!((first, second), (4, 5));
+        // Both share the fill, the base fonts are the same too.
+        assert_eq!((styles.new_fills.len(), styles.new_fonts.len()), (1, 1));
+
+        let written = Styles::parse(styles.to_xml().unwrap()).unwrap();
+        let xml = String::from_utf8(written.xml.clone()).unwrap();
+        assert!(xml.contains(r#"<cellXfs count="6">"#));
+        assert!(xml.contains(r#"<fills count="3">"#));
+        let highlighted = written.cell_style(first).unwrap();
+        assert_eq!(highlighted.background.as_deref(), Some("#FFFF00"));
+        assert_eq!(highlighted.color.as_deref(), Some("#FF0000"));
+        // The number format of the base format is kept.
+        assert!(highlighted.is_date);
+        assert!(!written.cell_style(second).unwrap().is_date);
+    }
+
+    #[test]
+    fn unchanged_styles_are_written_as_read() {
+        let styles = template_styles();
+        assert_eq!(styles.to_xml().unwrap(), template::STYLES.as_bytes());
+    }
+
+    #[test]
+    fn date_formats() {
+        assert!(is_date_format(14, ""));
+        assert!(is_date_format(164, "yyyy-mm-dd"));
+        assert!(is_date_format(164, "[h]:mm"));
+        assert!(!is_date_format(164, "0.00"));
+        assert!(!is_date_format(164, r#""days" 0"#));
+        assert!(!is_date_format(164, r"0\d"));
+        assert!(!is_date_format(164, "[Red]0.00"));
+    }
+}
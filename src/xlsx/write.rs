@@ -0,0 +1,498 @@
+use std::{
+    borrow::Cow,
+    io::{BufWriter, Read, Seek, Write},
+};
+
+use quick_xml::{
+    escape::escape,
+    events::{BytesStart, Event},
+    Reader, Writer,
+};
+use zip::{write::FileOptions, ZipWriter};
+
+use super::{
+    invalid, io_error,
+    read::{attribute, open_archive},
+    template, write_column_name, CellValue, Row, SheetXml, Workbook,
+};
+use crate::Result;
+
+const CONTENT_TYPES_PART: &str = "[Content_Types].xml";
+const VBA_PROJECT_TYPE: &str = "application/vnd.ms-office.vbaProject";
+const WORKBOOK_TYPE: &str =
+    "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml";
+const MACRO_ENABLED_WORKBOOK_TYPE: &str = "application/vnd.ms-excel.sheet.macroEnabled.main+xml";
+const WORKSHEET_TYPE: &str =
+    "application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml";
+const RELATIONSHIPS_NS: &str =
+    "http://schemas.openxmlformats.org/officeDocument/2006/relationships";
+
+/// Where an added sheet goes in the package.
+struct AddedPart {
+    part: String,
+    rel_id: String,
+}
+
+impl Workbook {
+    /// Writes the package back with the rows and styles as they are now.
+    /// Parts other than the first sheet and the styles are copied as is,
+    /// except for the calculation chain and, when stripped, the macros.
+    /// Added sheets are listed in the workbook after the existing ones.
+    pub fn write<W: Write + Seek>(&self, out: W) -> Result<W> {
+        let mut zip = ZipWriter::new(out);
+        let options = FileOptions::default();
+        let Some(source) = &self.source else {
+            let added = self.added_parts(|part| {
+                part == self.parts.sheet
+                    || template::PACKAGE_PARTS
+                        .iter()
+                        .any(|(name, _)| *name == part)
+            });
+            for (name, xml) in template::PACKAGE_PARTS {
+                zip.start_file(*name, options).map_err(io_error)?;
+                zip.write_all(&self.add_sheet_entries(name, xml.as_bytes(), &added)?)
+                    .map_err(io_error)?;
+            }
+            zip.start_file(self.parts.styles.as_str(), options)
+                .map_err(io_error)?;
+            zip.write_all(&self.styles.to_xml()?).map_err(io_error)?;
+            zip.start_file(self.parts.sheet.as_str(), options)
+                .map_err(io_error)?;
+            write_sheet(&mut zip, &self.sheet, &self.header, &self.rows)?;
+            self.write_added_sheets(&mut zip, &added)?;
+            return zip.finish().map_err(io_error);
+        };
+
+        let mut archive = open_archive(source)?;
+        let added = {
+            let names: Vec<&str> = archive.file_names().collect();
+            self.added_parts(|part| names.contains(&part))
+        };
+
+        // The calculation chain lists formula cells by position, once rows
+        // moved Excel would have to repair the file. Dropping it makes Excel
+        // rebuild it instead.
+        let mut dropped: Vec<&str> = self.parts.calc_chain.iter().map(String::as_str).collect();
+        let mut dropped_kinds = vec!["/calcChain"];
+        if self.strip_macros {
+            dropped.extend(self.parts.macros.iter().map(String::as_str));
+            dropped_kinds.push("/vbaProject");
+        }
+        let dropped_names: Vec<String> = dropped.iter().map(|name| format!("/{name}")).collect();
+
+        for idx in 0..archive.len() {
+            let name = archive
+                .by_index_raw(idx)
+                .map_err(invalid)?
+                .name()
+                .to_string();
+            if name == self.parts.sheet {
+                zip.start_file(name, options).map_err(io_error)?;
+                write_sheet(&mut zip, &self.sheet, &self.header, &self.rows)?;
+            } else if name == self.parts.styles {
+                zip.start_file(name, options).map_err(io_error)?;
+                zip.write_all(&self.styles.to_xml()?).map_err(io_error)?;
+            } else if dropped.contains(&name.as_str()) {
+                continue;
+            } else if (!dropped.is_empty() || !added.is_empty())
+                && (name == CONTENT_TYPES_PART || name == self.parts.workbook_rels)
+            {
+                let xml = read_entry(&mut archive, idx)?;
+                let mut xml = remove_elements(&xml, |e| {
+                    if let Some(part_name) = attribute(e, b"PartName")? {
+                        return Ok(dropped_names.contains(&part_name));
+                    }
+                    if let Some(kind) = attribute(e, b"Type")? {
+                        return Ok(dropped_kinds.iter().any(|k| kind.ends_with(k)));
+                    }
+                    Ok(self.strip_macros
+                        && attribute(e, b"ContentType")?.as_deref() == Some(VBA_PROJECT_TYPE))
+                })?;
+                if self.strip_macros && name == CONTENT_TYPES_PART {
+                    xml = String::from_utf8(xml)
+                        .map_err(invalid)?
+                        .replace(MACRO_ENABLED_WORKBOOK_TYPE, WORKBOOK_TYPE)
+                        .into_bytes();
+                }
+                let xml = self.add_sheet_entries(&name, &xml, &added)?;
+                zip.start_file(name, options).map_err(io_error)?;
+                zip.write_all(&xml).map_err(io_error)?;
+            } else if !added.is_empty() && name == self.parts.workbook {
+                let xml = read_entry(&mut archive, idx)?;
+                let xml = self.add_sheet_entries(&name, &xml, &added)?;
+                zip.start_file(name, options).map_err(io_error)?;
+                zip.write_all(&xml).map_err(io_error)?;
+            } else {
+                zip.raw_copy_file(archive.by_index_raw(idx).map_err(invalid)?)
+                    .map_err(io_error)?;
+            }
+        }
+        self.write_added_sheets(&mut zip, &added)?;
+
+        zip.finish().map_err(io_error)
+    }
+
+    /// Picks a part name and a relationship id for every added sheet, next
+    /// to the sheet that was read.
+    fn added_parts(&self, exists: impl Fn(&str) -> bool) -> Vec<AddedPart> {
+        let dir = self.parts.sheet.rsplit_once('/').map_or("", |(dir, _)| dir);
+        let mut parts: Vec<AddedPart> = Vec::with_capacity(self.added_sheets.len());
+        let mut number = 1;
+        for idx in 1..=self.added_sheets.len() {
+            let part = loop {
+                let part = format!("{dir}/sheet{number}.xml");
+                number += 1;
+                if !exists(&part) {
+                    break part;
+                }
+            };
+            parts.push(AddedPart {
+                part,
+                rel_id: format!("rIdAdded{idx}"),
+            });
+        }
+        parts
+    }
+
+    /// Lists the added sheets in the content types, the workbook and its
+    /// relationships. Other parts are returned as is.
+    fn add_sheet_entries<'a>(
+        &self,
+        name: &str,
+        xml: &'a [u8],
+        added: &[AddedPart],
+    ) -> Result<Cow<'a, [u8]>> {
+        if added.is_empty() {
+            return Ok(Cow::Borrowed(xml));
+        }
+        if name == CONTENT_TYPES_PART {
+            return insert_before_end(xml, b"Types", |p| {
+                added
+                    .iter()
+                    .map(|a| {
+                        format!(
+                            r#"<{p}Override PartName="/{}" ContentType="{WORKSHEET_TYPE}"/>"#,
+                            a.part
+                        )
+                    })
+                    .collect()
+            })
+            .map(Cow::Owned);
+        }
+        if name == self.parts.workbook_rels {
+            return insert_before_end(xml, b"Relationships", |p| {
+                added
+                    .iter()
+                    .map(|a| {
+                        format!(
+                            r#"<{p}Relationship Id="{}" Type="{RELATIONSHIPS_NS}/worksheet" Target="/{}"/>"#,
+                            a.rel_id, a.part
+                        )
+                    })
+                    .collect()
+            })
+            .map(Cow::Owned);
+        }
+        if name == self.parts.workbook {
+            return self.add_workbook_sheets(xml, added).map(Cow::Owned);
+        }
+        Ok(Cow::Borrowed(xml))
+    }
+
+    /// Adds a `<sheet>` per added sheet at the end of `<sheets>`, with the
+    /// next free sheet ids and names not used yet.
+    fn add_workbook_sheets(&self, xml: &[u8], added: &[AddedPart]) -> Result<Vec<u8>> {
+        let mut reader = Reader::from_reader(xml);
+        let mut out = Writer::new(Vec::with_capacity(xml.len() + 128 * added.len()));
+        let mut buf = Vec::new();
+        let mut rel_prefix: Option<String> = None;
+        let mut max_sheet_id: u32 = 0;
+        let mut names: Vec<String> = Vec::new();
+        loop {
+            let event = reader.read_event_into(&mut buf).map_err(invalid)?;
+            match &event {
+                Event::Eof => break,
+                Event::Start(e) | Event::Empty(e) => {
+                    if rel_prefix.is_none() {
+                        for attr in e.attributes() {
+                            let attr = attr.map_err(invalid)?;
+                            if attr.value.as_ref() == RELATIONSHIPS_NS.as_bytes() {
+                                if let Some(prefix) = attr.key.as_ref().strip_prefix(b"xmlns:") {
+                                    rel_prefix = Some(String::from_utf8_lossy(prefix).into_owned());
+                                }
+                            }
+                        }
+                    }
+                    if e.local_name().as_ref() == b"sheet" {
+                        if let Some(id) = attribute(e, b"sheetId")?.and_then(|id| id.parse().ok()) {
+                            max_sheet_id = max_sheet_id.max(id);
+                        }
+                        if let Some(name) = attribute(e, b"name")? {
+                            names.push(name.to_lowercase());
+                        }
+                    }
+                }
+                Event::End(e) if e.local_name().as_ref() == b"sheets" => {
+                    let p = match e.name().prefix() {
+                        None => String::new(),
+                        Some(prefix) => format!("{}:", String::from_utf8_lossy(prefix.as_ref())),
+                    };
+                    let (r, r_ns) = match &rel_prefix {
+                        Some(r) => (r.as_str(), String::new()),
+                        None => ("r", format!(r#" xmlns:r="{RELATIONSHIPS_NS}""#)),
+                    };
+                    for (sheet, part) in self.added_sheets.iter().zip(added) {
+                        let name = unique_name(&sheet.name, &names);
+                        names.push(name.to_lowercase());
+                        max_sheet_id += 1;
+                        write!(
+                            out.get_mut(),
+                            r#"<{p}sheet name="{}" sheetId="{max_sheet_id}" {r}:id="{}"{r_ns}/>"#,
+                            escape(&name),
+                            part.rel_id
+                        )
+                        .map_err(io_error)?;
+                    }
+                }
+                _ => {}
+            }
+            out.write_event(event).map_err(invalid)?;
+            buf.clear();
+        }
+        Ok(out.into_inner())
+    }
+
+    fn write_added_sheets<W: Write + Seek>(
+        &self,
+        zip: &mut ZipWriter<W>,
+        added: &[AddedPart],
+    ) -> Result<()> {
+        let sheet_xml = template::sheet_xml();
+        for (sheet, part) in self.added_sheets.iter().zip(added) {
+            zip.start_file(part.part.as_str(), FileOptions::default())
+                .map_err(io_error)?;
+            write_sheet(&mut *zip, &sheet_xml, &sheet.header, &sheet.rows)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_sheet<W: Write>(out: W, sheet: &SheetXml, header: &Row, rows: &[Row]) -> Result<()> {
+    let mut w = BufWriter::new(out);
+    let p = &sheet.ns_prefix;
+    match rows.iter().map(Row::outline_level).max().unwrap_or(0) {
+        0 => w.write_all(&sheet.prefix).map_err(io_error)?,
+        levels => w
+            .write_all(&with_outline_levels(&sheet.prefix, p, levels)?)
+            .map_err(io_error)?,
+    }
+    write!(w, "<{p}sheetData>").map_err(io_error)?;
+    write_row(&mut w, p, 1, header).map_err(io_error)?;
+    for (pos, row) in rows.iter().enumerate() {
+        write_row(&mut w, p, pos as u32 + 2, row).map_err(io_error)?;
+    }
+    write!(w, "</{p}sheetData>").map_err(io_error)?;
+    w.write_all(&sheet.suffix).map_err(io_error)?;
+    w.flush().map_err(io_error)
+}
+
+/// Copies the xml of a sheet up to its rows making the outline show at least
+/// `levels` row levels. Excel sizes the outline bar after the
+/// `outlineLevelRow` of the sheet format, which goes right before the
+/// column widths.
+fn with_outline_levels(prefix: &[u8], p: &str, levels: u8) -> Result<Vec<u8>> {
+    let mut reader = Reader::from_reader(prefix);
+    let mut out = Writer::new(Vec::with_capacity(prefix.len() + 64));
+    let mut buf = Vec::new();
+    let mut done = false;
+    loop {
+        let event = reader.read_event_into(&mut buf).map_err(invalid)?;
+        match &event {
+            Event::Eof => break,
+            Event::Empty(e) if !done && e.local_name().as_ref() == b"sheetFormatPr" => {
+                let existing = attribute(e, b"outlineLevelRow")?
+                    .and_then(|level| level.parse::<u8>().ok())
+                    .unwrap_or(0);
+                let name = String::from_utf8_lossy(e.name().as_ref()).into_owned();
+                let mut start = BytesStart::new(name);
+                for attr in e.attributes() {
+                    let attr = attr.map_err(invalid)?;
+                    if attr.key.as_ref() != b"outlineLevelRow" {
+                        start.push_attribute(attr);
+                    }
+                }
+                start
+                    .push_attribute(("outlineLevelRow", levels.max(existing).to_string().as_str()));
+                out.write_event(Event::Empty(start)).map_err(invalid)?;
+                done = true;
+            }
+            Event::Start(e) | Event::Empty(e) if !done && e.local_name().as_ref() == b"cols" => {
+                write_sheet_format(out.get_mut(), p, levels);
+                done = true;
+                out.write_event(event).map_err(invalid)?;
+            }
+            _ => out.write_event(event).map_err(invalid)?,
+        }
+        buf.clear();
+    }
+    let mut xml = out.into_inner();
+    if !done {
+        write_sheet_format(&mut xml, p, levels);
+    }
+    Ok(xml)
+}
+
+fn write_sheet_format(xml: &mut Vec<u8>, p: &str, levels: u8) {
+    xml.extend_from_slice(
+        format!(r#"<{p}sheetFormatPr defaultRowHeight="15" outlineLevelRow="{levels}"/>"#)
+            .as_bytes(),
+    );
+}
+
+/// `name`, or `name (2)`, `name (3)` and so on if taken. Excel compares
+/// sheet names ignoring case, `taken` is lowercase.
+fn unique_name(name: &str, taken: &[String]) -> String {
+    let mut candidate = name.to_string();
+    let mut number = 2;
+    while taken.contains(&candidate.to_lowercase()) {
+        candidate = format!("{name} ({number})");
+        number += 1;
+    }
+    candidate
+}
+
+fn read_entry<R: Read + Seek>(archive: &mut zip::ZipArchive<R>, idx: usize) -> Result<Vec<u8>> {
+    let mut xml = Vec::new();
+    archive
+        .by_index(idx)
+        .map_err(invalid)?
+        .read_to_end(&mut xml)
+        .map_err(invalid)?;
+    Ok(xml)
+}
+
+fn write_row<W: Write>(w: &mut W, p: &str, row_idx: u32, row: &Row) -> std::io::Result<()> {
+    if row.attrs.is_empty() && row.cells.is_empty() {
+        return Ok(());
+    }
+    write!(w, r#"<{p}row r="{row_idx}""#)?;
+    for (key, value) in row.attrs.iter() {
+        write!(w, r#" {key}="{value}""#)?;
+    }
+    if row.cells.is_empty() {
+        return w.write_all(b"/>");
+    }
+    w.write_all(b">")?;
+
+    for cell in &row.cells {
+        write!(w, r#"<{p}c r=""#)?;
+        write_column_name(w, cell.col)?;
+        write!(w, r#"{row_idx}""#)?;
+        if cell.style != 0 {
+            write!(w, r#" s="{}""#, cell.style)?;
+        }
+        let cell_type = match &cell.value {
+            CellValue::Empty | CellValue::Number(_) => "",
+            CellValue::SharedString { .. } => r#" t="s""#,
+            CellValue::Text(_) | CellValue::RichText(_) => r#" t="inlineStr""#,
+            CellValue::FormulaString(_) => r#" t="str""#,
+            CellValue::Bool(_) => r#" t="b""#,
+            CellValue::Error(_) => r#" t="e""#,
+            CellValue::Date(_) => r#" t="d""#,
+        };
+        w.write_all(cell_type.as_bytes())?;
+        if cell.formula.is_none() && matches!(cell.value, CellValue::Empty) {
+            w.write_all(b"/>")?;
+            continue;
+        }
+        w.write_all(b">")?;
+        if let Some(formula) = &cell.formula {
+            w.write_all(formula.as_bytes())?;
+        }
+        match &cell.value {
+            CellValue::Empty => {}
+            CellValue::SharedString { idx, .. } => write!(w, "<{p}v>{idx}</{p}v>")?,
+            CellValue::Number(v)
+            | CellValue::FormulaString(v)
+            | CellValue::Error(v)
+            | CellValue::Date(v) => write!(w, "<{p}v>{}</{p}v>", escape(v))?,
+            CellValue::Bool(v) => write!(w, "<{p}v>{}</{p}v>", u8::from(*v))?,
+            CellValue::Text(text) => write!(
+                w,
+                r#"<{p}is><{p}t xml:space="preserve">{}</{p}t></{p}is>"#,
+                escape(text)
+            )?,
+            CellValue::RichText(runs) => {
+                write!(w, "<{p}is>")?;
+                for run in runs.iter() {
+                    write!(w, "<{p}r>")?;
+                    if run.bold || run.color.is_some() {
+                        write!(w, "<{p}rPr>")?;
+                        if run.bold {
+                            write!(w, "<{p}b/>")?;
+                        }
+                        if let Some(color) = &run.color {
+                            write!(w, r#"<{p}color rgb="{color}"/>"#)?;
+                        }
+                        write!(w, "</{p}rPr>")?;
+                    }
+                    write!(
+                        w,
+                        r#"<{p}t xml:space="preserve">{}</{p}t></{p}r>"#,
+                        escape(&run.text)
+                    )?;
+                }
+                write!(w, "</{p}is>")?;
+            }
+        }
+        write!(w, "</{p}c>")?;
+    }
+    write!(w, "</{p}row>")
+}
+
+/// Copies `xml` adding what `added` returns, given the namespace prefix of
+/// the element, right before the end tag of the element named `local_name`.
+fn insert_before_end<F>(xml: &[u8], local_name: &[u8], added: F) -> Result<Vec<u8>>
+where
+    F: Fn(&str) -> String,
+{
+    let mut reader = Reader::from_reader(xml);
+    let mut out = Writer::new(Vec::with_capacity(xml.len()));
+    let mut buf = Vec::new();
+    loop {
+        let event = reader.read_event_into(&mut buf).map_err(invalid)?;
+        match &event {
+            Event::Eof => break,
+            Event::End(e) if e.local_name().as_ref() == local_name => {
+                let p = match e.name().prefix() {
+                    None => String::new(),
+                    Some(prefix) => format!("{}:", String::from_utf8_lossy(prefix.as_ref())),
+                };
+                out.get_mut().extend_from_slice(added(&p).as_bytes());
+            }
+            _ => {}
+        }
+        out.write_event(event).map_err(invalid)?;
+        buf.clear();
+    }
+    Ok(out.into_inner())
+}
+
+/// Copies `xml` leaving out the empty elements `remove` matches.
+fn remove_elements<F>(xml: &[u8], remove: F) -> Result<Vec<u8>>
+where
+    F: Fn(&BytesStart) -> Result<bool>,
+{
+    let mut reader = Reader::from_reader(xml);
+    let mut out = Writer::new(Vec::with_capacity(xml.len()));
+    let mut buf = Vec::new();
+    loop {
+        match reader.read_event_into(&mut buf).map_err(invalid)? {
+            Event::Eof => break,
+            Event::Empty(e) if remove(&e)? => {}
+            e => out.write_event(e).map_err(invalid)?,
+        }
+        buf.clear();
+    }
+    Ok(out.into_inner())
+}
@@ -0,0 +1,113 @@
+//! Preflight responses of the CORS policies `cors_layer` builds.
+
+use std::time::Duration;
+
+use axum::{
+    body::Body,
+    http::{header, HeaderValue, Method, Request, Response},
+    routing::post,
+    Router,
+};
+use excel_app::{
+    config::{AllowedOrigins, CorsConfig},
+    cors_layer,
+};
+use tower::ServiceExt;
+
+const ORIGIN: &str = "https://reports.example.com";
+
+async fn preflight(cors: &CorsConfig, origin: &str, method: Method) -> Response<Body> {
+    let app = Router::new()
+        .route("/runJob", post(|| async { "" }))
+        .layer(cors_layer(cors));
+    let request = Request::builder()
+        .method(Method::OPTIONS)
+        .uri("/runJob")
+        .header(header::ORIGIN, origin)
+        .header(header::ACCESS_CONTROL_REQUEST_METHOD, method.as_str())
+        .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "authorization")
+        .body(Body::empty())
+        .unwrap();
+    app.oneshot(request).await.unwrap()
+}
+
+fn header(response: &Response<Body>, name: header::HeaderName) -> Option<&str> {
+    response
+        .headers()
+        .get(name)
+        .map(|value| value.to_str().unwrap())
+}
+
+#[tokio::test]
+async fn default_policy_allows_no_origin() {
+    let response = preflight(&CorsConfig::default(), ORIGIN, Method::POST).await;
+
+    assert_eq!(header(&response, header::ACCESS_CONTROL_ALLOW_ORIGIN), None);
+    assert_eq!(
+        header(&response, header::ACCESS_CONTROL_ALLOW_CREDENTIALS),
+        None
+    );
+}
+
+#[tokio::test]
+async fn listed_origin_gets_the_configured_policy() {
+    let cors = CorsConfig {
+        allowed_origins: AllowedOrigins::List(vec![HeaderValue::from_static(ORIGIN)]),
+        allow_credentials: true,
+        max_age: Duration::from_secs(120),
+        ..CorsConfig::default()
+    };
+    let response = preflight(&cors, ORIGIN, Method::DELETE).await;
+
+    assert!(response.status().is_success());
+    assert_eq!(
+        header(&response, header::ACCESS_CONTROL_ALLOW_ORIGIN),
+        Some(ORIGIN)
+    );
+    assert_eq!(
+        header(&response, header::ACCESS_CONTROL_ALLOW_METHODS),
+        Some("GET,POST,DELETE")
+    );
+    assert_eq!(
+        header(&response, header::ACCESS_CONTROL_ALLOW_HEADERS),
+        Some("content-type,authorization")
+    );
+    assert_eq!(
+        header(&response, header::ACCESS_CONTROL_ALLOW_CREDENTIALS),
+        Some("true")
+    );
+    assert_eq!(
+        header(&response, header::ACCESS_CONTROL_MAX_AGE),
+        Some("120")
+    );
+}
+
+#[tokio::test]
+async fn unlisted_origin_is_not_allowed() {
+    let cors = CorsConfig {
+        allowed_origins: AllowedOrigins::List(vec![HeaderValue::from_static(ORIGIN)]),
+        ..CorsConfig::default()
+    };
+    let response = preflight(&cors, "https://evil.example.com", Method::POST).await;
+
+    assert_eq!(header(&response, header::ACCESS_CONTROL_ALLOW_ORIGIN), None);
+}
+
+#[tokio::test]
+async fn any_origin_is_answered_with_a_wildcard() {
+    let cors = CorsConfig {
+        allowed_origins: AllowedOrigins::Any,
+        allowed_methods: vec![Method::GET],
+        ..CorsConfig::default()
+    };
+    let response = preflight(&cors, ORIGIN, Method::GET).await;
+
+    assert_eq!(
+        header(&response, header::ACCESS_CONTROL_ALLOW_ORIGIN),
+        Some("*")
+    );
+    assert_eq!(
+        header(&response, header::ACCESS_CONTROL_ALLOW_METHODS),
+        Some("GET")
+    );
+}
@@ -0,0 +1,159 @@
+//! Spans of requests and of the phases of jobs, collected with an exporter
+//! of the test itself. Run with `cargo test --features otel`.
+#![cfg(feature = "otel")]
+
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+};
+
+use axum::{
+    body::{to_bytes, Body},
+    http::{header::CONTENT_TYPE, Request, StatusCode},
+    Router,
+};
+use excel_app::{
+    config::{Config, DatabaseConfig},
+    telemetry,
+};
+use opentelemetry::Value;
+use opentelemetry_sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+use tower::ServiceExt;
+use tracing::Level;
+use tracing_subscriber::{filter::Targets, layer::SubscriberExt, Layer};
+
+const BOUNDARY: &str = "excel-app-test-boundary";
+const REQUEST_ID: &str = "telemetry-test-job";
+const CSV: &str = "Name,Note\ncarol,apple pie\nalice,banana\nbob,apple juice\n";
+
+/// Keeps every span exported.
+#[derive(Debug, Clone, Default)]
+struct CollectingExporter(Arc<Mutex<Vec<SpanData>>>);
+
+impl SpanExporter for CollectingExporter {
+    fn export(
+        &mut self,
+        batch: Vec<SpanData>,
+    ) -> Pin<Box<dyn Future<Output = ExportResult> + Send + 'static>> {
+        self.0.lock().unwrap().extend(batch);
+        Box::pin(std::future::ready(Ok(())))
+    }
+}
+
+#[tokio::test]
+async fn job_phases_are_exported_as_children_of_the_job_and_request() {
+    let exporter = CollectingExporter::default();
+    let provider = telemetry::simple_provider(exporter.clone());
+    // The spans of the job are made on a blocking thread, the subscriber has
+    // to be the global one.
+    let subscriber = tracing_subscriber::registry().with(
+        telemetry::layer(&provider)
+            .with_filter(Targets::new().with_target("excel_app", Level::TRACE)),
+    );
+    tracing::subscriber::set_global_default(subscriber).unwrap();
+
+    let work_dir = std::env::temp_dir().join(format!("excel_app_telemetry_{}", std::process::id()));
+    std::fs::create_dir_all(&work_dir).unwrap();
+    std::env::set_current_dir(&work_dir).unwrap();
+    let config = Config {
+        database: DatabaseConfig::Sqlite {
+            path: work_dir.join("test.sqlite"),
+            pool_size: 1,
+        },
+        ..Config::default()
+    };
+    let app = excel_app::get_app_router(&config).await.unwrap();
+
+    let file_id = upload(&app).await;
+    run_job(&app, &file_id).await;
+    let _ = std::fs::remove_dir_all(&work_dir);
+
+    let spans = exporter.0.lock().unwrap().clone();
+    let request = spans
+        .iter()
+        .find(|span| {
+            span.name == "request" && attribute(span, "request_id") == Some(REQUEST_ID.into())
+        })
+        .expect("no span of the /runJob request");
+    let job = find(&spans, "job");
+    assert_eq!(job.parent_span_id, request.span_context.span_id());
+    assert_eq!(job.span_context.trace_id(), request.span_context.trace_id());
+    assert_eq!(attribute(job, "file_id"), Some(file_id.into()));
+    assert!(attribute(job, "job_id").is_some());
+
+    for phase in ["read", "validate", "sort", "highlight", "write"] {
+        let span = find(&spans, phase);
+        assert_eq!(span.parent_span_id, job.span_context.span_id(), "{phase}");
+    }
+    let contraction_load = find(&spans, "contraction_load");
+    assert_eq!(
+        contraction_load.parent_span_id,
+        find(&spans, "sort").span_context.span_id()
+    );
+}
+
+fn find<'a>(spans: &'a [SpanData], name: &str) -> &'a SpanData {
+    spans
+        .iter()
+        .find(|span| span.name == name)
+        .unwrap_or_else(|| panic!("no {name} span"))
+}
+
+fn attribute(span: &SpanData, key: &str) -> Option<Value> {
+    span.attributes
+        .iter()
+        .find(|kv| kv.key.as_str() == key)
+        .map(|kv| kv.value.clone())
+}
+
+async fn upload(app: &Router) -> String {
+    let mut body = format!(
+        "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"notes.csv\"\r\nContent-Type: text/csv\r\n\r\n"
+    );
+    body.push_str(CSV);
+    body.push_str(&format!("\r\n--{BOUNDARY}--\r\n"));
+
+    let response = app
+        .clone()
+        .oneshot(multipart_request("/upload", body))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let entry: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    entry["id"].as_str().unwrap().to_string()
+}
+
+async fn run_job(app: &Router, file_id: &str) {
+    let mut body = String::new();
+    for (name, value) in [
+        ("fileId", file_id),
+        ("sortCol", "asc,1"),
+        ("searchTerm", "apple"),
+    ] {
+        body.push_str(&format!(
+            "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"{name}\"\r\n\r\n{value}\r\n"
+        ));
+    }
+    body.push_str(&format!("--{BOUNDARY}--\r\n"));
+
+    let mut request = multipart_request("/runJob", body);
+    request
+        .headers_mut()
+        .insert("x-request-id", REQUEST_ID.parse().unwrap());
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    assert_eq!(status, StatusCode::OK, "{}", String::from_utf8_lossy(&body));
+}
+
+fn multipart_request(uri: &str, body: String) -> Request<Body> {
+    Request::post(uri)
+        .header(
+            CONTENT_TYPE,
+            format!("multipart/form-data; boundary={BOUNDARY}"),
+        )
+        .body(Body::from(body))
+        .unwrap()
+}